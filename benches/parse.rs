@@ -0,0 +1,63 @@
+use std::hint::black_box;
+use std::time::Instant;
+
+use seymour_protocol::{parse_any, Command, Response};
+
+const ITERATIONS: u32 = 1_000_000;
+
+const COMMANDS: &[&str] = &[
+    "USER jay",
+    "LISTSUBSCRIPTIONS",
+    "SUBSCRIBE gemini://example.org/feed.gmi",
+    "LISTUNREAD",
+    "MARKREAD 123456",
+    "PING",
+];
+
+const RESPONSES: &[&str] = &[
+    "20 1",
+    "21",
+    "22 42 gemini://example.org/feed.gmi",
+    "23",
+    "24 123456 42 gemini://example.org/feed.gmi gemini://example.org/2021-01-01.gmi A day in the life",
+    "25",
+    "28",
+    "41 missing argument \"id\"",
+];
+
+fn bench<F: Fn(&str)>(name: &str, line: &str, parse: F) {
+    let start = Instant::now();
+
+    for _ in 0..ITERATIONS {
+        parse(black_box(line));
+    }
+
+    let elapsed = start.elapsed();
+
+    println!(
+        "{:<10} {:>8.1} ns/iter  {}",
+        name,
+        elapsed.as_nanos() as f64 / f64::from(ITERATIONS),
+        line
+    );
+}
+
+fn main() {
+    for line in COMMANDS {
+        bench("command", line, |line| {
+            let _ = black_box(line.parse::<Command>());
+        });
+    }
+
+    for line in RESPONSES {
+        bench("response", line, |line| {
+            let _ = black_box(line.parse::<Response>());
+        });
+    }
+
+    for line in RESPONSES {
+        bench("bytes", line, |line| {
+            let _ = black_box(parse_any(line.as_bytes()));
+        });
+    }
+}
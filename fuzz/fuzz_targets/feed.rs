@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(document) = std::str::from_utf8(data) {
+        if let Ok(items) = seymour_protocol::feed::parse(document, "https://example.org/feed") {
+            for item in items {
+                let _ = item.into_entry(1, 1, "https://example.org/feed");
+            }
+        }
+    }
+});
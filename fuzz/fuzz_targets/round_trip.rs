@@ -0,0 +1,43 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use seymour_protocol::{Command, EntryFlags, ProtocolVersion, Response};
+
+fuzz_target!(|messages: (Command, Response)| {
+    let (command, response) = messages;
+
+    assert_eq!(command.to_string().parse::<Command>().ok(), Some(command));
+
+    for &version in ProtocolVersion::ALL {
+        let mut expected = response.clone();
+
+        // Fields added in version 2 are dropped by version 1
+        if version < ProtocolVersion::V2 {
+            match &mut expected {
+                Response::Subscription(subscription) => {
+                    subscription.icon = None;
+                    subscription.order = None;
+                }
+                Response::Entry(entry) => {
+                    entry.published = None;
+                    entry.guid = None;
+                    entry.read_at = None;
+                    entry.group_id = None;
+                    entry.duplicate = false;
+                    entry.flags = EntryFlags::empty();
+                    entry.language = None;
+                    entry.summary = None;
+                }
+                Response::AckUnsubscribe { revision, .. }
+                | Response::AckMarkRead { revision, .. } => {
+                    *revision = None;
+                }
+                _ => {}
+            }
+        }
+
+        let line = response.encode(version).to_string();
+
+        assert_eq!(Response::parse_versioned(&line, version).ok(), Some(expected));
+    }
+});
@@ -0,0 +1,363 @@
+//! Server addresses
+//!
+//! `seymour://user@host:port` names a server and the account to
+//! select on it in a single string, for command lines and
+//! configuration files. The user and port may be left out, the
+//! port defaulting to `DEFAULT_PORT`, and IPv6 hosts are written
+//! in brackets. ConnectConfig reads the same addresses without
+//! the scheme, as `host`, `host:port` or `[v6]:port`, so
+//! clients need not parse addresses themselves.
+//!
+//! A bare domain may also be looked up through DNS SRV records
+//! named by `srv_name`. The crate does no DNS itself: clients
+//! look the records up with their resolver and `srv_candidates`
+//! turns the answer into the configs to try, in order.
+//!
+//! Parsing must never panic, whatever the input, so the same
+//! lints as the line parser apply here.
+
+#![deny(
+    clippy::indexing_slicing,
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::arithmetic_side_effects
+)]
+
+use std::fmt;
+use std::net::Ipv6Addr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::socks5::Socks5Proxy;
+use crate::{ClientDriver, ClientHandle, TcpOptions};
+
+/// The port servers listen on unless configured otherwise
+pub const DEFAULT_PORT: u16 = 1966;
+
+const SCHEME: &str = "seymour://";
+
+/// The service and protocol labels of SRV records
+const SRV_PREFIX: &str = "_seymour._tcp.";
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AddressError {
+    #[error("URL does not start with {SCHEME}")]
+    MissingScheme,
+    #[error("missing host")]
+    MissingHost,
+    #[error("invalid host \"{0}\"")]
+    InvalidHost(String),
+    #[error("invalid port \"{0}\"")]
+    InvalidPort(String),
+    #[error("empty user")]
+    EmptyUser,
+    #[error("unexpected \"{0}\" after the address")]
+    UnexpectedPath(String),
+}
+
+/// Split `host`, `host:port`, `[v6]` or `[v6]:port` into its
+/// host, without brackets, and port
+fn parse_host_port(authority: &str) -> Result<(String, Option<u16>), AddressError> {
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(bracketed) => {
+            let (host, rest) = bracketed
+                .split_once(']')
+                .ok_or_else(|| AddressError::InvalidHost(authority.to_string()))?;
+
+            if host.parse::<Ipv6Addr>().is_err() {
+                return Err(AddressError::InvalidHost(host.to_string()));
+            }
+
+            let port = match rest {
+                "" => None,
+                rest => Some(
+                    rest.strip_prefix(':')
+                        .ok_or_else(|| AddressError::InvalidHost(authority.to_string()))?,
+                ),
+            };
+
+            (host, port)
+        }
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+
+    if host.is_empty() {
+        return Err(AddressError::MissingHost);
+    }
+
+    if host
+        .chars()
+        .any(|c| c.is_whitespace() || c.is_control() || "/?#@[]".contains(c))
+    {
+        return Err(AddressError::InvalidHost(host.to_string()));
+    }
+
+    let port = port
+        .map(|port| {
+            port.parse()
+                .ok()
+                .filter(|_| port.bytes().all(|byte| byte.is_ascii_digit()))
+                .ok_or_else(|| AddressError::InvalidPort(port.to_string()))
+        })
+        .transpose()?;
+
+    Ok((host.to_string(), port))
+}
+
+/// Write a host, bracketing IPv6 addresses
+struct Host<'a>(&'a str);
+
+impl<'a> fmt::Display for Host<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.contains(':') {
+            write!(f, "[{}]", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+/// A `seymour://` URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeymourUrl {
+    /// The user to select after connecting
+    pub user: Option<String>,
+    /// A name or IP address, IPv6 addresses without brackets
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl SeymourUrl {
+    /// The port to connect to
+    pub fn port_or_default(&self) -> u16 {
+        self.port.unwrap_or(DEFAULT_PORT)
+    }
+}
+
+impl FromStr for SeymourUrl {
+    type Err = AddressError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let rest = value
+            .get(..SCHEME.len())
+            .filter(|scheme| scheme.eq_ignore_ascii_case(SCHEME))
+            .and_then(|_| value.get(SCHEME.len()..))
+            .ok_or(AddressError::MissingScheme)?;
+
+        let (authority, path) = match rest.find(&['/', '?', '#'][..]) {
+            Some(end) => (
+                rest.get(..end).unwrap_or_default(),
+                rest.get(end..).unwrap_or_default(),
+            ),
+            None => (rest, ""),
+        };
+
+        if !path.is_empty() && path != "/" {
+            return Err(AddressError::UnexpectedPath(path.to_string()));
+        }
+
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some(("", _)) => return Err(AddressError::EmptyUser),
+            Some((user, host_port)) => (Some(user.to_string()), host_port),
+            None => (None, authority),
+        };
+        let (host, port) = parse_host_port(host_port)?;
+
+        Ok(SeymourUrl { user, host, port })
+    }
+}
+
+impl fmt::Display for SeymourUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", SCHEME)?;
+
+        if let Some(user) = &self.user {
+            write!(f, "{}@", user)?;
+        }
+
+        write!(f, "{}", Host(&self.host))?;
+
+        match self.port {
+            Some(port) => write!(f, ":{}", port),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Where a client connects and who it acts as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectConfig {
+    /// A name or IP address, IPv6 addresses without brackets
+    pub host: String,
+    pub port: u16,
+    /// The user to select after connecting
+    pub user: Option<String>,
+    /// A proxy to reach the server through
+    ///
+    /// Clients connect to the proxy's address instead and open
+    /// a tunnel to `address` with a Socks5Handshake.
+    pub proxy: Option<Socks5Proxy>,
+    pub tcp: TcpOptions,
+    /// How long to wait for each reply, or None to wait until
+    /// the connection closes
+    pub command_timeout: Option<Duration>,
+}
+
+impl ConnectConfig {
+    /// A config for the default port, without a user
+    pub fn new(host: &str) -> ConnectConfig {
+        ConnectConfig {
+            host: host.to_string(),
+            port: DEFAULT_PORT,
+            user: None,
+            proxy: None,
+            tcp: TcpOptions::default(),
+            command_timeout: Some(Duration::from_secs(30)),
+        }
+    }
+
+    pub fn with_port(self, port: u16) -> ConnectConfig {
+        ConnectConfig { port, ..self }
+    }
+
+    pub fn with_user(self, user: &str) -> ConnectConfig {
+        ConnectConfig {
+            user: Some(user.to_string()),
+            ..self
+        }
+    }
+
+    pub fn with_proxy(self, proxy: Socks5Proxy) -> ConnectConfig {
+        ConnectConfig {
+            proxy: Some(proxy),
+            ..self
+        }
+    }
+
+    pub fn with_tcp_options(self, tcp: TcpOptions) -> ConnectConfig {
+        ConnectConfig { tcp, ..self }
+    }
+
+    /// Set the connect, read and per-command timeouts, None
+    /// meaning no limit
+    pub fn with_timeouts(
+        self,
+        connect: Option<Duration>,
+        read: Option<Duration>,
+        command: Option<Duration>,
+    ) -> ConnectConfig {
+        ConnectConfig {
+            tcp: TcpOptions {
+                connect_timeout: connect,
+                read_timeout: read,
+                ..self.tcp
+            },
+            command_timeout: command,
+            ..self
+        }
+    }
+
+    /// A client whose commands time out after `command_timeout`
+    pub fn client(&self) -> (ClientHandle, ClientDriver) {
+        ClientHandle::with_timeout(self.command_timeout)
+    }
+
+    /// The host and port, accepted by
+    /// `std::net::ToSocketAddrs` and most async runtimes
+    pub fn address(&self) -> (&str, u16) {
+        (&self.host, self.port)
+    }
+}
+
+/// Accepts `host`, `host:port`, `[v6]`, `[v6]:port`, an
+/// unbracketed IPv6 address without a port, or a `seymour://`
+/// URL
+impl FromStr for ConnectConfig {
+    type Err = AddressError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value
+            .get(..SCHEME.len())
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case(SCHEME))
+        {
+            return value.parse::<SeymourUrl>().map(ConnectConfig::from);
+        }
+
+        if value.parse::<Ipv6Addr>().is_ok() {
+            return Ok(ConnectConfig::new(value));
+        }
+
+        let (host, port) = parse_host_port(value)?;
+
+        Ok(ConnectConfig::new(&host).with_port(port.unwrap_or(DEFAULT_PORT)))
+    }
+}
+
+/// Written as `host:port`, without the user
+impl fmt::Display for ConnectConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", Host(&self.host), self.port)
+    }
+}
+
+impl From<SeymourUrl> for ConnectConfig {
+    fn from(url: SeymourUrl) -> ConnectConfig {
+        ConnectConfig {
+            port: url.port_or_default(),
+            user: url.user,
+            ..ConnectConfig::new(&url.host)
+        }
+    }
+}
+
+/// A DNS SRV record, as returned by a resolver
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SrvRecord {
+    pub priority: u16,
+    pub weight: u16,
+    pub port: u16,
+    /// The host serving the domain, with or without the
+    /// trailing dot
+    pub target: String,
+}
+
+/// The name to look SRV records up under, such as
+/// `_seymour._tcp.example.com` for `example.com`
+pub fn srv_name(domain: &str) -> String {
+    format!("{}{}", SRV_PREFIX, domain.trim_end_matches('.'))
+}
+
+/// The configs to try for a domain, given its SRV records
+///
+/// Records are tried by ascending priority and, within a
+/// priority, by descending weight. Without records, the domain
+/// itself is tried on the default port, to be resolved through
+/// its A and AAAA records. A single record with the target `.`
+/// means the domain offers no seymour service, and no configs
+/// are returned.
+pub fn srv_candidates(domain: &str, records: &[SrvRecord]) -> Vec<ConnectConfig> {
+    match records {
+        [] => return vec![ConnectConfig::new(domain.trim_end_matches('.'))],
+        [record] if record.target == "." => return Vec::new(),
+        _ => {}
+    }
+
+    let mut records: Vec<&SrvRecord> = records
+        .iter()
+        .filter(|record| record.target != ".")
+        .collect();
+    records.sort_by_key(|record| (record.priority, std::cmp::Reverse(record.weight)));
+
+    records
+        .into_iter()
+        .map(|record| {
+            ConnectConfig::new(record.target.trim_end_matches('.')).with_port(record.port)
+        })
+        .collect()
+}
@@ -0,0 +1,135 @@
+//! Audit records of commands
+//!
+//! An AuditRecord names every argument a command was sent with,
+//! in wire form and under the current verb, so servers can log
+//! commands as structured data. Arguments the descriptors mark
+//! as secret are redacted.
+
+use std::fmt;
+
+use crate::json::Value;
+use crate::{ArgumentKind, Command};
+
+/// Written in place of secret argument values
+pub const REDACTED: &str = "[redacted]";
+
+/// A command as a verb and its named arguments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub verb: &'static str,
+    /// Arguments in the order they are sent; absent optional
+    /// arguments are left out
+    pub arguments: Vec<(&'static str, String)>,
+}
+
+impl AuditRecord {
+    /// The value of an argument
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.arguments
+            .iter()
+            .find(|(candidate, _)| *candidate == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The record as a JSON object, with the verb under `verb`
+    pub fn to_json(&self) -> String {
+        let mut fields = vec![("verb", Value::from(self.verb))];
+
+        fields.extend(
+            self.arguments
+                .iter()
+                .map(|(name, value)| (*name, Value::from(value.as_str()))),
+        );
+
+        Value::object(fields).to_string()
+    }
+}
+
+/// Written as `verb=<verb> <name>=<value> ...`, quoting values
+/// that contain spaces, quotes or control characters
+impl fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "verb={}", self.verb)?;
+
+        for (name, value) in &self.arguments {
+            if value.is_empty()
+                || value
+                    .chars()
+                    .any(|c| c == ' ' || c == '"' || c.is_control())
+            {
+                write!(f, " {}={}", name, Value::from(value.as_str()))?;
+            } else {
+                write!(f, " {}={}", name, value)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Remove the next word of a line
+fn take(rest: &mut &str) -> String {
+    let (word, remaining) = rest.split_once(' ').unwrap_or((*rest, ""));
+    *rest = remaining;
+
+    word.to_string()
+}
+
+impl Command {
+    /// Describe the command for an audit log
+    pub fn to_audit_record(&self) -> AuditRecord {
+        let descriptor = self.descriptor();
+        let line = self.to_string();
+        let mut rest = line
+            .strip_prefix(descriptor.verb)
+            .unwrap_or(&line)
+            .trim_start_matches(' ');
+        let mut arguments = Vec::new();
+
+        for argument in descriptor.arguments {
+            if rest.is_empty() {
+                break;
+            }
+
+            let next = rest.split(' ').next().unwrap_or_default();
+
+            let value = match (argument.kind, argument.keyword) {
+                (ArgumentKind::Literal, _) if next == argument.name => {
+                    take(&mut rest);
+                    "true".to_string()
+                }
+                (ArgumentKind::Literal, _) => continue,
+                (_, Some(keyword)) if next == keyword => {
+                    take(&mut rest);
+                    take(&mut rest)
+                }
+                (_, Some(_)) => continue,
+                (ArgumentKind::Text, None) => std::mem::take(&mut rest).to_string(),
+                (_, None) => {
+                    let value = take(&mut rest);
+
+                    if argument.optional && value == "-" {
+                        continue;
+                    }
+
+                    value
+                }
+            };
+
+            if argument.secret {
+                arguments.push((argument.name, REDACTED.to_string()));
+            } else {
+                arguments.push((argument.name, value));
+            }
+        }
+
+        if !rest.is_empty() {
+            arguments.push(("rest", rest.to_string()));
+        }
+
+        AuditRecord {
+            verb: descriptor.verb,
+            arguments,
+        }
+    }
+}
@@ -0,0 +1,209 @@
+//! A seymour server
+//!
+//! ```text
+//! seymour-server [--listen ADDRESS] [--database PATH] [--htpasswd PATH]
+//!                [--user NAME]... [--admin NAME]...
+//! ```
+//!
+//! Serves the protocol on ADDRESS, 127.0.0.1 on the default port
+//! unless given, with a thread for each connection. Built with the
+//! `sqlite` feature it keeps its state in the SQLite database at
+//! PATH, `seymour.db` unless given; without it, state is kept in
+//! memory and lost on exit. `--user` and `--admin` add users
+//! before serving, the latter with the Admin role.
+//!
+//! Without `--htpasswd`, USER selects any user the server knows.
+//! With it, clients must AUTH as a user in the htpasswd file
+//! first.
+//!
+//! Entries reach the database through `Storage::store_entries`,
+//! from whatever fetches the subscribed feeds.
+
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use seymour_protocol::{
+    serve, AuthProvider, ConnectionLimiter, ConnectionLimits, Handler, Htpasswd, LoginThrottle,
+    ProtocolVersion, Response, Role, ServeOptions, Storage, DEFAULT_PORT,
+};
+
+const USAGE: &str = "usage: seymour-server [--listen ADDRESS] [--database PATH] \
+                     [--htpasswd PATH] [--user NAME]... [--admin NAME]...";
+
+#[derive(Debug)]
+struct Options {
+    listen: SocketAddr,
+    database: Option<String>,
+    htpasswd: Option<String>,
+    users: Vec<(String, Role)>,
+}
+
+fn options() -> Result<Options, String> {
+    let mut options = Options {
+        listen: SocketAddr::from(([127, 0, 0, 1], DEFAULT_PORT)),
+        database: None,
+        htpasswd: None,
+        users: Vec::new(),
+    };
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+
+        match arg.as_str() {
+            "--listen" => {
+                let address = value()?;
+                options.listen = address
+                    .parse()
+                    .map_err(|error| format!("invalid address {}: {}", address, error))?;
+            }
+            "--database" => options.database = Some(value()?),
+            "--htpasswd" => options.htpasswd = Some(value()?),
+            "--user" => options.users.push((value()?, Role::User)),
+            "--admin" => options.users.push((value()?, Role::Admin)),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("unknown argument {}\n{}", arg, USAGE)),
+        }
+    }
+
+    Ok(options)
+}
+
+#[cfg(feature = "sqlite")]
+fn storage(options: &Options) -> Result<Arc<dyn Storage>, String> {
+    let path = options.database.as_deref().unwrap_or("seymour.db");
+    let storage = seymour_protocol::SqliteStorage::open(path)
+        .map_err(|error| format!("cannot open {}: {}", path, error))?;
+
+    for (username, role) in &options.users {
+        let user = storage
+            .add_user(username)
+            .map_err(|error| error.to_string())?;
+        storage
+            .set_role(user, *role)
+            .map_err(|error| error.to_string())?;
+    }
+
+    Ok(Arc::new(storage))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn storage(options: &Options) -> Result<Arc<dyn Storage>, String> {
+    if options.database.is_some() {
+        return Err("--database needs the sqlite feature".to_string());
+    }
+
+    let storage = seymour_protocol::MemoryStorage::new();
+
+    for (username, role) in &options.users {
+        let user = storage.add_user(username);
+        storage.set_role(user, *role);
+    }
+
+    Ok(Arc::new(storage))
+}
+
+fn run() -> Result<(), String> {
+    let options = options()?;
+    let storage = storage(&options)?;
+    let auth = match &options.htpasswd {
+        Some(path) => {
+            let htpasswd =
+                Htpasswd::load(path).map_err(|error| format!("cannot load {}: {}", path, error))?;
+            Some(Arc::new(htpasswd) as Arc<dyn AuthProvider>)
+        }
+        None => None,
+    };
+
+    let listener = TcpListener::bind(options.listen)
+        .map_err(|error| format!("cannot listen on {}: {}", options.listen, error))?;
+    let limiter = Arc::new(ConnectionLimiter::new(ConnectionLimits {
+        max_connections: Some(1024),
+        max_per_address: Some(16),
+    }));
+    let throttle = Arc::new(LoginThrottle::new(
+        5,
+        Duration::from_secs(1),
+        Duration::from_secs(15 * 60),
+    ));
+    let serve_options = Arc::new(ServeOptions::default());
+
+    eprintln!("listening on {}", options.listen);
+
+    for socket in listener.incoming() {
+        let socket = match socket {
+            Ok(socket) => socket,
+            Err(error) => {
+                eprintln!("accept failed: {}", error);
+                continue;
+            }
+        };
+
+        let storage = Arc::clone(&storage);
+        let auth = auth.clone();
+        let limiter = Arc::clone(&limiter);
+        let throttle = Arc::clone(&throttle);
+        let serve_options = Arc::clone(&serve_options);
+
+        thread::spawn(move || {
+            if let Err(error) =
+                connection(socket, storage, auth, &limiter, throttle, &serve_options)
+            {
+                eprintln!("connection failed: {}", error);
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn connection(
+    mut socket: TcpStream,
+    storage: Arc<dyn Storage>,
+    auth: Option<Arc<dyn AuthProvider>>,
+    limiter: &ConnectionLimiter,
+    throttle: Arc<LoginThrottle>,
+    options: &ServeOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let address = socket.peer_addr()?;
+
+    let _permit = match limiter.admit(address.ip()) {
+        Ok(permit) => permit,
+        Err(refused) => {
+            writeln!(
+                socket,
+                "{}",
+                Response::from(refused).encode(ProtocolVersion::V1)
+            )?;
+            return Ok(());
+        }
+    };
+
+    serve(socket.try_clone()?, socket, address, options, |peer| {
+        let handler = Handler::new(storage)
+            .with_peer(peer.address.ip())
+            .with_login_throttle(throttle);
+
+        match auth {
+            Some(auth) => handler.with_auth_provider(auth),
+            None => handler,
+        }
+        .checked()
+    })?;
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(error) = run() {
+        eprintln!("seymour-server: {}", error);
+        process::exit(1);
+    }
+}
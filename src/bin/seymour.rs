@@ -0,0 +1,174 @@
+//! A command line client for seymour servers
+//!
+//! ```text
+//! seymour [--server ADDRESS] [--user NAME] COMMAND
+//!
+//!     subscribe URL        subscribe to the feed at URL
+//!     unsubscribe ID       unsubscribe from a feed
+//!     list                 list the feeds subscribed to
+//!     list --unread        list the unread entries
+//!     mark-read ID...      mark entries read
+//! ```
+//!
+//! ADDRESS is anything ConnectConfig parses, such as
+//! `seymour://alice@example.com` or `example.com:1966`, and
+//! defaults to SEYMOUR_SERVER, then `localhost`. The user comes
+//! from `--user` or the address. When SEYMOUR_PASSWORD is set the
+//! client authenticates with it, as servers with an htpasswd file
+//! require; otherwise it only selects the user.
+//!
+//! Each command is a FeedReader call driven by a BlockingClient,
+//! which reconnects with the config's backoff if the connection
+//! drops.
+
+use std::env;
+use std::error::Error;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process;
+
+use seymour_protocol::{BlockingClient, ConnectConfig, Credentials, FeedReader};
+
+const USAGE: &str = "usage: seymour [--server ADDRESS] [--user NAME] COMMAND
+commands:
+    subscribe URL        subscribe to the feed at URL
+    unsubscribe ID       unsubscribe from a feed
+    list                 list the feeds subscribed to
+    list --unread        list the unread entries
+    mark-read ID...      mark entries read";
+
+#[derive(Debug)]
+enum Subcommand {
+    Subscribe(String),
+    Unsubscribe(i64),
+    List { unread: bool },
+    MarkRead(Vec<i64>),
+}
+
+fn id(value: &str) -> Result<i64, String> {
+    value.parse().map_err(|_| format!("invalid id {}", value))
+}
+
+fn parse(mut args: impl Iterator<Item = String>) -> Result<(ConnectConfig, Subcommand), String> {
+    let mut server = env::var("SEYMOUR_SERVER").unwrap_or_else(|_| "localhost".to_string());
+    let mut user = None;
+
+    let verb = loop {
+        match args.next().as_deref() {
+            Some("--server") => server = args.next().ok_or("--server needs a value")?,
+            Some("--user") => user = Some(args.next().ok_or("--user needs a value")?),
+            Some("--help" | "-h") | None => return Err(USAGE.to_string()),
+            Some(verb) => break verb.to_string(),
+        }
+    };
+
+    let rest: Vec<String> = args.collect();
+
+    let subcommand = match (verb.as_str(), rest.as_slice()) {
+        ("subscribe", [url]) => Subcommand::Subscribe(url.clone()),
+        ("unsubscribe", [feed]) => Subcommand::Unsubscribe(id(feed)?),
+        ("list", []) => Subcommand::List { unread: false },
+        ("list", [flag]) if flag == "--unread" => Subcommand::List { unread: true },
+        ("mark-read", ids) if !ids.is_empty() => Subcommand::MarkRead(
+            ids.iter()
+                .map(|entry| id(entry))
+                .collect::<Result<_, _>>()?,
+        ),
+        _ => return Err(USAGE.to_string()),
+    };
+
+    let config: ConnectConfig = server
+        .parse()
+        .map_err(|error| format!("invalid address {}: {}", server, error))?;
+
+    let config = match user {
+        Some(user) => config.with_user(&user),
+        None => config,
+    };
+
+    Ok((config, subcommand))
+}
+
+/// Connect to the first of the config's addresses that answers
+fn dial(config: &ConnectConfig) -> io::Result<TcpStream> {
+    let mut failed = io::Error::new(io::ErrorKind::NotFound, "no addresses for the server");
+
+    for address in config.address().to_socket_addrs()? {
+        let stream = match config.tcp.connect_timeout {
+            Some(timeout) => TcpStream::connect_timeout(&address, timeout),
+            None => TcpStream::connect(address),
+        };
+
+        match stream {
+            Ok(stream) => {
+                config.tcp.apply(&stream)?;
+                return Ok(stream);
+            }
+            Err(error) => failed = error,
+        }
+    }
+
+    Err(failed)
+}
+
+fn run(config: ConnectConfig, subcommand: Subcommand) -> Result<(), Box<dyn Error>> {
+    let username = config
+        .user
+        .clone()
+        .ok_or("no user: pass --user or an address such as seymour://USER@HOST")?;
+
+    let (handle, driver) = config.client();
+    let backoff = config.tcp.backoff;
+    let mut client = BlockingClient::connect(driver, move || dial(&config), backoff)?;
+    let reader = FeedReader::new(handle);
+
+    match env::var("SEYMOUR_PASSWORD") {
+        Ok(password) => {
+            client.block_on(reader.authenticate(Credentials::Password { username, password }))??
+        }
+        Err(_) => client.block_on(reader.user(&username))??,
+    }
+
+    match subcommand {
+        Subcommand::Subscribe(url) => {
+            let feed = client.block_on(reader.subscribe(&url))??;
+            println!("subscribed to {} as feed {}", feed.url, feed.id);
+        }
+        Subcommand::Unsubscribe(feed) => {
+            client.block_on(reader.unsubscribe(feed))??;
+            println!("unsubscribed from feed {}", feed);
+        }
+        Subcommand::List { unread: false } => {
+            for feed in client.block_on(reader.feeds())?? {
+                println!("{}\t{}", feed.id, feed.url);
+            }
+        }
+        Subcommand::List { unread: true } => {
+            for entry in client.block_on(reader.unread())?? {
+                println!("{}\t{}\t{}", entry.id, entry.title, entry.url);
+            }
+        }
+        Subcommand::MarkRead(entries) => {
+            for entry in entries {
+                client.block_on(reader.mark_read_id(entry))??;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let (config, subcommand) = match parse(env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(usage) => {
+            eprintln!("{}", usage);
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = run(config, subcommand) {
+        eprintln!("seymour: {}", error);
+        process::exit(1);
+    }
+}
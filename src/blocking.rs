@@ -0,0 +1,377 @@
+//! Driving a client over a blocking socket
+//!
+//! ClientDriver does no I/O of its own. BlockingClient runs it
+//! over a std stream for programs without an async runtime, such
+//! as the seymour CLI: `block_on` runs a future on the calling
+//! thread, writing the lines it queues and reading the responses
+//! it waits on.
+//!
+//! When the socket fails, BlockingClient dials again, waiting
+//! between attempts as its ConnectBackoff says, and hands the new
+//! socket to `ClientDriver::reconnect`, which replays the
+//! session's preamble and the idempotent commands still awaiting
+//! replies. A response line that cannot be parsed leaves the
+//! replies after it impossible to match, so it is treated as a
+//! failed socket too. Once the backoff gives up the connection
+//! closes and the last error is returned. Every step is reported
+//! as a ClientEvent.
+//!
+//! With the `tracing` feature, connecting, disconnecting, each
+//! attempt to reconnect and unparseable responses are recorded as
+//! events, alongside those of the driver.
+//!
+//! Read timeouts set on the socket, as TcpOptions does, are not
+//! failures: they let `ClientDriver::expire` fail the replies
+//! whose own timeouts have passed.
+
+use std::future::Future;
+use std::io::{self, Read, Write};
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::Instant;
+
+use crate::{ClientDriver, ClientEvent, ConnectBackoff, Decoder};
+
+/// Bytes read from the socket at a time
+const READ_SIZE: usize = 4096;
+
+/// A ClientDriver run over blocking sockets made by `dial`
+pub struct BlockingClient<S, D> {
+    driver: ClientDriver,
+    dial: D,
+    backoff: ConnectBackoff,
+    socket: Option<S>,
+    decoder: Decoder,
+}
+
+impl<S, D> std::fmt::Debug for BlockingClient<S, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("BlockingClient")
+            .field("driver", &self.driver)
+            .field("backoff", &self.backoff)
+            .field("connected", &self.socket.is_some())
+            .finish()
+    }
+}
+
+impl<S, D> BlockingClient<S, D>
+where
+    S: Read + Write,
+    D: FnMut() -> io::Result<S>,
+{
+    /// Connect with `dial`, failing if the first attempt does
+    pub fn connect(
+        driver: ClientDriver,
+        mut dial: D,
+        backoff: ConnectBackoff,
+    ) -> io::Result<BlockingClient<S, D>> {
+        let socket = dial()?;
+
+        #[cfg(feature = "tracing")]
+        tracing::info!("connected");
+
+        Ok(BlockingClient {
+            driver,
+            dial,
+            backoff,
+            socket: Some(socket),
+            decoder: Decoder::new(),
+        })
+    }
+
+    pub fn driver(&self) -> &ClientDriver {
+        &self.driver
+    }
+
+    /// Run `future` to completion, driving the connection while
+    /// it waits
+    ///
+    /// Fails once the socket is lost and cannot be connected
+    /// again, or if the future waits on something the connection
+    /// will never deliver.
+    pub fn block_on<F: Future>(&mut self, future: F) -> io::Result<F::Output> {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return Ok(output);
+            }
+
+            match self.step(&mut cx) {
+                Ok(true) => {}
+                Ok(false) => {
+                    return match future.as_mut().poll(&mut cx) {
+                        Poll::Ready(output) => Ok(output),
+                        Poll::Pending => Err(io::Error::new(
+                            io::ErrorKind::NotConnected,
+                            "the connection has nothing left to deliver",
+                        )),
+                    };
+                }
+                Err(error) => self.recover(error)?,
+            }
+        }
+    }
+
+    /// Write the queued lines and read what arrives, returning
+    /// whether the driver has more to do
+    fn step(&mut self, cx: &mut Context<'_>) -> io::Result<bool> {
+        let socket = match &mut self.socket {
+            Some(socket) => socket,
+            None => return Err(io::ErrorKind::NotConnected.into()),
+        };
+
+        let mut written = false;
+
+        loop {
+            match self.driver.poll_line(cx) {
+                Poll::Ready(Some(line)) => {
+                    socket.write_all(line.as_bytes())?;
+                    socket.write_all(b"\n")?;
+                    written = true;
+                }
+                Poll::Ready(None) => return Ok(false),
+                Poll::Pending => break,
+            }
+        }
+
+        if written {
+            socket.flush()?;
+        }
+
+        let mut chunk = [0; READ_SIZE];
+
+        let read = match socket.read(&mut chunk) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(read) => read,
+            Err(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                self.driver.expire(Instant::now());
+                return Ok(true);
+            }
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => return Ok(true),
+            Err(error) => return Err(error),
+        };
+
+        self.decoder.push(chunk.get(..read).unwrap_or_default());
+
+        while let Some(response) = self.decoder.next_response(self.driver.version()) {
+            let response = response.map_err(|error| {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(%error, "response not parsed");
+
+                io::Error::new(io::ErrorKind::InvalidData, error)
+            })?;
+
+            // A violation closes the connection, failing the
+            // replies still awaited
+            let _ = self.driver.receive(response);
+        }
+
+        Ok(true)
+    }
+
+    /// Connect again after `error`, carrying the session over
+    fn recover(&mut self, mut error: io::Error) -> io::Result<()> {
+        self.socket = None;
+        self.decoder = Decoder::new();
+
+        #[cfg(feature = "tracing")]
+        tracing::warn!(%error, "disconnected");
+
+        self.driver.report(ClientEvent::Disconnected {
+            reason: error.to_string(),
+        });
+
+        let mut attempt: u32 = 0;
+
+        loop {
+            attempt = attempt.saturating_add(1);
+
+            let delay = match self.backoff.delay(attempt) {
+                Some(delay) => delay,
+                None => {
+                    #[cfg(feature = "tracing")]
+                    tracing::error!(%error, attempts = attempt, "giving up reconnecting");
+
+                    self.driver.close();
+                    return Err(error);
+                }
+            };
+
+            #[cfg(feature = "tracing")]
+            tracing::info!(attempt, ?delay, "reconnecting");
+
+            self.driver
+                .report(ClientEvent::Reconnecting { attempt, delay });
+            thread::sleep(delay);
+
+            match (self.dial)() {
+                Ok(socket) => {
+                    self.socket = Some(socket);
+                    self.driver.reconnect();
+
+                    return Ok(());
+                }
+                Err(failed) => error = failed,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::VecDeque;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::{ClientHandle, Command, Credentials, ProtocolVersion, Response};
+
+    /// A socket reading a script and recording what is written
+    struct Scripted {
+        input: io::Cursor<Vec<u8>>,
+        output: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// What was written to each socket dialed
+    type Written = Arc<Mutex<Vec<Arc<Mutex<Vec<u8>>>>>>;
+
+    fn lines(responses: &[Response]) -> Vec<u8> {
+        responses
+            .iter()
+            .map(|response| format!("{}\n", response.encode(ProtocolVersion::V1)))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    /// Dial sockets reading each script in turn, keeping what is
+    /// written to each
+    fn dialer(scripts: Vec<Vec<u8>>) -> (impl FnMut() -> io::Result<Scripted>, Written) {
+        let mut scripts = VecDeque::from(scripts);
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let sockets = Arc::clone(&written);
+
+        let dial = move || {
+            let input = scripts
+                .pop_front()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+            let output = Arc::new(Mutex::new(Vec::new()));
+            sockets.lock().unwrap().push(Arc::clone(&output));
+
+            Ok(Scripted {
+                input: io::Cursor::new(input),
+                output,
+            })
+        };
+
+        (dial, written)
+    }
+
+    fn backoff(max_attempts: u32) -> ConnectBackoff {
+        ConnectBackoff {
+            initial: Duration::ZERO,
+            max: Duration::ZERO,
+            max_attempts: Some(max_attempts),
+        }
+    }
+
+    #[test]
+    fn reconnects_replaying_the_session() {
+        let list = [Response::StartSubscriptionList, Response::EndList];
+        let (dial, written) = dialer(vec![
+            lines(&[Response::AckUser { id: 1 }]),
+            [lines(&[Response::AckUser { id: 1 }]), lines(&list)].concat(),
+        ]);
+        let (handle, driver) = ClientHandle::new();
+        let mut events = handle.events();
+        let mut client = BlockingClient::connect(driver, dial, backoff(3)).unwrap();
+
+        let auth = Command::Auth(Credentials::Password {
+            username: "bob".to_string(),
+            password: "hunter2".to_string(),
+        });
+        client.block_on(handle.request(auth)).unwrap().unwrap();
+
+        let reply = client
+            .block_on(handle.request(Command::ListSubscriptions))
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(reply.responses, list);
+
+        let written: Vec<String> = written
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|output| String::from_utf8(output.lock().unwrap().clone()).unwrap())
+            .collect();
+
+        assert_eq!(
+            written,
+            [
+                "AUTH PASSWORD bob hunter2\nLISTSUBSCRIPTIONS\n",
+                "AUTH PASSWORD bob hunter2\nLISTSUBSCRIPTIONS\n",
+            ]
+        );
+
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut next = || match std::pin::Pin::new(&mut events).poll_next(&mut cx) {
+            Poll::Ready(event) => event,
+            Poll::Pending => None,
+        };
+
+        assert!(matches!(next(), Some(ClientEvent::Disconnected { .. })));
+        assert_eq!(
+            next(),
+            Some(ClientEvent::Reconnecting {
+                attempt: 1,
+                delay: Duration::ZERO,
+            })
+        );
+        assert_eq!(
+            next(),
+            Some(ClientEvent::Reconnected {
+                resent: 2,
+                abandoned: 0,
+            })
+        );
+        assert_eq!(next(), None);
+    }
+
+    #[test]
+    fn gives_up_once_the_backoff_does() {
+        let (dial, _) = dialer(vec![Vec::new()]);
+        let (handle, driver) = ClientHandle::new();
+        let mut client = BlockingClient::connect(driver, dial, backoff(2)).unwrap();
+
+        let error = client.block_on(handle.request(Command::Ping)).unwrap_err();
+
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(handle.is_closed());
+    }
+}
@@ -0,0 +1,205 @@
+//! Localizable error messages
+//!
+//! Error responses carry English prose. Servers can prefix the
+//! prose with a stable key and parameters, as in
+//! `41 [missing-argument argument=url] missing argument "url"`,
+//! and clients holding a `Catalog` for their language show a
+//! translation instead. Parameter values are percent-encoded.
+//! Messages without a key, or with a key the catalog does not
+//! know, are shown as sent.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::form;
+use crate::ParseMessageError;
+
+/// An error message with a stable key and parameters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorMessage {
+    /// Names the kind of error, such as `missing-argument`
+    pub key: String,
+    pub params: Vec<(String, String)>,
+    /// The English message, shown when no translation is known
+    pub text: String,
+}
+
+impl ErrorMessage {
+    pub fn new(key: &str, text: &str) -> ErrorMessage {
+        ErrorMessage {
+            key: key.to_string(),
+            params: Vec::new(),
+            text: text.to_string(),
+        }
+    }
+
+    /// Add a parameter
+    pub fn param(mut self, name: &str, value: impl fmt::Display) -> ErrorMessage {
+        self.params.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// The value of a parameter
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(candidate, _)| candidate == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Split the key and parameters from the message of an
+    /// error response
+    ///
+    /// Returns None for messages without a key.
+    pub fn parse(message: &str) -> Option<ErrorMessage> {
+        let (prefix, text) = message.strip_prefix('[')?.split_once(']')?;
+        let mut words = prefix.split(' ');
+        let key = words.next().filter(|key| !key.is_empty())?;
+        let params = words
+            .map(|word| {
+                word.split_once('=')
+                    .map(|(name, value)| (name.to_string(), form::decode(value)))
+            })
+            .collect::<Option<_>>()?;
+
+        Some(ErrorMessage {
+            key: key.to_string(),
+            params,
+            text: text.strip_prefix(' ').unwrap_or(text).to_string(),
+        })
+    }
+}
+
+/// Written as `[<key> <name>=<value> ...] <text>`
+impl fmt::Display for ErrorMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}", self.key)?;
+
+        for (name, value) in &self.params {
+            write!(f, " {}={}", name, form::encode(value))?;
+        }
+
+        write!(f, "] {}", self.text)
+    }
+}
+
+/// Keys for every way a line can fail to parse
+impl From<&ParseMessageError> for ErrorMessage {
+    fn from(error: &ParseMessageError) -> ErrorMessage {
+        let text = error.to_string();
+        let message = |key| ErrorMessage::new(key, &text);
+
+        match error {
+            ParseMessageError::EmptyMessage => message("empty-message"),
+            ParseMessageError::UnknownType(name) => message("unknown-type").param("type", name),
+            ParseMessageError::MissingArgument(argument) => {
+                message("missing-argument").param("argument", argument)
+            }
+            ParseMessageError::TooManyArguments { expected, actual } => {
+                message("too-many-arguments")
+                    .param("expected", expected)
+                    .param("actual", actual)
+            }
+            ParseMessageError::InvalidIntegerArgument { argument, value } => {
+                message("invalid-integer")
+                    .param("argument", argument)
+                    .param("value", value)
+            }
+            ParseMessageError::InvalidUtf8 => message("invalid-utf8"),
+            ParseMessageError::UnknownKeyword(keyword) => {
+                message("unknown-keyword").param("keyword", keyword)
+            }
+            ParseMessageError::InvalidWebhookUrl { url, .. } => {
+                message("invalid-webhook-url").param("url", url)
+            }
+            ParseMessageError::InvalidCursor { value, .. } => {
+                message("invalid-cursor").param("value", value)
+            }
+            ParseMessageError::InvalidQuery(_) => message("invalid-query"),
+            ParseMessageError::NotUndoable(command) => {
+                message("not-undoable").param("command", command)
+            }
+            ParseMessageError::InvalidLanguage { value, .. } => {
+                message("invalid-language").param("value", value)
+            }
+            ParseMessageError::InvalidUsername { value, .. } => {
+                message("invalid-username").param("value", value)
+            }
+            ParseMessageError::UnknownContentFormat(format) => {
+                message("unknown-content-format").param("format", &format.0)
+            }
+            ParseMessageError::InvalidEntryFlags(flags) => {
+                message("invalid-entry-flags").param("value", &flags.0)
+            }
+            ParseMessageError::InvalidSyncToken { value, .. } => {
+                message("invalid-sync-token").param("value", value)
+            }
+            ParseMessageError::InvalidRequestId { value, .. } => {
+                message("invalid-request-id").param("value", value)
+            }
+        }
+    }
+}
+
+/// Translations of error messages into one language
+///
+/// Templates name parameters in braces, as in
+/// `argument manquant : {argument}`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Catalog {
+    templates: HashMap<String, String>,
+}
+
+impl Catalog {
+    pub fn new() -> Catalog {
+        Catalog::default()
+    }
+
+    /// Read a catalog with one `<key> = <template>` per line
+    ///
+    /// Blank lines, lines starting with `#` and lines without
+    /// `=` are skipped.
+    pub fn parse(text: &str) -> Catalog {
+        let mut catalog = Catalog::new();
+
+        for line in text.lines().map(str::trim) {
+            if line.starts_with('#') {
+                continue;
+            }
+
+            if let Some((key, template)) = line.split_once('=') {
+                catalog.insert(key.trim(), template.trim());
+            }
+        }
+
+        catalog
+    }
+
+    pub fn insert(&mut self, key: &str, template: &str) {
+        self.templates.insert(key.to_string(), template.to_string());
+    }
+
+    /// The translation of a message, or its English text when
+    /// the key is unknown
+    pub fn render(&self, message: &ErrorMessage) -> String {
+        match self.templates.get(&message.key) {
+            Some(template) => message
+                .params
+                .iter()
+                .fold(template.clone(), |rendered, (name, value)| {
+                    rendered.replace(&format!("{{{}}}", name), value)
+                }),
+            None => message.text.clone(),
+        }
+    }
+
+    /// The translation of the message of an error response
+    ///
+    /// Messages without a key are returned unchanged.
+    pub fn localize(&self, message: &str) -> String {
+        match ErrorMessage::parse(message) {
+            Some(message) => self.render(&message),
+            None => message.to_string(),
+        }
+    }
+}
@@ -0,0 +1,1219 @@
+//! A connection shared between tasks
+//!
+//! A TUI's refresh task and its input handler both want to send
+//! commands over one connection. ClientHandle is cheap to clone
+//! and records each command with a Demultiplexer under one lock,
+//! so lines go out in the order their replies come back. A single
+//! ClientDriver owns the socket: it writes the queued lines and
+//! passes it the responses read, which complete the handles'
+//! PendingReplies. Both sides are plain futures, so any runtime
+//! can drive them.
+//!
+//! A Pipeline queues several commands at once, their lines
+//! written back to back, and resolves to all their replies.
+//!
+//! Long replies can also be taken as they arrive: an EntryStream
+//! yields the entries of an unread list one at a time, so a UI
+//! can render them during a large sync. It has the shape of the
+//! futures crate's Stream, which is not in std.
+//!
+//! Commands can be given a timeout, for the whole connection or
+//! one at a time. std has no timers, so the runtime calls
+//! `ClientDriver::expire` when the earliest deadline passes, and
+//! replies not in by then fail with a TimeoutError. The reply
+//! may still arrive later and is discarded; the connection stays
+//! usable. Connect and read timeouts are socket settings, in
+//! TcpOptions, whose errors it maps to TimeoutError too.
+//!
+//! `ClientDriver::reconnect` carries the session over to a new
+//! socket. The driver remembers what set the session up: the
+//! capabilities negotiated and the user selected, by USER, AUTH
+//! or RESUME. The new socket starts by replaying them, with
+//! RESUME in place of AUTH once the server has issued a resume
+//! token, so commands written again after the preamble find the
+//! same session they were first sent on. Credentials are kept in
+//! memory for as long as the connection is open to do so.
+//!
+//! What happens to the connection, a lost socket, the wait before
+//! the next attempt, a reconnect or the final close, is reported
+//! as ClientEvents to any task watching `ClientHandle::events`.
+//!
+//! With the `tracing` feature the driver records events as
+//! commands are queued and replies received, carrying each
+//! command's id, and when the connection reconnects, is closed
+//! or fails with a protocol violation.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::{
+    Capabilities, Command, Credentials, Cursor, Demultiplexer, DemuxError, DemuxEvent, Entry,
+    ProtocolVersion, Reply, Response, ServerError,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+#[error("connection closed before the reply arrived")]
+pub struct ConnectionClosed;
+
+/// A wait that ran out, telling a slow server apart from a
+/// protocol failure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum TimeoutError {
+    #[error("could not connect within {0:?}")]
+    Connect(Duration),
+    #[error("nothing read from the server for {0:?}")]
+    Read(Duration),
+    #[error("no reply within {0:?}")]
+    Command(Duration),
+}
+
+/// Why a reply or an EntryStream failed
+///
+/// Server errors end an EntryStream early, but are part of the
+/// Reply a PendingReply resolves to.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ClientError {
+    #[error(transparent)]
+    Closed(#[from] ConnectionClosed),
+    #[error(transparent)]
+    Timeout(#[from] TimeoutError),
+    #[error(transparent)]
+    Server(#[from] ServerError),
+}
+
+/// Something that happened to a connection, as told by
+/// `ClientHandle::events`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    /// The socket failed or was closed by the server
+    Disconnected { reason: String },
+    /// Waiting `delay` before connecting again, on attempt
+    /// `attempt` since the disconnect
+    Reconnecting { attempt: u32, delay: Duration },
+    /// A new socket took over, `resent` commands written again
+    /// after the preamble and `abandoned` ones failed
+    Reconnected { resent: usize, abandoned: usize },
+    /// The connection closed for good
+    Closed,
+}
+
+#[derive(Debug, Default)]
+struct Watcher {
+    events: VecDeque<ClientEvent>,
+    waker: Option<Waker>,
+}
+
+/// What set up the session, replayed on a new socket
+#[derive(Debug, Default)]
+struct Session {
+    /// What the client advertised, once the server answered
+    capabilities: Option<Capabilities>,
+    /// The credentials the server last accepted
+    credentials: Option<Credentials>,
+    /// The user last selected with USER
+    username: Option<String>,
+    /// The token to resume the session with
+    resume_token: Option<String>,
+}
+
+impl Session {
+    /// Note the session a reply changed
+    fn update(&mut self, command: &Command, response: Option<&Response>) {
+        match (command, response) {
+            (Command::Capabilities(capabilities), Some(Response::Capabilities(_))) => {
+                self.capabilities = Some(capabilities.clone());
+            }
+            (Command::User { username }, Some(Response::AckUser { .. })) => {
+                self.username = Some(username.clone());
+            }
+            (Command::Auth(credentials), Some(Response::AckUser { .. })) => {
+                self.credentials = Some(credentials.clone());
+            }
+            (Command::ResumeToken, Some(Response::ResumeToken { token, .. }))
+            | (Command::Resume { token }, Some(Response::AckUser { .. })) => {
+                self.resume_token = Some(token.clone());
+            }
+            (Command::Resume { .. }, Some(_)) => self.resume_token = None,
+            _ => {}
+        }
+    }
+
+    /// The commands a new socket starts with
+    fn preamble(&self) -> Vec<Command> {
+        let capabilities = self.capabilities.clone().map(Command::Capabilities);
+        let user = match (&self.resume_token, &self.credentials, &self.username) {
+            (Some(token), _, _) => Some(Command::Resume {
+                token: token.clone(),
+            }),
+            (None, Some(credentials), _) => Some(Command::Auth(credentials.clone())),
+            (None, None, Some(username)) => Some(Command::User {
+                username: username.clone(),
+            }),
+            (None, None, None) => None,
+        };
+
+        capabilities.into_iter().chain(user).collect()
+    }
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    reply: Option<Result<Reply, ClientError>>,
+    /// When the reply times out, and the timeout it was given
+    deadline: Option<(Instant, Duration)>,
+    /// Responses received so far, kept when the reply is
+    /// streamed
+    items: Option<VecDeque<Response>>,
+    /// Ids of the entries a stream has yielded, so a list
+    /// started over after reconnecting skips them
+    delivered: HashSet<i64>,
+    waker: Option<Waker>,
+}
+
+impl Slot {
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    demux: Demultiplexer,
+    lines: VecDeque<String>,
+    slots: HashMap<u64, Slot>,
+    driver: Option<Waker>,
+    handles: usize,
+    closed: bool,
+    /// The timeout of commands sent without one of their own
+    timeout: Option<Duration>,
+    session: Session,
+    /// The version responses are sent in
+    version: ProtocolVersion,
+    watchers: HashMap<u64, Watcher>,
+    next_watcher: u64,
+}
+
+impl State {
+    fn queue(&mut self, command: Command, streamed: bool, timeout: Option<Duration>) -> u64 {
+        let line = command.to_string();
+        #[cfg(feature = "tracing")]
+        let verb = command.verb();
+        let id = self.demux.send(command);
+
+        #[cfg(feature = "tracing")]
+        tracing::debug!(id, verb, "command queued");
+
+        self.lines.push_back(line);
+        self.slots.insert(
+            id,
+            Slot {
+                items: if streamed {
+                    Some(VecDeque::new())
+                } else {
+                    None
+                },
+                deadline: timeout
+                    .and_then(|timeout| Some((Instant::now().checked_add(timeout)?, timeout))),
+                ..Slot::default()
+            },
+        );
+
+        id
+    }
+
+    fn wake_driver(&mut self) {
+        if let Some(waker) = self.driver.take() {
+            waker.wake();
+        }
+    }
+
+    /// Whether the driver has nothing left to do: the connection
+    /// is closed, or no handle can send another command and no
+    /// reply is still awaited
+    fn finished(&self) -> bool {
+        self.closed || (self.handles == 0 && self.slots.values().all(|slot| slot.reply.is_some()))
+    }
+
+    /// Let the driver see it may finish, after a reply stopped
+    /// being awaited
+    fn settle(&mut self) {
+        if self.finished() {
+            self.wake_driver();
+        }
+    }
+
+    fn report(&mut self, event: ClientEvent) {
+        for watcher in self.watchers.values_mut() {
+            watcher.events.push_back(event.clone());
+
+            if let Some(waker) = watcher.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    fn close(&mut self) {
+        if !self.closed {
+            #[cfg(feature = "tracing")]
+            tracing::info!(pending = self.demux.outstanding(), "connection closed");
+
+            self.report(ClientEvent::Closed);
+        }
+
+        self.closed = true;
+        self.lines.clear();
+
+        for slot in self.slots.values_mut() {
+            slot.reply.get_or_insert(Err(ConnectionClosed.into()));
+            slot.wake();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+}
+
+impl Shared {
+    /// Lock the state, which a panicking task leaves consistent,
+    /// since every change to it is made in one step
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Sends commands over a shared connection
+///
+/// Clones share the connection. Once every handle is dropped
+/// the driver finishes, as soon as the replies still awaited
+/// have arrived.
+#[derive(Debug)]
+pub struct ClientHandle {
+    shared: Arc<Shared>,
+}
+
+impl ClientHandle {
+    /// A handle and the driver for its connection
+    pub fn new() -> (ClientHandle, ClientDriver) {
+        let shared = Arc::new(Shared::default());
+        shared.lock().handles = 1;
+
+        (
+            ClientHandle {
+                shared: Arc::clone(&shared),
+            },
+            ClientDriver { shared },
+        )
+    }
+
+    /// A handle and driver whose commands time out after
+    /// `timeout` unless given their own
+    pub fn with_timeout(timeout: Option<Duration>) -> (ClientHandle, ClientDriver) {
+        let (handle, driver) = ClientHandle::new();
+        handle.shared.lock().timeout = timeout;
+
+        (handle, driver)
+    }
+
+    /// Queue a command to be written, returning its reply to
+    /// await
+    pub fn send(&self, command: Command) -> Result<PendingReply, ConnectionClosed> {
+        let timeout = self.shared.lock().timeout;
+
+        self.send_within(command, timeout)
+    }
+
+    /// Queue a command whose reply fails with a TimeoutError
+    /// unless it arrives within `timeout`
+    pub fn send_within(
+        &self,
+        command: Command,
+        timeout: Option<Duration>,
+    ) -> Result<PendingReply, ConnectionClosed> {
+        Ok(PendingReply {
+            id: self.queue(command, false, timeout)?,
+            shared: Arc::clone(&self.shared),
+        })
+    }
+
+    /// List unread entries, yielding each as it arrives instead
+    /// of waiting for the end of the list
+    pub fn unread_stream(&self, after: Option<Cursor>) -> Result<EntryStream, ConnectionClosed> {
+        let command = Command::ListUnread {
+            after,
+            summary: None,
+            count: false,
+        };
+
+        let timeout = self.shared.lock().timeout;
+
+        Ok(EntryStream {
+            id: self.queue(command, true, timeout)?,
+            shared: Arc::clone(&self.shared),
+            done: false,
+        })
+    }
+
+    /// Send a command and wait for its reply
+    pub async fn request(&self, command: Command) -> Result<Reply, ClientError> {
+        self.send(command)?.await
+    }
+
+    /// Whether the connection has closed
+    pub fn is_closed(&self) -> bool {
+        self.shared.lock().closed
+    }
+
+    /// The token the session resumes with, once the server has
+    /// issued one
+    pub fn resume_token(&self) -> Option<String> {
+        self.shared.lock().session.resume_token.clone()
+    }
+
+    /// Watch what happens to the connection from now on
+    pub fn events(&self) -> ClientEvents {
+        let mut state = self.shared.lock();
+        let id = state.next_watcher;
+
+        state.next_watcher = id.wrapping_add(1);
+        state.watchers.insert(id, Watcher::default());
+
+        ClientEvents {
+            id,
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// Start a batch of commands to write back to back
+    pub fn pipeline(&self) -> Pipeline<'_> {
+        Pipeline {
+            handle: self,
+            commands: Vec::new(),
+            timeout: self.shared.lock().timeout,
+        }
+    }
+
+    fn queue(
+        &self,
+        command: Command,
+        streamed: bool,
+        timeout: Option<Duration>,
+    ) -> Result<u64, ConnectionClosed> {
+        let mut state = self.shared.lock();
+
+        if state.closed {
+            return Err(ConnectionClosed);
+        }
+
+        let id = state.queue(command, streamed, timeout);
+        state.wake_driver();
+
+        Ok(id)
+    }
+}
+
+impl Clone for ClientHandle {
+    fn clone(&self) -> Self {
+        let mut state = self.shared.lock();
+        state.handles = state.handles.saturating_add(1);
+
+        ClientHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl Drop for ClientHandle {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.handles = state.handles.saturating_sub(1);
+        state.settle();
+    }
+}
+
+/// Commands queued together, their lines written back to back
+///
+/// Sending queues them under one lock, so no other task's
+/// commands are written between them, and resolves to their
+/// replies in the order the commands were added.
+///
+/// ```ignore
+/// let replies = client.pipeline().list_subscriptions().list_unread().send().await?;
+/// ```
+#[derive(Debug)]
+#[must_use = "a pipeline does nothing until sent"]
+pub struct Pipeline<'a> {
+    handle: &'a ClientHandle,
+    commands: Vec<Command>,
+    timeout: Option<Duration>,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn command(mut self, command: Command) -> Pipeline<'a> {
+        self.commands.push(command);
+        self
+    }
+
+    pub fn list_subscriptions(self) -> Pipeline<'a> {
+        self.command(Command::ListSubscriptions)
+    }
+
+    pub fn list_unread(self) -> Pipeline<'a> {
+        self.command(Command::ListUnread {
+            after: None,
+            summary: None,
+            count: false,
+        })
+    }
+
+    /// Give each reply `timeout` instead of the handle's
+    pub fn within(self, timeout: Option<Duration>) -> Pipeline<'a> {
+        Pipeline { timeout, ..self }
+    }
+
+    /// Queue the commands without waiting, returning a reply to
+    /// await for each
+    pub fn queue(self) -> Result<Vec<PendingReply>, ConnectionClosed> {
+        let Pipeline {
+            handle,
+            commands,
+            timeout,
+        } = self;
+        let shared = &handle.shared;
+        let mut state = shared.lock();
+
+        if state.closed {
+            return Err(ConnectionClosed);
+        }
+
+        let replies = commands
+            .into_iter()
+            .map(|command| PendingReply {
+                id: state.queue(command, false, timeout),
+                shared: Arc::clone(shared),
+            })
+            .collect();
+        state.wake_driver();
+
+        Ok(replies)
+    }
+
+    /// Queue the commands, returning their replies to await
+    /// together
+    pub fn send(self) -> PipelineReplies {
+        match self.queue() {
+            Ok(pending) => PipelineReplies {
+                replies: pending.into_iter().map(Err).collect(),
+                closed: false,
+            },
+            Err(ConnectionClosed) => PipelineReplies {
+                replies: Vec::new(),
+                closed: true,
+            },
+        }
+    }
+}
+
+/// The replies to a Pipeline, in the order of its commands
+///
+/// Fails with the first reply that does. Server errors are not
+/// failures here, but part of their command's Reply.
+#[derive(Debug)]
+pub struct PipelineReplies {
+    /// Each reply, once it has arrived, or the PendingReply
+    /// still to poll
+    replies: Vec<Result<Reply, PendingReply>>,
+    closed: bool,
+}
+
+impl Future for PipelineReplies {
+    type Output = Result<Vec<Reply>, ClientError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.closed {
+            return Poll::Ready(Err(ConnectionClosed.into()));
+        }
+
+        for slot in self.replies.iter_mut() {
+            if let Err(pending) = slot {
+                match Pin::new(pending).poll(cx) {
+                    Poll::Ready(Ok(reply)) => *slot = Ok(reply),
+                    Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+
+        let replies = std::mem::take(&mut self.replies);
+
+        Poll::Ready(Ok(replies.into_iter().filter_map(Result::ok).collect()))
+    }
+}
+
+/// The reply to a command sent through a ClientHandle
+///
+/// Dropping it discards the reply when it arrives.
+#[derive(Debug)]
+pub struct PendingReply {
+    id: u64,
+    shared: Arc<Shared>,
+}
+
+impl PendingReply {
+    /// The id the reply will carry
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+}
+
+impl Future for PendingReply {
+    type Output = Result<Reply, ClientError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.shared.lock();
+
+        let slot = match state.slots.get_mut(&self.id) {
+            Some(slot) => slot,
+            None => return Poll::Ready(Err(ConnectionClosed.into())),
+        };
+
+        match slot.reply.take() {
+            Some(reply) => {
+                state.slots.remove(&self.id);
+                Poll::Ready(reply)
+            }
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for PendingReply {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.slots.remove(&self.id);
+        state.settle();
+    }
+}
+
+/// Owns the socket of a shared connection
+///
+/// Dropping the driver closes the connection, failing every
+/// reply still pending.
+#[derive(Debug)]
+pub struct ClientDriver {
+    shared: Arc<Shared>,
+}
+
+impl ClientDriver {
+    /// The next line to write, without its line ending
+    ///
+    /// Ready with None once the connection is closed, or once
+    /// every handle has been dropped and no PendingReply or
+    /// EntryStream still awaits its reply. Until then the driver
+    /// should keep reading responses.
+    pub fn poll_line(&mut self, cx: &mut Context<'_>) -> Poll<Option<String>> {
+        let mut state = self.shared.lock();
+
+        if let Some(line) = state.lines.pop_front() {
+            return Poll::Ready(Some(line));
+        }
+
+        if state.finished() {
+            return Poll::Ready(None);
+        }
+
+        state.driver = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    /// Wait for the next line to write, as with `poll_line`
+    pub fn next_line(&mut self) -> NextLine<'_> {
+        NextLine { driver: self }
+    }
+
+    /// Pass on a response read from the socket
+    ///
+    /// Returns announcements, which answer no command. After an
+    /// error the replies can no longer be told apart, so the
+    /// connection is closed.
+    pub fn receive(&mut self, response: Response) -> Result<Option<Response>, DemuxError> {
+        let mut state = self.shared.lock();
+
+        let streamed = state
+            .demux
+            .current()
+            .filter(|id| state.slots.get(id).is_some_and(|slot| slot.items.is_some()));
+        let item = streamed.map(|_| response.clone());
+
+        match state.demux.accept(response) {
+            Ok(Some(DemuxEvent::Reply(reply))) => {
+                let first = reply.responses.first();
+
+                if let (Command::Capabilities(client), Some(Response::Capabilities(server))) =
+                    (&reply.command, first)
+                {
+                    state.version = client.negotiate(server);
+                }
+
+                state.session.update(&reply.command, first);
+
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    id = reply.id,
+                    verb = reply.command.verb(),
+                    code = ?first.map(Response::code),
+                    latency = ?reply.latency.complete,
+                    "reply received"
+                );
+
+                // A reply that timed out keeps its TimeoutError
+                if let Some(slot) = state.slots.get_mut(&reply.id) {
+                    slot.reply.get_or_insert(Ok(reply));
+                    slot.wake();
+                }
+
+                state.settle();
+
+                Ok(None)
+            }
+            Ok(Some(DemuxEvent::Announcement(response))) => {
+                #[cfg(feature = "tracing")]
+                tracing::info!(code = response.code(), "announcement received");
+
+                Ok(Some(response))
+            }
+            Ok(None) => {
+                let slot = streamed.and_then(|id| state.slots.get_mut(&id));
+
+                if let (Some(slot), Some(item)) = (slot, item) {
+                    let repeated = match &item {
+                        Response::Entry(entry) => slot.delivered.contains(&entry.id),
+                        _ => false,
+                    };
+
+                    if !repeated {
+                        slot.items.get_or_insert_with(VecDeque::new).push_back(item);
+                        slot.wake();
+                    }
+                }
+
+                Ok(None)
+            }
+            Err(error) => {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(%error, "protocol violation");
+
+                state.close();
+                Err(error)
+            }
+        }
+    }
+
+    /// The protocol version responses are read in
+    ///
+    /// Version 1 until CAPABILITIES is answered, and again on a
+    /// new socket until the preamble's is.
+    pub fn version(&self) -> ProtocolVersion {
+        self.shared.lock().version
+    }
+
+    /// Tell the tasks watching the connection's events about
+    /// something only the driver's owner sees, such as a lost
+    /// socket
+    pub fn report(&mut self, event: ClientEvent) {
+        self.shared.lock().report(event);
+    }
+
+    /// Carry the connection over to a new socket
+    ///
+    /// Lines not yet written are dropped. The session's preamble
+    /// is written first: CAPABILITIES as last negotiated, then
+    /// RESUME with the resume token if there is one, or else AUTH
+    /// with the credentials last accepted, or else USER with the
+    /// user last selected. Idempotent commands still awaiting
+    /// replies are written again after it, their replies starting
+    /// over. An EntryStream's list starts over too, but entries
+    /// it yielded before the disconnect are not yielded again.
+    /// Replies to other commands, which may or may not have been
+    /// applied, fail with ConnectionClosed.
+    pub fn reconnect(&mut self) {
+        let mut state = self.shared.lock();
+
+        if state.closed {
+            return;
+        }
+
+        let preamble = state.session.preamble();
+        let reconnect = state.demux.reconnect(preamble);
+
+        state.version = ProtocolVersion::V1;
+
+        state.lines = reconnect
+            .resend
+            .iter()
+            .map(|(_, command)| command.to_string())
+            .collect();
+
+        for (id, _) in &reconnect.resend {
+            if let Some(items) = state.slots.get_mut(id).and_then(|slot| slot.items.as_mut()) {
+                items.clear();
+            }
+        }
+
+        for (id, _) in &reconnect.abandoned {
+            if let Some(slot) = state.slots.get_mut(id) {
+                slot.reply = Some(Err(ConnectionClosed.into()));
+                slot.wake();
+            }
+        }
+
+        #[cfg(feature = "tracing")]
+        tracing::info!(
+            resent = reconnect.resend.len(),
+            abandoned = reconnect.abandoned.len(),
+            "reconnected"
+        );
+
+        state.report(ClientEvent::Reconnected {
+            resent: reconnect.resend.len(),
+            abandoned: reconnect.abandoned.len(),
+        });
+        state.wake_driver();
+    }
+
+    /// Fail the replies whose timeout has passed by `now`,
+    /// returning the next deadline to call this at
+    pub fn expire(&mut self, now: Instant) -> Option<Instant> {
+        let mut state = self.shared.lock();
+        let mut next = None;
+
+        for slot in state.slots.values_mut() {
+            match slot.deadline {
+                Some((deadline, timeout)) if slot.reply.is_none() && deadline <= now => {
+                    slot.reply = Some(Err(TimeoutError::Command(timeout).into()));
+                    slot.deadline = None;
+                    slot.wake();
+                }
+                Some((deadline, _)) if slot.reply.is_none() => {
+                    next = Some(next.map_or(deadline, |next: Instant| next.min(deadline)));
+                }
+                _ => {}
+            }
+        }
+
+        state.settle();
+
+        next
+    }
+
+    /// Close the connection, failing every reply still pending
+    pub fn close(&mut self) {
+        self.shared.lock().close();
+    }
+}
+
+impl Drop for ClientDriver {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// The future returned by `ClientDriver::next_line`
+#[derive(Debug)]
+pub struct NextLine<'a> {
+    driver: &'a mut ClientDriver,
+}
+
+impl<'a> Future for NextLine<'a> {
+    type Output = Option<String>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.driver.poll_line(cx)
+    }
+}
+
+/// What happens to a connection, as reported since the watch
+/// began
+///
+/// Ends once the connection closes and every event has been
+/// taken. It has the shape of the futures crate's Stream.
+#[derive(Debug)]
+pub struct ClientEvents {
+    id: u64,
+    shared: Arc<Shared>,
+}
+
+impl ClientEvents {
+    /// The next event, as with the futures crate's
+    /// `Stream::poll_next`
+    pub fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ClientEvent>> {
+        let mut state = self.shared.lock();
+        let closed = state.closed;
+
+        let watcher = match state.watchers.get_mut(&self.id) {
+            Some(watcher) => watcher,
+            None => return Poll::Ready(None),
+        };
+
+        match watcher.events.pop_front() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if closed => Poll::Ready(None),
+            None => {
+                watcher.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Wait for the next event, as with `poll_next`
+    pub fn next_event(&mut self) -> NextEvent<'_> {
+        NextEvent { events: self }
+    }
+}
+
+impl Drop for ClientEvents {
+    fn drop(&mut self) {
+        self.shared.lock().watchers.remove(&self.id);
+    }
+}
+
+/// The future returned by `ClientEvents::next_event`
+#[derive(Debug)]
+pub struct NextEvent<'a> {
+    events: &'a mut ClientEvents,
+}
+
+impl<'a> Future for NextEvent<'a> {
+    type Output = Option<ClientEvent>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.events).poll_next(cx)
+    }
+}
+
+/// Unread entries, taken as they arrive
+///
+/// Ends once the list does. A server error, a timeout or a
+/// closed connection is yielded as the last item. Dropping the stream
+/// discards the rest of the list. Across a reconnect each entry
+/// is yielded once, even though the list is sent again.
+#[derive(Debug)]
+pub struct EntryStream {
+    id: u64,
+    shared: Arc<Shared>,
+    done: bool,
+}
+
+impl EntryStream {
+    /// The next entry, as with the futures crate's
+    /// `Stream::poll_next`
+    pub fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Entry, ClientError>>> {
+        let this = self.get_mut();
+
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        let mut state = this.shared.lock();
+
+        let slot = match state.slots.get_mut(&this.id) {
+            Some(slot) => slot,
+            None => {
+                this.done = true;
+                return Poll::Ready(Some(Err(ConnectionClosed.into())));
+            }
+        };
+
+        while let Some(response) = slot.items.as_mut().and_then(VecDeque::pop_front) {
+            if let Response::Entry(entry) = response {
+                slot.delivered.insert(entry.id);
+                return Poll::Ready(Some(Ok(entry)));
+            }
+        }
+
+        let reply = match slot.reply.take() {
+            Some(reply) => reply,
+            None => {
+                slot.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+        };
+
+        state.slots.remove(&this.id);
+        this.done = true;
+
+        match reply {
+            Ok(mut reply) => Poll::Ready(
+                reply
+                    .responses
+                    .pop()
+                    .and_then(|response| ServerError::try_from(response).ok())
+                    .map(|error| Err(error.into())),
+            ),
+            Err(error) => Poll::Ready(Some(Err(error))),
+        }
+    }
+
+    /// Wait for the next entry, as with `poll_next`
+    pub fn next_entry(&mut self) -> NextEntry<'_> {
+        NextEntry { stream: self }
+    }
+}
+
+impl Drop for EntryStream {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.slots.remove(&self.id);
+        state.settle();
+    }
+}
+
+/// The future returned by `EntryStream::next_entry`
+#[derive(Debug)]
+pub struct NextEntry<'a> {
+    stream: &'a mut EntryStream,
+}
+
+impl<'a> Future for NextEntry<'a> {
+    type Output = Option<Result<Entry, ClientError>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut *self.stream).poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poll<F: Future + Unpin>(future: &mut F) -> Poll<F::Output> {
+        Pin::new(future).poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    fn poll_line(driver: &mut ClientDriver) -> Poll<Option<String>> {
+        driver.poll_line(&mut Context::from_waker(Waker::noop()))
+    }
+
+    #[test]
+    fn driver_waits_for_awaited_replies() {
+        let (handle, mut driver) = ClientHandle::new();
+        let mut reply = handle.send(Command::Ping).unwrap();
+        drop(handle);
+
+        assert_eq!(
+            poll_line(&mut driver),
+            Poll::Ready(Some("PING".to_string()))
+        );
+        assert_eq!(poll_line(&mut driver), Poll::Pending);
+
+        driver.receive(Response::Pong).unwrap();
+
+        assert_eq!(poll_line(&mut driver), Poll::Ready(None));
+        assert!(matches!(
+            poll(&mut reply),
+            Poll::Ready(Ok(Reply { responses, .. })) if responses == vec![Response::Pong]
+        ));
+    }
+
+    #[test]
+    fn driver_finishes_once_replies_are_dropped() {
+        let (handle, mut driver) = ClientHandle::new();
+        let reply = handle.send(Command::Ping).unwrap();
+        drop(handle);
+
+        assert_eq!(
+            poll_line(&mut driver),
+            Poll::Ready(Some("PING".to_string()))
+        );
+        assert_eq!(poll_line(&mut driver), Poll::Pending);
+
+        drop(reply);
+
+        assert_eq!(poll_line(&mut driver), Poll::Ready(None));
+    }
+
+    #[test]
+    fn replies_time_out() {
+        let timeout = Duration::from_secs(5);
+        let (handle, mut driver) = ClientHandle::with_timeout(Some(timeout));
+        let mut timed = handle.send(Command::Ping).unwrap();
+        let mut untimed = handle.send_within(Command::Ping, None).unwrap();
+        let start = Instant::now();
+
+        assert!(driver.expire(start).is_some_and(|next| next > start));
+        assert!(poll(&mut timed).is_pending());
+
+        assert_eq!(driver.expire(start + timeout * 2), None);
+        assert_eq!(
+            poll(&mut timed),
+            Poll::Ready(Err(ClientError::Timeout(TimeoutError::Command(timeout))))
+        );
+
+        // The late reply is discarded and the next one still
+        // matches its command
+        while let Poll::Ready(Some(_)) = poll_line(&mut driver) {}
+        driver.receive(Response::Pong).unwrap();
+        driver.receive(Response::Pong).unwrap();
+
+        assert!(matches!(
+            poll(&mut untimed),
+            Poll::Ready(Ok(Reply { responses, .. })) if responses == vec![Response::Pong]
+        ));
+    }
+
+    #[test]
+    fn pipelined_replies() {
+        let (handle, mut driver) = ClientHandle::new();
+        let mut other = handle.send(Command::Ping).unwrap();
+        let mut replies = handle.pipeline().list_subscriptions().list_unread().send();
+        let mut later = handle.send(Command::Ping).unwrap();
+
+        assert!(poll(&mut replies).is_pending());
+
+        let mut lines = Vec::new();
+
+        while let Poll::Ready(Some(line)) = poll_line(&mut driver) {
+            lines.push(line);
+        }
+
+        assert_eq!(lines, ["PING", "LISTSUBSCRIPTIONS", "LISTUNREAD", "PING"]);
+
+        let subscriptions = vec![Response::StartSubscriptionList, Response::EndList];
+        let unread = vec![Response::StartEntryList, Response::EndList];
+
+        for response in std::iter::once(Response::Pong)
+            .chain(subscriptions.clone())
+            .chain(unread.clone())
+            .chain(std::iter::once(Response::Pong))
+        {
+            assert!(matches!(driver.receive(response), Ok(None)));
+        }
+
+        assert!(poll(&mut other).is_ready());
+        assert!(poll(&mut later).is_ready());
+
+        match poll(&mut replies) {
+            Poll::Ready(Ok(replies)) => {
+                let responses: Vec<_> = replies.into_iter().map(|reply| reply.responses).collect();
+
+                assert_eq!(responses, [subscriptions, unread]);
+            }
+            other => panic!("expected the pipeline's replies, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reconnect_replays_the_preamble() {
+        let (handle, mut driver) = ClientHandle::new();
+        let mut events = handle.events();
+        let capabilities = Capabilities::new();
+        let user = Command::User {
+            username: "bob".to_string(),
+        };
+
+        let _negotiated = handle
+            .send(Command::Capabilities(capabilities.clone()))
+            .unwrap();
+        let _selected = handle.send(user.clone()).unwrap();
+        let mut listed = handle.send(Command::ListSubscriptions).unwrap();
+        let mut marked = handle.send(Command::MarkAllRead).unwrap();
+
+        while let Poll::Ready(Some(_)) = poll_line(&mut driver) {}
+        driver
+            .receive(Response::Capabilities(capabilities.clone()))
+            .unwrap();
+        driver.receive(Response::AckUser { id: 1 }).unwrap();
+
+        assert_eq!(driver.version(), ProtocolVersion::LATEST);
+
+        driver.reconnect();
+
+        let mut lines = Vec::new();
+
+        while let Poll::Ready(Some(line)) = poll_line(&mut driver) {
+            lines.push(line);
+        }
+
+        assert_eq!(
+            lines,
+            [
+                Command::Capabilities(capabilities).to_string(),
+                user.to_string(),
+                Command::ListSubscriptions.to_string(),
+            ]
+        );
+        assert_eq!(driver.version(), ProtocolVersion::V1);
+        assert_eq!(
+            poll(&mut marked),
+            Poll::Ready(Err(ClientError::Closed(ConnectionClosed)))
+        );
+        assert!(poll(&mut listed).is_pending());
+        assert_eq!(
+            poll(&mut events.next_event()),
+            Poll::Ready(Some(ClientEvent::Reconnected {
+                resent: 3,
+                abandoned: 1,
+            }))
+        );
+
+        driver.close();
+
+        assert_eq!(
+            poll(&mut events.next_event()),
+            Poll::Ready(Some(ClientEvent::Closed))
+        );
+        assert_eq!(poll(&mut events.next_event()), Poll::Ready(None));
+    }
+
+    #[test]
+    fn streams_skip_entries_yielded_before_reconnecting() {
+        let (handle, mut driver) = ClientHandle::new();
+        let mut stream = handle.unread_stream(None).unwrap();
+        let entry =
+            |id| Response::entry(id, 1, "https://example.com", "Title", "https://example.com");
+        let next = |stream: &mut EntryStream| match poll(&mut stream.next_entry()) {
+            Poll::Ready(Some(Ok(entry))) => Some(entry.id),
+            _ => None,
+        };
+
+        while let Poll::Ready(Some(_)) = poll_line(&mut driver) {}
+        for response in [Response::StartEntryList, entry(1), entry(2)] {
+            driver.receive(response).unwrap();
+        }
+
+        assert_eq!(next(&mut stream), Some(1));
+
+        driver.reconnect();
+
+        while let Poll::Ready(Some(_)) = poll_line(&mut driver) {}
+        for response in [
+            Response::StartEntryList,
+            entry(1),
+            entry(2),
+            entry(3),
+            Response::EndList,
+        ] {
+            driver.receive(response).unwrap();
+        }
+
+        assert_eq!(next(&mut stream), Some(2));
+        assert_eq!(next(&mut stream), Some(3));
+        assert!(matches!(poll(&mut stream.next_entry()), Poll::Ready(None)));
+    }
+}
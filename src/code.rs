@@ -0,0 +1,65 @@
+//! Numeric response codes
+//!
+//! The single table both encoding and parsing use, so the two
+//! directions cannot drift apart. Codes 40 to 59 are errors.
+
+pub const ACK_USER: u8 = 20;
+pub const START_SUBSCRIPTION_LIST: u8 = 21;
+pub const SUBSCRIPTION: u8 = 22;
+pub const START_ENTRY_LIST: u8 = 23;
+pub const ENTRY: u8 = 24;
+pub const END_LIST: u8 = 25;
+pub const ACK_SUBSCRIBE: u8 = 26;
+pub const ACK_UNSUBSCRIBE: u8 = 27;
+pub const ACK_MARK_READ: u8 = 28;
+pub const PONG: u8 = 29;
+pub const CAPABILITIES: u8 = 30;
+pub const QUOTA: u8 = 31;
+pub const ACK_SHARE: u8 = 32;
+pub const ACK_UNSHARE: u8 = 33;
+pub const START_SHARE_LIST: u8 = 34;
+pub const SHARE: u8 = 35;
+pub const ACK_SET_ORDER: u8 = 36;
+pub const FEED_STATUS: u8 = 37;
+pub const ACK_SET_INTERVAL: u8 = 38;
+pub const ACK_ADD_WEBHOOK: u8 = 39;
+pub const RESOURCE_NOT_FOUND: u8 = 40;
+pub const BAD_COMMAND: u8 = 41;
+pub const NEED_USER: u8 = 42;
+pub const RATE_LIMITED: u8 = 43;
+pub const FORBIDDEN: u8 = 44;
+pub const CONFLICT: u8 = 45;
+pub const INTERNAL_ERROR: u8 = 51;
+pub const SHUTTING_DOWN: u8 = 52;
+pub const OVERLOADED: u8 = 53;
+pub const UPSTREAM_TIMEOUT: u8 = 54;
+pub const MAINTENANCE: u8 = 55;
+pub const START_WEBHOOK_LIST: u8 = 60;
+pub const WEBHOOK: u8 = 61;
+pub const ACK_DELETE_WEBHOOK: u8 = 62;
+pub const END_PAGE: u8 = 63;
+pub const ACK_MARK_ALL_READ: u8 = 64;
+pub const ACK_UNDO: u8 = 65;
+pub const START_CONTENT: u8 = 66;
+pub const PAYLOAD_LINE: u8 = 67;
+pub const START_SYNC: u8 = 68;
+pub const READ_STATE_CHANGE: u8 = 69;
+pub const UNSUBSCRIBED: u8 = 70;
+pub const END_SYNC: u8 = 71;
+pub const ACK_MARK_UNREAD: u8 = 72;
+pub const START_IMPORT: u8 = 73;
+pub const IMPORT_RESULT: u8 = 74;
+pub const START_EXPORT: u8 = 75;
+pub const START_RAW_FEED: u8 = 76;
+pub const COUNT: u8 = 77;
+pub const START_CATEGORY_LIST: u8 = 78;
+pub const CATEGORY: u8 = 79;
+pub const ACK_SAVE_SEARCH: u8 = 80;
+pub const START_SAVED_SEARCH_LIST: u8 = 81;
+pub const SAVED_SEARCH: u8 = 82;
+pub const ACK_SESSION: u8 = 83;
+pub const RESUME_TOKEN: u8 = 84;
+
+/// The code InternalError was sent with before it moved to 51,
+/// still accepted when parsing
+pub const INTERNAL_ERROR_LEGACY: u8 = 50;
@@ -0,0 +1,244 @@
+//! Scripted conformance checks for seymour server implementations
+//!
+//! `run` drives a server over any line-based reader/writer pair
+//! (typically both halves of a `TcpStream`) through a fixed
+//! battery of commands and reports a result per case.
+//!
+//! The battery only issues commands that do not change server
+//! state, apart from selecting the given user.
+
+use std::io::{self, BufRead, Write};
+
+use thiserror::Error;
+
+use crate::{Command, ParseMessageError, ProtocolViolation, ReplyValidator, Response};
+
+/// Reasons a conformance case can fail
+#[derive(Debug, Error)]
+pub enum Failure {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("connection closed by server")]
+    ConnectionClosed,
+    #[error("unparseable response \"{line}\": {error}")]
+    Parse {
+        line: String,
+        error: ParseMessageError,
+    },
+    #[error("protocol violation: {0}")]
+    Violation(#[from] ProtocolViolation),
+    #[error("server answered with error \"{0}\"")]
+    ErrorResponse(String),
+    #[error("expected response code {expected}, got \"{actual}\"")]
+    UnexpectedResponse { expected: u8, actual: String },
+}
+
+/// The outcome of a single conformance case
+#[derive(Debug)]
+pub struct CaseResult {
+    pub name: &'static str,
+    pub result: Result<(), Failure>,
+}
+
+impl CaseResult {
+    pub fn passed(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// The outcome of a full conformance run
+#[derive(Debug)]
+pub struct Report {
+    pub cases: Vec<CaseResult>,
+}
+
+impl Report {
+    /// Whether every case passed
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(CaseResult::passed)
+    }
+
+    /// The cases that failed
+    pub fn failures(&self) -> impl Iterator<Item = &CaseResult> {
+        self.cases.iter().filter(|case| !case.passed())
+    }
+}
+
+enum Expect {
+    /// A well-formed reply to the command
+    Reply(Command),
+
+    /// A single response with the given code
+    Code(u8),
+}
+
+struct Case {
+    name: &'static str,
+    line: String,
+    expect: Expect,
+}
+
+impl Case {
+    fn reply(name: &'static str, command: Command) -> Self {
+        Case {
+            name,
+            line: command.to_string(),
+            expect: Expect::Reply(command),
+        }
+    }
+
+    fn code(name: &'static str, command: Command, code: u8) -> Self {
+        Case {
+            name,
+            line: command.to_string(),
+            expect: Expect::Code(code),
+        }
+    }
+
+    fn malformed(name: &'static str, line: &str) -> Self {
+        Case {
+            name,
+            line: line.to_string(),
+            expect: Expect::Code(41),
+        }
+    }
+}
+
+fn battery(username: &str) -> Vec<Case> {
+    vec![
+        Case::reply("ping before user", Command::Ping),
+        Case::code(
+            "list subscriptions without user",
+            Command::ListSubscriptions,
+            42,
+        ),
+        Case::code(
+            "list unread without user",
+            Command::ListUnread {
+                after: None,
+                summary: None,
+                count: false,
+            },
+            42,
+        ),
+        Case::malformed("unknown command", "NOTACOMMAND"),
+        Case::malformed("missing argument", "USER"),
+        Case::malformed("too many arguments", "PING extra"),
+        Case::malformed("invalid integer argument", "MARKREAD notanumber"),
+        Case::reply(
+            "select user",
+            Command::User {
+                username: username.to_string(),
+            },
+        ),
+        Case::reply("list subscriptions", Command::ListSubscriptions),
+        Case::reply(
+            "list unread",
+            Command::ListUnread {
+                after: None,
+                summary: None,
+                count: false,
+            },
+        ),
+        Case::code(
+            "unsubscribe unknown feed",
+            Command::Unsubscribe {
+                id: i64::MAX,
+                expected: None,
+                key: None,
+            },
+            40,
+        ),
+        Case::code(
+            "mark unknown entry read",
+            Command::MarkRead {
+                id: i64::MAX,
+                expected: None,
+                key: None,
+            },
+            40,
+        ),
+        Case::reply("ping after user", Command::Ping),
+    ]
+}
+
+fn receive<R: BufRead>(reader: &mut R) -> Result<Response, Failure> {
+    let mut line = String::new();
+
+    if reader.read_line(&mut line)? == 0 {
+        return Err(Failure::ConnectionClosed);
+    }
+
+    let line = line.trim_end_matches(&['\r', '\n'][..]);
+
+    line.parse().map_err(|error| Failure::Parse {
+        line: line.to_string(),
+        error,
+    })
+}
+
+fn run_case<R: BufRead, W: Write>(
+    case: &Case,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<(), Failure> {
+    writeln!(writer, "{}", case.line)?;
+    writer.flush()?;
+
+    match &case.expect {
+        Expect::Reply(command) => {
+            let mut validator = ReplyValidator::new(command);
+
+            let response = receive(reader)?;
+            validator.accept(&response)?;
+
+            if response.is_error() {
+                return Err(Failure::ErrorResponse(response.to_string()));
+            }
+
+            while !validator.is_complete() {
+                validator.accept(&receive(reader)?)?;
+            }
+
+            Ok(())
+        }
+        Expect::Code(expected) => {
+            let expected = *expected;
+            let response = receive(reader)?;
+
+            if response.code() != expected {
+                return Err(Failure::UnexpectedResponse {
+                    expected,
+                    actual: response.to_string(),
+                });
+            }
+
+            Ok(())
+        }
+    }
+}
+
+/// Run the conformance battery against a server
+///
+/// `username` is selected partway through the run and should
+/// name an existing account on the server under test.
+pub fn run<R: BufRead, W: Write>(reader: &mut R, writer: &mut W, username: &str) -> Report {
+    let mut cases = Vec::new();
+
+    for case in battery(username) {
+        let result = run_case(&case, reader, writer);
+
+        let closed = matches!(result, Err(Failure::ConnectionClosed));
+
+        cases.push(CaseResult {
+            name: case.name,
+            result,
+        });
+
+        if closed {
+            break;
+        }
+    }
+
+    Report { cases }
+}
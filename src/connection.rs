@@ -0,0 +1,380 @@
+//! Serving one connection
+//!
+//! `serve` runs a client's session over a blocking stream, such
+//! as an accepted TcpStream and a clone of it for writing. When
+//! the listener sits behind a load balancer speaking the PROXY
+//! protocol, the header is read first and the client's address
+//! it names replaces the socket's, so the Service, with its login
+//! throttle, rate limits and logs, sees the client rather than
+//! the proxy:
+//!
+//! ```text
+//! let (socket, address) = listener.accept()?;
+//! serve(socket.try_clone()?, socket, address, &options, |peer| {
+//!     Handler::new(Arc::clone(&storage))
+//!         .with_peer(peer.address.ip())
+//!         .checked()
+//! })?;
+//! ```
+//!
+//! Each line is then answered in turn. CAPABILITIES is answered
+//! with the server's own, and the connection switches to the
+//! highest version both sides speak. Every other command goes to
+//! the Service, its reply encoded for the negotiated version. A
+//! line that fails to decode is answered with the error response
+//! it converts to. The session ends when the client closes the
+//! connection.
+//!
+//! With the `tracing` feature, each connection runs in a
+//! `connection` span carrying its id and peer, with events as it
+//! opens and closes, when a line fails to decode and when a
+//! version is negotiated. The Handler adds an event for each
+//! command inside it.
+//!
+//! With the `metrics` feature, lines that fail to decode are
+//! counted, and so are connections as they open, with their
+//! durations recorded as they close.
+
+use std::io::{self, BufWriter, Read, Write};
+use std::net::SocketAddr;
+#[cfg(feature = "tracing")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use thiserror::Error;
+
+use crate::proxy::{self, ProxyError};
+use crate::{
+    Capabilities, Command, Decoder, ProtocolVersion, Response, Service, DEFAULT_MAX_LINE_LENGTH,
+};
+
+/// Bytes read from the stream at a time
+const READ_SIZE: usize = 4096;
+
+/// The id of the next connection served, for its span
+#[cfg(feature = "tracing")]
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+
+/// How a server serves its connections
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServeOptions {
+    /// Read a PROXY header before the first line, refusing
+    /// connections without one
+    pub proxy_protocol: bool,
+    /// What the server answers CAPABILITIES with
+    pub capabilities: Capabilities,
+    /// Longest line accepted, in bytes
+    pub max_line_length: usize,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        ServeOptions {
+            proxy_protocol: false,
+            capabilities: Capabilities::new(),
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+        }
+    }
+}
+
+/// Who a connection is from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Peer {
+    /// The client's address, as named by the PROXY header if
+    /// there is one
+    pub address: SocketAddr,
+    /// The address the connection was accepted from, the
+    /// proxy's when there is one
+    pub socket: SocketAddr,
+}
+
+impl Peer {
+    /// A connection accepted straight from the client
+    pub fn direct(socket: SocketAddr) -> Peer {
+        Peer {
+            address: socket,
+            socket,
+        }
+    }
+
+    /// Whether the connection came through a proxy naming
+    /// another address
+    pub fn is_proxied(&self) -> bool {
+        self.address != self.socket
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ServeError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    /// The connection did not start with a valid PROXY header
+    #[error(transparent)]
+    Proxy(#[from] ProxyError),
+}
+
+/// Read the PROXY header a connection accepted from `socket`
+/// starts with
+///
+/// Returns the peer along with any bytes read past the header,
+/// which begin the client's first line. Connections the proxy
+/// opened itself, as LOCAL headers say, are from the socket's
+/// address.
+pub fn read_proxy_header<R: Read>(
+    reader: &mut R,
+    socket: SocketAddr,
+) -> Result<(Peer, Vec<u8>), ServeError> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; READ_SIZE];
+
+    loop {
+        if let Some((header, length)) = proxy::parse(&buffer)? {
+            let rest = buffer.split_off(length.min(buffer.len()));
+            let peer = Peer {
+                address: header.source.unwrap_or(socket),
+                socket,
+            };
+
+            return Ok((peer, rest));
+        }
+
+        match reader.read(&mut chunk) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            Ok(read) => buffer.extend_from_slice(chunk.get(..read).unwrap_or_default()),
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+/// Serve a connection accepted from `socket` until the client
+/// closes it
+///
+/// `service` makes the Service answering the connection's
+/// commands once the peer is known.
+pub fn serve<R, W, S, F>(
+    mut reader: R,
+    writer: W,
+    socket: SocketAddr,
+    options: &ServeOptions,
+    service: F,
+) -> Result<(), ServeError>
+where
+    R: Read,
+    W: Write,
+    S: Service,
+    F: FnOnce(&Peer) -> S,
+{
+    #[cfg(feature = "tracing")]
+    let span = tracing::info_span!(
+        "connection",
+        id = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed),
+        peer = tracing::field::Empty,
+        socket = %socket,
+    );
+    #[cfg(feature = "tracing")]
+    let _entered = span.enter();
+
+    let (peer, rest) = if options.proxy_protocol {
+        read_proxy_header(&mut reader, socket).inspect_err(|_error| {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(error = %_error, "no valid PROXY header");
+        })?
+    } else {
+        (Peer::direct(socket), Vec::new())
+    };
+
+    #[cfg(feature = "tracing")]
+    let opened = {
+        span.record("peer", tracing::field::display(peer.address));
+        tracing::info!(proxied = peer.is_proxied(), "connection opened");
+        std::time::Instant::now()
+    };
+
+    #[cfg(feature = "metrics")]
+    let (connection, started) = (
+        crate::metrics::OpenConnection::open(),
+        std::time::Instant::now(),
+    );
+
+    let mut decoder = Decoder::new().with_max_line_length(options.max_line_length);
+    decoder.push(&rest);
+
+    let served = answer(reader, writer, decoder, options, service(&peer));
+
+    #[cfg(feature = "metrics")]
+    connection.close(started.elapsed());
+
+    #[cfg(feature = "tracing")]
+    match &served {
+        Ok(()) => tracing::info!(duration = ?opened.elapsed(), "connection closed"),
+        Err(error) => tracing::warn!(duration = ?opened.elapsed(), %error, "connection failed"),
+    }
+
+    served
+}
+
+/// Answer each line until the client closes the connection
+fn answer<R, W, S>(
+    mut reader: R,
+    writer: W,
+    mut decoder: Decoder,
+    options: &ServeOptions,
+    mut service: S,
+) -> Result<(), ServeError>
+where
+    R: Read,
+    W: Write,
+    S: Service,
+{
+    let mut writer = BufWriter::new(writer);
+    let mut version = ProtocolVersion::V1;
+    let mut chunk = [0; READ_SIZE];
+
+    loop {
+        while let Some(command) = decoder.next_command() {
+            let mut negotiated = version;
+
+            let reply = match command {
+                Ok(Command::Capabilities(capabilities)) => {
+                    negotiated = options.capabilities.negotiate(&capabilities);
+
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(version = %negotiated, "version negotiated");
+
+                    vec![Response::Capabilities(options.capabilities.clone())]
+                }
+                Ok(command) => service.call(&command),
+                Err(error) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(%error, "line not decoded");
+                    #[cfg(feature = "metrics")]
+                    crate::metrics::parse_error();
+
+                    vec![Response::from(error)]
+                }
+            };
+
+            for response in &reply {
+                writeln!(writer, "{}", response.encode(version))?;
+            }
+
+            writer.flush()?;
+            version = negotiated;
+        }
+
+        match reader.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(read) => decoder.push(chunk.get(..read).unwrap_or_default()),
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::IpAddr;
+
+    use crate::{Handler, MemoryStorage};
+
+    fn socket() -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], 40000))
+    }
+
+    /// Serve `input`, returning the lines written and the address
+    /// the Handler was given
+    fn run(input: &[u8], options: &ServeOptions) -> Result<(String, Option<IpAddr>), ServeError> {
+        let storage = MemoryStorage::new();
+        storage.add_user("bob");
+
+        let mut output = Vec::new();
+        let mut peer = None;
+
+        serve(input, &mut output, socket(), options, |connection| {
+            peer = Some(connection.address.ip());
+            Handler::new(storage).with_peer(connection.address.ip())
+        })?;
+
+        Ok((String::from_utf8(output).unwrap(), peer))
+    }
+
+    #[test]
+    fn answers_each_line() {
+        let (output, peer) = run(b"USER bob\r\nPING\nBOGUS\n", &ServeOptions::default()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "20 1");
+        assert_eq!(
+            lines[1],
+            Response::Pong.encode(ProtocolVersion::V1).to_string()
+        );
+        assert_eq!(
+            lines[2],
+            Response::from("BOGUS".parse::<Command>().unwrap_err())
+                .encode(ProtocolVersion::V1)
+                .to_string()
+        );
+        assert_eq!(peer, Some(socket().ip()));
+    }
+
+    #[test]
+    fn negotiates_the_version() {
+        let (output, _) = run(
+            b"CAPABILITIES VERSIONS=1,2\nUSER bob\n",
+            &ServeOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            output.lines().collect::<Vec<_>>(),
+            [
+                Response::Capabilities(Capabilities::new())
+                    .encode(ProtocolVersion::V1)
+                    .to_string()
+                    .as_str(),
+                "20 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_the_proxy_header() {
+        let options = ServeOptions {
+            proxy_protocol: true,
+            ..ServeOptions::default()
+        };
+
+        let (output, peer) = run(
+            b"PROXY TCP4 192.0.2.7 10.0.0.1 5000 1965\r\nUSER bob\n",
+            &options,
+        )
+        .unwrap();
+
+        assert_eq!(output, "20 1\n");
+        assert_eq!(peer, Some(IpAddr::from([192, 0, 2, 7])));
+
+        assert!(matches!(
+            run(b"USER bob\n", &options),
+            Err(ServeError::Proxy(ProxyError::Missing))
+        ));
+        assert!(matches!(
+            run(b"PROXY TCP4 192.0.2.7", &options),
+            Err(ServeError::Io(error)) if error.kind() == io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn local_connections_keep_the_socket_address() {
+        let mut input = b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00".to_vec();
+        input.extend_from_slice(b"PING\n");
+
+        let (peer, rest) = read_proxy_header(&mut input.as_slice(), socket()).unwrap();
+
+        assert_eq!(peer, Peer::direct(socket()));
+        assert!(!peer.is_proxied());
+        assert_eq!(rest, b"PING\n");
+    }
+}
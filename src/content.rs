@@ -0,0 +1,74 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+/// A format entry content can be served in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentFormat {
+    Plain,
+    Html,
+    Gemtext,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("unknown content format \"{0}\"")]
+pub struct UnknownContentFormat(pub String);
+
+impl ContentFormat {
+    /// Every format, in the order servers should prefer them
+    /// when the client does not ask for one
+    pub const ALL: [ContentFormat; 3] = [
+        ContentFormat::Html,
+        ContentFormat::Gemtext,
+        ContentFormat::Plain,
+    ];
+
+    /// The name sent on the wire
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentFormat::Plain => "plain",
+            ContentFormat::Html => "html",
+            ContentFormat::Gemtext => "gemtext",
+        }
+    }
+
+    /// The media type of content in this format
+    pub fn media_type(self) -> &'static str {
+        match self {
+            ContentFormat::Plain => "text/plain",
+            ContentFormat::Html => "text/html",
+            ContentFormat::Gemtext => "text/gemini",
+        }
+    }
+}
+
+/// The first `characters` characters of a text, for entry
+/// summaries
+pub fn summarize(text: &str, characters: u32) -> &str {
+    let characters = usize::try_from(characters).unwrap_or(usize::MAX);
+
+    match text.char_indices().nth(characters) {
+        Some((end, _)) => text.get(..end).unwrap_or(text),
+        None => text,
+    }
+}
+
+impl FromStr for ContentFormat {
+    type Err = UnknownContentFormat;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        ContentFormat::ALL
+            .iter()
+            .copied()
+            .find(|format| format.name() == value)
+            .ok_or_else(|| UnknownContentFormat(value.to_string()))
+    }
+}
+
+impl fmt::Display for ContentFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
@@ -0,0 +1,150 @@
+//! Opaque pagination cursors
+//!
+//! A server that cuts a list short ends it with EndPage and a
+//! cursor instead of EndList. The client sends the cursor back
+//! unchanged with `AFTER` to continue the list from that point,
+//! which stays correct even if entries are marked read between
+//! pages.
+//!
+//! Cursors carry whatever bytes the server chooses, encoded as
+//! unpadded URL-safe base64 so they are always a single token.
+//! Decoding must never panic, whatever the input, so the same
+//! lints as the line parser apply here.
+
+#![deny(
+    clippy::indexing_slicing,
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::arithmetic_side_effects
+)]
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+/// Longest cursor accepted, in characters
+pub const MAX_CURSOR_LENGTH: usize = 256;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// A position in a list, handed out by the server
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cursor(String);
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CursorError {
+    #[error("empty cursor")]
+    Empty,
+    #[error("cursor longer than {MAX_CURSOR_LENGTH} characters")]
+    TooLong,
+    #[error("cursor is not valid base64")]
+    InvalidEncoding,
+}
+
+/// The base64 digit for the low six bits of a value
+fn digit(value: u32) -> char {
+    ALPHABET
+        .get(usize::try_from(value & 0x3f).unwrap_or_default())
+        .map_or('A', |&digit| char::from(digit))
+}
+
+fn digit_value(digit: u8) -> Option<u32> {
+    ALPHABET
+        .iter()
+        .position(|&candidate| candidate == digit)
+        .and_then(|position| u32::try_from(position).ok())
+}
+
+/// Decode unpadded URL-safe base64, rejecting encodings whose
+/// unused trailing bits are set so every cursor has exactly
+/// one spelling
+fn decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut buffer: u32 = 0;
+    let mut bits: u32 = 0;
+
+    for digit in encoded.bytes() {
+        buffer = buffer.checked_shl(6)? | digit_value(digit)?;
+        bits = bits.checked_add(6)?;
+
+        if bits >= 8 {
+            bits = bits.checked_sub(8)?;
+            decoded.push(u8::try_from(buffer.checked_shr(bits)? & 0xff).ok()?);
+            buffer &= 1u32.checked_shl(bits)?.checked_sub(1)?;
+        }
+    }
+
+    // A single leftover digit cannot hold a whole byte
+    if bits >= 6 || buffer != 0 {
+        return None;
+    }
+
+    Some(decoded)
+}
+
+impl Cursor {
+    /// Encode server-defined position data
+    ///
+    /// Fails if the data is empty or its encoding would be
+    /// longer than `MAX_CURSOR_LENGTH`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Cursor, CursorError> {
+        let mut encoded = String::new();
+
+        for chunk in bytes.chunks(3) {
+            let mut group: u32 = 0;
+
+            for (index, &byte) in chunk.iter().enumerate() {
+                let shift = u32::try_from(index).unwrap_or_default().saturating_mul(8);
+                group |= u32::from(byte) << 16u32.saturating_sub(shift);
+            }
+
+            // 1, 2 or 3 bytes need 2, 3 or 4 digits
+            for position in 0..chunk.len().saturating_add(1) {
+                let shift = u32::try_from(position)
+                    .unwrap_or_default()
+                    .saturating_mul(6);
+                encoded.push(digit(group >> 18u32.saturating_sub(shift)));
+            }
+        }
+
+        encoded.parse()
+    }
+
+    /// Decode the position data the server encoded
+    pub fn to_bytes(&self) -> Vec<u8> {
+        decode(&self.0).unwrap_or_default()
+    }
+
+    /// The cursor as sent on the wire
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = CursorError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.is_empty() {
+            return Err(CursorError::Empty);
+        }
+
+        if value.len() > MAX_CURSOR_LENGTH {
+            return Err(CursorError::TooLong);
+        }
+
+        match decode(value) {
+            Some(_) => Ok(Cursor(value.to_string())),
+            None => Err(CursorError::InvalidEncoding),
+        }
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
@@ -0,0 +1,315 @@
+//! Incremental decoding of byte streams
+//!
+//! Reads from a socket end wherever the kernel likes, often in
+//! the middle of a line. A Decoder is fed those chunks as they
+//! arrive and hands out messages once their lines are complete,
+//! so it fits any event loop without tying the crate to one.
+//!
+//! Chunks arrive from the network, so the same lints as the
+//! line parser apply here.
+
+#![deny(
+    clippy::indexing_slicing,
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::arithmetic_side_effects
+)]
+
+use thiserror::Error;
+
+use crate::catalog::ErrorMessage;
+use crate::{Command, Identified, ParseMessageError, ParseOptions, ProtocolVersion, Response};
+
+/// Longest line accepted by default, in bytes
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 64 * 1024;
+
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error(transparent)]
+    Parse(#[from] ParseMessageError),
+    /// The line was discarded up to its end
+    #[error("line longer than {0} bytes")]
+    LineTooLong(usize),
+}
+
+/// The response to a line that could not be decoded
+impl From<DecodeError> for Response {
+    fn from(error: DecodeError) -> Response {
+        match error {
+            DecodeError::Parse(error) => Response::from(error),
+            DecodeError::LineTooLong(max) => Response::BadCommand(
+                ErrorMessage::new("line-too-long", "line too long")
+                    .param("max", max)
+                    .to_string(),
+            ),
+        }
+    }
+}
+
+/// Buffers partial lines across reads
+///
+/// Lines end with `\n`, optionally preceded by `\r`. Lines taken
+/// are only marked consumed; the buffer is compacted once per
+/// pushed chunk, so a chunk of many lines is decoded in linear
+/// time.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    /// Bytes at the start of the buffer already taken as lines
+    consumed: usize,
+    /// Bytes of the buffer already searched for a line end
+    scanned: usize,
+    max_line_length: usize,
+    /// Whether the rest of an overlong line is being dropped
+    discarding: bool,
+    options: ParseOptions,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+impl Decoder {
+    pub fn new() -> Decoder {
+        Decoder {
+            buffer: Vec::new(),
+            consumed: 0,
+            scanned: 0,
+            max_line_length: DEFAULT_MAX_LINE_LENGTH,
+            discarding: false,
+            options: ParseOptions::default(),
+        }
+    }
+
+    /// Reject lines longer than `length` bytes, line ending
+    /// excluded
+    pub fn with_max_line_length(self, length: usize) -> Decoder {
+        Decoder {
+            max_line_length: length,
+            ..self
+        }
+    }
+
+    /// Parse commands with these options
+    pub fn with_parse_options(self, options: ParseOptions) -> Decoder {
+        Decoder { options, ..self }
+    }
+
+    /// Add bytes read from the stream
+    pub fn push(&mut self, bytes: &[u8]) {
+        if self.consumed > 0 {
+            self.buffer.drain(..self.consumed);
+            self.scanned = self.scanned.saturating_sub(self.consumed);
+            self.consumed = 0;
+        }
+
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Bytes pushed but not yet taken as lines
+    pub fn buffered(&self) -> usize {
+        self.buffer.len().saturating_sub(self.consumed)
+    }
+
+    /// Drop every buffered byte
+    fn clear(&mut self) {
+        self.buffer.clear();
+        self.consumed = 0;
+        self.scanned = 0;
+    }
+
+    /// Take the next complete line, without its line ending
+    ///
+    /// Returns None until a whole line has been pushed.
+    pub fn next_line(&mut self) -> Option<Result<Vec<u8>, DecodeError>> {
+        loop {
+            let unscanned = self.buffer.get(self.scanned..).unwrap_or_default();
+
+            let end = match unscanned.iter().position(|byte| *byte == b'\n') {
+                Some(offset) => self.scanned.checked_add(offset)?,
+                None => {
+                    self.scanned = self.buffer.len();
+
+                    if self.buffered() > self.max_line_length {
+                        self.clear();
+
+                        if !self.discarding {
+                            self.discarding = true;
+                            return Some(Err(DecodeError::LineTooLong(self.max_line_length)));
+                        }
+                    }
+
+                    return None;
+                }
+            };
+
+            let next = end.checked_add(1)?;
+            let mut line = self.buffer.get(self.consumed..end)?;
+            self.consumed = next;
+            self.scanned = next;
+
+            if let Some(stripped) = line.strip_suffix(b"\r") {
+                line = stripped;
+            }
+
+            if self.discarding {
+                self.discarding = false;
+                continue;
+            }
+
+            if line.len() > self.max_line_length {
+                return Some(Err(DecodeError::LineTooLong(self.max_line_length)));
+            }
+
+            let line = line.to_vec();
+
+            if self.consumed == self.buffer.len() {
+                self.clear();
+            }
+
+            return Some(Ok(line));
+        }
+    }
+
+    fn next_text(&mut self) -> Option<Result<String, DecodeError>> {
+        Some(self.next_line()?.and_then(|line| {
+            String::from_utf8(line).map_err(|_| DecodeError::Parse(ParseMessageError::InvalidUtf8))
+        }))
+    }
+
+    /// Take the next command, once its line is complete
+    pub fn next_command(&mut self) -> Option<Result<Command, DecodeError>> {
+        let line = self.next_text()?;
+
+        Some(
+            line.and_then(|line| {
+                Command::parse_with(&line, &self.options).map_err(DecodeError::from)
+            }),
+        )
+    }
+
+    /// Take the next command and the request id its line
+    /// carries, once its line is complete
+    pub fn next_identified_command(&mut self) -> Option<Result<Identified<Command>, DecodeError>> {
+        let line = self.next_text()?;
+
+        Some(line.and_then(|line| {
+            Identified::<Command>::parse_with(&line, &self.options).map_err(DecodeError::from)
+        }))
+    }
+
+    /// Take the next response, once its line is complete
+    pub fn next_response(
+        &mut self,
+        version: ProtocolVersion,
+    ) -> Option<Result<Response, DecodeError>> {
+        Some(
+            self.next_text()?.and_then(|line| {
+                Response::parse_versioned(&line, version).map_err(DecodeError::from)
+            }),
+        )
+    }
+
+    /// Take the next response and the request id its line
+    /// carries, once its line is complete
+    pub fn next_identified_response(
+        &mut self,
+        version: ProtocolVersion,
+    ) -> Option<Result<Identified<Response>, DecodeError>> {
+        Some(self.next_text()?.and_then(|line| {
+            Identified::<Response>::parse_versioned(&line, version).map_err(DecodeError::from)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every complete line, as text, with errors as their
+    /// message
+    fn lines(decoder: &mut Decoder) -> Vec<String> {
+        std::iter::from_fn(|| decoder.next_line())
+            .map(|line| match line {
+                Ok(line) => String::from_utf8_lossy(&line).into_owned(),
+                Err(error) => error.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn joins_lines_split_across_chunks() {
+        let mut decoder = Decoder::new();
+
+        decoder.push(b"PI");
+        assert!(lines(&mut decoder).is_empty());
+        assert_eq!(decoder.buffered(), 2);
+
+        decoder.push(b"NG\r\nLISTSUB");
+        assert_eq!(lines(&mut decoder), ["PING"]);
+        assert_eq!(decoder.buffered(), 7);
+
+        decoder.push(b"SCRIPTIONS\n");
+        assert_eq!(lines(&mut decoder), ["LISTSUBSCRIPTIONS"]);
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn takes_many_lines_from_one_chunk() {
+        let mut decoder = Decoder::new();
+        let chunk = "29\r\n".repeat(1000);
+
+        decoder.push(chunk.as_bytes());
+        decoder.push(b"25\n\n");
+
+        let taken = lines(&mut decoder);
+
+        assert_eq!(taken.len(), 1002);
+        assert!(taken.iter().take(1000).all(|line| line == "29"));
+        assert_eq!(
+            taken.get(1000..),
+            Some(&["25".to_string(), String::new()][..])
+        );
+    }
+
+    #[test]
+    fn rejects_overlong_lines() {
+        let mut decoder = Decoder::new().with_max_line_length(4);
+
+        // Whole in one chunk: the line is dropped, the next kept
+        decoder.push(b"TOOLONG\nPING\n");
+        assert_eq!(lines(&mut decoder), ["line longer than 4 bytes", "PING"]);
+
+        // Spanning chunks: one error, then the rest is discarded
+        decoder.push(b"TOOLO");
+        assert_eq!(lines(&mut decoder), ["line longer than 4 bytes"]);
+        decoder.push(b"NG STILL");
+        assert!(lines(&mut decoder).is_empty());
+        decoder.push(b"\n29\n");
+        assert_eq!(lines(&mut decoder), ["29"]);
+
+        // The line ending does not count against the limit
+        decoder.push(b"PING\r\n");
+        assert_eq!(lines(&mut decoder), ["PING"]);
+    }
+
+    #[test]
+    fn parses_messages() {
+        let mut decoder = Decoder::new();
+        decoder.push(b"PING\n29\n\xff\n");
+
+        assert!(matches!(decoder.next_command(), Some(Ok(Command::Ping))));
+        assert!(matches!(
+            decoder.next_response(ProtocolVersion::V1),
+            Some(Ok(Response::Pong))
+        ));
+        assert!(matches!(
+            decoder.next_command(),
+            Some(Err(DecodeError::Parse(ParseMessageError::InvalidUtf8)))
+        ));
+        assert!(decoder.next_command().is_none());
+    }
+}
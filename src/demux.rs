@@ -0,0 +1,456 @@
+//! Attributing responses to pipelined commands
+//!
+//! Servers answer commands in the order they were sent, so a
+//! client may send several before reading any reply. The
+//! Demultiplexer keeps the outstanding commands in order and
+//! uses a ReplyValidator for each to tell where one reply ends
+//! and the next begins. When the connection drops, it tells
+//! which of them can be sent again on the next one. Each reply
+//! carries how long it took, and LatencyStats sums those up by
+//! verb to show which commands are slow against a server.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::wirelog::{redact_secrets, Direction};
+use crate::{Command, ProtocolViolation, ReplyStatus, ReplyValidator, Response};
+
+/// A complete reply to one outstanding command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    /// The id returned when the command was sent
+    pub id: u64,
+    pub command: Command,
+    pub responses: Vec<Response>,
+    pub latency: Latency,
+}
+
+/// How long a reply took, measured from when its command was
+/// recorded as sent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Latency {
+    /// Until the first response of the reply arrived
+    pub first_response: Duration,
+    /// Until the reply was complete
+    pub complete: Duration,
+}
+
+/// What a received response completed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DemuxEvent {
+    /// The reply to the oldest outstanding command
+    Reply(Reply),
+    /// A ShuttingDown announcement, which belongs to no reply
+    Announcement(Response),
+}
+
+/// The outstanding commands once a connection has dropped
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Reconnect {
+    /// Commands to write to the new connection, in order, with
+    /// the ids their replies will carry
+    pub resend: Vec<(u64, Command)>,
+    /// Commands that are not idempotent and may or may not have
+    /// been applied, with their ids
+    pub abandoned: Vec<(u64, Command)>,
+}
+
+/// After a violation the remaining replies cannot be told
+/// apart reliably, and the connection should be closed
+#[derive(Debug, Error)]
+pub enum DemuxError {
+    #[error("response \"{0}\" received with no command outstanding")]
+    Unsolicited(String),
+    #[error("reply to command {id}: {violation}")]
+    Violation {
+        id: u64,
+        command: Box<Command>,
+        violation: ProtocolViolation,
+    },
+}
+
+#[derive(Debug)]
+struct Outstanding {
+    id: u64,
+    command: Command,
+    validator: ReplyValidator,
+    responses: Vec<Response>,
+    sent_at: Instant,
+    first_response: Option<Duration>,
+}
+
+/// Groups received responses by the command they answer
+#[derive(Debug, Default)]
+pub struct Demultiplexer {
+    outstanding: VecDeque<Outstanding>,
+    next_id: u64,
+}
+
+impl Demultiplexer {
+    pub fn new() -> Demultiplexer {
+        Demultiplexer::default()
+    }
+
+    /// Record a command as sent, returning the id its reply
+    /// will carry
+    pub fn send(&mut self, command: Command) -> u64 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        self.outstanding.push_back(Outstanding {
+            id,
+            validator: ReplyValidator::new(&command),
+            command,
+            responses: Vec::new(),
+            sent_at: Instant::now(),
+            first_response: None,
+        });
+
+        id
+    }
+
+    /// The id of the command whose reply is being received
+    pub fn current(&self) -> Option<u64> {
+        self.outstanding.front().map(|current| current.id)
+    }
+
+    /// Commands sent whose replies are not yet complete
+    pub fn outstanding(&self) -> usize {
+        self.outstanding.len()
+    }
+
+    /// Prepare the outstanding commands for a new connection
+    ///
+    /// Idempotent commands, keyed mutations included, stay
+    /// outstanding under their ids and are returned to be sent
+    /// again, after `preamble`, such as the User command the
+    /// new connection needs first. Any part of their replies
+    /// already received is dropped. Other commands are
+    /// abandoned.
+    pub fn reconnect(&mut self, preamble: Vec<Command>) -> Reconnect {
+        let mut reconnect = Reconnect::default();
+        let previous = std::mem::take(&mut self.outstanding);
+
+        for command in preamble {
+            let id = self.send(command.clone());
+            reconnect.resend.push((id, command));
+        }
+
+        for current in previous {
+            if current.command.is_idempotent() {
+                reconnect.resend.push((current.id, current.command.clone()));
+                self.outstanding.push_back(Outstanding {
+                    id: current.id,
+                    validator: ReplyValidator::new(&current.command),
+                    command: current.command,
+                    responses: Vec::new(),
+                    sent_at: Instant::now(),
+                    first_response: None,
+                });
+            } else {
+                reconnect.abandoned.push((current.id, current.command));
+            }
+        }
+
+        reconnect
+    }
+
+    /// Accept the next received response
+    ///
+    /// Returns None while the oldest outstanding reply is
+    /// incomplete.
+    pub fn accept(&mut self, response: Response) -> Result<Option<DemuxEvent>, DemuxError> {
+        if let Response::ShuttingDown(_) = response {
+            return Ok(Some(DemuxEvent::Announcement(response)));
+        }
+
+        let current = match self.outstanding.front_mut() {
+            Some(current) => current,
+            None => return Err(unsolicited(&response)),
+        };
+
+        match current.validator.accept(&response) {
+            Ok(ReplyStatus::Incomplete) => {
+                let sent_at = current.sent_at;
+
+                current.responses.push(response);
+                current
+                    .first_response
+                    .get_or_insert_with(|| sent_at.elapsed());
+
+                Ok(None)
+            }
+            Ok(ReplyStatus::Complete) => {
+                current.responses.push(response);
+
+                Ok(self.outstanding.pop_front().map(|current| {
+                    let complete = current.sent_at.elapsed();
+
+                    DemuxEvent::Reply(Reply {
+                        id: current.id,
+                        command: current.command,
+                        responses: current.responses,
+                        latency: Latency {
+                            first_response: current.first_response.unwrap_or(complete),
+                            complete,
+                        },
+                    })
+                }))
+            }
+            Err(violation) => match self.outstanding.pop_front() {
+                Some(current) => Err(DemuxError::Violation {
+                    id: current.id,
+                    command: Box::new(current.command),
+                    violation,
+                }),
+                None => Err(unsolicited(&response)),
+            },
+        }
+    }
+}
+
+fn unsolicited(response: &Response) -> DemuxError {
+    let line = response.to_string();
+
+    DemuxError::Unsolicited(redact_secrets(Direction::Received, &line).unwrap_or(line))
+}
+
+/// Latencies of the replies to one verb
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VerbLatency {
+    /// Replies recorded
+    pub count: u32,
+    /// Sum of the times to first response
+    pub first_response: Duration,
+    /// Sum of the times to complete
+    pub complete: Duration,
+    /// Longest time to complete
+    pub slowest: Duration,
+}
+
+impl VerbLatency {
+    pub fn mean_first_response(&self) -> Option<Duration> {
+        self.first_response.checked_div(self.count)
+    }
+
+    pub fn mean_complete(&self) -> Option<Duration> {
+        self.complete.checked_div(self.count)
+    }
+}
+
+/// Reply latencies summed up by command verb
+#[derive(Debug, Clone, Default)]
+pub struct LatencyStats {
+    verbs: HashMap<&'static str, VerbLatency>,
+}
+
+impl LatencyStats {
+    pub fn new() -> LatencyStats {
+        LatencyStats::default()
+    }
+
+    /// Add a completed reply
+    pub fn record(&mut self, reply: &Reply) {
+        let verb = self.verbs.entry(reply.command.verb()).or_default();
+
+        verb.count = verb.count.saturating_add(1);
+        verb.first_response = verb
+            .first_response
+            .saturating_add(reply.latency.first_response);
+        verb.complete = verb.complete.saturating_add(reply.latency.complete);
+        verb.slowest = verb.slowest.max(reply.latency.complete);
+    }
+
+    /// The latencies recorded for a verb, such as `LISTUNREAD`
+    pub fn get(&self, verb: &str) -> Option<&VerbLatency> {
+        self.verbs.get(verb)
+    }
+
+    /// Every verb with recorded replies, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &VerbLatency)> + '_ {
+        self.verbs.iter().map(|(verb, latency)| (*verb, latency))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(event: Result<Option<DemuxEvent>, DemuxError>) -> Option<(u64, Vec<Response>)> {
+        match event {
+            Ok(Some(DemuxEvent::Reply(reply))) => Some((reply.id, reply.responses)),
+            _ => None,
+        }
+    }
+
+    fn subscription(id: i64) -> Response {
+        Response::Subscription(crate::Subscription {
+            id,
+            url: "https://example.com/feed".to_string(),
+            icon: None,
+            order: None,
+        })
+    }
+
+    #[test]
+    fn groups_responses_into_replies() {
+        let mut demux = Demultiplexer::new();
+        let ping = demux.send(Command::Ping);
+        let list = demux.send(Command::ListSubscriptions);
+        let last = demux.send(Command::Ping);
+
+        assert_eq!(demux.outstanding(), 3);
+        assert_eq!(
+            reply(demux.accept(Response::Pong)),
+            Some((ping, vec![Response::Pong]))
+        );
+        assert_eq!(demux.current(), Some(list));
+
+        assert!(matches!(
+            demux.accept(Response::StartSubscriptionList),
+            Ok(None)
+        ));
+        assert!(matches!(demux.accept(subscription(1)), Ok(None)));
+
+        let shutting_down = Response::ShuttingDown("restarting".to_string());
+        assert!(matches!(
+            demux.accept(shutting_down.clone()),
+            Ok(Some(DemuxEvent::Announcement(announcement))) if announcement == shutting_down
+        ));
+
+        assert_eq!(
+            reply(demux.accept(Response::EndList)),
+            Some((
+                list,
+                vec![
+                    Response::StartSubscriptionList,
+                    subscription(1),
+                    Response::EndList
+                ]
+            ))
+        );
+        assert_eq!(
+            reply(demux.accept(Response::Pong)),
+            Some((last, vec![Response::Pong]))
+        );
+        assert_eq!(demux.outstanding(), 0);
+    }
+
+    #[test]
+    fn reports_violations() {
+        let mut demux = Demultiplexer::new();
+
+        assert!(matches!(
+            demux.accept(Response::Pong),
+            Err(DemuxError::Unsolicited(line)) if line == "29"
+        ));
+
+        let token = Response::ResumeToken {
+            token: "s3cr3t".to_string(),
+            expires: 0,
+        };
+        assert!(matches!(
+            demux.accept(token),
+            Err(DemuxError::Unsolicited(line)) if !line.contains("s3cr3t")
+        ));
+
+        let ping = demux.send(Command::Ping);
+        demux.send(Command::Ping);
+
+        assert!(matches!(
+            demux.accept(Response::EndList),
+            Err(DemuxError::Violation { id, .. }) if id == ping
+        ));
+        assert_eq!(demux.outstanding(), 1);
+    }
+
+    #[test]
+    fn reconnect_resends_idempotent_commands() {
+        let mut demux = Demultiplexer::new();
+        let list = demux.send(Command::ListSubscriptions);
+        let subscribe = demux.send(Command::Subscribe {
+            url: "https://example.com/feed".to_string(),
+            category: None,
+            key: None,
+            deadline: None,
+        });
+        let keyed = Command::MarkRead {
+            id: 1,
+            expected: None,
+            key: Some("k1".to_string()),
+        };
+        let mark = demux.send(keyed.clone());
+
+        assert!(matches!(
+            demux.accept(Response::StartSubscriptionList),
+            Ok(None)
+        ));
+
+        let user = Command::User {
+            username: "bob".to_string(),
+        };
+        let reconnect = demux.reconnect(vec![user.clone()]);
+        let (preamble, _) = reconnect.resend.first().cloned().unwrap();
+
+        assert_eq!(
+            reconnect.resend,
+            vec![
+                (preamble, user),
+                (list, Command::ListSubscriptions),
+                (mark, keyed)
+            ]
+        );
+        assert_eq!(
+            reconnect
+                .abandoned
+                .iter()
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>(),
+            [subscribe]
+        );
+
+        // Replies start over, behind the preamble's
+        assert_eq!(
+            reply(demux.accept(Response::AckUser { id: 7 })),
+            Some((preamble, vec![Response::AckUser { id: 7 }]))
+        );
+        assert!(matches!(
+            demux.accept(Response::StartSubscriptionList),
+            Ok(None)
+        ));
+        assert_eq!(
+            reply(demux.accept(Response::EndList)).map(|(id, responses)| (id, responses.len())),
+            Some((list, 2))
+        );
+    }
+
+    #[test]
+    fn latency_stats_sum_by_verb() {
+        let reply = |command, first: u64, complete: u64| Reply {
+            id: 0,
+            command,
+            responses: Vec::new(),
+            latency: Latency {
+                first_response: Duration::from_millis(first),
+                complete: Duration::from_millis(complete),
+            },
+        };
+        let mut stats = LatencyStats::new();
+
+        stats.record(&reply(Command::Ping, 10, 10));
+        stats.record(&reply(Command::Ping, 30, 50));
+        stats.record(&reply(Command::ListSubscriptions, 5, 100));
+
+        let ping = stats.get("PING").copied().unwrap_or_default();
+
+        assert_eq!(ping.count, 2);
+        assert_eq!(ping.mean_first_response(), Some(Duration::from_millis(20)));
+        assert_eq!(ping.mean_complete(), Some(Duration::from_millis(30)));
+        assert_eq!(ping.slowest, Duration::from_millis(50));
+        assert_eq!(stats.iter().count(), 2);
+        assert_eq!(stats.get("LISTUNREAD"), None);
+        assert_eq!(VerbLatency::default().mean_complete(), None);
+    }
+}
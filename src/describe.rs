@@ -0,0 +1,1196 @@
+//! Structured descriptions of every command and response
+//!
+//! Servers can answer HELP from these, and documentation can
+//! be generated from them instead of being written by hand.
+//! `Command::verb` and `Response::code` read from the same
+//! tables, and response codes come from the `code` module like
+//! everywhere else, so the descriptions cannot drift from the
+//! wire format.
+
+use std::fmt;
+
+use crate::code;
+use crate::{Capabilities, Command, Dialect, ProtocolVersion, Response};
+
+/// The kind of value an argument holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgumentKind {
+    /// A signed 64-bit integer
+    Integer,
+    /// A single word without spaces
+    Token,
+    /// Free text running to the end of the line
+    Text,
+    /// A fixed word, sent as the argument's name
+    Literal,
+}
+
+/// A single argument of a command or response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgumentDescriptor {
+    pub name: &'static str,
+    pub kind: ArgumentKind,
+    /// The first protocol version sending the argument
+    pub since: ProtocolVersion,
+    /// Whether the argument may be left out
+    pub optional: bool,
+    /// Keyword sent before the value, such as `SINCE`
+    pub keyword: Option<&'static str>,
+    /// Whether the value may hold a secret and is left out of
+    /// audit records
+    pub secret: bool,
+}
+
+impl ArgumentDescriptor {
+    const fn since(self, since: ProtocolVersion) -> ArgumentDescriptor {
+        ArgumentDescriptor { since, ..self }
+    }
+
+    const fn optional(self) -> ArgumentDescriptor {
+        ArgumentDescriptor {
+            optional: true,
+            ..self
+        }
+    }
+
+    const fn secret(self) -> ArgumentDescriptor {
+        ArgumentDescriptor {
+            secret: true,
+            ..self
+        }
+    }
+
+    const fn keyword(self, keyword: &'static str) -> ArgumentDescriptor {
+        ArgumentDescriptor {
+            keyword: Some(keyword),
+            ..self
+        }
+    }
+}
+
+/// Metadata describing a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandDescriptor {
+    pub verb: &'static str,
+    /// Legacy verbs accepted in place of `verb`
+    pub aliases: &'static [&'static str],
+    pub arguments: &'static [ArgumentDescriptor],
+    /// Whether a User command must have been issued first
+    pub requires_user: bool,
+    /// Whether servers advertising `ANONYMOUS` answer the
+    /// command without a User command
+    pub anonymous: bool,
+    pub summary: &'static str,
+}
+
+impl CommandDescriptor {
+    /// The verb to send in the given dialect
+    ///
+    /// Commands without a legacy alias use their current verb
+    /// in every dialect.
+    pub fn verb_in(&self, dialect: Dialect) -> &'static str {
+        match (dialect, self.aliases.first()) {
+            (Dialect::Legacy, Some(alias)) => alias,
+            _ => self.verb,
+        }
+    }
+
+    /// Whether a User command must have been issued first on a
+    /// server with the given capabilities
+    pub fn requires_user_on(&self, server: &Capabilities) -> bool {
+        self.requires_user && !(self.anonymous && server.anonymous)
+    }
+}
+
+/// Metadata describing a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResponseDescriptor {
+    pub code: u8,
+    pub name: &'static str,
+    pub arguments: &'static [ArgumentDescriptor],
+    pub summary: &'static str,
+}
+
+const fn argument(name: &'static str, kind: ArgumentKind) -> ArgumentDescriptor {
+    ArgumentDescriptor {
+        name,
+        kind,
+        since: ProtocolVersion::V1,
+        optional: false,
+        keyword: None,
+        secret: false,
+    }
+}
+
+const ID: &[ArgumentDescriptor] = &[argument("id", ArgumentKind::Integer)];
+const MESSAGE: &[ArgumentDescriptor] = &[argument("message", ArgumentKind::Text)];
+const AFTER: ArgumentDescriptor = argument("after", ArgumentKind::Token)
+    .optional()
+    .keyword("AFTER");
+const SUMMARY: ArgumentDescriptor = argument("summary", ArgumentKind::Integer)
+    .optional()
+    .keyword("SUMMARY");
+const COUNT: ArgumentDescriptor = argument("COUNT", ArgumentKind::Literal).optional();
+const EXPECTED_REVISION: ArgumentDescriptor = argument("revision", ArgumentKind::Integer)
+    .optional()
+    .keyword("IF");
+const IDEMPOTENCY_KEY: ArgumentDescriptor = argument("key", ArgumentKind::Token)
+    .optional()
+    .keyword("KEY");
+const DEADLINE: ArgumentDescriptor = argument("deadline", ArgumentKind::Integer)
+    .optional()
+    .keyword("DEADLINE");
+const KEYED_REVISION: &[ArgumentDescriptor] = &[
+    argument("revision", ArgumentKind::Integer)
+        .since(ProtocolVersion::V2)
+        .optional(),
+    IDEMPOTENCY_KEY,
+];
+const CAPABILITY_LIST: &[ArgumentDescriptor] =
+    &[argument("capabilities", ArgumentKind::Text).optional()];
+
+const USER: CommandDescriptor = CommandDescriptor {
+    verb: "USER",
+    aliases: &[],
+    arguments: &[argument("username", ArgumentKind::Token)],
+    requires_user: false,
+    anonymous: false,
+    summary: "Select the current user",
+};
+
+const LIST_SUBSCRIPTIONS: CommandDescriptor = CommandDescriptor {
+    verb: "LISTSUBSCRIPTIONS",
+    aliases: &["LISTFEEDS"],
+    arguments: &[],
+    requires_user: true,
+    anonymous: true,
+    summary: "List the current user's subscriptions",
+};
+
+const SUBSCRIBE: CommandDescriptor = CommandDescriptor {
+    verb: "SUBSCRIBE",
+    aliases: &[],
+    arguments: &[
+        argument("url", ArgumentKind::Token),
+        argument("category", ArgumentKind::Token)
+            .optional()
+            .keyword("CATEGORY"),
+        IDEMPOTENCY_KEY,
+        DEADLINE,
+    ],
+    requires_user: true,
+    anonymous: false,
+    summary: "Subscribe the current user to a new feed",
+};
+
+const UNSUBSCRIBE: CommandDescriptor = CommandDescriptor {
+    verb: "UNSUBSCRIBE",
+    aliases: &[],
+    arguments: &[
+        argument("id", ArgumentKind::Integer),
+        EXPECTED_REVISION,
+        IDEMPOTENCY_KEY,
+    ],
+    requires_user: true,
+    anonymous: false,
+    summary: "Unsubscribe the current user from a feed",
+};
+
+const LIST_UNREAD: CommandDescriptor = CommandDescriptor {
+    verb: "LISTUNREAD",
+    aliases: &[],
+    arguments: &[AFTER, SUMMARY, COUNT],
+    requires_user: true,
+    anonymous: true,
+    summary: "List the current user's unread feed entries",
+};
+
+const MARK_READ: CommandDescriptor = CommandDescriptor {
+    verb: "MARKREAD",
+    aliases: &[],
+    arguments: &[
+        argument("id", ArgumentKind::Integer),
+        EXPECTED_REVISION,
+        IDEMPOTENCY_KEY,
+    ],
+    requires_user: true,
+    anonymous: false,
+    summary: "Mark a feed entry as read by the current user",
+};
+
+const MARK_UNREAD: CommandDescriptor = CommandDescriptor {
+    verb: "MARKUNREAD",
+    aliases: &[],
+    arguments: &[argument("id", ArgumentKind::Integer), EXPECTED_REVISION],
+    requires_user: true,
+    anonymous: false,
+    summary: "Mark a feed entry as unread again by the current user",
+};
+
+const MARK_ALL_READ: CommandDescriptor = CommandDescriptor {
+    verb: "MARKREAD",
+    aliases: &[],
+    arguments: &[argument("ALL", ArgumentKind::Literal)],
+    requires_user: true,
+    anonymous: false,
+    summary: "Mark every unread entry as read by the current user",
+};
+
+const SET_ORDER: CommandDescriptor = CommandDescriptor {
+    verb: "SETORDER",
+    aliases: &[],
+    arguments: &[
+        argument("id", ArgumentKind::Integer),
+        argument("order", ArgumentKind::Integer),
+    ],
+    requires_user: true,
+    anonymous: false,
+    summary: "Set a subscription's position in the current user's ordering",
+};
+
+const SET_INTERVAL: CommandDescriptor = CommandDescriptor {
+    verb: "SETINTERVAL",
+    aliases: &[],
+    arguments: &[
+        argument("feed_id", ArgumentKind::Integer),
+        argument("seconds", ArgumentKind::Integer),
+    ],
+    requires_user: true,
+    anonymous: false,
+    summary: "Set how often the server refreshes a feed",
+};
+
+const FEED_STATUS: CommandDescriptor = CommandDescriptor {
+    verb: "FEEDSTATUS",
+    aliases: &[],
+    arguments: &[argument("feed_id", ArgumentKind::Integer)],
+    requires_user: true,
+    anonymous: false,
+    summary: "Show how the server is refreshing a feed",
+};
+
+const ADD_WEBHOOK: CommandDescriptor = CommandDescriptor {
+    verb: "ADDWEBHOOK",
+    aliases: &[],
+    // Webhook URLs often carry an access token
+    arguments: &[argument("url", ArgumentKind::Token).secret()],
+    requires_user: true,
+    anonymous: false,
+    summary: "Register a URL to be notified when new entries arrive",
+};
+
+const LIST_WEBHOOKS: CommandDescriptor = CommandDescriptor {
+    verb: "LISTWEBHOOKS",
+    aliases: &[],
+    arguments: &[],
+    requires_user: true,
+    anonymous: false,
+    summary: "List the current user's webhooks",
+};
+
+const DELETE_WEBHOOK: CommandDescriptor = CommandDescriptor {
+    verb: "DELETEWEBHOOK",
+    aliases: &[],
+    arguments: ID,
+    requires_user: true,
+    anonymous: false,
+    summary: "Delete one of the current user's webhooks",
+};
+
+const SAVE_SEARCH: CommandDescriptor = CommandDescriptor {
+    verb: "SAVESEARCH",
+    aliases: &[],
+    arguments: &[
+        argument("name", ArgumentKind::Token),
+        argument("query", ArgumentKind::Text),
+    ],
+    requires_user: true,
+    anonymous: false,
+    summary: "Save a search query under a name",
+};
+
+const LIST_SAVED_SEARCHES: CommandDescriptor = CommandDescriptor {
+    verb: "LISTSEARCHES",
+    aliases: &[],
+    arguments: &[],
+    requires_user: true,
+    anonymous: false,
+    summary: "List the current user's saved searches",
+};
+
+const RUN_SEARCH: CommandDescriptor = CommandDescriptor {
+    verb: "RUNSEARCH",
+    aliases: &[],
+    arguments: &[argument("name", ArgumentKind::Token), AFTER, DEADLINE],
+    requires_user: true,
+    anonymous: false,
+    summary: "List the current user's entries matching a saved search",
+};
+
+const SESSION: CommandDescriptor = CommandDescriptor {
+    verb: "SESSION",
+    aliases: &[],
+    arguments: &[
+        argument("action", ArgumentKind::Token),
+        argument("name", ArgumentKind::Token),
+    ],
+    requires_user: false,
+    anonymous: false,
+    summary: "Open, select or close a named session with its own user",
+};
+
+const AUTH: CommandDescriptor = CommandDescriptor {
+    verb: "AUTH",
+    aliases: &[],
+    arguments: &[
+        argument("method", ArgumentKind::Token),
+        argument("credentials", ArgumentKind::Text).secret(),
+    ],
+    requires_user: false,
+    anonymous: false,
+    summary: "Authenticate and select the current user",
+};
+
+const RESUME_TOKEN: CommandDescriptor = CommandDescriptor {
+    verb: "RESUMETOKEN",
+    aliases: &[],
+    arguments: &[],
+    requires_user: true,
+    anonymous: false,
+    summary: "Issue a token resuming this session on a later connection",
+};
+
+const RESUME: CommandDescriptor = CommandDescriptor {
+    verb: "RESUME",
+    aliases: &[],
+    arguments: &[argument("token", ArgumentKind::Token).secret()],
+    requires_user: false,
+    anonymous: false,
+    summary: "Resume the session a token was issued for",
+};
+
+const SEARCH: CommandDescriptor = CommandDescriptor {
+    verb: "SEARCH",
+    aliases: &[],
+    arguments: &[DEADLINE, argument("query", ArgumentKind::Text)],
+    requires_user: true,
+    anonymous: false,
+    summary: "List the current user's entries matching a query",
+};
+
+const LIST_READ: CommandDescriptor = CommandDescriptor {
+    verb: "LISTREAD",
+    aliases: &[],
+    arguments: &[
+        argument("since", ArgumentKind::Integer)
+            .optional()
+            .keyword("SINCE"),
+        AFTER,
+        SUMMARY,
+        COUNT,
+    ],
+    requires_user: true,
+    anonymous: false,
+    summary: "List the current user's read entries, optionally only those read since a time",
+};
+
+const GET_CONTENT: CommandDescriptor = CommandDescriptor {
+    verb: "GETCONTENT",
+    aliases: &[],
+    arguments: &[
+        argument("id", ArgumentKind::Integer),
+        argument("format", ArgumentKind::Token)
+            .optional()
+            .keyword("FORMAT"),
+    ],
+    requires_user: true,
+    anonymous: false,
+    summary: "Fetch the content of a feed entry",
+};
+
+const SYNC: CommandDescriptor = CommandDescriptor {
+    verb: "SYNC",
+    aliases: &[],
+    arguments: &[argument("token", ArgumentKind::Token).optional()],
+    requires_user: true,
+    anonymous: false,
+    summary: "List every change since a sync token",
+};
+
+const GET_RAW_FEED: CommandDescriptor = CommandDescriptor {
+    verb: "GETRAWFEED",
+    aliases: &[],
+    arguments: &[argument("feed_id", ArgumentKind::Integer)],
+    requires_user: true,
+    anonymous: false,
+    summary: "Fetch the document last fetched for a feed, as received",
+};
+
+const EXPORT: CommandDescriptor = CommandDescriptor {
+    verb: "EXPORT",
+    aliases: &[],
+    arguments: &[argument("feed_id", ArgumentKind::Integer)
+        .optional()
+        .keyword("FEED")],
+    requires_user: true,
+    anonymous: false,
+    summary: "Export a feed's or the unread list's entries as JSON Feed",
+};
+
+const IMPORT: CommandDescriptor = CommandDescriptor {
+    verb: "IMPORT",
+    aliases: &[],
+    arguments: &[],
+    requires_user: true,
+    anonymous: false,
+    summary: "Subscribe to every feed of a plain-text list of URLs",
+};
+
+const UNDO: CommandDescriptor = CommandDescriptor {
+    verb: "UNDO",
+    aliases: &[],
+    arguments: &[],
+    requires_user: true,
+    anonymous: false,
+    summary: "Reverse the last mark-read or unsubscribe of the session",
+};
+
+const PING: CommandDescriptor = CommandDescriptor {
+    verb: "PING",
+    aliases: &[],
+    arguments: &[],
+    requires_user: false,
+    anonymous: false,
+    summary: "Check that the connection is still alive",
+};
+
+const CAPABILITIES: CommandDescriptor = CommandDescriptor {
+    verb: "CAPABILITIES",
+    aliases: &[],
+    arguments: CAPABILITY_LIST,
+    requires_user: false,
+    anonymous: false,
+    summary: "Advertise capabilities and negotiate the protocol version",
+};
+
+const SET_QUOTA: CommandDescriptor = CommandDescriptor {
+    verb: "SETQUOTA",
+    aliases: &[],
+    arguments: &[
+        argument("username", ArgumentKind::Token),
+        argument("max_feeds", ArgumentKind::Integer),
+        argument("max_entries", ArgumentKind::Integer),
+    ],
+    requires_user: true,
+    anonymous: false,
+    summary: "Set a user's quota (administrators only)",
+};
+
+const GET_QUOTA: CommandDescriptor = CommandDescriptor {
+    verb: "GETQUOTA",
+    aliases: &[],
+    arguments: &[argument("username", ArgumentKind::Token).optional()],
+    requires_user: true,
+    anonymous: false,
+    summary: "Read a user's quota and usage",
+};
+
+const TAG_AND_USERNAME: &[ArgumentDescriptor] = &[
+    argument("tag", ArgumentKind::Token),
+    argument("username", ArgumentKind::Token),
+];
+
+const SHARE: CommandDescriptor = CommandDescriptor {
+    verb: "SHARE",
+    aliases: &[],
+    arguments: TAG_AND_USERNAME,
+    requires_user: true,
+    anonymous: false,
+    summary: "Share the feeds under a tag with another user",
+};
+
+const UNSHARE: CommandDescriptor = CommandDescriptor {
+    verb: "UNSHARE",
+    aliases: &[],
+    arguments: TAG_AND_USERNAME,
+    requires_user: true,
+    anonymous: false,
+    summary: "Stop sharing the feeds under a tag with another user",
+};
+
+const LIST_CATEGORIES: CommandDescriptor = CommandDescriptor {
+    verb: "LISTCATEGORIES",
+    aliases: &[],
+    arguments: &[],
+    requires_user: false,
+    anonymous: false,
+    summary: "List the feed categories the server knows",
+};
+
+const LIST_SHARES: CommandDescriptor = CommandDescriptor {
+    verb: "LISTSHARES",
+    aliases: &[],
+    arguments: &[],
+    requires_user: true,
+    anonymous: false,
+    summary: "List the tags shared by or with the current user",
+};
+
+const COMMANDS: &[CommandDescriptor] = &[
+    USER,
+    LIST_SUBSCRIPTIONS,
+    SUBSCRIBE,
+    UNSUBSCRIBE,
+    LIST_UNREAD,
+    MARK_READ,
+    MARK_UNREAD,
+    MARK_ALL_READ,
+    GET_CONTENT,
+    EXPORT,
+    IMPORT,
+    UNDO,
+    SYNC,
+    LIST_READ,
+    PING,
+    CAPABILITIES,
+    SET_QUOTA,
+    GET_QUOTA,
+    SHARE,
+    UNSHARE,
+    LIST_SHARES,
+    LIST_CATEGORIES,
+    SET_ORDER,
+    SET_INTERVAL,
+    FEED_STATUS,
+    GET_RAW_FEED,
+    ADD_WEBHOOK,
+    LIST_WEBHOOKS,
+    DELETE_WEBHOOK,
+    SEARCH,
+    SAVE_SEARCH,
+    LIST_SAVED_SEARCHES,
+    RUN_SEARCH,
+    SESSION,
+    AUTH,
+    RESUME_TOKEN,
+    RESUME,
+];
+
+const fn response(
+    code: u8,
+    name: &'static str,
+    arguments: &'static [ArgumentDescriptor],
+    summary: &'static str,
+) -> ResponseDescriptor {
+    ResponseDescriptor {
+        code,
+        name,
+        arguments,
+        summary,
+    }
+}
+
+const ACK_USER: ResponseDescriptor =
+    response(code::ACK_USER, "AckUser", ID, "Current user selected");
+const START_SUBSCRIPTION_LIST: ResponseDescriptor = response(
+    code::START_SUBSCRIPTION_LIST,
+    "StartSubscriptionList",
+    &[],
+    "Beginning of a list of subscriptions",
+);
+const SUBSCRIPTION: ResponseDescriptor = response(
+    code::SUBSCRIPTION,
+    "Subscription",
+    &[
+        argument("id", ArgumentKind::Integer),
+        argument("url", ArgumentKind::Token),
+        argument("icon", ArgumentKind::Token).since(ProtocolVersion::V2),
+        argument("order", ArgumentKind::Integer).since(ProtocolVersion::V2),
+    ],
+    "A single subscription",
+);
+const START_ENTRY_LIST: ResponseDescriptor = response(
+    code::START_ENTRY_LIST,
+    "StartEntryList",
+    &[],
+    "Beginning of a list of feed entries",
+);
+const ENTRY: ResponseDescriptor = response(
+    code::ENTRY,
+    "Entry",
+    &[
+        argument("id", ArgumentKind::Integer),
+        argument("feed_id", ArgumentKind::Integer),
+        argument("feed_url", ArgumentKind::Token),
+        argument("url", ArgumentKind::Token),
+        argument("published", ArgumentKind::Integer).since(ProtocolVersion::V2),
+        argument("guid", ArgumentKind::Token).since(ProtocolVersion::V2),
+        argument("read_at", ArgumentKind::Integer).since(ProtocolVersion::V2),
+        argument("group_id", ArgumentKind::Integer).since(ProtocolVersion::V2),
+        argument("duplicate", ArgumentKind::Integer).since(ProtocolVersion::V2),
+        argument("flags", ArgumentKind::Token).since(ProtocolVersion::V2),
+        argument("language", ArgumentKind::Token).since(ProtocolVersion::V2),
+        argument("summary", ArgumentKind::Token).since(ProtocolVersion::V2),
+        argument("title", ArgumentKind::Text),
+    ],
+    "A single feed entry",
+);
+const END_LIST: ResponseDescriptor = response(code::END_LIST, "EndList", &[], "End of a list");
+const ACK_SUBSCRIBE: ResponseDescriptor = response(
+    code::ACK_SUBSCRIBE,
+    "AckSubscribe",
+    &[IDEMPOTENCY_KEY],
+    "Subscribed to the feed",
+);
+const ACK_UNSUBSCRIBE: ResponseDescriptor = response(
+    code::ACK_UNSUBSCRIBE,
+    "AckUnsubscribe",
+    KEYED_REVISION,
+    "Unsubscribed from the feed",
+);
+const ACK_MARK_READ: ResponseDescriptor = response(
+    code::ACK_MARK_READ,
+    "AckMarkRead",
+    KEYED_REVISION,
+    "Entry marked as read",
+);
+const PONG: ResponseDescriptor = response(code::PONG, "Pong", &[], "Reply to a Ping");
+const SERVER_CAPABILITIES: ResponseDescriptor = response(
+    code::CAPABILITIES,
+    "Capabilities",
+    CAPABILITY_LIST,
+    "The server's capabilities",
+);
+const QUOTA: ResponseDescriptor = response(
+    code::QUOTA,
+    "Quota",
+    &[
+        argument("max_feeds", ArgumentKind::Integer),
+        argument("max_entries", ArgumentKind::Integer),
+        argument("feeds", ArgumentKind::Integer),
+        argument("entries", ArgumentKind::Integer),
+    ],
+    "A user's quota and usage",
+);
+const ACK_SHARE: ResponseDescriptor = response(code::ACK_SHARE, "AckShare", &[], "Tag shared");
+const ACK_UNSHARE: ResponseDescriptor =
+    response(code::ACK_UNSHARE, "AckUnshare", &[], "Tag no longer shared");
+const START_SHARE_LIST: ResponseDescriptor = response(
+    code::START_SHARE_LIST,
+    "StartShareList",
+    &[],
+    "Beginning of a list of shares",
+);
+const SHARE_ITEM: ResponseDescriptor = response(
+    code::SHARE,
+    "Share",
+    &[
+        argument("tag", ArgumentKind::Token),
+        argument("owner", ArgumentKind::Token),
+        argument("member", ArgumentKind::Token),
+    ],
+    "A single shared tag",
+);
+const ACK_SET_ORDER: ResponseDescriptor = response(
+    code::ACK_SET_ORDER,
+    "AckSetOrder",
+    &[],
+    "Subscription position set",
+);
+const FEED_STATUS_ITEM: ResponseDescriptor = response(
+    code::FEED_STATUS,
+    "FeedStatus",
+    &[
+        argument("feed_id", ArgumentKind::Integer),
+        argument("interval", ArgumentKind::Integer),
+        argument("last_fetched", ArgumentKind::Integer),
+        argument("etag", ArgumentKind::Token),
+        argument("last_modified", ArgumentKind::Integer),
+        argument("failures", ArgumentKind::Integer),
+        argument("failing_since", ArgumentKind::Integer),
+        argument("next_retry", ArgumentKind::Integer),
+    ],
+    "How a feed is being refreshed",
+);
+const ACK_SET_INTERVAL: ResponseDescriptor = response(
+    code::ACK_SET_INTERVAL,
+    "AckSetInterval",
+    &[],
+    "Refresh interval set",
+);
+const ACK_ADD_WEBHOOK: ResponseDescriptor = response(
+    code::ACK_ADD_WEBHOOK,
+    "AckAddWebhook",
+    ID,
+    "Webhook registered",
+);
+const RESOURCE_NOT_FOUND: ResponseDescriptor = response(
+    code::RESOURCE_NOT_FOUND,
+    "ResourceNotFound",
+    MESSAGE,
+    "The specified resource was not found",
+);
+const BAD_COMMAND: ResponseDescriptor = response(
+    code::BAD_COMMAND,
+    "BadCommand",
+    MESSAGE,
+    "The command sent was not valid",
+);
+const NEED_USER: ResponseDescriptor = response(
+    code::NEED_USER,
+    "NeedUser",
+    MESSAGE,
+    "The command requires a selected user",
+);
+const RATE_LIMITED: ResponseDescriptor = response(
+    code::RATE_LIMITED,
+    "RateLimited",
+    MESSAGE,
+    "Too many commands, slow down before retrying",
+);
+const FORBIDDEN: ResponseDescriptor = response(
+    code::FORBIDDEN,
+    "Forbidden",
+    MESSAGE,
+    "The selected user may not issue the command",
+);
+const CONFLICT: ResponseDescriptor = response(
+    code::CONFLICT,
+    "Conflict",
+    MESSAGE,
+    "The resource changed since the expected revision",
+);
+const INTERNAL_ERROR: ResponseDescriptor = response(
+    code::INTERNAL_ERROR,
+    "InternalError",
+    MESSAGE,
+    "The server hit an internal problem",
+);
+const SHUTTING_DOWN: ResponseDescriptor = response(
+    code::SHUTTING_DOWN,
+    "ShuttingDown",
+    MESSAGE,
+    "The server is going down, reconnect later",
+);
+const OVERLOADED: ResponseDescriptor = response(
+    code::OVERLOADED,
+    "Overloaded",
+    MESSAGE,
+    "The server is too busy, retry later",
+);
+const UPSTREAM_TIMEOUT: ResponseDescriptor = response(
+    code::UPSTREAM_TIMEOUT,
+    "UpstreamTimeout",
+    MESSAGE,
+    "A service the server depends on timed out, retry later",
+);
+const MAINTENANCE: ResponseDescriptor = response(
+    code::MAINTENANCE,
+    "Maintenance",
+    MESSAGE,
+    "The server is down for maintenance, retry later",
+);
+const START_WEBHOOK_LIST: ResponseDescriptor = response(
+    code::START_WEBHOOK_LIST,
+    "StartWebhookList",
+    &[],
+    "Beginning of a list of webhooks",
+);
+const WEBHOOK: ResponseDescriptor = response(
+    code::WEBHOOK,
+    "Webhook",
+    &[
+        argument("id", ArgumentKind::Integer),
+        argument("url", ArgumentKind::Token),
+    ],
+    "A single webhook",
+);
+const ACK_DELETE_WEBHOOK: ResponseDescriptor = response(
+    code::ACK_DELETE_WEBHOOK,
+    "AckDeleteWebhook",
+    &[],
+    "Webhook deleted",
+);
+const END_PAGE: ResponseDescriptor = response(
+    code::END_PAGE,
+    "EndPage",
+    &[argument("cursor", ArgumentKind::Token)],
+    "End of a page of a list, continued with AFTER <cursor>",
+);
+const ACK_MARK_ALL_READ: ResponseDescriptor = response(
+    code::ACK_MARK_ALL_READ,
+    "AckMarkAllRead",
+    &[argument("count", ArgumentKind::Integer)],
+    "Every unread entry marked as read",
+);
+const ACK_UNDO: ResponseDescriptor = response(
+    code::ACK_UNDO,
+    "AckUndo",
+    &[argument("command", ArgumentKind::Text)],
+    "The named command was reversed",
+);
+const START_CONTENT: ResponseDescriptor = response(
+    code::START_CONTENT,
+    "StartContent",
+    &[
+        argument("entry_id", ArgumentKind::Integer),
+        argument("format", ArgumentKind::Token),
+    ],
+    "Beginning of an entry's content",
+);
+const PAYLOAD_LINE: ResponseDescriptor = response(
+    code::PAYLOAD_LINE,
+    "PayloadLine",
+    &[argument("line", ArgumentKind::Text)],
+    "A single line of a document",
+);
+const START_SYNC: ResponseDescriptor = response(
+    code::START_SYNC,
+    "StartSync",
+    &[],
+    "Beginning of a list of changes",
+);
+const READ_STATE_CHANGE: ResponseDescriptor = response(
+    code::READ_STATE_CHANGE,
+    "ReadStateChange",
+    &[
+        argument("entry_id", ArgumentKind::Integer),
+        argument("read_at", ArgumentKind::Integer),
+    ],
+    "An entry was marked read, or unread again",
+);
+const UNSUBSCRIBED: ResponseDescriptor = response(
+    code::UNSUBSCRIBED,
+    "Unsubscribed",
+    &[argument("feed_id", ArgumentKind::Integer)],
+    "The user unsubscribed from a feed",
+);
+const END_SYNC: ResponseDescriptor = response(
+    code::END_SYNC,
+    "EndSync",
+    &[argument("token", ArgumentKind::Token)],
+    "End of a list of changes, with the next sync token",
+);
+const ACK_MARK_UNREAD: ResponseDescriptor = response(
+    code::ACK_MARK_UNREAD,
+    "AckMarkUnread",
+    &[argument("revision", ArgumentKind::Integer).optional()],
+    "Entry marked as unread",
+);
+const START_IMPORT: ResponseDescriptor = response(
+    code::START_IMPORT,
+    "StartImport",
+    &[],
+    "Beginning of a list of import results",
+);
+const IMPORT_RESULT: ResponseDescriptor = response(
+    code::IMPORT_RESULT,
+    "ImportResult",
+    &[
+        argument("url", ArgumentKind::Token),
+        argument("status", ArgumentKind::Token),
+        argument("detail", ArgumentKind::Text),
+    ],
+    "The feed id, or the error, for one imported URL",
+);
+const START_EXPORT: ResponseDescriptor = response(
+    code::START_EXPORT,
+    "StartExport",
+    &[],
+    "Beginning of a JSON Feed document",
+);
+const START_RAW_FEED: ResponseDescriptor = response(
+    code::START_RAW_FEED,
+    "StartRawFeed",
+    &[
+        argument("feed_id", ArgumentKind::Integer),
+        argument("media_type", ArgumentKind::Text).optional(),
+    ],
+    "Beginning of the document last fetched for a feed",
+);
+const COUNT_RESPONSE: ResponseDescriptor = response(
+    code::COUNT,
+    "Count",
+    &[argument("count", ArgumentKind::Integer)],
+    "The number of entries a list would hold",
+);
+const START_CATEGORY_LIST: ResponseDescriptor = response(
+    code::START_CATEGORY_LIST,
+    "StartCategoryList",
+    &[],
+    "Beginning of a list of categories",
+);
+const CATEGORY: ResponseDescriptor = response(
+    code::CATEGORY,
+    "Category",
+    &[
+        argument("slug", ArgumentKind::Token),
+        argument("name", ArgumentKind::Text),
+    ],
+    "A feed category known to the server",
+);
+const ACK_SAVE_SEARCH: ResponseDescriptor =
+    response(code::ACK_SAVE_SEARCH, "AckSaveSearch", &[], "Search saved");
+const START_SAVED_SEARCH_LIST: ResponseDescriptor = response(
+    code::START_SAVED_SEARCH_LIST,
+    "StartSavedSearchList",
+    &[],
+    "Beginning of a list of saved searches",
+);
+const SAVED_SEARCH: ResponseDescriptor = response(
+    code::SAVED_SEARCH,
+    "SavedSearch",
+    &[
+        argument("name", ArgumentKind::Token),
+        argument("query", ArgumentKind::Text),
+    ],
+    "A search saved by the current user",
+);
+const ACK_SESSION: ResponseDescriptor = response(
+    code::ACK_SESSION,
+    "AckSession",
+    &[],
+    "Session opened, selected or closed",
+);
+const RESUME_TOKEN_ISSUED: ResponseDescriptor = response(
+    code::RESUME_TOKEN,
+    "ResumeToken",
+    &[
+        argument("token", ArgumentKind::Token).secret(),
+        argument("expires", ArgumentKind::Integer),
+    ],
+    "A token resuming the session on a later connection",
+);
+
+const RESPONSES: &[ResponseDescriptor] = &[
+    ACK_USER,
+    START_SUBSCRIPTION_LIST,
+    SUBSCRIPTION,
+    START_ENTRY_LIST,
+    ENTRY,
+    END_LIST,
+    ACK_SUBSCRIBE,
+    ACK_UNSUBSCRIBE,
+    ACK_MARK_READ,
+    PONG,
+    SERVER_CAPABILITIES,
+    QUOTA,
+    ACK_SHARE,
+    ACK_UNSHARE,
+    START_SHARE_LIST,
+    SHARE_ITEM,
+    ACK_SET_ORDER,
+    FEED_STATUS_ITEM,
+    ACK_SET_INTERVAL,
+    ACK_ADD_WEBHOOK,
+    RESOURCE_NOT_FOUND,
+    BAD_COMMAND,
+    NEED_USER,
+    RATE_LIMITED,
+    FORBIDDEN,
+    CONFLICT,
+    INTERNAL_ERROR,
+    SHUTTING_DOWN,
+    OVERLOADED,
+    UPSTREAM_TIMEOUT,
+    MAINTENANCE,
+    START_WEBHOOK_LIST,
+    WEBHOOK,
+    ACK_DELETE_WEBHOOK,
+    END_PAGE,
+    ACK_MARK_ALL_READ,
+    ACK_UNDO,
+    START_CONTENT,
+    PAYLOAD_LINE,
+    START_SYNC,
+    READ_STATE_CHANGE,
+    UNSUBSCRIBED,
+    END_SYNC,
+    ACK_MARK_UNREAD,
+    START_IMPORT,
+    IMPORT_RESULT,
+    START_EXPORT,
+    START_RAW_FEED,
+    COUNT_RESPONSE,
+    START_CATEGORY_LIST,
+    CATEGORY,
+    ACK_SAVE_SEARCH,
+    START_SAVED_SEARCH_LIST,
+    SAVED_SEARCH,
+    ACK_SESSION,
+    RESUME_TOKEN_ISSUED,
+];
+
+impl Command {
+    /// Descriptions of every command, in protocol order
+    pub fn descriptors() -> &'static [CommandDescriptor] {
+        COMMANDS
+    }
+
+    /// Look up the description of a command by its verb or
+    /// one of its aliases
+    pub fn descriptor_for(verb: &str) -> Option<&'static CommandDescriptor> {
+        COMMANDS
+            .iter()
+            .find(|descriptor| descriptor.verb == verb || descriptor.aliases.contains(&verb))
+    }
+
+    /// The description of this command
+    pub fn descriptor(&self) -> &'static CommandDescriptor {
+        match self {
+            Command::User { .. } => &USER,
+            Command::ListSubscriptions => &LIST_SUBSCRIPTIONS,
+            Command::Subscribe { .. } => &SUBSCRIBE,
+            Command::Unsubscribe { .. } => &UNSUBSCRIBE,
+            Command::ListUnread { .. } => &LIST_UNREAD,
+            Command::MarkRead { .. } => &MARK_READ,
+            Command::MarkUnread { .. } => &MARK_UNREAD,
+            Command::MarkAllRead => &MARK_ALL_READ,
+            Command::GetContent { .. } => &GET_CONTENT,
+            Command::Export { .. } => &EXPORT,
+            Command::GetRawFeed { .. } => &GET_RAW_FEED,
+            Command::Import => &IMPORT,
+            Command::Undo => &UNDO,
+            Command::Sync { .. } => &SYNC,
+            Command::ListRead { .. } => &LIST_READ,
+            Command::Ping => &PING,
+            Command::Capabilities(_) => &CAPABILITIES,
+            Command::SetQuota { .. } => &SET_QUOTA,
+            Command::GetQuota { .. } => &GET_QUOTA,
+            Command::Share { .. } => &SHARE,
+            Command::Unshare { .. } => &UNSHARE,
+            Command::ListShares => &LIST_SHARES,
+            Command::ListCategories => &LIST_CATEGORIES,
+            Command::SetOrder { .. } => &SET_ORDER,
+            Command::SetInterval { .. } => &SET_INTERVAL,
+            Command::FeedStatus { .. } => &FEED_STATUS,
+            Command::AddWebhook { .. } => &ADD_WEBHOOK,
+            Command::ListWebhooks => &LIST_WEBHOOKS,
+            Command::DeleteWebhook { .. } => &DELETE_WEBHOOK,
+            Command::Search { .. } => &SEARCH,
+            Command::SaveSearch { .. } => &SAVE_SEARCH,
+            Command::ListSavedSearches => &LIST_SAVED_SEARCHES,
+            Command::RunSearch { .. } => &RUN_SEARCH,
+            Command::Session { .. } => &SESSION,
+            Command::Auth(_) => &AUTH,
+            Command::ResumeToken => &RESUME_TOKEN,
+            Command::Resume { .. } => &RESUME,
+        }
+    }
+}
+
+impl Response {
+    /// Descriptions of every response, in code order
+    pub fn descriptors() -> &'static [ResponseDescriptor] {
+        RESPONSES
+    }
+
+    /// Look up the description of a response by its code
+    ///
+    /// Legacy codes still accepted when parsing resolve to the
+    /// response that replaced them.
+    pub fn descriptor_for(code: u8) -> Option<&'static ResponseDescriptor> {
+        let code = match code {
+            code::INTERNAL_ERROR_LEGACY => code::INTERNAL_ERROR,
+            code => code,
+        };
+
+        RESPONSES.iter().find(|descriptor| descriptor.code == code)
+    }
+
+    /// The description of this response
+    pub fn descriptor(&self) -> &'static ResponseDescriptor {
+        match self {
+            Response::AckUser { .. } => &ACK_USER,
+            Response::StartSubscriptionList => &START_SUBSCRIPTION_LIST,
+            Response::Subscription(_) => &SUBSCRIPTION,
+            Response::StartEntryList => &START_ENTRY_LIST,
+            Response::Entry(_) => &ENTRY,
+            Response::EndList => &END_LIST,
+            Response::AckSubscribe { .. } => &ACK_SUBSCRIBE,
+            Response::AckUnsubscribe { .. } => &ACK_UNSUBSCRIBE,
+            Response::AckMarkRead { .. } => &ACK_MARK_READ,
+            Response::Pong => &PONG,
+            Response::Capabilities(_) => &SERVER_CAPABILITIES,
+            Response::Quota(_) => &QUOTA,
+            Response::AckShare => &ACK_SHARE,
+            Response::AckUnshare => &ACK_UNSHARE,
+            Response::StartShareList => &START_SHARE_LIST,
+            Response::Share(_) => &SHARE_ITEM,
+            Response::AckSetOrder => &ACK_SET_ORDER,
+            Response::FeedStatus(_) => &FEED_STATUS_ITEM,
+            Response::AckSetInterval => &ACK_SET_INTERVAL,
+            Response::AckAddWebhook { .. } => &ACK_ADD_WEBHOOK,
+            Response::StartWebhookList => &START_WEBHOOK_LIST,
+            Response::Webhook(_) => &WEBHOOK,
+            Response::AckDeleteWebhook => &ACK_DELETE_WEBHOOK,
+            Response::EndPage(_) => &END_PAGE,
+            Response::AckMarkAllRead { .. } => &ACK_MARK_ALL_READ,
+            Response::AckUndo(_) => &ACK_UNDO,
+            Response::StartContent { .. } => &START_CONTENT,
+            Response::PayloadLine(_) => &PAYLOAD_LINE,
+            Response::StartSync => &START_SYNC,
+            Response::ReadStateChange { .. } => &READ_STATE_CHANGE,
+            Response::Unsubscribed { .. } => &UNSUBSCRIBED,
+            Response::EndSync(_) => &END_SYNC,
+            Response::AckMarkUnread { .. } => &ACK_MARK_UNREAD,
+            Response::StartImport => &START_IMPORT,
+            Response::ImportResult(_) => &IMPORT_RESULT,
+            Response::StartExport => &START_EXPORT,
+            Response::StartRawFeed { .. } => &START_RAW_FEED,
+            Response::Count(_) => &COUNT_RESPONSE,
+            Response::StartCategoryList => &START_CATEGORY_LIST,
+            Response::Category(_) => &CATEGORY,
+            Response::AckSaveSearch => &ACK_SAVE_SEARCH,
+            Response::StartSavedSearchList => &START_SAVED_SEARCH_LIST,
+            Response::SavedSearch(_) => &SAVED_SEARCH,
+            Response::AckSession => &ACK_SESSION,
+            Response::ResumeToken { .. } => &RESUME_TOKEN_ISSUED,
+            Response::ResourceNotFound(_) => &RESOURCE_NOT_FOUND,
+            Response::BadCommand(_) => &BAD_COMMAND,
+            Response::NeedUser(_) => &NEED_USER,
+            Response::RateLimited(_) => &RATE_LIMITED,
+            Response::Forbidden(_) => &FORBIDDEN,
+            Response::Conflict(_) => &CONFLICT,
+            Response::InternalError(_) => &INTERNAL_ERROR,
+            Response::ShuttingDown(_) => &SHUTTING_DOWN,
+            Response::Overloaded(_) => &OVERLOADED,
+            Response::UpstreamTimeout(_) => &UPSTREAM_TIMEOUT,
+            Response::Maintenance(_) => &MAINTENANCE,
+        }
+    }
+}
+
+fn write_arguments(f: &mut fmt::Formatter, arguments: &[ArgumentDescriptor]) -> fmt::Result {
+    for argument in arguments {
+        let value = match (argument.kind, argument.keyword) {
+            (ArgumentKind::Literal, _) => argument.name.to_string(),
+            (_, Some(keyword)) => format!("{} <{}>", keyword, argument.name),
+            (_, None) => format!("<{}>", argument.name),
+        };
+
+        if argument.optional {
+            write!(f, " [{}]", value)?;
+        } else {
+            write!(f, " {}", value)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Usage line, such as `MARKREAD <id>`
+impl fmt::Display for CommandDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.verb)?;
+        write_arguments(f, self.arguments)
+    }
+}
+
+/// Usage line, such as `20 <id>`
+impl fmt::Display for ResponseDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.code)?;
+        write_arguments(f, self.arguments)
+    }
+}
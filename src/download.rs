@@ -0,0 +1,312 @@
+//! Downloading feeds with reqwest
+//!
+//! With the `fetch` feature, Downloader makes the requests a
+//! RefreshScheduler hands out. It sends each Refresh's
+//! conditional headers, classifies the answer into the
+//! FetchOutcome `RefreshScheduler::complete` records, and parses
+//! a fetched document into the items `Storage::store_entries`
+//! takes. When `fetch` fails, complete the refresh with
+//! `FetchOutcome::Failed`, so the feed backs off.
+//!
+//! Downloads are async and need a Tokio runtime, as reqwest
+//! does.
+
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::feed::{self, FeedError, FeedItem};
+use crate::{FetchOutcome, Refresh};
+
+/// Largest feed document downloaded unless configured
+pub const DEFAULT_MAX_FEED_SIZE: usize = 4 * 1024 * 1024;
+
+/// How long a download may take unless configured
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+const USER_AGENT: &str = concat!("seymour-protocol/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug, Error)]
+pub enum DownloadError {
+    #[error("request failed: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("server answered {0}")]
+    Status(u16),
+    #[error("document is larger than {0} bytes")]
+    TooLarge(usize),
+    #[error(transparent)]
+    Feed(#[from] FeedError),
+}
+
+/// A feed refreshed by a Downloader
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fetched {
+    /// For `RefreshScheduler::complete`
+    pub outcome: FetchOutcome,
+    /// The document's items, none if it was not modified
+    pub items: Vec<FeedItem>,
+}
+
+/// Downloads feeds over HTTP
+#[derive(Debug, Clone)]
+pub struct Downloader {
+    client: reqwest::Client,
+    max_size: usize,
+}
+
+impl Downloader {
+    /// A downloader with its own client, giving up on requests
+    /// after 30 seconds
+    pub fn new() -> Result<Downloader, DownloadError> {
+        let client = reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(TIMEOUT)
+            .build()?;
+
+        Ok(Downloader::with_client(client))
+    }
+
+    /// A downloader making requests with `client`
+    pub fn with_client(client: reqwest::Client) -> Downloader {
+        Downloader {
+            client,
+            max_size: DEFAULT_MAX_FEED_SIZE,
+        }
+    }
+
+    /// Refuse documents larger than `bytes`
+    pub fn with_max_size(mut self, bytes: usize) -> Downloader {
+        self.max_size = bytes;
+        self
+    }
+
+    /// Make a refresh
+    ///
+    /// Relative links in the document are resolved against the
+    /// URL it was fetched from, after any redirects.
+    pub async fn fetch(&self, refresh: &Refresh) -> Result<Fetched, DownloadError> {
+        let mut request = self.client.get(&refresh.url);
+
+        for (name, value) in &refresh.headers {
+            request = request.header(*name, value);
+        }
+
+        let mut response = request.send().await?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+        let outcome = FetchOutcome::from_response(
+            response.status().as_u16(),
+            header(reqwest::header::ETAG),
+            header(reqwest::header::LAST_MODIFIED),
+        );
+
+        match outcome {
+            FetchOutcome::Modified { .. } => {}
+            FetchOutcome::NotModified => {
+                return Ok(Fetched {
+                    outcome,
+                    items: Vec::new(),
+                })
+            }
+            FetchOutcome::Failed => return Err(DownloadError::Status(response.status().as_u16())),
+        }
+
+        if response
+            .content_length()
+            .is_some_and(|length| length > self.max_size as u64)
+        {
+            return Err(DownloadError::TooLarge(self.max_size));
+        }
+
+        let url = response.url().to_string();
+        let mut body = Vec::new();
+
+        while let Some(chunk) = response.chunk().await? {
+            if body.len().saturating_add(chunk.len()) > self.max_size {
+                return Err(DownloadError::TooLarge(self.max_size));
+            }
+
+            body.extend_from_slice(&chunk);
+        }
+
+        let items = feed::parse(&String::from_utf8_lossy(&body), &url)?;
+
+        Ok(Fetched { outcome, items })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const RSS: &str = "<rss><channel>\
+                       <item><title>One</title><link>/1</link><guid>1</guid></item>\
+                       </channel></rss>";
+
+    /// Answer one request with `response`, returning the
+    /// request's lines
+    fn serve_once(response: String) -> (String, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/feed.xml", listener.local_addr().unwrap());
+
+        let server = thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(socket.try_clone().unwrap());
+            let mut request = Vec::new();
+
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+
+                if line.trim_end().is_empty() {
+                    break;
+                }
+
+                request.push(line.trim_end().to_lowercase());
+            }
+
+            (&socket).write_all(response.as_bytes()).unwrap();
+            request
+        });
+
+        (url, server)
+    }
+
+    fn fetch(downloader: Downloader, refresh: &Refresh) -> Result<Fetched, DownloadError> {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(downloader.fetch(refresh))
+    }
+
+    fn downloader() -> Downloader {
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+
+        Downloader::with_client(client)
+    }
+
+    fn refresh(url: String, headers: Vec<(&'static str, String)>) -> Refresh {
+        Refresh {
+            feed_id: 1,
+            url,
+            headers,
+        }
+    }
+
+    #[test]
+    fn fetches_and_parses_feeds() {
+        let (url, server) = serve_once(format!(
+            "HTTP/1.1 200 OK\r\n\
+             ETag: \"v2\"\r\n\
+             Last-Modified: Sun, 06 Nov 1994 08:49:37 GMT\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            RSS.len(),
+            RSS
+        ));
+        let headers = vec![
+            ("If-None-Match", "\"v1\"".to_string()),
+            (
+                "If-Modified-Since",
+                "Thu, 01 Jan 1970 00:00:00 GMT".to_string(),
+            ),
+        ];
+
+        let fetched = fetch(downloader(), &refresh(url.clone(), headers)).unwrap();
+        let request = server.join().unwrap();
+
+        assert!(request.contains(&"if-none-match: \"v1\"".to_string()));
+        assert!(request.contains(&"if-modified-since: thu, 01 jan 1970 00:00:00 gmt".to_string()));
+        assert_eq!(
+            fetched.outcome,
+            FetchOutcome::Modified {
+                etag: Some("\"v2\"".to_string()),
+                last_modified: Some(784_111_777),
+            }
+        );
+        assert_eq!(
+            fetched.items,
+            [FeedItem {
+                guid: Some("1".to_string()),
+                title: "One".to_string(),
+                url: url.replace("/feed.xml", "/1"),
+                published: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_unmodified_feeds() {
+        let (url, server) =
+            serve_once("HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n".to_string());
+
+        let fetched = fetch(
+            downloader(),
+            &refresh(url, vec![("If-None-Match", "\"v1\"".to_string())]),
+        );
+        server.join().unwrap();
+
+        assert_eq!(
+            fetched.unwrap(),
+            Fetched {
+                outcome: FetchOutcome::NotModified,
+                items: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn fails_on_error_statuses() {
+        let (url, server) = serve_once(
+            "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_string(),
+        );
+
+        let fetched = fetch(downloader(), &refresh(url, Vec::new()));
+        server.join().unwrap();
+
+        assert!(matches!(fetched, Err(DownloadError::Status(503))));
+    }
+
+    #[test]
+    fn refuses_large_documents() {
+        // Without a Content-Length, the limit applies as the body
+        // arrives
+        let (url, server) = serve_once(format!(
+            "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n{}",
+            RSS
+        ));
+
+        let fetched = fetch(downloader().with_max_size(16), &refresh(url, Vec::new()));
+        server.join().unwrap();
+
+        assert!(matches!(fetched, Err(DownloadError::TooLarge(16))));
+    }
+
+    #[test]
+    fn fails_on_documents_that_are_not_feeds() {
+        let body = r#"{"version": "1"}"#;
+        let (url, server) = serve_once(format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        ));
+
+        let fetched = fetch(downloader(), &refresh(url, Vec::new()));
+        server.join().unwrap();
+
+        assert!(matches!(
+            fetched,
+            Err(DownloadError::Feed(FeedError::UnknownFormat))
+        ));
+    }
+}
@@ -0,0 +1,207 @@
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::catalog::ErrorMessage;
+use crate::{ParseMessageError, Response, Role};
+
+/// An error reported by the server
+///
+/// Converted from error responses so client code can propagate
+/// them with `?` and match on structured variants.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ServerError {
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("bad command: {0}")]
+    BadCommand(String),
+    #[error("no user selected: {0}")]
+    NeedUser(String),
+    #[error("rate limited: {0}")]
+    RateLimited(String),
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("internal server error: {0}")]
+    Internal(String),
+    #[error("server shutting down: {0}")]
+    ShuttingDown(String),
+    #[error("server overloaded: {0}")]
+    Overloaded(String),
+    #[error("upstream timeout: {0}")]
+    UpstreamTimeout(String),
+    #[error("server under maintenance: {0}")]
+    Maintenance(String),
+}
+
+impl ServerError {
+    /// The message sent by the server
+    pub fn message(&self) -> &str {
+        match self {
+            ServerError::NotFound(message)
+            | ServerError::BadCommand(message)
+            | ServerError::NeedUser(message)
+            | ServerError::RateLimited(message)
+            | ServerError::Forbidden(message)
+            | ServerError::Conflict(message)
+            | ServerError::Internal(message)
+            | ServerError::ShuttingDown(message)
+            | ServerError::Overloaded(message)
+            | ServerError::UpstreamTimeout(message)
+            | ServerError::Maintenance(message) => message,
+        }
+    }
+
+    /// The key and parameters of the message, if the server
+    /// sent them
+    pub fn detail(&self) -> Option<ErrorMessage> {
+        ErrorMessage::parse(self.message())
+    }
+
+    /// How long the server asked to wait before retrying, from
+    /// the `retry_after` parameter of a RateLimited message
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ServerError::RateLimited(_) => self
+                .detail()?
+                .get("retry_after")?
+                .parse()
+                .ok()
+                .map(Duration::from_secs),
+            _ => None,
+        }
+    }
+
+    /// Whether the command may succeed if retried later
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ServerError::RateLimited(_)
+                | ServerError::ShuttingDown(_)
+                | ServerError::Overloaded(_)
+                | ServerError::UpstreamTimeout(_)
+                | ServerError::Maintenance(_)
+        )
+    }
+}
+
+/// Fails with the response itself if it is not an error
+impl TryFrom<Response> for ServerError {
+    type Error = Response;
+
+    fn try_from(response: Response) -> Result<Self, Self::Error> {
+        match response {
+            Response::ResourceNotFound(message) => Ok(ServerError::NotFound(message)),
+            Response::BadCommand(message) => Ok(ServerError::BadCommand(message)),
+            Response::NeedUser(message) => Ok(ServerError::NeedUser(message)),
+            Response::RateLimited(message) => Ok(ServerError::RateLimited(message)),
+            Response::Forbidden(message) => Ok(ServerError::Forbidden(message)),
+            Response::Conflict(message) => Ok(ServerError::Conflict(message)),
+            Response::InternalError(message) => Ok(ServerError::Internal(message)),
+            Response::ShuttingDown(message) => Ok(ServerError::ShuttingDown(message)),
+            Response::Overloaded(message) => Ok(ServerError::Overloaded(message)),
+            Response::UpstreamTimeout(message) => Ok(ServerError::UpstreamTimeout(message)),
+            Response::Maintenance(message) => Ok(ServerError::Maintenance(message)),
+            response => Err(response),
+        }
+    }
+}
+
+impl From<ServerError> for Response {
+    fn from(error: ServerError) -> Response {
+        match error {
+            ServerError::NotFound(message) => Response::ResourceNotFound(message),
+            ServerError::BadCommand(message) => Response::BadCommand(message),
+            ServerError::NeedUser(message) => Response::NeedUser(message),
+            ServerError::RateLimited(message) => Response::RateLimited(message),
+            ServerError::Forbidden(message) => Response::Forbidden(message),
+            ServerError::Conflict(message) => Response::Conflict(message),
+            ServerError::Internal(message) => Response::InternalError(message),
+            ServerError::ShuttingDown(message) => Response::ShuttingDown(message),
+            ServerError::Overloaded(message) => Response::Overloaded(message),
+            ServerError::UpstreamTimeout(message) => Response::UpstreamTimeout(message),
+            ServerError::Maintenance(message) => Response::Maintenance(message),
+        }
+    }
+}
+
+/// An error from a server's command handler
+///
+/// Handlers return it with `?` and the server sends the
+/// response it converts to. Storage errors become a generic
+/// InternalError so their details stay in the server's logs.
+#[derive(Debug, Error)]
+pub enum HandlerError {
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error(transparent)]
+    BadCommand(#[from] ParseMessageError),
+    #[error("no user selected")]
+    NeedUser,
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+    /// The user lacks the role a command needs
+    #[error("permission denied: {0} role required")]
+    PermissionDenied(Role),
+    #[error("conflict: {0}")]
+    Conflict(String),
+    #[error("rate limited: {0}")]
+    RateLimited(String),
+    #[error("upstream timeout: {0}")]
+    UpstreamTimeout(String),
+    /// Too many failed logins; retry after the duration
+    #[error("too many failed attempts, retry after {0:?}")]
+    TooManyAttempts(Duration),
+    #[error("storage error: {0}")]
+    Storage(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// Any other error response
+    #[error(transparent)]
+    Server(#[from] ServerError),
+}
+
+impl HandlerError {
+    pub fn storage<E>(error: E) -> HandlerError
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        HandlerError::Storage(Box::new(error))
+    }
+}
+
+impl From<HandlerError> for Response {
+    fn from(error: HandlerError) -> Response {
+        match error {
+            HandlerError::NotFound(message) => Response::ResourceNotFound(message),
+            HandlerError::BadCommand(error) => Response::from(error),
+            HandlerError::NeedUser => Response::NeedUser("no user selected".to_string()),
+            HandlerError::Forbidden(message) => Response::Forbidden(message),
+            HandlerError::PermissionDenied(role) => Response::Forbidden(
+                ErrorMessage::new("permission-denied", "permission denied")
+                    .param("role", role)
+                    .to_string(),
+            ),
+            HandlerError::Conflict(message) => Response::Conflict(message),
+            HandlerError::RateLimited(message) => Response::RateLimited(message),
+            HandlerError::UpstreamTimeout(message) => Response::UpstreamTimeout(message),
+            HandlerError::TooManyAttempts(retry_after) => Response::RateLimited(
+                ErrorMessage::new("too-many-attempts", "too many failed attempts")
+                    .param("retry_after", retry_after.as_secs().saturating_add(1))
+                    .to_string(),
+            ),
+            HandlerError::Storage(_) => Response::InternalError("internal error".to_string()),
+            HandlerError::Server(error) => Response::from(error),
+        }
+    }
+}
+
+impl Response {
+    /// Turn error responses into a ServerError
+    pub fn into_result(self) -> Result<Response, ServerError> {
+        match ServerError::try_from(self) {
+            Ok(error) => Err(error),
+            Err(response) => Ok(response),
+        }
+    }
+}
@@ -0,0 +1,228 @@
+//! Escaping of untrusted fields at encoding time
+//!
+//! Titles, messages and URLs often come straight from feeds. A
+//! line break inside one would end the line early and let the
+//! rest be read as a response of its own, so every such field
+//! goes through `Text` or `Token` when a line is encoded.
+//!
+//! Free text loses its control characters. Single-word fields
+//! are percent-encoded instead, `%` included, and parsing
+//! decodes them with `decode_token`, so they arrive exactly as
+//! they were sent.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use thiserror::Error;
+
+/// A field that cannot be sent unchanged, reported by
+/// `Response::encode_strict`
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("{field} contains characters that cannot be sent on a protocol line")]
+pub struct UnencodableField {
+    pub field: &'static str,
+}
+
+/// Whether free text can be sent unchanged
+pub(crate) fn is_clean_text(text: &str) -> bool {
+    !text.chars().any(char::is_control)
+}
+
+/// Whether a single-word argument can be sent unchanged
+pub(crate) fn is_clean_token(token: &str) -> bool {
+    !token.chars().any(|c| c.is_control() || c.is_whitespace())
+}
+
+/// Free text as `Text` sends it
+pub(crate) fn clean_text(text: String) -> String {
+    if is_clean_text(&text) {
+        text
+    } else {
+        Text(&text).to_string()
+    }
+}
+
+/// Free text with control characters, line breaks included,
+/// replaced by spaces
+pub(crate) struct Text<'a>(pub &'a str);
+
+impl<'a> fmt::Display for Text<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if is_clean_text(self.0) {
+            return write!(f, "{}", self.0);
+        }
+
+        for c in self.0.chars() {
+            write!(f, "{}", if c.is_control() { ' ' } else { c })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Write `value` with the characters `escape` picks
+/// percent-encoded
+fn percent_encode(
+    f: &mut fmt::Formatter,
+    value: &str,
+    escape: impl Fn(char) -> bool,
+) -> fmt::Result {
+    if !value.chars().any(&escape) {
+        return write!(f, "{}", value);
+    }
+
+    let mut buffer = [0; 4];
+
+    for c in value.chars() {
+        if escape(c) {
+            for byte in c.encode_utf8(&mut buffer).bytes() {
+                write!(f, "%{:02X}", byte)?;
+            }
+        } else {
+            write!(f, "{}", c)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A single-word argument with `%`, whitespace and control
+/// characters percent-encoded, as `decode_token` reads it back
+pub(crate) struct Token<'a>(pub &'a str);
+
+impl<'a> fmt::Display for Token<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        percent_encode(f, self.0, |c| {
+            c == '%' || c.is_control() || c.is_whitespace()
+        })
+    }
+}
+
+/// An optional single-word argument, sent as `-` when absent
+///
+/// A present `-` is escaped so it cannot be mistaken for an
+/// absent value.
+pub(crate) struct OptionalToken<'a>(pub &'a Option<String>);
+
+impl<'a> fmt::Display for OptionalToken<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.as_deref() {
+            Some("-") => write!(f, "%2D"),
+            Some(token) => write!(f, "{}", Token(token)),
+            None => write!(f, "-"),
+        }
+    }
+}
+
+/// A URL with whitespace and control characters
+/// percent-encoded
+///
+/// Unlike Token, `%` is left alone: in a URL it already starts
+/// an escape, so readers of the URL decode it themselves.
+pub(crate) struct Url<'a>(pub &'a str);
+
+impl<'a> fmt::Display for Url<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        percent_encode(f, self.0, |c| c.is_control() || c.is_whitespace())
+    }
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte.wrapping_sub(b'0')),
+        b'a'..=b'f' => Some(byte.wrapping_sub(b'a').wrapping_add(10)),
+        b'A'..=b'F' => Some(byte.wrapping_sub(b'A').wrapping_add(10)),
+        _ => None,
+    }
+}
+
+/// A single-word argument as it was before `Token` encoded it
+///
+/// Malformed escapes are kept as they are, and bytes that do
+/// not decode to UTF-8 are replaced. Never panics.
+pub(crate) fn decode_token(token: &str) -> Cow<'_, str> {
+    if !token.contains('%') {
+        return Cow::Borrowed(token);
+    }
+
+    let bytes = token.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut rest = bytes;
+
+    while let Some((&byte, after)) = rest.split_first() {
+        let escaped = match (byte, after) {
+            (b'%', [high, low, ..]) => hex_value(*high)
+                .zip(hex_value(*low))
+                .map(|(high, low)| high << 4 | low),
+            _ => None,
+        };
+
+        match escaped {
+            Some(value) => {
+                decoded.push(value);
+                rest = after.get(2..).unwrap_or_default();
+            }
+            None => {
+                decoded.push(byte);
+                rest = after;
+            }
+        }
+    }
+
+    match String::from_utf8(decoded) {
+        Ok(decoded) => Cow::Owned(decoded),
+        Err(error) => Cow::Owned(String::from_utf8_lossy(error.as_bytes()).into_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_round_trip() {
+        for token in [
+            "gemini://example.com/feed",
+            "a b",
+            "a%20b",
+            "100%",
+            "%",
+            "%zz",
+            "line\nbreak",
+            "tab\there",
+            "é\u{2028}🦀",
+            "-",
+        ] {
+            let encoded = Token(token).to_string();
+
+            assert!(is_clean_token(&encoded), "{:?}", encoded);
+            assert_eq!(decode_token(&encoded), token, "{:?}", encoded);
+        }
+
+        assert_ne!(Token("a%20b").to_string(), Token("a b").to_string());
+    }
+
+    #[test]
+    fn optional_tokens_keep_a_present_dash() {
+        assert_eq!(OptionalToken(&None).to_string(), "-");
+        assert_eq!(OptionalToken(&Some("-".to_string())).to_string(), "%2D");
+        assert_eq!(decode_token("%2D"), "-");
+    }
+
+    #[test]
+    fn decoding_keeps_malformed_escapes() {
+        assert_eq!(decode_token("100%"), "100%");
+        assert_eq!(decode_token("%4"), "%4");
+        assert_eq!(decode_token("%G0"), "%G0");
+        assert_eq!(decode_token("%FF"), "\u{fffd}");
+        assert!(matches!(decode_token("plain"), Cow::Borrowed("plain")));
+    }
+
+    #[test]
+    fn urls_keep_their_escapes() {
+        assert_eq!(
+            Url("gemini://a/b%20c d").to_string(),
+            "gemini://a/b%20c%20d"
+        );
+    }
+}
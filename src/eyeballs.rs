@@ -0,0 +1,109 @@
+//! Dual-stack connection attempts, after RFC 8305
+//!
+//! On networks with broken IPv6, trying a server's addresses one
+//! at a time stalls on each IPv6 address until its connection
+//! times out. HappyEyeballs orders the addresses so the families
+//! alternate and tells the client when to start each attempt,
+//! staggered by a short delay, so a working address wins soon
+//! whatever the family. The client opens the sockets and drops
+//! the planner once one connects.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// Delay between starting attempts recommended by RFC 8305
+pub const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Plans connection attempts to a server's addresses
+#[derive(Debug, Clone)]
+pub struct HappyEyeballs {
+    pending: VecDeque<SocketAddr>,
+    delay: Duration,
+    last_attempt: Option<Instant>,
+    in_flight: usize,
+}
+
+impl HappyEyeballs {
+    /// Plan attempts to `addresses`, in the order the resolver
+    /// returned them within each family, IPv6 first
+    pub fn new<I>(addresses: I) -> HappyEyeballs
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        let (v6, v4): (Vec<_>, Vec<_>) = addresses.into_iter().partition(SocketAddr::is_ipv6);
+        let mut pending = VecDeque::with_capacity(v6.len() + v4.len());
+        let mut v6 = v6.into_iter();
+        let mut v4 = v4.into_iter();
+
+        loop {
+            match (v6.next(), v4.next()) {
+                (None, None) => break,
+                (first, second) => pending.extend(first.into_iter().chain(second)),
+            }
+        }
+
+        HappyEyeballs {
+            pending,
+            delay: CONNECTION_ATTEMPT_DELAY,
+            last_attempt: None,
+            in_flight: 0,
+        }
+    }
+
+    /// Wait `delay` before starting another attempt while
+    /// earlier ones are in flight
+    pub fn with_attempt_delay(self, delay: Duration) -> HappyEyeballs {
+        HappyEyeballs { delay, ..self }
+    }
+
+    /// The next address to start connecting to, if one is due
+    ///
+    /// One is due when no attempt is in flight or the delay has
+    /// passed since the last one started.
+    pub fn next_attempt(&mut self, now: Instant) -> Option<SocketAddr> {
+        let due = match self.last_attempt {
+            Some(last_attempt) if self.in_flight > 0 => {
+                now.saturating_duration_since(last_attempt) >= self.delay
+            }
+            _ => true,
+        };
+
+        if !due {
+            return None;
+        }
+
+        let address = self.pending.pop_front()?;
+        self.last_attempt = Some(now);
+        self.in_flight += 1;
+
+        Some(address)
+    }
+
+    /// When the next attempt is due, or None if there is
+    /// nothing left to try
+    ///
+    /// Clients wait until then, or until an attempt finishes,
+    /// before calling `next_attempt` again.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.pending.is_empty() {
+            return None;
+        }
+
+        match self.last_attempt {
+            Some(last_attempt) if self.in_flight > 0 => Some(last_attempt + self.delay),
+            _ => Some(Instant::now()),
+        }
+    }
+
+    /// Record that an attempt failed, so the next one may start
+    /// at once
+    pub fn failed(&mut self) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+    }
+
+    /// Whether every attempt has been started and has failed
+    pub fn is_exhausted(&self) -> bool {
+        self.pending.is_empty() && self.in_flight == 0
+    }
+}
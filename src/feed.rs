@@ -0,0 +1,908 @@
+//! Parsing fetched feed documents into entries
+//!
+//! Servers hand `parse` the body of a fetched feed and store the
+//! items it returns. RSS 2.0, Atom, JSON Feed and Gemini feeds
+//! (gemtext link lines labelled with a date) are recognised from
+//! the document itself. Each item keeps the feed's stable id as
+//! its guid, its title with markup and line breaks removed, its
+//! URL resolved against the feed's, and its publication time.
+//!
+//! The XML reader only goes as far as feeds need: elements, text,
+//! CDATA and the predefined and numeric entities. Documents come
+//! from the network, so parsing never panics.
+
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+use thiserror::Error;
+
+use crate::escape::clean_text;
+use crate::fetcher::days_from_civil;
+use crate::gemtext::Link;
+use crate::json::Value;
+use crate::{Entry, Response};
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum FeedError {
+    #[error("document is not an RSS, Atom, JSON or Gemini feed")]
+    UnknownFormat,
+    #[error("invalid JSON Feed: {0}")]
+    InvalidJson(String),
+}
+
+/// One item of a feed document
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeedItem {
+    /// The feed's own id for the item, if it has one
+    pub guid: Option<String>,
+    pub title: String,
+    /// Absolute, resolved against the feed's URL
+    pub url: String,
+    /// Publication time in seconds since the Unix epoch
+    pub published: Option<i64>,
+}
+
+impl FeedItem {
+    /// The entry to store for the item, under ids the server
+    /// assigns
+    pub fn into_entry(self, id: i64, feed_id: i64, feed_url: &str) -> Option<Entry> {
+        match Response::entry(id, feed_id, feed_url, self.title, self.url) {
+            Response::Entry(entry) => Some(Entry {
+                guid: self.guid,
+                published: self.published,
+                ..entry
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a feed document fetched from `feed_url`
+///
+/// Items without a URL are skipped.
+pub fn parse(document: &str, feed_url: &str) -> Result<Vec<FeedItem>, FeedError> {
+    let document = document.trim_start_matches('\u{feff}').trim_start();
+
+    let items = if document.starts_with('{') {
+        parse_json(document)?
+    } else if document.starts_with('<') {
+        parse_xml(document)
+    } else {
+        parse_gemini(document)
+    };
+
+    Ok(items
+        .into_iter()
+        .filter(|item| !item.url.is_empty())
+        .map(|item| FeedItem {
+            title: tidy_title(&item.title),
+            url: resolve(feed_url, &item.url),
+            guid: item.guid.filter(|guid| !guid.trim().is_empty()),
+            ..item
+        })
+        .collect())
+}
+
+/// Resolve a link against the URL of the document it is in
+fn resolve(base: &str, link: &str) -> String {
+    let link = link.trim();
+
+    if link.contains("://") {
+        return link.to_string();
+    }
+
+    let (scheme, rest) = match base.split_once("://") {
+        Some(parts) => parts,
+        None => return link.to_string(),
+    };
+
+    if let Some(network) = link.strip_prefix("//") {
+        return format!("{}://{}", scheme, network);
+    }
+
+    let authority_end = rest.find(&['/', '?', '#'][..]).unwrap_or(rest.len());
+    let authority = rest.get(..authority_end).unwrap_or(rest);
+
+    if link.starts_with('/') {
+        return format!("{}://{}{}", scheme, authority, link);
+    }
+
+    let path = rest.get(authority_end..).unwrap_or_default();
+    let path = path.split(&['?', '#'][..]).next().unwrap_or_default();
+    let directory = path
+        .rfind('/')
+        .and_then(|end| path.get(..=end))
+        .unwrap_or("/");
+
+    format!("{}://{}{}{}", scheme, authority, directory, link)
+}
+
+/// Strip markup from a title and collapse its whitespace
+fn tidy_title(title: &str) -> String {
+    let mut text = String::with_capacity(title.len());
+    let mut chars = title.chars().peekable();
+    let mut in_tag = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '<' if chars
+                .peek()
+                .is_some_and(|next| next.is_ascii_alphabetic() || *next == '/') =>
+            {
+                in_tag = true
+            }
+            '>' if in_tag => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    let text = if text.contains('&') {
+        decode_entities(&text).into_owned()
+    } else {
+        text
+    };
+
+    clean_text(text.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// A time in RFC 3339 form, or a bare date, in seconds since the
+/// Unix epoch
+fn parse_rfc3339(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let date = value.get(..10)?;
+    let mut fields = date.splitn(3, '-');
+    let year = digits(fields.next()?, 4)?;
+    let month = digits(fields.next()?, 2)?;
+    let day = digits(fields.next()?, 2)?;
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    let days = days_from_civil(year, month, day);
+    let time = match value.get(10..) {
+        None | Some("") => return Some(days * 86_400),
+        Some(time) => time.strip_prefix(&['T', 't', ' '][..])?,
+    };
+
+    let hours = digits(time.get(0..2)?, 2)?;
+    let minutes = digits(time.get(3..5)?, 2)?;
+    let seconds = digits(time.get(6..8)?, 2)?;
+    let zone = time.get(8..)?;
+    let zone = zone.trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+
+    if hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    let offset = match zone {
+        "Z" | "z" => 0,
+        zone => numeric_offset(&zone.replace(':', ""))?,
+    };
+
+    Some(days * 86_400 + hours * 3600 + minutes * 60 + seconds - offset)
+}
+
+/// A time in the RFC 822 form of RSS, such as `Sun, 06 Nov 1994
+/// 08:49:37 GMT`, in seconds since the Unix epoch
+fn parse_rfc822(value: &str) -> Option<i64> {
+    let value = value.trim();
+    let value = match value.split_once(',') {
+        Some((_, rest)) => rest,
+        None => value,
+    };
+    let mut parts = value.split_whitespace();
+
+    let day = parts.next()?;
+    let day = digits(day, day.len().min(2))?;
+    let month = parts.next()?;
+    let month = MONTHS
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(month.get(..3).unwrap_or(month)))?;
+    let month = i64::try_from(month).ok()? + 1;
+    let year = parts.next()?;
+    let year = match year.len() {
+        4 => digits(year, 4)?,
+        2 => digits(year, 2).map(|year| if year < 50 { 2000 + year } else { 1900 + year })?,
+        _ => return None,
+    };
+
+    let mut time = parts.next()?.split(':');
+    let hours = digits(time.next()?, 2)?;
+    let minutes = digits(time.next()?, 2)?;
+    let seconds = match time.next() {
+        Some(seconds) => digits(seconds, 2)?,
+        None => 0,
+    };
+
+    let offset = match parts.next().unwrap_or("GMT") {
+        "GMT" | "UT" | "UTC" | "Z" => 0,
+        "EDT" => -4 * 3600,
+        "EST" | "CDT" => -5 * 3600,
+        "CST" | "MDT" => -6 * 3600,
+        "MST" | "PDT" => -7 * 3600,
+        "PST" => -8 * 3600,
+        zone => numeric_offset(zone)?,
+    };
+
+    if !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    Some(
+        days_from_civil(year, month, day) * 86_400 + hours * 3600 + minutes * 60 + seconds - offset,
+    )
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Exactly `length` ASCII digits
+fn digits(value: &str, length: usize) -> Option<i64> {
+    if value.len() != length || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+
+    value.parse().ok()
+}
+
+/// An offset such as `+0200` or `-0530`, in seconds
+fn numeric_offset(zone: &str) -> Option<i64> {
+    let (sign, zone) = match zone.get(..1)? {
+        "+" => (1, zone.get(1..)?),
+        "-" => (-1, zone.get(1..)?),
+        _ => return None,
+    };
+
+    let hours = digits(zone.get(..2)?, 2)?;
+    let minutes = digits(zone.get(2..)?, 2)?;
+
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+fn parse_json(document: &str) -> Result<Vec<FeedItem>, FeedError> {
+    let document =
+        Value::parse(document).map_err(|error| FeedError::InvalidJson(error.to_string()))?;
+
+    if !document
+        .get("version")
+        .and_then(Value::as_str)
+        .is_some_and(|version| version.starts_with("https://jsonfeed.org/version/"))
+    {
+        return Err(FeedError::UnknownFormat);
+    }
+
+    let items = document
+        .get("items")
+        .and_then(Value::as_array)
+        .unwrap_or_default();
+
+    Ok(items
+        .iter()
+        .map(|item| {
+            let text = |key: &str| item.get(key).and_then(Value::as_str).map(str::to_string);
+
+            FeedItem {
+                guid: text("id"),
+                title: text("title").unwrap_or_default(),
+                url: text("url")
+                    .or_else(|| text("external_url"))
+                    .unwrap_or_default(),
+                published: text("date_published")
+                    .or_else(|| text("date_modified"))
+                    .and_then(|date| parse_rfc3339(&date)),
+            }
+        })
+        .collect())
+}
+
+/// Link lines labelled `YYYY-MM-DD title`, per the Gemini
+/// subscription convention
+fn parse_gemini(document: &str) -> Vec<FeedItem> {
+    document
+        .lines()
+        .filter_map(Link::parse)
+        .filter_map(|link| {
+            let label = link.label?;
+            let date = label.get(..10)?;
+            let published = parse_rfc3339(date)?;
+            let title = label.get(10..).unwrap_or_default();
+            let title = title.trim_start_matches(&[' ', '\t', '-', ':'][..]);
+
+            Some(FeedItem {
+                guid: None,
+                title: title.to_string(),
+                url: link.url,
+                published: Some(published),
+            })
+        })
+        .collect()
+}
+
+/// Replace the predefined XML entities and character references
+fn decode_entities(text: &str) -> Cow<'_, str> {
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(rest.get(..start).unwrap_or_default());
+        let reference = rest.get(start..).unwrap_or_default();
+
+        let end = match reference.find(';').filter(|end| *end <= 10) {
+            Some(end) => end,
+            None => {
+                decoded.push('&');
+                rest = reference.get(1..).unwrap_or_default();
+                continue;
+            }
+        };
+
+        let name = reference.get(1..end).unwrap_or_default();
+        let character = match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            name => name
+                .strip_prefix("#x")
+                .or_else(|| name.strip_prefix("#X"))
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| name.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+
+        match character {
+            Some(character) => {
+                decoded.push(character);
+                rest = reference.get(end + 1..).unwrap_or_default();
+            }
+            None => {
+                decoded.push('&');
+                rest = reference.get(1..).unwrap_or_default();
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    Cow::Owned(decoded)
+}
+
+/// One piece of an XML document
+#[derive(Debug)]
+enum Token<'a> {
+    Open {
+        name: &'a str,
+        attributes: &'a str,
+        empty: bool,
+    },
+    Close(&'a str),
+    Text(Cow<'a, str>),
+}
+
+/// The name without its namespace prefix
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// Reads the tokens of an XML document, skipping declarations,
+/// comments and processing instructions
+struct Tokens<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        loop {
+            if self.rest.is_empty() {
+                return None;
+            }
+
+            if !self.rest.starts_with('<') {
+                let end = self.rest.find('<').unwrap_or(self.rest.len());
+                let text = self.rest.get(..end).unwrap_or_default();
+                self.rest = self.rest.get(end..).unwrap_or_default();
+
+                return Some(Token::Text(decode_entities(text)));
+            }
+
+            let skipped = [("<!--", "-->"), ("<?", "?>"), ("<![CDATA[", "]]>")]
+                .iter()
+                .find(|(open, _)| self.rest.starts_with(open));
+
+            if let Some((open, close)) = skipped {
+                let body = self.rest.get(open.len()..).unwrap_or_default();
+                let end = body.find(close).unwrap_or(body.len());
+                self.rest = body
+                    .get(end.saturating_add(close.len())..)
+                    .unwrap_or_default();
+
+                if *open == "<![CDATA[" {
+                    return Some(Token::Text(Cow::Borrowed(body.get(..end).unwrap_or(body))));
+                }
+
+                continue;
+            }
+
+            let end = match self.rest.find('>') {
+                Some(end) => end,
+                None => {
+                    self.rest = "";
+                    return None;
+                }
+            };
+            let tag = self.rest.get(1..end).unwrap_or_default();
+            self.rest = self.rest.get(end + 1..).unwrap_or_default();
+
+            if tag.starts_with('!') {
+                continue;
+            }
+
+            if let Some(name) = tag.strip_prefix('/') {
+                return Some(Token::Close(local_name(name.trim())));
+            }
+
+            let (tag, empty) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+            let (name, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+
+            return Some(Token::Open {
+                name: local_name(name),
+                attributes,
+                empty,
+            });
+        }
+    }
+}
+
+/// The value of an attribute in a tag's attribute list
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<Cow<'a, str>> {
+    let mut rest = attributes;
+
+    loop {
+        let (key, after) = rest.split_once('=')?;
+        let after = after.trim_start();
+        let quote = after.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value = after.get(1..)?;
+        let end = value.find(quote)?;
+
+        if local_name(key.trim()) == name {
+            return value.get(..end).map(decode_entities);
+        }
+
+        rest = value.get(end + 1..)?;
+    }
+}
+
+/// The items of an RSS channel or Atom feed
+fn parse_xml(document: &str) -> Vec<FeedItem> {
+    let mut items = Vec::new();
+    let mut item: Option<FeedItem> = None;
+    let mut depth = 0usize;
+    let mut item_depth = 0usize;
+    let mut field: Option<(&str, String)> = None;
+    let mut updated = None;
+
+    for token in (Tokens { rest: document }) {
+        match token {
+            Token::Open {
+                name,
+                attributes,
+                empty,
+            } => {
+                match (&mut item, name) {
+                    (None, "item") | (None, "entry") if !empty => {
+                        item = Some(FeedItem::default());
+                        item_depth = depth;
+                        updated = None;
+                    }
+                    (Some(current), "link") if depth == item_depth + 1 => {
+                        let rel = attribute(attributes, "rel");
+
+                        match attribute(attributes, "href") {
+                            Some(href) if rel.as_deref().is_none_or(|rel| rel == "alternate") => {
+                                current.url = href.into_owned();
+                            }
+                            Some(_) => {}
+                            None => field = Some((name, String::new())),
+                        }
+                    }
+                    (Some(_), name) if depth == item_depth + 1 => {
+                        field = Some((name, String::new()));
+                    }
+                    _ => {}
+                }
+
+                if !empty {
+                    depth = depth.saturating_add(1);
+                } else if field.as_ref().is_some_and(|(field, _)| *field == name) {
+                    field = None;
+                }
+            }
+            Token::Close(name) => {
+                depth = depth.saturating_sub(1);
+
+                if depth == item_depth && (name == "item" || name == "entry") {
+                    if let Some(mut finished) = item.take() {
+                        finished.published = finished.published.or(updated);
+                        items.push(finished);
+                    }
+                    continue;
+                }
+
+                if depth != item_depth + 1 {
+                    continue;
+                }
+
+                if let (Some(current), Some((field, text))) = (&mut item, field.take()) {
+                    let text = text.trim();
+
+                    match field {
+                        "title" => current.title = text.to_string(),
+                        "link" if current.url.is_empty() => current.url = text.to_string(),
+                        "guid" | "id" => current.guid = Some(text.to_string()),
+                        "pubDate" => {
+                            current.published = parse_rfc822(text).or_else(|| parse_rfc3339(text))
+                        }
+                        "published" | "date" | "issued" => current.published = parse_rfc3339(text),
+                        "updated" | "modified" => updated = parse_rfc3339(text),
+                        _ => {}
+                    }
+                }
+            }
+            Token::Text(text) => {
+                if let Some((_, buffer)) = &mut field {
+                    buffer.push_str(&text);
+                }
+            }
+        }
+    }
+
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!-- generated -->
+<rss version="2.0" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <channel>
+    <title>Channel title, not an item</title>
+    <link>https://example.org/</link>
+    <item>
+      <title>Fish &amp; chips &#8211; &#x201C;quoted&#x201d;</title>
+      <link>/posts/1</link>
+      <guid isPermaLink="false">post-1</guid>
+      <pubDate>Sun, 06 Nov 1994 08:49:37 GMT</pubDate>
+    </item>
+    <item>
+      <title><![CDATA[<b>Bold</b> & <i>brave</i>]]></title>
+      <link>posts/2?x=1&amp;y=2</link>
+      <pubDate>Sat, 13 Dec 03 18:30:02 +0000</pubDate>
+    </item>
+    <item>
+      <title>Dublin Core</title>
+      <link>https://other.example/3</link>
+      <dc:date>2024-01-02T03:04:05Z</dc:date>
+    </item>
+    <item>
+      <title>No link, skipped</title>
+    </item>
+  </channel>
+</rss>"#;
+
+    const ATOM: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom">
+  <title>Feed title</title>
+  <link href="https://example.org/" />
+  <entry>
+    <title type="html">Tags &lt;em&gt;stripped&lt;/em&gt;
+      and   lines joined</title>
+    <link rel="edit" href="https://example.org/edit/1"/>
+    <link rel="alternate" href="/entries/1"/>
+    <id>tag:example.org,2024:1</id>
+    <published>2024-01-02T05:04:05.123+02:00</published>
+    <updated>2024-02-01T00:00:00Z</updated>
+  </entry>
+  <atom:entry xmlns:atom="http://www.w3.org/2005/Atom">
+    <atom:title>Only updated</atom:title>
+    <atom:link href='https://example.org/entries/2'/>
+    <atom:id>  </atom:id>
+    <atom:updated>2024-01-02t03:04:05z</atom:updated>
+  </atom:entry>
+</feed>"#;
+
+    const JSON_FEED: &str = r#"{
+  "version": "https://jsonfeed.org/version/1.1",
+  "title": "JSON Feed",
+  "items": [
+    {
+      "id": "1",
+      "title": "First",
+      "url": "https://example.org/1",
+      "date_published": "2024-01-02T03:04:05Z"
+    },
+    {
+      "id": "2",
+      "external_url": "/2",
+      "date_modified": "2024-01-02"
+    },
+    { "id": "3", "title": "No URL" }
+  ]
+}"#;
+
+    const GEMINI: &str = "# A gemlog\n\
+                          \n\
+                          => gemini://example.org/about About\n\
+                          => /posts/1.gmi 2024-01-02 - First post\n\
+                          =>\t/posts/2.gmi\t2024-01-03: Second\n\
+                          => posts/3.gmi 2024-13-01 Not a date\n\
+                          Plain text\n";
+
+    fn item(guid: Option<&str>, title: &str, url: &str, published: Option<i64>) -> FeedItem {
+        FeedItem {
+            guid: guid.map(str::to_string),
+            title: title.to_string(),
+            url: url.to_string(),
+            published,
+        }
+    }
+
+    #[test]
+    fn parses_rss() {
+        assert_eq!(
+            parse(RSS, "https://example.org/feed/rss.xml"),
+            Ok(vec![
+                item(
+                    Some("post-1"),
+                    "Fish & chips \u{2013} \u{201c}quoted\u{201d}",
+                    "https://example.org/posts/1",
+                    Some(784_111_777)
+                ),
+                item(
+                    None,
+                    "Bold & brave",
+                    "https://example.org/feed/posts/2?x=1&y=2",
+                    Some(1_071_340_202)
+                ),
+                item(
+                    None,
+                    "Dublin Core",
+                    "https://other.example/3",
+                    Some(1_704_164_645)
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_atom() {
+        assert_eq!(
+            parse(ATOM, "https://example.org/atom.xml"),
+            Ok(vec![
+                item(
+                    Some("tag:example.org,2024:1"),
+                    "Tags stripped and lines joined",
+                    "https://example.org/entries/1",
+                    Some(1_704_164_645)
+                ),
+                item(
+                    None,
+                    "Only updated",
+                    "https://example.org/entries/2",
+                    Some(1_704_164_645)
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn parses_json_feed() {
+        assert_eq!(
+            parse(JSON_FEED, "https://example.org/feed.json"),
+            Ok(vec![
+                item(
+                    Some("1"),
+                    "First",
+                    "https://example.org/1",
+                    Some(1_704_164_645)
+                ),
+                item(Some("2"), "", "https://example.org/2", Some(1_704_153_600)),
+            ])
+        );
+
+        assert_eq!(
+            parse(r#"{"version": "1", "items": []}"#, "https://example.org/"),
+            Err(FeedError::UnknownFormat)
+        );
+        assert!(matches!(
+            parse("{\"version\": ", "https://example.org/"),
+            Err(FeedError::InvalidJson(_))
+        ));
+    }
+
+    #[test]
+    fn parses_gemini_feeds() {
+        assert_eq!(
+            parse(GEMINI, "gemini://example.org/gemlog/"),
+            Ok(vec![
+                item(
+                    None,
+                    "First post",
+                    "gemini://example.org/posts/1.gmi",
+                    Some(1_704_153_600)
+                ),
+                item(
+                    None,
+                    "Second",
+                    "gemini://example.org/posts/2.gmi",
+                    Some(1_704_240_000)
+                ),
+            ])
+        );
+    }
+
+    #[test]
+    fn decodes_entities() {
+        assert_eq!(decode_entities("no references"), "no references");
+        assert_eq!(
+            decode_entities("&lt;&gt;&amp;&quot;&apos; &#65;&#x42;&#X43;"),
+            "<>&\"' ABC"
+        );
+
+        // Unknown, unterminated and invalid references are kept
+        assert_eq!(
+            decode_entities("&nbsp; &#xD800; & &amp"),
+            "&nbsp; &#xD800; & &amp"
+        );
+        assert_eq!(
+            decode_entities("AT&T; &averyveryverylongname;"),
+            "AT&T; &averyveryverylongname;"
+        );
+
+        // Entities are decoded once, not again after decoding
+        assert_eq!(decode_entities("&amp;lt;"), "&lt;");
+    }
+
+    #[test]
+    fn keeps_cdata_as_is() {
+        let rss = "<rss><channel><item>\
+                   <title><![CDATA[<b>1 < 2</b>]]> &amp; more</title>\
+                   <link><![CDATA[https://example.org/?a=1&amp;b=2]]></link>\
+                   </item></channel></rss>";
+
+        assert_eq!(
+            parse(rss, "https://example.org/"),
+            Ok(vec![item(
+                None,
+                "1 < 2 & more",
+                "https://example.org/?a=1&amp;b=2",
+                None
+            )])
+        );
+    }
+
+    #[test]
+    fn parses_rfc822_dates() {
+        let cases = [
+            ("Sun, 06 Nov 1994 08:49:37 GMT", Some(784_111_777)),
+            ("06 Nov 1994 08:49:37 GMT", Some(784_111_777)),
+            ("Sun, 6 november 1994 08:49:37", Some(784_111_777)),
+            ("Sun, 06 Nov 1994 08:49 GMT", Some(784_111_740)),
+            ("Sun, 06 Nov 1994 09:49:37 +0100", Some(784_111_777)),
+            ("Sun, 06 Nov 1994 00:49:37 PST", Some(784_111_777)),
+            ("Sun, 06 Nov 1994 03:49:37 EST", Some(784_111_777)),
+            ("Sat, 13 Dec 03 18:30:02 UT", Some(1_071_340_202)),
+            ("Thu, 01 Jan 70 00:00:00 GMT", Some(0)),
+            ("Fri, 01 Jan 49 00:00:00 GMT", Some(2_493_072_000)),
+            ("Tue, 01 Jan 69 00:00:00 GMT", Some(-31_536_000)),
+            ("Sun, 06 Nov 1994 08:49:37 CEST", None),
+            ("Sun, 32 Nov 1994 08:49:37 GMT", None),
+            ("Sun, 06 Foo 1994 08:49:37 GMT", None),
+            ("Sun, 06 Nov 1994 24:00:00 GMT", None),
+            ("Sun, 06 Nov 994 08:49:37 GMT", None),
+            ("Sunday", None),
+            ("", None),
+        ];
+
+        for (value, expected) in cases.iter() {
+            assert_eq!(parse_rfc822(value), *expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn parses_rfc3339_dates() {
+        let cases = [
+            ("2024-01-02T03:04:05Z", Some(1_704_164_645)),
+            ("2024-01-02t03:04:05z", Some(1_704_164_645)),
+            ("2024-01-02 03:04:05Z", Some(1_704_164_645)),
+            ("2024-01-02T03:04:05.999999Z", Some(1_704_164_645)),
+            ("2024-01-02T05:04:05+02:00", Some(1_704_164_645)),
+            ("2024-01-01T21:34:05-0530", Some(1_704_164_645)),
+            ("2024-01-02", Some(1_704_153_600)),
+            ("  2024-01-02  ", Some(1_704_153_600)),
+            ("1970-01-01T00:00:00Z", Some(0)),
+            ("2024-01-02T03:04:05", None),
+            ("2024-01-02T03:04Z", None),
+            ("2024-13-02", None),
+            ("2024-01-32", None),
+            ("2024-1-2", None),
+            ("2024-01-02T25:00:00Z", None),
+            ("2024-01-02T03:04:05+2", None),
+            ("yesterday", None),
+        ];
+
+        for (value, expected) in cases.iter() {
+            assert_eq!(parse_rfc3339(value), *expected, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn resolves_links() {
+        let base = "https://example.org/blog/feed.xml?page=2#top";
+
+        assert_eq!(
+            resolve(base, "https://other.example/a"),
+            "https://other.example/a"
+        );
+        assert_eq!(resolve(base, "//cdn.example/a"), "https://cdn.example/a");
+        assert_eq!(resolve(base, "/a"), "https://example.org/a");
+        assert_eq!(resolve(base, "a"), "https://example.org/blog/a");
+        assert_eq!(
+            resolve("https://example.org", " a "),
+            "https://example.org/a"
+        );
+        assert_eq!(resolve("not a url", "a"), "a");
+    }
+
+    #[test]
+    fn rejects_documents_that_are_not_feeds() {
+        assert_eq!(
+            parse("<html><body>Hi</body></html>", "https://example.org/"),
+            Ok(vec![])
+        );
+        assert_eq!(parse("just text", "https://example.org/"), Ok(vec![]));
+        assert_eq!(parse("", "https://example.org/"), Ok(vec![]));
+    }
+
+    #[test]
+    fn survives_truncated_documents() {
+        for end in 0..RSS.len() {
+            if let Some(document) = RSS.get(..end) {
+                assert!(parse(document, "https://example.org/").is_ok());
+            }
+        }
+
+        for end in 0..ATOM.len() {
+            if let Some(document) = ATOM.get(..end) {
+                assert!(parse(document, "https://example.org/").is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn makes_entries() {
+        let entry = item(Some("post-1"), "A title", "https://example.org/1", Some(0))
+            .into_entry(3, 4, "https://example.org/feed")
+            .unwrap();
+
+        assert_eq!(entry.id, 3);
+        assert_eq!(entry.feed_id, 4);
+        assert_eq!(&*entry.feed_url, "https://example.org/feed");
+        assert_eq!(entry.guid.as_deref(), Some("post-1"));
+        assert_eq!(entry.published, Some(0));
+    }
+}
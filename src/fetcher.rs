@@ -0,0 +1,549 @@
+//! Refreshing feeds politely
+//!
+//! Servers fetch feeds with the HTTP client of their choice, or
+//! with the Downloader the `fetch` feature adds; this module
+//! holds the decisions around each request. FeedStatus
+//! supplies the conditional headers a refresh sends and records
+//! what came back, keeping the validators for the next request and
+//! backing off a failing feed. HostLimiter spaces out requests to
+//! the same host and caps how many run at once, so a server with
+//! many feeds on one host does not hammer it.
+//!
+//! Times are Unix timestamps in seconds, as in FeedStatus.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::{Backoff, FeedStatus};
+
+/// Delay before retrying a failing feed whose refresh interval
+/// is shorter
+const MIN_BACKOFF: i64 = 60;
+
+/// Longest delay between retries of a failing feed
+const MAX_BACKOFF: i64 = 24 * 60 * 60;
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Days since the Unix epoch of a date in the proleptic
+/// Gregorian calendar
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The year, month and day of a count of days since the Unix
+/// epoch
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// Format a Unix timestamp as an HTTP date, such as
+/// `Sun, 06 Nov 1994 08:49:37 GMT`
+///
+/// Timestamps are clamped to the years 1 to 9999.
+pub fn http_date(timestamp: i64) -> String {
+    let timestamp = timestamp.clamp(-62_135_596_800, 253_402_300_799);
+    let days = timestamp.div_euclid(SECONDS_PER_DAY);
+    let seconds = timestamp.rem_euclid(SECONDS_PER_DAY);
+    let (year, month, day) = civil_from_days(days);
+    let weekday = usize::try_from(days.rem_euclid(7)).unwrap_or_default();
+    let month_name = usize::try_from(month - 1)
+        .ok()
+        .and_then(|index| MONTHS.get(index))
+        .unwrap_or(&"Jan");
+
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS.get(weekday).unwrap_or(&"Thu"),
+        day,
+        month_name,
+        year,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Parse an HTTP date in the preferred format, as sent in
+/// Last-Modified, into a Unix timestamp
+///
+/// The obsolete RFC 850 and asctime formats are not accepted.
+pub fn parse_http_date(value: &str) -> Option<i64> {
+    let mut parts = value.split_whitespace();
+
+    let weekday = parts.next()?.strip_suffix(',')?;
+    let day = parts.next()?;
+    let month = parts.next()?;
+    let year = parts.next()?;
+    let time = parts.next()?;
+
+    if parts.next()? != "GMT" || parts.next().is_some() {
+        return None;
+    }
+
+    if !WEEKDAYS.contains(&weekday)
+        || day.len() != 2
+        || year.len() != 4
+        || time.len() != 8
+        || !day
+            .bytes()
+            .chain(year.bytes())
+            .all(|byte| byte.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let day: i64 = day.parse().ok()?;
+    let year: i64 = year.parse().ok()?;
+    let month = MONTHS.iter().position(|name| *name == month)?;
+    let month = i64::try_from(month).ok()? + 1;
+
+    let mut fields = time.split(':').map(|field| {
+        field
+            .parse::<i64>()
+            .ok()
+            .filter(|_| field.len() == 2 && field.bytes().all(|byte| byte.is_ascii_digit()))
+    });
+    let (hours, minutes, seconds) = (fields.next()??, fields.next()??, fields.next()??);
+
+    if !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    Some(
+        days_from_civil(year, month, day) * SECONDS_PER_DAY + hours * 3600 + minutes * 60 + seconds,
+    )
+}
+
+/// What a refresh of a feed came back with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FetchOutcome {
+    /// The feed was fetched, with the validators to send next
+    /// time
+    Modified {
+        etag: Option<String>,
+        last_modified: Option<i64>,
+    },
+    /// The server answered 304 Not Modified
+    NotModified,
+    /// The request failed or the server answered with an error
+    Failed,
+}
+
+impl FetchOutcome {
+    /// Classify an HTTP response by its status and its ETag and
+    /// Last-Modified headers
+    pub fn from_response(
+        status: u16,
+        etag: Option<&str>,
+        last_modified: Option<&str>,
+    ) -> FetchOutcome {
+        match status {
+            304 => FetchOutcome::NotModified,
+            200..=299 => FetchOutcome::Modified {
+                etag: etag.map(str::to_string),
+                last_modified: last_modified.and_then(parse_http_date),
+            },
+            _ => FetchOutcome::Failed,
+        }
+    }
+}
+
+impl FeedStatus {
+    /// The If-None-Match and If-Modified-Since headers to send
+    /// with the next refresh, as names and values
+    pub fn conditional_headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+
+        if let Some(etag) = &self.etag {
+            headers.push(("If-None-Match", etag.clone()));
+        }
+
+        if let Some(last_modified) = self.last_modified {
+            headers.push(("If-Modified-Since", http_date(last_modified)));
+        }
+
+        headers
+    }
+
+    /// Record the outcome of a refresh made at `now`
+    ///
+    /// A failure schedules a retry after the refresh interval,
+    /// doubled with each failure in a row, at least a minute and
+    /// at most a day. Success clears the backoff.
+    pub fn record_fetch(&mut self, outcome: FetchOutcome, now: i64) {
+        match outcome {
+            FetchOutcome::Modified {
+                etag,
+                last_modified,
+            } => {
+                self.etag = etag;
+                self.last_modified = last_modified;
+                self.last_fetched = Some(now);
+                self.backoff = None;
+            }
+            FetchOutcome::NotModified => {
+                self.last_fetched = Some(now);
+                self.backoff = None;
+            }
+            FetchOutcome::Failed => {
+                let (failures, failing_since) = match self.backoff {
+                    Some(backoff) => (backoff.failures.saturating_add(1), backoff.failing_since),
+                    None => (1, now),
+                };
+                let delay = i64::from(self.interval)
+                    .max(MIN_BACKOFF)
+                    .saturating_mul(1 << failures.saturating_sub(1).min(20))
+                    .min(MAX_BACKOFF);
+
+                self.backoff = Some(Backoff {
+                    failures,
+                    failing_since,
+                    next_retry: now.saturating_add(delay),
+                });
+            }
+        }
+    }
+}
+
+/// Whether a request to a host may start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// Start the request, and call `HostLimiter::finish` once
+    /// it is done
+    Start,
+    /// The host was requested too recently; try again at this
+    /// time
+    WaitUntil(i64),
+    /// Too many requests to the host are running; try again
+    /// once one finishes
+    Busy,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct HostState {
+    in_flight: usize,
+    last_start: Option<i64>,
+}
+
+/// Spaces out requests to each host
+#[derive(Debug, Clone)]
+pub struct HostLimiter {
+    interval: i64,
+    max_in_flight: usize,
+    hosts: HashMap<String, HostState>,
+}
+
+/// The host of a URL, lowercased, with its port
+fn host_of(url: &str) -> String {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(&['/', '?', '#'][..]).next().unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+
+    host.to_ascii_lowercase()
+}
+
+impl HostLimiter {
+    /// Allow a request to each host every `interval` seconds,
+    /// with at most `max_in_flight` running at once
+    pub fn new(interval: u32, max_in_flight: usize) -> HostLimiter {
+        HostLimiter {
+            interval: i64::from(interval),
+            max_in_flight: max_in_flight.max(1),
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// Ask to start a request for `url` at `now`
+    pub fn admit(&mut self, url: &str, now: i64) -> Admission {
+        let state = self.hosts.entry(host_of(url)).or_default();
+
+        if state.in_flight >= self.max_in_flight {
+            return Admission::Busy;
+        }
+
+        if let Some(last_start) = state.last_start {
+            let due = last_start.saturating_add(self.interval);
+
+            if now < due {
+                return Admission::WaitUntil(due);
+            }
+        }
+
+        state.in_flight += 1;
+        state.last_start = Some(now);
+
+        Admission::Start
+    }
+
+    /// Record that a request admitted for `url` is done
+    pub fn finish(&mut self, url: &str) {
+        let host = host_of(url);
+
+        if let Some(state) = self.hosts.get_mut(&host) {
+            state.in_flight = state.in_flight.saturating_sub(1);
+        }
+    }
+
+    /// Forget hosts not requested since `before`, so the limiter
+    /// does not grow with every host ever fetched
+    pub fn prune(&mut self, before: i64) {
+        self.hosts.retain(|_, state| {
+            state.in_flight > 0 || state.last_start.is_some_and(|last| last >= before)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(interval: u32) -> FeedStatus {
+        FeedStatus {
+            feed_id: 1,
+            interval,
+            last_fetched: None,
+            etag: None,
+            last_modified: None,
+            backoff: None,
+        }
+    }
+
+    #[test]
+    fn formats_http_dates() {
+        assert_eq!(http_date(784_111_777), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(http_date(0), "Thu, 01 Jan 1970 00:00:00 GMT");
+        assert_eq!(http_date(951_782_400), "Tue, 29 Feb 2000 00:00:00 GMT");
+        assert_eq!(http_date(-1), "Wed, 31 Dec 1969 23:59:59 GMT");
+        assert_eq!(http_date(i64::MIN), "Mon, 01 Jan 0001 00:00:00 GMT");
+        assert_eq!(http_date(i64::MAX), "Fri, 31 Dec 9999 23:59:59 GMT");
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(784_111_777)
+        );
+
+        for timestamp in [-62_135_596_800, -1, 0, 951_782_400, 1_704_164_645] {
+            assert_eq!(parse_http_date(&http_date(timestamp)), Some(timestamp));
+        }
+
+        for value in [
+            "",
+            "Sun, 06 Nov 1994 08:49:37",
+            "Sun, 06 Nov 1994 08:49:37 UTC",
+            "Sun, 06 Nov 1994 08:49:37 GMT extra",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+            "Sun 06 Nov 1994 08:49:37 GMT",
+            "Sun, 6 Nov 1994 08:49:37 GMT",
+            "Sun, 06 nov 1994 08:49:37 GMT",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:60:00 GMT",
+            "Sun, 06 Nov 1994 8:49:37. GMT",
+            "Sun, 06 Nov 1994 +8:49:37 GMT",
+        ] {
+            assert_eq!(parse_http_date(value), None, "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn classifies_responses() {
+        assert_eq!(
+            FetchOutcome::from_response(200, Some("\"v1\""), Some("Sun, 06 Nov 1994 08:49:37 GMT")),
+            FetchOutcome::Modified {
+                etag: Some("\"v1\"".to_string()),
+                last_modified: Some(784_111_777),
+            }
+        );
+        assert_eq!(
+            FetchOutcome::from_response(203, None, Some("yesterday")),
+            FetchOutcome::Modified {
+                etag: None,
+                last_modified: None,
+            }
+        );
+        assert_eq!(
+            FetchOutcome::from_response(304, Some("\"v1\""), None),
+            FetchOutcome::NotModified
+        );
+
+        for status in [100, 301, 404, 500] {
+            assert_eq!(
+                FetchOutcome::from_response(status, None, None),
+                FetchOutcome::Failed
+            );
+        }
+    }
+
+    #[test]
+    fn sends_validators_from_the_last_fetch() {
+        let mut status = status(60);
+
+        assert!(status.conditional_headers().is_empty());
+
+        status.record_fetch(
+            FetchOutcome::Modified {
+                etag: Some("\"v1\"".to_string()),
+                last_modified: Some(784_111_777),
+            },
+            1000,
+        );
+
+        assert_eq!(status.last_fetched, Some(1000));
+        assert_eq!(
+            status.conditional_headers(),
+            [
+                ("If-None-Match", "\"v1\"".to_string()),
+                (
+                    "If-Modified-Since",
+                    "Sun, 06 Nov 1994 08:49:37 GMT".to_string()
+                ),
+            ]
+        );
+
+        // Not Modified keeps the validators
+        status.record_fetch(FetchOutcome::NotModified, 2000);
+
+        assert_eq!(status.last_fetched, Some(2000));
+        assert_eq!(status.conditional_headers().len(), 2);
+    }
+
+    #[test]
+    fn backs_off_failing_feeds() {
+        let mut feed = status(30);
+        let mut retries = Vec::new();
+
+        for now in [1000, 2000, 3000] {
+            feed.record_fetch(FetchOutcome::Failed, now);
+            retries.push(feed.backoff.map(|backoff| backoff.next_retry - now));
+        }
+
+        // At least a minute, doubling with each failure
+        assert_eq!(retries, [Some(60), Some(120), Some(240)]);
+        assert_eq!(
+            feed.backoff
+                .map(|backoff| (backoff.failures, backoff.failing_since)),
+            Some((3, 1000))
+        );
+        assert_eq!(feed.next_fetch(), Some(3240));
+
+        // At most a day
+        let mut feed = status(3600);
+
+        for now in 0..64 {
+            feed.record_fetch(FetchOutcome::Failed, now);
+        }
+
+        assert_eq!(
+            feed.backoff.map(|backoff| backoff.next_retry),
+            Some(63 + MAX_BACKOFF)
+        );
+
+        // Success clears the backoff
+        feed.record_fetch(FetchOutcome::NotModified, 100);
+
+        assert_eq!(feed.backoff, None);
+        assert_eq!(feed.next_fetch(), Some(3700));
+    }
+
+    #[test]
+    fn spaces_out_requests_to_each_host() {
+        let mut limiter = HostLimiter::new(10, 4);
+
+        assert_eq!(
+            limiter.admit("https://example.org/a", 100),
+            Admission::Start
+        );
+        limiter.finish("https://example.org/a");
+
+        // Hosts compare without case, path or user info
+        assert_eq!(
+            limiter.admit("https://user@EXAMPLE.org/b?c", 105),
+            Admission::WaitUntil(110)
+        );
+        assert_eq!(
+            limiter.admit("https://example.org/b", 110),
+            Admission::Start
+        );
+
+        // Other hosts, and other ports, are separate
+        assert_eq!(limiter.admit("https://example.com/", 110), Admission::Start);
+        assert_eq!(
+            limiter.admit("https://example.org:8443/", 110),
+            Admission::Start
+        );
+    }
+
+    #[test]
+    fn caps_requests_in_flight() {
+        let mut limiter = HostLimiter::new(0, 2);
+
+        assert_eq!(limiter.admit("https://example.org/a", 0), Admission::Start);
+        assert_eq!(limiter.admit("https://example.org/b", 0), Admission::Start);
+        assert_eq!(limiter.admit("https://example.org/c", 0), Admission::Busy);
+
+        limiter.finish("https://example.org/a");
+
+        assert_eq!(limiter.admit("https://example.org/c", 0), Admission::Start);
+
+        // At least one request is always allowed
+        let mut limiter = HostLimiter::new(0, 0);
+
+        assert_eq!(limiter.admit("https://example.org/", 0), Admission::Start);
+        assert_eq!(limiter.admit("https://example.org/", 0), Admission::Busy);
+    }
+
+    #[test]
+    fn prunes_idle_hosts() {
+        let mut limiter = HostLimiter::new(10, 1);
+
+        limiter.admit("https://idle.example/", 0);
+        limiter.finish("https://idle.example/");
+        limiter.admit("https://busy.example/", 0);
+        limiter.admit("https://recent.example/", 50);
+        limiter.finish("https://recent.example/");
+
+        limiter.prune(50);
+
+        let mut hosts: Vec<&str> = limiter.hosts.keys().map(String::as_str).collect();
+        hosts.sort_unstable();
+
+        assert_eq!(hosts, ["busy.example", "recent.example"]);
+
+        // A pruned host may be requested at once
+        assert_eq!(limiter.admit("https://idle.example/", 5), Admission::Start);
+    }
+}
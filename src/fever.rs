@@ -0,0 +1,361 @@
+//! Fever API compatibility mapping
+//!
+//! Translates Fever API requests into seymour Commands and
+//! renders seymour lists as Fever JSON replies, so a gateway
+//! can put a seymour server behind readers that speak Fever.
+//!
+//! Only the parts of Fever that seymour can answer are mapped:
+//! feeds, items, unread item ids, and marking an item read.
+//! Groups, favicons, links and saved items are answered with
+//! empty values. Resolving the `api_key` to a seymour user is
+//! left to the gateway.
+
+use thiserror::Error;
+
+use crate::form;
+use crate::json::Value;
+use crate::{Command, EntryFlags, EntryList, SubscriptionList};
+
+/// The Fever API version implemented by this mapping
+pub const API_VERSION: i64 = 3;
+
+#[derive(Debug, Error)]
+pub enum FeverError {
+    #[error("not a Fever API request")]
+    NotApiRequest,
+    #[error("missing parameter \"{0}\"")]
+    MissingParameter(String),
+    #[error("invalid value \"{value}\" for parameter \"{parameter}\"")]
+    InvalidParameter { parameter: String, value: String },
+    #[error("unsupported mark \"{mark}\" as \"{state}\"")]
+    UnsupportedMark { mark: String, state: String },
+}
+
+/// A section of data requested by a Fever client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeverQuery {
+    Groups,
+    Feeds,
+    Favicons,
+    Items,
+    Links,
+    UnreadItemIds,
+    SavedItemIds,
+}
+
+impl FeverQuery {
+    const ALL: [FeverQuery; 7] = [
+        FeverQuery::Groups,
+        FeverQuery::Feeds,
+        FeverQuery::Favicons,
+        FeverQuery::Items,
+        FeverQuery::Links,
+        FeverQuery::UnreadItemIds,
+        FeverQuery::SavedItemIds,
+    ];
+
+    /// The request parameter selecting this section
+    pub fn parameter(&self) -> &'static str {
+        match self {
+            FeverQuery::Groups => "groups",
+            FeverQuery::Feeds => "feeds",
+            FeverQuery::Favicons => "favicons",
+            FeverQuery::Items => "items",
+            FeverQuery::Links => "links",
+            FeverQuery::UnreadItemIds => "unread_item_ids",
+            FeverQuery::SavedItemIds => "saved_item_ids",
+        }
+    }
+}
+
+/// A parsed Fever API request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeverRequest {
+    pub api_key: Option<String>,
+    pub queries: Vec<FeverQuery>,
+
+    /// Entry to mark as read, from `mark=item&as=read&id=<id>`
+    pub mark_read: Option<i64>,
+}
+
+impl FeverRequest {
+    /// Parse a Fever request from its query string and
+    /// (possibly empty) form body
+    pub fn parse(query: &str, body: &str) -> Result<FeverRequest, FeverError> {
+        let mut pairs = form::parse(query);
+        pairs.extend(form::parse(body));
+
+        if form::get(&pairs, "api").is_none() {
+            return Err(FeverError::NotApiRequest);
+        }
+
+        let queries = FeverQuery::ALL
+            .iter()
+            .copied()
+            .filter(|query| form::get(&pairs, query.parameter()).is_some())
+            .collect();
+
+        let mark_read = match form::get(&pairs, "mark") {
+            Some(mark) => {
+                let state = form::get(&pairs, "as")
+                    .ok_or_else(|| FeverError::MissingParameter("as".to_string()))?;
+
+                if mark != "item" || state != "read" {
+                    return Err(FeverError::UnsupportedMark {
+                        mark: mark.to_string(),
+                        state: state.to_string(),
+                    });
+                }
+
+                let id = form::get(&pairs, "id")
+                    .ok_or_else(|| FeverError::MissingParameter("id".to_string()))?;
+
+                Some(id.parse().map_err(|_| FeverError::InvalidParameter {
+                    parameter: "id".to_string(),
+                    value: id.to_string(),
+                })?)
+            }
+            None => None,
+        };
+
+        Ok(FeverRequest {
+            api_key: form::get(&pairs, "api_key").map(str::to_string),
+            queries,
+            mark_read,
+        })
+    }
+
+    fn wants(&self, query: FeverQuery) -> bool {
+        self.queries.contains(&query)
+    }
+
+    /// Whether answering the request needs the subscription list
+    pub fn needs_subscriptions(&self) -> bool {
+        self.wants(FeverQuery::Feeds)
+    }
+
+    /// Whether answering the request needs the unread list
+    pub fn needs_unread(&self) -> bool {
+        self.wants(FeverQuery::Items) || self.wants(FeverQuery::UnreadItemIds)
+    }
+
+    /// The seymour commands to issue for this request
+    ///
+    /// Does not include the User command, which the gateway
+    /// must send after resolving the api_key.
+    pub fn commands(&self) -> Vec<Command> {
+        let mut commands = Vec::new();
+
+        if let Some(id) = self.mark_read {
+            commands.push(Command::MarkRead {
+                id,
+                expected: None,
+                key: None,
+            });
+        }
+
+        if self.needs_subscriptions() {
+            commands.push(Command::ListSubscriptions);
+        }
+
+        if self.needs_unread() {
+            commands.push(Command::ListUnread {
+                after: None,
+                summary: None,
+                count: false,
+            });
+        }
+
+        commands
+    }
+
+    /// Build the reply to this request from the lists fetched
+    /// by its commands
+    ///
+    /// Pass None for a list the request does not need.
+    pub fn reply(
+        &self,
+        subscriptions: Option<&SubscriptionList>,
+        unread: Option<&EntryList>,
+    ) -> FeverReply {
+        let mut reply = FeverReply::new(true);
+        let empty_subscriptions = SubscriptionList::default();
+        let empty_entries = EntryList::default();
+        let subscriptions = subscriptions.unwrap_or(&empty_subscriptions);
+        let unread = unread.unwrap_or(&empty_entries);
+
+        for query in &self.queries {
+            match query {
+                FeverQuery::Groups => {
+                    reply.push("groups", Value::Array(Vec::new()));
+                    reply.push("feeds_groups", Value::Array(Vec::new()));
+                }
+                FeverQuery::Feeds => {
+                    reply.push("feeds", feeds(subscriptions));
+                    if !self.wants(FeverQuery::Groups) {
+                        reply.push("feeds_groups", Value::Array(Vec::new()));
+                    }
+                }
+                FeverQuery::Favicons => reply.push("favicons", Value::Array(Vec::new())),
+                FeverQuery::Items => {
+                    reply.push("items", items(unread));
+                    reply.push("total_items", Value::Integer(unread.0.len() as i64));
+                }
+                FeverQuery::Links => reply.push("links", Value::Array(Vec::new())),
+                FeverQuery::UnreadItemIds => {
+                    reply.push("unread_item_ids", item_ids(unread));
+                }
+                FeverQuery::SavedItemIds => reply.push("saved_item_ids", Value::from("")),
+            }
+        }
+
+        reply
+    }
+}
+
+/// A Fever JSON reply
+#[derive(Debug, Clone)]
+pub struct FeverReply {
+    fields: Vec<(String, Value)>,
+}
+
+impl FeverReply {
+    /// Start a reply, stating whether the api_key was accepted
+    pub fn new(authenticated: bool) -> FeverReply {
+        FeverReply {
+            fields: vec![
+                ("api_version".to_string(), Value::Integer(API_VERSION)),
+                ("auth".to_string(), Value::Integer(authenticated as i64)),
+            ],
+        }
+    }
+
+    fn push(&mut self, key: &str, value: Value) {
+        self.fields.push((key.to_string(), value));
+    }
+}
+
+impl std::fmt::Display for FeverReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", Value::Object(self.fields.clone()))
+    }
+}
+
+fn feeds(subscriptions: &SubscriptionList) -> Value {
+    Value::Array(
+        subscriptions
+            .0
+            .iter()
+            .map(|subscription| {
+                Value::object(vec![
+                    ("id", Value::Integer(subscription.id)),
+                    ("favicon_id", Value::Integer(0)),
+                    ("title", Value::from(subscription.url.as_str())),
+                    ("url", Value::from(subscription.url.as_str())),
+                    ("site_url", Value::from(subscription.url.as_str())),
+                    ("is_spark", Value::Integer(0)),
+                    ("last_updated_on_time", Value::Integer(0)),
+                ])
+            })
+            .collect(),
+    )
+}
+
+/// A Fever boolean, sent as 0 or 1
+fn flag(value: bool) -> Value {
+    Value::Integer(i64::from(value))
+}
+
+/// Items are saved when the user saved or starred the entry
+fn items(entries: &EntryList) -> Value {
+    Value::Array(
+        entries
+            .0
+            .iter()
+            .map(|entry| {
+                Value::object(vec![
+                    ("id", Value::Integer(entry.id)),
+                    ("feed_id", Value::Integer(entry.feed_id)),
+                    ("title", Value::from(entry.title.as_str())),
+                    ("author", Value::from("")),
+                    ("html", Value::from("")),
+                    ("url", Value::from(entry.url.as_str())),
+                    (
+                        "is_saved",
+                        flag(
+                            entry.flags.contains(EntryFlags::SAVED)
+                                || entry.flags.contains(EntryFlags::STARRED),
+                        ),
+                    ),
+                    ("is_read", flag(entry.flags.contains(EntryFlags::READ))),
+                    (
+                        "created_on_time",
+                        Value::Integer(entry.published.unwrap_or_default()),
+                    ),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn item_ids(entries: &EntryList) -> Value {
+    let ids: Vec<String> = entries.0.iter().map(|entry| entry.id.to_string()).collect();
+
+    Value::String(ids.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Entry;
+
+    fn entry(id: i64, flags: EntryFlags) -> Entry {
+        Entry {
+            id,
+            feed_id: 1,
+            feed_url: "https://example.com/feed".into(),
+            title: "Title".to_string(),
+            url: format!("https://example.com/{}", id),
+            published: None,
+            guid: None,
+            read_at: None,
+            group_id: None,
+            duplicate: false,
+            flags,
+            language: None,
+            summary: None,
+        }
+    }
+
+    #[test]
+    fn items_carry_flags() {
+        let items = items(&EntryList(vec![
+            entry(1, EntryFlags::empty()),
+            entry(2, EntryFlags::READ),
+            entry(3, EntryFlags::STARRED),
+            entry(4, EntryFlags::SAVED | EntryFlags::READ),
+        ]));
+
+        let flags: Vec<_> = items
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| {
+                (
+                    item.get("is_read").and_then(Value::as_i64),
+                    item.get("is_saved").and_then(Value::as_i64),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            flags,
+            vec![
+                (Some(0), Some(0)),
+                (Some(1), Some(0)),
+                (Some(0), Some(1)),
+                (Some(1), Some(1)),
+            ]
+        );
+    }
+}
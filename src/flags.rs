@@ -0,0 +1,122 @@
+use std::fmt;
+use std::ops::{BitOr, BitOrAssign};
+use std::str::FromStr;
+
+use thiserror::Error;
+
+/// Per-user state of an entry, sent as one letter per flag set
+///
+/// `R` for read, `S` for starred and `V` for saved, in that
+/// order, or `-` when no flag is set.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct EntryFlags(u8);
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("invalid entry flags \"{0}\"")]
+pub struct InvalidEntryFlags(pub String);
+
+const LETTERS: [(EntryFlags, char); 3] = [
+    (EntryFlags::READ, 'R'),
+    (EntryFlags::STARRED, 'S'),
+    (EntryFlags::SAVED, 'V'),
+];
+
+impl EntryFlags {
+    pub const READ: EntryFlags = EntryFlags(1);
+    pub const STARRED: EntryFlags = EntryFlags(1 << 1);
+    pub const SAVED: EntryFlags = EntryFlags(1 << 2);
+
+    /// Every flag known to this crate
+    pub const ALL: EntryFlags = EntryFlags(0b111);
+
+    /// No flags set
+    pub const fn empty() -> EntryFlags {
+        EntryFlags(0)
+    }
+
+    /// The flags of a bitmask, if it sets no unknown bits
+    pub const fn from_bits(bits: u8) -> Option<EntryFlags> {
+        if bits & !EntryFlags::ALL.0 == 0 {
+            Some(EntryFlags(bits))
+        } else {
+            None
+        }
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether every flag of `other` is set
+    pub const fn contains(self, other: EntryFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn insert(&mut self, other: EntryFlags) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: EntryFlags) {
+        self.0 &= !other.0;
+    }
+}
+
+impl BitOr for EntryFlags {
+    type Output = EntryFlags;
+
+    fn bitor(self, other: EntryFlags) -> EntryFlags {
+        EntryFlags(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for EntryFlags {
+    fn bitor_assign(&mut self, other: EntryFlags) {
+        self.insert(other);
+    }
+}
+
+/// Letters may come in any order, but each at most once
+impl FromStr for EntryFlags {
+    type Err = InvalidEntryFlags;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut flags = EntryFlags::empty();
+
+        if value == "-" {
+            return Ok(flags);
+        }
+
+        for letter in value.chars() {
+            match LETTERS.iter().find(|(_, candidate)| *candidate == letter) {
+                Some((flag, _)) if !flags.contains(*flag) => flags.insert(*flag),
+                _ => return Err(InvalidEntryFlags(value.to_string())),
+            }
+        }
+
+        if flags.is_empty() {
+            return Err(InvalidEntryFlags(value.to_string()));
+        }
+
+        Ok(flags)
+    }
+}
+
+impl fmt::Display for EntryFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "-");
+        }
+
+        for (flag, letter) in LETTERS.iter() {
+            if self.contains(*flag) {
+                write!(f, "{}", letter)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+/// Parse an `application/x-www-form-urlencoded` string (or a
+/// URL query string) into its key/value pairs
+///
+/// Keys without a value, such as `feeds` in `api&feeds`, are
+/// returned with an empty value.
+pub(crate) fn parse(input: &str) -> Vec<(String, String)> {
+    input
+        .trim_start_matches('?')
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+            (decode(key), decode(value))
+        })
+        .collect()
+}
+
+/// Look up the first value for a key
+pub(crate) fn get<'a>(pairs: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    pairs
+        .iter()
+        .find(|(candidate, _)| candidate == key)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Look up every value for a key
+pub(crate) fn get_all<'a>(
+    pairs: &'a [(String, String)],
+    key: &'a str,
+) -> impl Iterator<Item = &'a str> + 'a {
+    pairs
+        .iter()
+        .filter(move |(candidate, _)| candidate == key)
+        .map(|(_, value)| value.as_str())
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Percent-decode a form component, treating `+` as a space
+///
+/// Malformed escapes are kept as-is.
+pub(crate) fn decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while let Some(&byte) = bytes.get(index) {
+        let escaped = match byte {
+            b'%' => bytes
+                .get(index + 1)
+                .and_then(|&high| hex_value(high))
+                .zip(bytes.get(index + 2).and_then(|&low| hex_value(low))),
+            _ => None,
+        };
+
+        match (byte, escaped) {
+            (_, Some((high, low))) => {
+                out.push(high << 4 | low);
+                index += 3;
+            }
+            (b'+', None) => {
+                out.push(b' ');
+                index += 1;
+            }
+            (byte, None) => {
+                out.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Percent-encode a form component or path segment
+///
+/// Everything but unreserved characters is escaped.
+pub(crate) fn encode(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(char::from(byte))
+            }
+            byte => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    out
+}
@@ -0,0 +1,118 @@
+//! Rendering seymour lists as gemtext
+//!
+//! Lists are rendered as one link line per item, so a Gemini
+//! capsule can serve them directly. A list of feed links in
+//! the same form (as used by gmisub) can be parsed back into
+//! Subscribe commands.
+
+use crate::escape::Url;
+use crate::{Command, EntryList, SubscriptionList};
+
+/// A single gemtext link line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub url: String,
+    pub label: Option<String>,
+}
+
+impl Link {
+    /// Parse a gemtext link line, returning None for any
+    /// other kind of line
+    pub fn parse(line: &str) -> Option<Link> {
+        let rest = line.strip_prefix("=>")?.trim();
+
+        let (url, label) = match rest.split_once(char::is_whitespace) {
+            Some((url, label)) => (url, Some(label.trim_start())),
+            None => (rest, None),
+        };
+
+        if url.is_empty() {
+            return None;
+        }
+
+        Some(Link {
+            url: url.to_string(),
+            label: label.filter(|label| !label.is_empty()).map(str::to_string),
+        })
+    }
+
+    /// Write a link line, percent-encoding whitespace and
+    /// control characters in the url so it ends neither the
+    /// url nor the line early
+    fn render(url: &str, label: Option<&str>, out: &mut String) {
+        out.push_str("=> ");
+        out.push_str(&Url(url).to_string());
+
+        if let Some(label) = label {
+            out.push(' ');
+            out.extend(label.chars().map(|c| if c.is_control() { ' ' } else { c }));
+        }
+
+        out.push('\n');
+    }
+}
+
+/// Render subscriptions as one link line per feed
+pub fn render_subscriptions(subscriptions: &SubscriptionList) -> String {
+    let mut out = String::new();
+
+    for subscription in &subscriptions.0 {
+        Link::render(&subscription.url, None, &mut out);
+    }
+
+    out
+}
+
+/// Render entries as one link line per entry, labelled
+/// with the entry title
+pub fn render_entries(entries: &EntryList) -> String {
+    let mut out = String::new();
+
+    for entry in &entries.0 {
+        Link::render(&entry.url, Some(&entry.title), &mut out);
+    }
+
+    out
+}
+
+/// Parse every link line of a gemtext document
+pub fn parse_links(text: &str) -> Vec<Link> {
+    text.lines().filter_map(Link::parse).collect()
+}
+
+/// Parse a gemtext list of feed links into Subscribe commands
+pub fn parse_subscriptions(text: &str) -> Vec<Command> {
+    parse_links(text)
+        .into_iter()
+        .map(|link| Command::Subscribe {
+            url: link.url,
+            category: None,
+            key: None,
+            deadline: None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_url() {
+        let mut out = String::new();
+        Link::render(
+            "gemini://example.com/a b\n=> evil",
+            Some("A\ntitle"),
+            &mut out,
+        );
+
+        assert_eq!(out, "=> gemini://example.com/a%20b%0A=>%20evil A title\n");
+        assert_eq!(
+            parse_links(&out),
+            vec![Link {
+                url: "gemini://example.com/a%20b%0A=>%20evil".to_string(),
+                label: Some("A title".to_string()),
+            }]
+        );
+    }
+}
@@ -0,0 +1,332 @@
+//! Google Reader API compatibility mapping
+//!
+//! Maps the commonly cloned subset of the Google Reader API
+//! onto seymour Commands, and renders seymour lists as the
+//! JSON those endpoints return, so a gateway can serve apps
+//! that speak it.
+//!
+//! Subscriptions are exposed as `feed/<id>` streams. Only
+//! unread entries are available from seymour, so every stream
+//! only ever contains unread items.
+
+use thiserror::Error;
+
+use crate::escape::is_clean_token;
+use crate::form;
+use crate::json::Value;
+use crate::{Command, Entry, EntryList, SubscriptionList};
+
+const API_PREFIX: &str = "reader/api/0/";
+const ITEM_ID_PREFIX: &str = "tag:google.com,2005:reader/item/";
+const READING_LIST: &str = "user/-/state/com.google/reading-list";
+const READ: &str = "user/-/state/com.google/read";
+
+#[derive(Debug, Error)]
+pub enum ReaderError {
+    #[error("unknown endpoint \"{0}\"")]
+    UnknownEndpoint(String),
+    #[error("missing parameter \"{0}\"")]
+    MissingParameter(String),
+    #[error("invalid value \"{value}\" for parameter \"{parameter}\"")]
+    InvalidParameter { parameter: String, value: String },
+    #[error("unsupported stream \"{0}\"")]
+    UnsupportedStream(String),
+    #[error("unsupported tag edit")]
+    UnsupportedTagEdit,
+}
+
+/// A parsed Google Reader API request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReaderRequest {
+    /// `subscription/list`
+    SubscriptionList,
+
+    /// `stream/contents`, optionally limited to one feed
+    StreamContents { feed_id: Option<i64> },
+
+    /// `stream/items/ids`, optionally limited to one feed
+    StreamItemIds { feed_id: Option<i64> },
+
+    /// `edit-tag` adding the read state to items
+    MarkRead { ids: Vec<i64> },
+
+    /// `subscription/quickadd`, or `subscription/edit` with
+    /// `ac=subscribe`
+    Subscribe { url: String },
+
+    /// `subscription/edit` with `ac=unsubscribe`
+    Unsubscribe { id: i64 },
+}
+
+fn invalid(parameter: &str, value: &str) -> ReaderError {
+    ReaderError::InvalidParameter {
+        parameter: parameter.to_string(),
+        value: value.to_string(),
+    }
+}
+
+/// A Subscribe request, if the url is a single argument that
+/// cannot end the command line or split into several
+fn subscribe(parameter: &str, url: &str) -> Result<ReaderRequest, ReaderError> {
+    if !is_clean_token(url) {
+        return Err(invalid(parameter, url));
+    }
+
+    Ok(ReaderRequest::Subscribe {
+        url: url.to_string(),
+    })
+}
+
+fn required<'a>(pairs: &'a [(String, String)], key: &str) -> Result<&'a str, ReaderError> {
+    form::get(pairs, key).ok_or_else(|| ReaderError::MissingParameter(key.to_string()))
+}
+
+/// Parse a stream id, returning the feed it is limited to
+fn parse_stream(stream: &str) -> Result<Option<i64>, ReaderError> {
+    if stream == READING_LIST {
+        return Ok(None);
+    }
+
+    stream
+        .strip_prefix("feed/")
+        .and_then(|id| id.parse().ok())
+        .map(Some)
+        .ok_or_else(|| ReaderError::UnsupportedStream(stream.to_string()))
+}
+
+/// The Google Reader id of a subscription
+pub fn stream_id(feed_id: i64) -> String {
+    format!("feed/{}", feed_id)
+}
+
+/// The long-form Google Reader id of an entry
+pub fn item_id(id: i64) -> String {
+    format!("{}{:016x}", ITEM_ID_PREFIX, id)
+}
+
+/// Parse an entry id in either the long (hex) or short
+/// (decimal) Google Reader form
+pub fn parse_item_id(value: &str) -> Option<i64> {
+    match value.strip_prefix(ITEM_ID_PREFIX) {
+        Some(hex) => u64::from_str_radix(hex, 16).ok().map(|id| id as i64),
+        None => value.parse().ok(),
+    }
+}
+
+impl ReaderRequest {
+    /// Parse a request from its path, query string and form body
+    ///
+    /// The path may include any prefix before `reader/api/0/`.
+    pub fn parse(path: &str, query: &str, body: &str) -> Result<ReaderRequest, ReaderError> {
+        let endpoint = path
+            .find(API_PREFIX)
+            .map(|index| &path[index + API_PREFIX.len()..])
+            .ok_or_else(|| ReaderError::UnknownEndpoint(path.to_string()))?;
+
+        let mut pairs = form::parse(query);
+        pairs.extend(form::parse(body));
+
+        if let Some(stream) = endpoint.strip_prefix("stream/contents") {
+            let stream = form::decode(stream.trim_start_matches('/'));
+            let stream = match stream.as_str() {
+                "" => form::get(&pairs, "s").unwrap_or(READING_LIST),
+                stream => stream,
+            };
+
+            return Ok(ReaderRequest::StreamContents {
+                feed_id: parse_stream(stream)?,
+            });
+        }
+
+        match endpoint {
+            "subscription/list" => Ok(ReaderRequest::SubscriptionList),
+            "stream/items/ids" => Ok(ReaderRequest::StreamItemIds {
+                feed_id: parse_stream(required(&pairs, "s")?)?,
+            }),
+            "edit-tag" => {
+                if form::get(&pairs, "a") != Some(READ) || form::get(&pairs, "r").is_some() {
+                    return Err(ReaderError::UnsupportedTagEdit);
+                }
+
+                let ids = form::get_all(&pairs, "i")
+                    .map(|id| parse_item_id(id).ok_or_else(|| invalid("i", id)))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                if ids.is_empty() {
+                    return Err(ReaderError::MissingParameter("i".to_string()));
+                }
+
+                Ok(ReaderRequest::MarkRead { ids })
+            }
+            "subscription/quickadd" => subscribe("quickadd", required(&pairs, "quickadd")?),
+            "subscription/edit" => {
+                let stream = required(&pairs, "s")?;
+
+                match required(&pairs, "ac")? {
+                    "subscribe" => subscribe("s", stream.strip_prefix("feed/").unwrap_or(stream)),
+                    "unsubscribe" => match parse_stream(stream)? {
+                        Some(id) => Ok(ReaderRequest::Unsubscribe { id }),
+                        None => Err(invalid("s", stream)),
+                    },
+                    action => Err(invalid("ac", action)),
+                }
+            }
+            _ => Err(ReaderError::UnknownEndpoint(endpoint.to_string())),
+        }
+    }
+
+    /// The seymour commands to issue for this request
+    ///
+    /// Does not include the User command, which the gateway
+    /// must send after authenticating the client.
+    pub fn commands(&self) -> Vec<Command> {
+        match self {
+            ReaderRequest::SubscriptionList => vec![Command::ListSubscriptions],
+            ReaderRequest::StreamContents { .. } | ReaderRequest::StreamItemIds { .. } => {
+                vec![Command::ListUnread {
+                    after: None,
+                    summary: None,
+                    count: false,
+                }]
+            }
+            ReaderRequest::MarkRead { ids } => ids
+                .iter()
+                .map(|&id| Command::MarkRead {
+                    id,
+                    expected: None,
+                    key: None,
+                })
+                .collect(),
+            ReaderRequest::Subscribe { url } => vec![Command::Subscribe {
+                url: url.clone(),
+                category: None,
+                key: None,
+                deadline: None,
+            }],
+            ReaderRequest::Unsubscribe { id } => vec![Command::Unsubscribe {
+                id: *id,
+                expected: None,
+                key: None,
+            }],
+        }
+    }
+}
+
+/// Render the `subscription/list` reply
+pub fn subscription_list(subscriptions: &SubscriptionList) -> String {
+    let subscriptions = subscriptions
+        .0
+        .iter()
+        .map(|subscription| {
+            Value::object(vec![
+                ("id", Value::from(stream_id(subscription.id))),
+                ("title", Value::from(subscription.url.as_str())),
+                ("categories", Value::Array(Vec::new())),
+                ("url", Value::from(subscription.url.as_str())),
+                ("htmlUrl", Value::from(subscription.url.as_str())),
+                (
+                    "iconUrl",
+                    Value::from(subscription.icon.as_deref().unwrap_or_default()),
+                ),
+            ])
+        })
+        .collect();
+
+    Value::object(vec![("subscriptions", Value::Array(subscriptions))]).to_string()
+}
+
+fn in_stream(entry: &Entry, feed_id: Option<i64>) -> bool {
+    match feed_id {
+        Some(feed_id) => entry.feed_id == feed_id,
+        None => true,
+    }
+}
+
+/// Render the `stream/contents` reply from the unread list
+pub fn stream_contents(entries: &EntryList, feed_id: Option<i64>) -> String {
+    let stream = feed_id.map_or_else(|| READING_LIST.to_string(), stream_id);
+
+    let items = entries
+        .0
+        .iter()
+        .filter(|entry| in_stream(entry, feed_id))
+        .map(|entry| {
+            let link = || {
+                Value::Array(vec![Value::object(vec![(
+                    "href",
+                    Value::from(entry.url.as_str()),
+                )])])
+            };
+
+            Value::object(vec![
+                ("id", Value::from(item_id(entry.id))),
+                ("title", Value::from(entry.title.as_str())),
+                (
+                    "published",
+                    Value::Integer(entry.published.unwrap_or_default()),
+                ),
+                ("canonical", link()),
+                ("alternate", link()),
+                (
+                    "origin",
+                    Value::object(vec![
+                        ("streamId", Value::from(stream_id(entry.feed_id))),
+                        ("title", Value::from(&*entry.feed_url)),
+                        ("htmlUrl", Value::from(&*entry.feed_url)),
+                    ]),
+                ),
+                ("categories", Value::Array(vec![Value::from(READING_LIST)])),
+            ])
+        })
+        .collect();
+
+    Value::object(vec![
+        ("id", Value::from(stream)),
+        ("updated", Value::Integer(0)),
+        ("items", Value::Array(items)),
+    ])
+    .to_string()
+}
+
+/// Render the `stream/items/ids` reply from the unread list
+pub fn stream_item_ids(entries: &EntryList, feed_id: Option<i64>) -> String {
+    let refs = entries
+        .0
+        .iter()
+        .filter(|entry| in_stream(entry, feed_id))
+        .map(|entry| Value::object(vec![("id", Value::from(entry.id.to_string()))]))
+        .collect();
+
+    Value::object(vec![("itemRefs", Value::Array(refs))]).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUICKADD: &str = "/reader/api/0/subscription/quickadd";
+    const EDIT: &str = "/reader/api/0/subscription/edit";
+
+    #[test]
+    fn subscribes_to_url() {
+        assert_eq!(
+            ReaderRequest::parse(QUICKADD, "", "quickadd=https%3A%2F%2Fexample.com%2Ffeed").ok(),
+            Some(ReaderRequest::Subscribe {
+                url: "https://example.com/feed".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_line_break_in_url() {
+        assert!(matches!(
+            ReaderRequest::parse(QUICKADD, "", "quickadd=a%0AUNSUBSCRIBE%201"),
+            Err(ReaderError::InvalidParameter { parameter, .. }) if parameter == "quickadd"
+        ));
+
+        assert!(matches!(
+            ReaderRequest::parse(EDIT, "", "ac=subscribe&s=feed/a%20b"),
+            Err(ReaderError::InvalidParameter { parameter, .. }) if parameter == "s"
+        ));
+    }
+}
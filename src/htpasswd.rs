@@ -0,0 +1,524 @@
+//! Users and passwords from an htpasswd file
+//!
+//! Htpasswd is an AuthProvider reading the `username:hash` lines
+//! written by Apache's htpasswd tool. SHA-1 (`{SHA}`, written by
+//! `htpasswd -s`) and Apache MD5 (`$apr1$`, written by
+//! `htpasswd -m`) hashes are supported. Files holding bcrypt or
+//! crypt(3) hashes are refused when loaded, rather than locking
+//! those users out. Tokens are not kept in htpasswd files, so no
+//! token is accepted.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::{AuthProvider, HandlerError};
+
+#[derive(Debug, Error)]
+pub enum HtpasswdError {
+    #[error("could not read htpasswd file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("line {line}: expected <username>:<hash>")]
+    InvalidLine { line: usize },
+    #[error("line {line}: unsupported hash scheme {scheme}")]
+    UnsupportedHash { line: usize, scheme: String },
+}
+
+/// Digits of the standard base64 alphabet
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Digits of the base64 alphabet used by crypt(3) hashes
+const CRYPT64: &[u8; 64] = b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+const MD5_SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+const MD5_CONSTANTS: [u32; 64] = [
+    0xd76a_a478,
+    0xe8c7_b756,
+    0x2420_70db,
+    0xc1bd_ceee,
+    0xf57c_0faf,
+    0x4787_c62a,
+    0xa830_4613,
+    0xfd46_9501,
+    0x6980_98d8,
+    0x8b44_f7af,
+    0xffff_5bb1,
+    0x895c_d7be,
+    0x6b90_1122,
+    0xfd98_7193,
+    0xa679_438e,
+    0x49b4_0821,
+    0xf61e_2562,
+    0xc040_b340,
+    0x265e_5a51,
+    0xe9b6_c7aa,
+    0xd62f_105d,
+    0x0244_1453,
+    0xd8a1_e681,
+    0xe7d3_fbc8,
+    0x21e1_cde6,
+    0xc337_07d6,
+    0xf4d5_0d87,
+    0x455a_14ed,
+    0xa9e3_e905,
+    0xfcef_a3f8,
+    0x676f_02d9,
+    0x8d2a_4c8a,
+    0xfffa_3942,
+    0x8771_f681,
+    0x6d9d_6122,
+    0xfde5_380c,
+    0xa4be_ea44,
+    0x4bde_cfa9,
+    0xf6bb_4b60,
+    0xbebf_bc70,
+    0x289b_7ec6,
+    0xeaa1_27fa,
+    0xd4ef_3085,
+    0x0488_1d05,
+    0xd9d4_d039,
+    0xe6db_99e5,
+    0x1fa2_7cf8,
+    0xc4ac_5665,
+    0xf429_2244,
+    0x432a_ff97,
+    0xab94_23a7,
+    0xfc93_a039,
+    0x655b_59c3,
+    0x8f0c_cc92,
+    0xffef_f47d,
+    0x8584_5dd1,
+    0x6fa8_7e4f,
+    0xfe2c_e6e0,
+    0xa301_4314,
+    0x4e08_11a1,
+    0xf753_7e82,
+    0xbd3a_f235,
+    0x2ad7_d2bb,
+    0xeb86_d391,
+];
+
+/// Pad a message into 64-byte blocks as MD5 and SHA-1 do,
+/// ending with its length in bits
+fn pad(data: &[u8], big_endian: bool) -> Vec<u8> {
+    let bits = (data.len() as u64).wrapping_mul(8);
+    let mut padded = data.to_vec();
+
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+
+    if big_endian {
+        padded.extend_from_slice(&bits.to_be_bytes());
+    } else {
+        padded.extend_from_slice(&bits.to_le_bytes());
+    }
+
+    padded
+}
+
+fn md5(data: &[u8]) -> [u8; 16] {
+    let mut state: [u32; 4] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476];
+
+    for block in pad(data, false).chunks_exact(64) {
+        let mut words = [0u32; 16];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        let [mut a, mut b, mut c, mut d] = state;
+
+        for (round, constant) in MD5_CONSTANTS.iter().enumerate() {
+            let (mixed, word) = match round / 16 {
+                0 => ((b & c) | (!b & d), round),
+                1 => ((d & b) | (!d & c), (5 * round + 1) % 16),
+                2 => (b ^ c ^ d, (3 * round + 5) % 16),
+                _ => (c ^ (b | !d), (7 * round) % 16),
+            };
+            let shift = MD5_SHIFTS[round / 16 * 4 + round % 4];
+            let sum = mixed
+                .wrapping_add(a)
+                .wrapping_add(*constant)
+                .wrapping_add(words[word]);
+
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(sum.rotate_left(shift));
+        }
+
+        for (value, added) in state.iter_mut().zip([a, b, c, d].iter()) {
+            *value = value.wrapping_add(*added);
+        }
+    }
+
+    let mut digest = [0u8; 16];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&value.to_le_bytes());
+    }
+
+    digest
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [
+        0x6745_2301,
+        0xefcd_ab89,
+        0x98ba_dcfe,
+        0x1032_5476,
+        0xc3d2_e1f0,
+    ];
+
+    for block in pad(data, true).chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        for index in 16..80 {
+            words[index] =
+                (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16])
+                    .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+
+        for (round, word) in words.iter().enumerate() {
+            let (mixed, constant) = match round / 20 {
+                0 => ((b & c) | (!b & d), 0x5a82_7999),
+                1 => (b ^ c ^ d, 0x6ed9_eba1),
+                2 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let next = a
+                .rotate_left(5)
+                .wrapping_add(mixed)
+                .wrapping_add(e)
+                .wrapping_add(constant)
+                .wrapping_add(*word);
+
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = next;
+        }
+
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *value = value.wrapping_add(*added);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+
+    digest
+}
+
+/// Padded standard base64
+fn base64(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (index, byte)| {
+            group | u32::from(*byte) << (16 - 8 * index)
+        });
+
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let digit = (group >> (18 - 6 * index)) & 0x3f;
+                encoded.push(char::from(BASE64[digit as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// The hash part of an Apache MD5 (`$apr1$`) hash of a password
+fn apr1(password: &[u8], salt: &[u8]) -> String {
+    let magic = b"$apr1$";
+
+    let alternate = md5(&[password, salt, password].concat());
+
+    let mut message = [password, magic, salt].concat();
+    for chunk in alternate.iter().cycle().take(password.len()) {
+        message.push(*chunk);
+    }
+
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            message.push(0);
+        } else {
+            message.extend(password.first());
+        }
+        length >>= 1;
+    }
+
+    let mut digest = md5(&message);
+
+    for round in 0..1000 {
+        let mut message = Vec::new();
+
+        if round % 2 == 1 {
+            message.extend_from_slice(password);
+        } else {
+            message.extend_from_slice(&digest);
+        }
+        if round % 3 != 0 {
+            message.extend_from_slice(salt);
+        }
+        if round % 7 != 0 {
+            message.extend_from_slice(password);
+        }
+        if round % 2 == 1 {
+            message.extend_from_slice(&digest);
+        } else {
+            message.extend_from_slice(password);
+        }
+
+        digest = md5(&message);
+    }
+
+    let mut encoded = String::new();
+    let mut push = |bytes: &[u8], digits: usize| {
+        let mut group = bytes
+            .iter()
+            .fold(0u32, |group, byte| group << 8 | u32::from(*byte));
+
+        for _ in 0..digits {
+            encoded.push(char::from(CRYPT64[(group & 0x3f) as usize]));
+            group >>= 6;
+        }
+    };
+
+    for &(first, second, third) in &[(0, 6, 12), (1, 7, 13), (2, 8, 14), (3, 9, 15), (4, 10, 5)] {
+        push(&[digest[first], digest[second], digest[third]], 4);
+    }
+    push(&[digest[11]], 2);
+
+    encoded
+}
+
+/// Compare without returning early, so timing does not reveal
+/// how much of a hash matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter()
+            .zip(b.iter())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+#[derive(Clone, PartialEq, Eq)]
+enum Hash {
+    /// Base64 SHA-1 digest
+    Sha1(String),
+    Apr1 {
+        salt: String,
+        hash: String,
+    },
+}
+
+impl Hash {
+    fn parse(hash: &str, line: usize) -> Result<Hash, HtpasswdError> {
+        if let Some(digest) = hash.strip_prefix("{SHA}") {
+            return Ok(Hash::Sha1(digest.to_string()));
+        }
+
+        if let Some((salt, hash)) = hash
+            .strip_prefix("$apr1$")
+            .and_then(|rest| rest.split_once('$'))
+        {
+            return Ok(Hash::Apr1 {
+                salt: salt.to_string(),
+                hash: hash.to_string(),
+            });
+        }
+
+        let scheme = match hash.strip_prefix('$').and_then(|rest| rest.split_once('$')) {
+            Some((scheme, _)) => scheme,
+            None => "crypt",
+        };
+
+        Err(HtpasswdError::UnsupportedHash {
+            line,
+            scheme: scheme.to_string(),
+        })
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        match self {
+            Hash::Sha1(digest) => constant_time_eq(
+                base64(&sha1(password.as_bytes())).as_bytes(),
+                digest.as_bytes(),
+            ),
+            Hash::Apr1 { salt, hash } => constant_time_eq(
+                apr1(password.as_bytes(), salt.as_bytes()).as_bytes(),
+                hash.as_bytes(),
+            ),
+        }
+    }
+}
+
+/// Users and password hashes loaded from an htpasswd file
+///
+/// Loaded once; load the file again to pick up changes.
+#[derive(Clone, Default)]
+pub struct Htpasswd {
+    users: HashMap<String, Hash>,
+}
+
+impl fmt::Debug for Htpasswd {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Htpasswd")
+            .field("users", &self.users.len())
+            .finish()
+    }
+}
+
+impl Htpasswd {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Htpasswd, HtpasswdError> {
+        Htpasswd::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Read the contents of an htpasswd file
+    ///
+    /// Blank lines and lines starting with `#` are skipped. A
+    /// user listed twice keeps the later hash.
+    pub fn parse(contents: &str) -> Result<Htpasswd, HtpasswdError> {
+        let mut users = HashMap::new();
+
+        for (index, text) in contents.lines().enumerate() {
+            let line = index.saturating_add(1);
+            let text = text.trim();
+
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+
+            let (username, hash) = text
+                .split_once(':')
+                .filter(|(username, _)| !username.is_empty())
+                .ok_or(HtpasswdError::InvalidLine { line })?;
+
+            users.insert(username.to_string(), Hash::parse(hash, line)?);
+        }
+
+        Ok(Htpasswd { users })
+    }
+}
+
+impl AuthProvider for Htpasswd {
+    fn lookup_user(&self, username: &str) -> Result<bool, HandlerError> {
+        Ok(self.users.contains_key(username))
+    }
+
+    fn verify_password(&self, username: &str, password: &str) -> Result<bool, HandlerError> {
+        Ok(self
+            .users
+            .get(username)
+            .is_some_and(|hash| hash.verify(password)))
+    }
+
+    fn verify_token(&self, _token: &str) -> Result<Option<String>, HandlerError> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// The test suite of RFC 1321, appendix A.5
+    #[test]
+    fn md5_known_answers() {
+        let vectors: &[(&str, &str)] = &[
+            ("", "d41d8cd98f00b204e9800998ecf8427e"),
+            ("a", "0cc175b9c0f1b6a831c399e269772661"),
+            ("abc", "900150983cd24fb0d6963f7d28e17f72"),
+            ("message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+            (
+                "abcdefghijklmnopqrstuvwxyz",
+                "c3fcd3d76192e4007dfb496cca67e13b",
+            ),
+            (
+                "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+                "d174ab98d277d9f5a5611c2c9f419d9f",
+            ),
+            (
+                "12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+                "57edf4a22be3c955ac49da2e2107b67a",
+            ),
+        ];
+
+        for (input, digest) in vectors {
+            assert_eq!(hex(&md5(input.as_bytes())), *digest, "{:?}", input);
+        }
+    }
+
+    /// Examples from FIPS 180
+    #[test]
+    fn sha1_known_answers() {
+        let vectors: &[(&str, &str)] = &[
+            ("", "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+            ("abc", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (
+                "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+            ),
+        ];
+
+        for (input, digest) in vectors {
+            assert_eq!(hex(&sha1(input.as_bytes())), *digest, "{:?}", input);
+        }
+
+        assert_eq!(
+            hex(&sha1(&[b'a'; 1_000_000])),
+            "34aa973cd4c4daa4f61eeb2bdbad27316534016f"
+        );
+    }
+
+    /// Lines written by `htpasswd -m` and `htpasswd -s`
+    #[test]
+    fn verifies_htpasswd_lines() {
+        let htpasswd = Htpasswd::parse(
+            "# users\n\
+             alice:$apr1$abcdefgh$h9FWgUz3n9YxylKLlR5SQ/\n\
+             bob:{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=\n\
+             carol:$apr1$ab$rsn8vNvoLDi/BIzFGAuf1/\n\
+             dave:$apr1$xyz12345$pY54s7LX3DRQiKoihbCKm1\n",
+        )
+        .unwrap();
+
+        let verify = |username, password| htpasswd.verify_password(username, password).unwrap();
+
+        assert!(verify("alice", "secret"));
+        assert!(!verify("alice", "Secret"));
+        assert!(verify("bob", "secret"));
+        assert!(!verify("bob", "secret "));
+        assert!(verify("carol", "longer-password-than-sixteen-bytes"));
+        assert!(verify("dave", ""));
+        assert!(!verify("erin", "secret"));
+    }
+
+    #[test]
+    fn rejects_unsupported_hashes() {
+        assert!(matches!(
+            Htpasswd::parse("alice:$2y$05$abcdefghijklmnopqrstuv"),
+            Err(HtpasswdError::UnsupportedHash { line: 1, scheme }) if scheme == "2y"
+        ));
+    }
+}
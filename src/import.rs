@@ -0,0 +1,68 @@
+//! Importing feeds from a plain list of URLs
+//!
+//! After IMPORT the client sends the list as PayloadLine
+//! lines and one EndList, the same framing GETCONTENT uses for
+//! documents. The server answers with one ImportResult per URL
+//! in list order.
+
+use std::fmt;
+
+use crate::escape::{Text, Token};
+
+/// What happened to a single imported URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+    /// The user is now subscribed to the feed
+    Subscribed { feed_id: i64 },
+    /// The user was already subscribed to the feed
+    AlreadySubscribed { feed_id: i64 },
+    /// The URL could not be subscribed to
+    Failed(String),
+}
+
+/// The result of importing one URL of a list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportResult {
+    pub url: String,
+    pub outcome: ImportOutcome,
+}
+
+impl ImportResult {
+    /// The feed the URL is subscribed as, unless it failed
+    pub fn feed_id(&self) -> Option<i64> {
+        match self.outcome {
+            ImportOutcome::Subscribed { feed_id }
+            | ImportOutcome::AlreadySubscribed { feed_id } => Some(feed_id),
+            ImportOutcome::Failed(_) => None,
+        }
+    }
+}
+
+/// Written as `<url> SUBSCRIBED <feed_id>`, `<url> EXISTING
+/// <feed_id>` or `<url> FAILED <message>`
+impl fmt::Display for ImportResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.outcome {
+            ImportOutcome::Subscribed { feed_id } => {
+                write!(f, "{} SUBSCRIBED {}", Token(&self.url), feed_id)
+            }
+            ImportOutcome::AlreadySubscribed { feed_id } => {
+                write!(f, "{} EXISTING {}", Token(&self.url), feed_id)
+            }
+            ImportOutcome::Failed(message) => {
+                write!(f, "{} FAILED {}", Token(&self.url), Text(message))
+            }
+        }
+    }
+}
+
+/// The URLs of a plain-text list, in order
+///
+/// Each line holds a URL, optionally followed by whitespace
+/// and anything else, such as a title. Blank lines and lines
+/// starting with `#` are skipped.
+pub fn url_list(text: &str) -> impl Iterator<Item = &str> {
+    text.lines()
+        .filter_map(|line| line.split_whitespace().next())
+        .filter(|url| !url.starts_with('#'))
+}
@@ -0,0 +1,70 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::{Entry, ParseMessageError, Response};
+
+/// Shares one allocation per distinct feed URL between
+/// decoded entries
+///
+/// A sync repeats the same feed URL on every entry of a feed,
+/// so clients holding large backlogs should decode entry
+/// lines through a single Interner.
+#[derive(Debug, Default)]
+pub struct Interner {
+    urls: HashSet<Arc<str>>,
+}
+
+impl Interner {
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    /// Return the shared copy of a string, storing it if it
+    /// has not been seen before
+    pub fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(shared) = self.urls.get(value) {
+            return Arc::clone(shared);
+        }
+
+        let shared: Arc<str> = Arc::from(value);
+        self.urls.insert(Arc::clone(&shared));
+
+        shared
+    }
+
+    /// Replace an entry's feed URL with the shared copy
+    pub fn share(&mut self, entry: &mut Entry) {
+        if let Some(shared) = self.urls.get(&*entry.feed_url) {
+            entry.feed_url = Arc::clone(shared);
+        } else {
+            self.urls.insert(Arc::clone(&entry.feed_url));
+        }
+    }
+
+    /// Parse a response line, sharing the feed URL of entries
+    pub fn parse_response(&mut self, line: &str) -> Result<Response, ParseMessageError> {
+        let mut response: Response = line.parse()?;
+
+        if let Response::Entry(entry) = &mut response {
+            self.share(entry);
+        }
+
+        Ok(response)
+    }
+
+    /// Number of distinct strings stored
+    pub fn len(&self) -> usize {
+        self.urls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.urls.is_empty()
+    }
+
+    /// Forget every stored string
+    ///
+    /// Entries already decoded keep their copies.
+    pub fn clear(&mut self) {
+        self.urls.clear();
+    }
+}
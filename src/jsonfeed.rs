@@ -0,0 +1,105 @@
+//! JSON Feed export
+//!
+//! Renders entries as a JSON Feed 1.1 document, the reply to
+//! EXPORT, so seymour data can be read by any tool that
+//! understands the format. Seymour's own ids and read state are
+//! kept under the `_seymour` extension of each item.
+
+use crate::json::Value;
+use crate::Entry;
+
+/// The media type of JSON Feed documents
+pub const MEDIA_TYPE: &str = "application/feed+json";
+
+const VERSION: &str = "https://jsonfeed.org/version/1.1";
+
+/// A time in seconds since the Unix epoch as an RFC 3339 UTC
+/// timestamp, for years 0 through 9999
+fn rfc3339(seconds: i64) -> Option<String> {
+    let days = seconds.div_euclid(86_400);
+    let time = seconds.rem_euclid(86_400);
+
+    // Days to a civil date, from Howard Hinnant's date algorithms
+    let days = days.checked_add(719_468)?;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = era.checked_mul(400)?.checked_add(year_of_era)? + i64::from(month <= 2);
+
+    if !(0..=9999).contains(&year) {
+        return None;
+    }
+
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    ))
+}
+
+fn item(entry: &Entry) -> Value {
+    let mut fields = vec![
+        (
+            "id",
+            Value::from(entry.guid.clone().unwrap_or_else(|| entry.id.to_string())),
+        ),
+        ("url", Value::from(entry.url.as_str())),
+        ("title", Value::from(entry.title.as_str())),
+    ];
+
+    if let Some(published) = entry.published.and_then(rfc3339) {
+        fields.push(("date_published", Value::from(published)));
+    }
+
+    if let Some(summary) = &entry.summary {
+        fields.push(("summary", Value::from(summary.as_str())));
+    }
+
+    if let Some(language) = &entry.language {
+        fields.push(("language", Value::from(language.as_str())));
+    }
+
+    fields.push((
+        "_seymour",
+        Value::object(vec![
+            ("entry_id", Value::Integer(entry.id)),
+            ("feed_id", Value::Integer(entry.feed_id)),
+            ("feed_url", Value::from(&*entry.feed_url)),
+            ("read_at", entry.read_at.map_or(Value::Null, Value::Integer)),
+        ]),
+    ));
+
+    Value::object(fields)
+}
+
+/// Render entries as a JSON Feed document
+///
+/// `feed_url` is the upstream feed of a single-feed export,
+/// given as the document's `feed_url`.
+pub fn document(title: &str, feed_url: Option<&str>, entries: &[Entry]) -> String {
+    let mut fields = vec![
+        ("version", Value::from(VERSION)),
+        ("title", Value::from(title)),
+    ];
+
+    if let Some(feed_url) = feed_url {
+        fields.push(("feed_url", Value::from(feed_url)));
+    }
+
+    fields.push(("items", Value::Array(entries.iter().map(item).collect())));
+
+    Value::object(fields).to_string()
+}
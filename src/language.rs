@@ -0,0 +1,206 @@
+//! BCP 47 language tags
+//!
+//! Tags are checked against the well-formedness grammar of
+//! RFC 5646 and stored with the recommended casing, such as
+//! `zh-Hant-TW`, so equal tags compare equal. Subtags are not
+//! checked against the IANA registry, and the grandfathered
+//! irregular tags such as `i-klingon` are rejected.
+//!
+//! Tags arrive from the network, so the same lints as the line
+//! parser apply here.
+
+#![deny(
+    clippy::indexing_slicing,
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::arithmetic_side_effects
+)]
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::{FromStr, Split};
+
+use thiserror::Error;
+
+/// A well-formed BCP 47 language tag
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Language(String);
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LanguageError {
+    #[error("empty language tag")]
+    Empty,
+    #[error("malformed or misplaced subtag \"{0}\"")]
+    InvalidSubtag(String),
+}
+
+fn is_alpha(subtag: &str) -> bool {
+    subtag.bytes().all(|byte| byte.is_ascii_alphabetic())
+}
+
+fn is_digit(subtag: &str) -> bool {
+    subtag.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+fn is_alphanumeric(subtag: &str) -> bool {
+    subtag.bytes().all(|byte| byte.is_ascii_alphanumeric())
+}
+
+fn titlecase(subtag: &str) -> String {
+    let mut chars = subtag.chars();
+
+    match chars.next() {
+        Some(first) => format!(
+            "{}{}",
+            first.to_ascii_uppercase(),
+            chars.as_str().to_ascii_lowercase()
+        ),
+        None => String::new(),
+    }
+}
+
+type Subtags<'a> = Peekable<Split<'a, char>>;
+
+/// Take the subtags of a private use sequence after its `x`
+fn private_use(subtags: &mut Subtags, canonical: &mut Vec<String>) -> Result<(), LanguageError> {
+    canonical.push("x".to_string());
+
+    let mut any = false;
+
+    while let Some(subtag) =
+        subtags.next_if(|subtag| (1..=8).contains(&subtag.len()) && is_alphanumeric(subtag))
+    {
+        canonical.push(subtag.to_ascii_lowercase());
+        any = true;
+    }
+
+    if any {
+        Ok(())
+    } else {
+        Err(LanguageError::InvalidSubtag("x".to_string()))
+    }
+}
+
+fn canonicalize(tag: &str) -> Result<String, LanguageError> {
+    if tag.is_empty() {
+        return Err(LanguageError::Empty);
+    }
+
+    let mut subtags = tag.split('-').peekable();
+    let mut canonical = Vec::new();
+
+    if subtags
+        .next_if(|subtag| subtag.eq_ignore_ascii_case("x"))
+        .is_some()
+    {
+        private_use(&mut subtags, &mut canonical)?;
+    } else {
+        let language = subtags.next().unwrap_or_default();
+
+        if !(2..=8).contains(&language.len()) || !is_alpha(language) {
+            return Err(LanguageError::InvalidSubtag(language.to_string()));
+        }
+
+        canonical.push(language.to_ascii_lowercase());
+
+        // Up to three extended language subtags follow a short
+        // primary language
+        if language.len() <= 3 {
+            for _ in 0..3 {
+                match subtags.next_if(|subtag| subtag.len() == 3 && is_alpha(subtag)) {
+                    Some(extlang) => canonical.push(extlang.to_ascii_lowercase()),
+                    None => break,
+                }
+            }
+        }
+
+        if let Some(script) = subtags.next_if(|subtag| subtag.len() == 4 && is_alpha(subtag)) {
+            canonical.push(titlecase(script));
+        }
+
+        if let Some(region) = subtags.next_if(|subtag| {
+            (subtag.len() == 2 && is_alpha(subtag)) || (subtag.len() == 3 && is_digit(subtag))
+        }) {
+            canonical.push(region.to_ascii_uppercase());
+        }
+
+        while let Some(variant) = subtags.next_if(|subtag| {
+            is_alphanumeric(subtag)
+                && match subtag.len() {
+                    5..=8 => true,
+                    4 => subtag.starts_with(|c: char| c.is_ascii_digit()),
+                    _ => false,
+                }
+        }) {
+            canonical.push(variant.to_ascii_lowercase());
+        }
+
+        let mut singletons = Vec::new();
+
+        while let Some(singleton) = subtags.next_if(|subtag| {
+            subtag.len() == 1 && is_alphanumeric(subtag) && !subtag.eq_ignore_ascii_case("x")
+        }) {
+            let singleton = singleton.to_ascii_lowercase();
+
+            if singletons.contains(&singleton) {
+                return Err(LanguageError::InvalidSubtag(singleton));
+            }
+
+            singletons.push(singleton.clone());
+            canonical.push(singleton.clone());
+
+            let mut any = false;
+
+            while let Some(subtag) =
+                subtags.next_if(|subtag| (2..=8).contains(&subtag.len()) && is_alphanumeric(subtag))
+            {
+                canonical.push(subtag.to_ascii_lowercase());
+                any = true;
+            }
+
+            if !any {
+                return Err(LanguageError::InvalidSubtag(singleton));
+            }
+        }
+
+        if subtags
+            .next_if(|subtag| subtag.eq_ignore_ascii_case("x"))
+            .is_some()
+        {
+            private_use(&mut subtags, &mut canonical)?;
+        }
+    }
+
+    match subtags.next() {
+        Some(subtag) => Err(LanguageError::InvalidSubtag(subtag.to_string())),
+        None => Ok(canonical.join("-")),
+    }
+}
+
+impl Language {
+    /// The tag as sent on the wire
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The primary language subtag, such as `zh` for
+    /// `zh-Hant-TW`, or `x` for private use tags
+    pub fn primary(&self) -> &str {
+        self.0.split('-').next().unwrap_or_default()
+    }
+}
+
+impl FromStr for Language {
+    type Err = LanguageError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        canonicalize(value).map(Language)
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
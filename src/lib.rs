@@ -1,28 +1,285 @@
 use std::fmt;
-use std::str::FromStr;
+use std::sync::Arc;
 
 use thiserror::Error;
 
+use crate::escape::{clean_text, is_clean_text, is_clean_token, OptionalToken, Text, Token};
+use crate::search::{Query, QueryError, SavedSearch};
+
+mod address;
+#[cfg(any(test, feature = "arbitrary"))]
+mod arbitrary;
+mod audit;
+mod blocking;
+pub mod catalog;
+mod client;
+pub mod code;
+pub mod conformance;
+mod connection;
+mod content;
+mod cursor;
+mod decoder;
+mod demux;
+mod describe;
+#[cfg(feature = "fetch")]
+mod download;
+mod error;
+mod escape;
+mod eyeballs;
+pub mod feed;
+mod fetcher;
+pub mod fever;
+mod flags;
+mod form;
+pub mod gemtext;
+pub mod greader;
+mod htpasswd;
+mod import;
+mod intern;
+mod json;
+pub mod jsonfeed;
+mod language;
+mod list;
+mod listener;
+#[cfg(any(test, feature = "memory-storage"))]
+mod memory;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+mod middleware;
+mod normalize;
+mod parse;
+pub mod proxy;
+mod quota;
+mod reader;
+pub mod record;
+mod request_id;
+pub mod rest;
+mod scheduler;
+pub mod search;
+mod server;
+pub mod socks5;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod status;
+mod sync;
+mod tcp;
+mod undo;
+mod username;
+mod validate;
+mod version;
+mod webhook;
+mod wirelog;
+
+#[cfg(feature = "metrics")]
+pub use self::metrics::CommandMetrics;
+pub use address::{
+    srv_candidates, srv_name, AddressError, ConnectConfig, SeymourUrl, SrvRecord, DEFAULT_PORT,
+};
+pub use audit::{AuditRecord, REDACTED};
+pub use blocking::BlockingClient;
+pub use client::{
+    ClientDriver, ClientError, ClientEvent, ClientEvents, ClientHandle, ConnectionClosed,
+    EntryStream, NextEntry, NextEvent, NextLine, PendingReply, Pipeline, PipelineReplies,
+    TimeoutError,
+};
+pub use connection::{read_proxy_header, serve, Peer, ServeError, ServeOptions};
+pub use content::{summarize, ContentFormat, UnknownContentFormat};
+pub use cursor::{Cursor, CursorError, MAX_CURSOR_LENGTH};
+pub use decoder::{DecodeError, Decoder, DEFAULT_MAX_LINE_LENGTH};
+pub use demux::{
+    Demultiplexer, DemuxError, DemuxEvent, Latency, LatencyStats, Reconnect, Reply, VerbLatency,
+};
+pub use describe::{ArgumentDescriptor, ArgumentKind, CommandDescriptor, ResponseDescriptor};
+#[cfg(feature = "fetch")]
+pub use download::{DownloadError, Downloader, Fetched, DEFAULT_MAX_FEED_SIZE};
+pub use error::{HandlerError, ServerError};
+pub use escape::UnencodableField;
+pub use eyeballs::{HappyEyeballs, CONNECTION_ATTEMPT_DELAY};
+pub use fetcher::{http_date, parse_http_date, Admission, FetchOutcome, HostLimiter};
+pub use flags::{EntryFlags, InvalidEntryFlags};
+pub use htpasswd::{Htpasswd, HtpasswdError};
+pub use import::{url_list, ImportOutcome, ImportResult};
+pub use intern::Interner;
+pub use language::{Language, LanguageError};
+pub use list::{
+    encode_entry_list, encode_subscription_list, EntryList, EntrySortKey, ListFrame, ListItems,
+    SubscriptionList, SubscriptionSortKey,
+};
+pub use listener::{ConnectionLimiter, ConnectionLimits, ConnectionPermit, ConnectionRefused};
+#[cfg(any(test, feature = "memory-storage"))]
+pub use memory::MemoryStorage;
+pub use middleware::{
+    AuthCheck, Call, CommandLog, Layer, Layered, QuotaPolicy, RateKey, RateLimiter, RoleCheck,
+    Service,
+};
+pub use parse::{parse_any, parse_any_versioned, Message, ParseOptions};
+pub use quota::{Quota, QuotaUsage};
+pub use reader::{Feed, FeedReader, FeedReaderError};
+pub use request_id::{Identified, RequestId, RequestIdError, MAX_REQUEST_ID_LENGTH};
+pub use scheduler::{Refresh, RefreshScheduler, DEFAULT_REFRESH_INTERVAL};
+pub use server::{
+    AuthProvider, EntryPage, Handler, LoginThrottle, ResumeTokens, ResumedSession, Role, Storage,
+    TokenGenerator,
+};
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+pub use status::{Backoff, FeedStatus};
+pub use sync::{Change, SyncEngine, SyncToken};
+pub use tcp::{ConnectBackoff, TcpOptions};
+pub use undo::Undone;
+pub use username::{UsernameError, UsernamePolicy};
+pub use validate::{validate_reply, ProtocolViolation, ReplyStatus, ReplyValidator};
+pub use version::{Capabilities, Limits, ProtocolVersion};
+pub use webhook::{validate_webhook_url, Webhook, WebhookUrlError};
+pub use wirelog::{redact_secrets, Direction, Redactor, WireLog, WireRecord, WireSink};
+
 // ############
 // # Protocol #
 // ############
 //
 // [connect]
+// > CAPABILITIES VERSIONS=1,2
+// < 30 VERSIONS=1,2
+// < 30 VERSIONS=1,2 ANONYMOUS (LISTSUBSCRIPTIONS and LISTUNREAD work without USER)
 // > USER <username>
 // < 20 <user_id>
-// > LISTFEEDS
+// > AUTH PASSWORD <username> <password>
+// > AUTH TOKEN <token>
+// < 20 <user_id>
+// < 44 <message> (invalid credentials)
+// > RESUMETOKEN
+// < 84 <token> <expires_at>
+// > RESUME <token>
+// < 20 <user_id>
+// < 44 <message> (unknown or expired token)
+// > LISTCATEGORIES
+// < 78
+// < 79 <slug> <name>
+// < 25
+// > SUBSCRIBE <url> [CATEGORY <slug>] [KEY <key>] [DEADLINE <milliseconds>]
+// < 26 [KEY <key>]
+// > LISTSUBSCRIPTIONS (legacy: LISTFEEDS)
 // < 21
 // < 22 <feed_id> <feed_url> :<feed_name>
+// < 22 <feed_id> <feed_url> <icon_url> <order> (v2)
 // < 25
-// > LISTUNREAD
+// > LISTUNREAD [AFTER <cursor>] [SUMMARY <characters>] [COUNT]
 // < 23
 // < 24 <entry_id> <feed_id> <feed_url> <entry_title> :<entry_link>
+// < 24 <entry_id> <feed_id> <feed_url> <entry_link> <published> <guid> <read_at> <group_id> <duplicate> <flags> <language> <summary> <entry_title> (v2)
+// < 25
+// < 63 <cursor> (in place of 25 when the list continues)
+// < 77 <count> (in place of the list with COUNT)
+// > SETORDER <feed_id> <order>
+// < 36
+// > GETRAWFEED <feed_id>
+// < 76 <feed_id> <media_type> (the rest of the line, or -)
+// < 67 <line> (the document last fetched)
+// < 25
+// > SETINTERVAL <feed_id> <seconds>
+// < 38
+// > FEEDSTATUS <feed_id>
+// < 37 <feed_id> <interval> <last_fetched> <etag> <last_modified> <failures> <failing_since> <next_retry>
+// > MARKREAD <entry_id> [IF <revision>] [KEY <key>]
+// < 28 [KEY <key>]
+// < 28 <revision> [KEY <key>] (v2)
+// < 45 <message> (revision changed)
+// > MARKUNREAD <entry_id> [IF <revision>]
+// < 72 <revision>
+// > UNSUBSCRIBE <feed_id> [IF <revision>] [KEY <key>]
+// < 27 [KEY <key>]
+// < 27 <revision> [KEY <key>] (v2)
+// > MARKREAD ALL
+// < 64 <count>
+// > GETCONTENT <entry_id> [FORMAT <plain|html|gemtext>]
+// < 66 <entry_id> <format>
+// < 67 <line>
+// < 25
+// > EXPORT [FEED <feed_id>]
+// < 75
+// < 67 <line> (a JSON Feed document)
 // < 25
-// > MARKREAD <entry_id>
-// < 28
+// > IMPORT
+// > 67 <url> (once per line of the list)
+// > 25
+// < 73
+// < 74 <url> SUBSCRIBED <feed_id>
+// < 74 <url> EXISTING <feed_id>
+// < 74 <url> FAILED <message>
+// < 25
+// > UNDO
+// < 65 <undone_command> (MARKREAD <entry_id>, MARKREAD ALL or UNSUBSCRIBE <feed_id>)
+// < 40 <message> (nothing to undo)
+// > LISTREAD [SINCE <timestamp>] [AFTER <cursor>] [SUMMARY <characters>] [COUNT]
+// < 23
+// < 24 ...
+// < 25
+// > SYNC [<token>]
+// < 68
+// < 22 ... (subscribed)
+// < 24 ... (new entry)
+// < 69 <entry_id> <read_at> (read state changed, - when unread again)
+// < 70 <feed_id> (unsubscribed)
+// < 71 <token>
+// > SEARCH [DEADLINE <milliseconds>] <query>
+// < 23
+// < 24 ...
+// < 25
+// > SAVESEARCH <name> <query>
+// < 80
+// > LISTSEARCHES
+// < 81
+// < 82 <name> <query>
+// < 25
+// > RUNSEARCH <name> [AFTER <cursor>] [DEADLINE <milliseconds>]
+// < 23
+// < 24 ...
+// < 25
+// > SESSION OPEN|SELECT|CLOSE <name>
+// < 83
+// > PING
+// < 29
+//
+// [admin]
+// > SETQUOTA <username> <max_feeds> <max_entries>
+// < 31 <max_feeds> <max_entries> <feeds> <entries>
+// > GETQUOTA [<username>]
+// < 31 <max_feeds> <max_entries> <feeds> <entries>
+// < 44 <message>
+//
+// [webhooks]
+// > ADDWEBHOOK <url>
+// < 39 <webhook_id>
+// > LISTWEBHOOKS
+// < 60
+// < 61 <webhook_id> <url>
+// < 25
+// > DELETEWEBHOOK <webhook_id>
+// < 62
+//
+// [sharing]
+// > SHARE <tag> <username>
+// < 32
+// > UNSHARE <tag> <username>
+// < 33
+// > LISTSHARES
+// < 34
+// < 35 <tag> <owner> <member>
+// < 25
+//
+// [transient failures, any command]
+// < 43 <message> (rate limited)
+// < 52 <message> (shutting down)
+// < 53 <message> (overloaded)
+// < 54 <message> (upstream timeout)
+// < 55 <message> (maintenance)
+//
+// [request ids, any line]
+// > @<request_id> PING
+// < @<request_id> 29
 
 /// Commands sent to seymour server
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Command {
     /// Select the user user
     User { username: String },
@@ -35,68 +292,555 @@ pub enum Command {
 
     /// Subscribe the current user to a new feed
     ///
-    /// Requires a client to issue a User
+    /// With `category`, the server files the feed under one of
+    /// the categories it lists. With `key`, see
+    /// `Command::idempotency_key`. With `deadline`, see
+    /// `Command::deadline`. Requires a client to issue a User
     /// command prior.
-    Subscribe { url: String },
+    Subscribe {
+        url: String,
+        category: Option<String>,
+        key: Option<String>,
+        deadline: Option<u32>,
+    },
 
     /// Unsubscribe the current user from a feed
     ///
+    /// With `expected`, the server refuses with Conflict unless
+    /// the subscription is still at that revision. With `key`,
+    /// see `Command::idempotency_key`. Requires a client to
+    /// issue a User command prior.
+    Unsubscribe {
+        id: i64,
+        expected: Option<i64>,
+        key: Option<String>,
+    },
+
+    /// List the current user's unread feed entries
+    ///
+    /// With a cursor, the list continues after the page that
+    /// ended in it. With `summary`, each entry carries up to
+    /// that many characters of its content. With `count`, the
+    /// server answers with the number of entries instead of the
+    /// list. Requires a client to issue a User command prior.
+    ListUnread {
+        after: Option<Cursor>,
+        summary: Option<u32>,
+        count: bool,
+    },
+
+    /// Mark a feed entry as read by the current user
+    ///
+    /// With `expected`, the server refuses with Conflict unless
+    /// the entry's read state is still at that revision. With
+    /// `key`, see `Command::idempotency_key`. Requires a client
+    /// to issue a User command prior.
+    MarkRead {
+        id: i64,
+        expected: Option<i64>,
+        key: Option<String>,
+    },
+
+    /// Mark a feed entry as unread again by the current user
+    ///
+    /// With `expected`, the server refuses with Conflict unless
+    /// the entry's read state is still at that revision.
+    /// Requires a client to issue a User command prior.
+    MarkUnread { id: i64, expected: Option<i64> },
+
+    /// Mark every unread entry of every feed as read by the
+    /// current user
+    ///
+    /// Sent as `MARKREAD ALL`. Requires a client to issue a
+    /// User command prior.
+    MarkAllRead,
+
+    /// Fetch the content of a feed entry
+    ///
+    /// The server answers in the requested format, or in a
+    /// format of its choosing without one. Requires a client
+    /// to issue a User command prior.
+    GetContent {
+        id: i64,
+        format: Option<ContentFormat>,
+    },
+
+    /// Fetch the document the server last fetched for a feed,
+    /// as it was received
+    ///
+    /// Requires a client to issue a User command prior.
+    GetRawFeed { feed_id: i64 },
+
+    /// Export the entries of a subscribed feed, or the unread
+    /// list without one, as a JSON Feed document
+    ///
+    /// Requires a client to issue a User command prior.
+    Export { feed_id: Option<i64> },
+
+    /// Subscribe the current user to every feed of a plain-text
+    /// list of URLs
+    ///
+    /// Followed by the list as PayloadLine lines and one
+    /// EndList, sent by the client. Requires a client to issue
+    /// a User command prior.
+    Import,
+
+    /// Reverse the last MarkRead, MarkAllRead or Unsubscribe
+    /// sent in this session
+    ///
+    /// Answered with ResourceNotFound if there is nothing to
+    /// undo. Requires a client to issue a User command prior.
+    Undo,
+
+    /// List the current user's read feed entries
+    ///
+    /// With `since`, only entries marked read at or after that
+    /// time (in seconds since the Unix epoch) are listed. With
+    /// a cursor, the list continues after the page that ended
+    /// in it. With `summary`, each entry carries up to that
+    /// many characters of its content. With `count`, the
+    /// server answers with the number of entries instead of the
+    /// list. Requires a client to issue a User command prior.
+    ListRead {
+        since: Option<i64>,
+        after: Option<Cursor>,
+        summary: Option<u32>,
+        count: bool,
+    },
+
+    /// List every change to the current user's state since a
+    /// sync token, ending with a new token
+    ///
+    /// Without a token the server sends its current state.
+    /// Requires a client to issue a User command prior.
+    Sync { token: Option<SyncToken> },
+
+    /// Check that the connection is still alive
+    Ping,
+
+    /// Advertise the client's capabilities and negotiate
+    /// the protocol version
+    Capabilities(Capabilities),
+
+    /// Set a user's quota
+    ///
+    /// Requires the selected user to be an administrator.
+    SetQuota { username: String, quota: Quota },
+
+    /// Read a user's quota and usage
+    ///
+    /// Without a username, reads the selected user's own quota.
+    /// Reading another user's quota requires the selected user
+    /// to be an administrator.
+    GetQuota { username: Option<String> },
+
+    /// Share the feeds under a tag with another user
+    ///
     /// Requires a client to issue a User
     /// command prior.
-    Unsubscribe { id: i64 },
+    Share { tag: String, username: String },
 
-    /// List the current user's unread feed entries
+    /// Stop sharing the feeds under a tag with another user
     ///
     /// Requires a client to issue a User
     /// command prior.
-    ListUnread,
+    Unshare { tag: String, username: String },
 
-    /// Mark a feed entry as read by the current user
+    /// List the tags shared by or with the current user
     ///
     /// Requires a client to issue a User
     /// command prior.
-    MarkRead { id: i64 },
+    ListShares,
+
+    /// List the feed categories the server knows, for
+    /// discovering feeds and filing new subscriptions
+    ListCategories,
+
+    /// Set a subscription's position in the current user's
+    /// ordering
+    ///
+    /// Requires a client to issue a User
+    /// command prior.
+    SetOrder { id: i64, order: i64 },
+
+    /// Set how often the server refreshes a feed
+    ///
+    /// Requires a client to issue a User
+    /// command prior.
+    SetInterval { feed_id: i64, seconds: u32 },
+
+    /// Show how the server is refreshing a feed
+    ///
+    /// Requires a client to issue a User
+    /// command prior.
+    FeedStatus { feed_id: i64 },
+
+    /// Register a URL to be notified when new entries arrive
+    ///
+    /// The URL is validated with `validate_webhook_url` when
+    /// parsed. Requires a client to issue a User command prior.
+    AddWebhook { url: String },
+
+    /// List the current user's webhooks
+    ///
+    /// Requires a client to issue a User
+    /// command prior.
+    ListWebhooks,
+
+    /// Delete one of the current user's webhooks
+    ///
+    /// Requires a client to issue a User
+    /// command prior.
+    DeleteWebhook { id: i64 },
+
+    /// List the current user's entries matching a query
+    ///
+    /// With `deadline`, see `Command::deadline`. Requires a
+    /// client to issue a User command prior.
+    Search { query: Query, deadline: Option<u32> },
+
+    /// Store a query under a name, replacing any query already
+    /// saved under it
+    ///
+    /// Requires a client to issue a User command prior.
+    SaveSearch { name: String, query: Query },
+
+    /// List the current user's saved searches
+    ///
+    /// Requires a client to issue a User command prior.
+    ListSavedSearches,
+
+    /// List the current user's entries matching a saved search
+    ///
+    /// Answered like Search, and continued with a cursor like
+    /// ListUnread. With `deadline`, see `Command::deadline`.
+    /// Requires a client to issue a User command prior.
+    RunSearch {
+        name: String,
+        after: Option<Cursor>,
+        deadline: Option<u32>,
+    },
+
+    /// Open, select or close a named session
+    ///
+    /// Each session keeps its own User state, so a gateway can
+    /// serve many users over one connection. Commands apply to
+    /// the selected session; a connection starts in an unnamed
+    /// one, selected again when the selected session is closed.
+    Session { action: SessionAction, name: String },
+
+    /// Authenticate and select the user the credentials belong
+    /// to
+    ///
+    /// Answered with AckUser, or Forbidden if the credentials
+    /// are not accepted.
+    Auth(Credentials),
+
+    /// Ask for a token resuming this session on a later
+    /// connection
+    ///
+    /// Requires a client to issue a User or Auth command prior.
+    ResumeToken,
+
+    /// Resume the session a token was issued for, selecting
+    /// its user again without authenticating
+    ///
+    /// Answered with AckUser, or Forbidden if the token is
+    /// unknown or has expired.
+    Resume { token: String },
 }
 
-impl fmt::Display for Command {
+/// Which verbs to use when encoding commands
+///
+/// Parsing always accepts both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dialect {
+    /// Current verbs, such as LISTSUBSCRIPTIONS
+    #[default]
+    Current,
+    /// Legacy verbs, such as LISTFEEDS, for peers predating
+    /// the current names
+    Legacy,
+}
+
+/// A command encoded in a specific dialect
+///
+/// Created by `Command::encode`.
+#[derive(Debug, Clone, Copy)]
+pub struct EncodedCommand<'a> {
+    command: &'a Command,
+    dialect: Dialect,
+}
+
+impl<'a> fmt::Display for EncodedCommand<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match self {
-            Command::User { username } => write!(f, "USER {}", username),
-            Command::ListSubscriptions => write!(f, "LISTSUBSCRIPTIONS"),
-            Command::Subscribe { url } => write!(f, "SUBSCRIBE {}", url),
-            Command::Unsubscribe { id } => write!(f, "UNSUBSCRIBE {}", id),
-            Command::ListUnread => write!(f, "LISTUNREAD"),
-            Command::MarkRead { id } => write!(f, "MARKREAD {}", id),
+        write!(f, "{}", self.command.descriptor().verb_in(self.dialect))?;
+
+        match self.command {
+            Command::User { username } => write!(f, " {}", Token(username)),
+            Command::Subscribe {
+                url,
+                category,
+                key,
+                deadline,
+            } => {
+                write!(f, " {}", Token(url))?;
+
+                if let Some(category) = category {
+                    write!(f, " CATEGORY {}", Token(category))?;
+                }
+
+                write!(f, "{}", IdempotencyKey(key))?;
+
+                match deadline {
+                    Some(deadline) => write!(f, " DEADLINE {}", deadline),
+                    None => Ok(()),
+                }
+            }
+            Command::Unsubscribe { id, expected, key }
+            | Command::MarkRead { id, expected, key } => {
+                write!(f, " {}", id)?;
+
+                if let Some(expected) = expected {
+                    write!(f, " IF {}", expected)?;
+                }
+
+                write!(f, "{}", IdempotencyKey(key))
+            }
+            Command::MarkUnread { id, expected } => {
+                write!(f, " {}", id)?;
+
+                match expected {
+                    Some(expected) => write!(f, " IF {}", expected),
+                    None => Ok(()),
+                }
+            }
+            Command::MarkAllRead => write!(f, " ALL"),
+            Command::GetContent { id, format } => {
+                write!(f, " {}", id)?;
+
+                match format {
+                    Some(format) => write!(f, " FORMAT {}", format),
+                    None => Ok(()),
+                }
+            }
+            Command::SetOrder { id, order } => write!(f, " {} {}", id, order),
+            Command::SetInterval { feed_id, seconds } => write!(f, " {} {}", feed_id, seconds),
+            Command::FeedStatus { feed_id } | Command::GetRawFeed { feed_id } => {
+                write!(f, " {}", feed_id)
+            }
+            Command::AddWebhook { url } => write!(f, " {}", Token(url)),
+            Command::DeleteWebhook { id } => write!(f, " {}", id),
+            Command::Search { query, deadline } => match deadline {
+                Some(deadline) => write!(f, " DEADLINE {} {}", deadline, query),
+                None => write!(f, " {}", query),
+            },
+            Command::SaveSearch { name, query } => write!(f, " {} {}", Token(name), query),
+            Command::RunSearch {
+                name,
+                after,
+                deadline,
+            } => {
+                write!(f, " {}", Token(name))?;
+
+                if let Some(after) = after {
+                    write!(f, " AFTER {}", after)?;
+                }
+
+                match deadline {
+                    Some(deadline) => write!(f, " DEADLINE {}", deadline),
+                    None => Ok(()),
+                }
+            }
+            Command::Session { action, name } => write!(f, " {} {}", action, Token(name)),
+            Command::Auth(credentials) => write!(f, " {}", credentials),
+            Command::Resume { token } => write!(f, " {}", Token(token)),
+            Command::Sync { token: Some(token) } => write!(f, " {}", token),
+            Command::Export {
+                feed_id: Some(feed_id),
+            } => write!(f, " FEED {}", feed_id),
+            Command::ListUnread {
+                after,
+                summary,
+                count,
+            } => {
+                if let Some(after) = after {
+                    write!(f, " AFTER {}", after)?;
+                }
+
+                if let Some(summary) = summary {
+                    write!(f, " SUMMARY {}", summary)?;
+                }
+
+                if *count {
+                    write!(f, " COUNT")?;
+                }
+
+                Ok(())
+            }
+            Command::ListRead {
+                since,
+                after,
+                summary,
+                count,
+            } => {
+                if let Some(since) = since {
+                    write!(f, " SINCE {}", since)?;
+                }
+
+                if let Some(after) = after {
+                    write!(f, " AFTER {}", after)?;
+                }
+
+                if let Some(summary) = summary {
+                    write!(f, " SUMMARY {}", summary)?;
+                }
+
+                if *count {
+                    write!(f, " COUNT")?;
+                }
+
+                Ok(())
+            }
+            Command::SetQuota { username, quota } => write!(
+                f,
+                " {} {} {}",
+                Token(username),
+                Optional(&quota.max_feeds),
+                Optional(&quota.max_entries)
+            ),
+            Command::GetQuota {
+                username: Some(username),
+            } => write!(f, " {}", Token(username)),
+            Command::Share { tag, username } | Command::Unshare { tag, username } => {
+                write!(f, " {} {}", Token(tag), Token(username))
+            }
+            Command::Capabilities(capabilities) if !capabilities.is_empty() => {
+                write!(f, " {}", capabilities)
+            }
+            Command::ListSubscriptions
+            | Command::GetQuota { username: None }
+            | Command::ListShares
+            | Command::ListCategories
+            | Command::ListSavedSearches
+            | Command::ResumeToken
+            | Command::ListWebhooks
+            | Command::Sync { token: None }
+            | Command::Export { feed_id: None }
+            | Command::Import
+            | Command::Undo
+            | Command::Ping
+            | Command::Capabilities(_) => Ok(()),
         }
     }
 }
 
-fn check_arguments(parts: &Vec<&str>, expected: usize) -> Result<(), ParseMessageError> {
-    if parts.len() > expected + 1 {
-        return Err(ParseMessageError::TooManyArguments {
-            expected,
-            actual: parts.len() - 1,
-        });
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.encode(Dialect::Current))
     }
-
-    Ok(())
 }
 
-fn at_position<T: FromStr>(
-    parts: &[&str],
-    argument_name: &str,
-    position: usize,
-) -> Result<T, ParseMessageError> {
-    let possible = parts
-        .get(position)
-        .ok_or_else(|| ParseMessageError::MissingArgument(argument_name.to_string()))?;
-
-    possible
-        .parse()
-        .map_err(|_| ParseMessageError::InvalidIntegerArgument {
-            argument: argument_name.to_string(),
-            value: possible.to_string(),
-        })
+impl Command {
+    /// Encode the command using the verbs of a dialect
+    pub fn encode(&self, dialect: Dialect) -> EncodedCommand<'_> {
+        EncodedCommand {
+            command: self,
+            dialect,
+        }
+    }
+
+    /// The verb sent on the wire for this command
+    pub fn verb(&self) -> &'static str {
+        self.descriptor().verb
+    }
+
+    /// Whether the command can be safely re-issued
+    ///
+    /// Re-sending an idempotent command (for example after
+    /// reconnecting mid-request) leaves the server in the
+    /// same state as sending it once. Subscribe and Unsubscribe
+    /// are only idempotent with an idempotency key.
+    ///
+    /// MarkAllRead is not: sent again it also marks the entries
+    /// that arrived in between, which the user never saw, and
+    /// leaves Undo reversing the second run instead of the first.
+    /// Nor are Share and Unshare, since sending one again after
+    /// another session reversed it would change who can read the
+    /// user's feeds behind their back. SetQuota sets absolute
+    /// limits, so sending it again changes nothing.
+    pub fn is_idempotent(&self) -> bool {
+        match self {
+            Command::User { .. } => true,
+            Command::ListSubscriptions => true,
+            Command::Subscribe { key, .. } => key.is_some(),
+            Command::Unsubscribe { key, .. } => key.is_some(),
+            Command::ListUnread { .. } => true,
+            Command::MarkRead { .. } => true,
+            Command::MarkUnread { .. } => true,
+            Command::MarkAllRead => false,
+            Command::GetContent { .. } => true,
+            Command::Sync { .. } => true,
+            Command::Export { .. } => true,
+            Command::GetRawFeed { .. } => true,
+            Command::Import => false,
+            Command::Undo => false,
+            Command::ListRead { .. } => true,
+            Command::Ping => true,
+            Command::Capabilities(_) => true,
+            Command::SetQuota { .. } => true,
+            Command::GetQuota { .. } => true,
+            Command::Share { .. } => false,
+            Command::Unshare { .. } => false,
+            Command::ListShares => true,
+            Command::ListCategories => true,
+            Command::SetOrder { .. } => true,
+            Command::SetInterval { .. } => true,
+            Command::FeedStatus { .. } => true,
+            Command::AddWebhook { .. } => false,
+            Command::ListWebhooks => true,
+            Command::DeleteWebhook { .. } => false,
+            Command::Search { .. } => true,
+            Command::SaveSearch { .. } => true,
+            Command::ListSavedSearches => true,
+            Command::RunSearch { .. } => true,
+            Command::Session { action, .. } => *action == SessionAction::Select,
+            Command::Auth(_) => true,
+            Command::ResumeToken => false,
+            Command::Resume { .. } => true,
+        }
+    }
+
+    /// The idempotency key the command carries
+    ///
+    /// A server that has already applied a command with the
+    /// same key for the same user acknowledges it again without
+    /// applying it twice, so mutations can be retried after a
+    /// dropped connection. Keys are chosen by the client and
+    /// must be a single word.
+    pub fn idempotency_key(&self) -> Option<&str> {
+        match self {
+            Command::Subscribe { key, .. }
+            | Command::Unsubscribe { key, .. }
+            | Command::MarkRead { key, .. } => key.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// How long the client is willing to wait, in milliseconds
+    ///
+    /// A server that cannot finish the command in time gives up
+    /// and answers with UpstreamTimeout, which clients may
+    /// retry, instead of keeping the client waiting behind a
+    /// slow upstream fetch or search.
+    pub fn deadline(&self) -> Option<u32> {
+        match self {
+            Command::Subscribe { deadline, .. }
+            | Command::Search { deadline, .. }
+            | Command::RunSearch { deadline, .. } => *deadline,
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -111,62 +855,196 @@ pub enum ParseMessageError {
     TooManyArguments { expected: usize, actual: usize },
     #[error("invalid integer value \"{value}\" for argument \"{argument}\"")]
     InvalidIntegerArgument { argument: String, value: String },
+    #[error("message is not valid UTF-8")]
+    InvalidUtf8,
+    #[error("unknown keyword \"{0}\"")]
+    UnknownKeyword(String),
+    #[error("invalid webhook URL \"{url}\": {error}")]
+    InvalidWebhookUrl { url: String, error: WebhookUrlError },
+    #[error("invalid cursor \"{value}\": {error}")]
+    InvalidCursor { value: String, error: CursorError },
+    #[error("invalid search query: {0}")]
+    InvalidQuery(#[from] QueryError),
+    #[error("command \"{0}\" cannot be undone")]
+    NotUndoable(String),
+    #[error("invalid language tag \"{value}\": {error}")]
+    InvalidLanguage { value: String, error: LanguageError },
+    #[error("invalid username \"{value}\": {error}")]
+    InvalidUsername { value: String, error: UsernameError },
+    #[error(transparent)]
+    UnknownContentFormat(#[from] UnknownContentFormat),
+    #[error(transparent)]
+    InvalidEntryFlags(#[from] InvalidEntryFlags),
+    #[error("invalid sync token \"{value}\": {error}")]
+    InvalidSyncToken { value: String, error: CursorError },
+    #[error("invalid request id \"{value}\": {error}")]
+    InvalidRequestId {
+        value: String,
+        error: RequestIdError,
+    },
 }
 
-impl FromStr for Command {
-    type Err = ParseMessageError;
-
-    fn from_str(value: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<&str> = value.split(' ').collect();
-
-        let command = parts.get(0).ok_or(ParseMessageError::EmptyMessage)?;
-
-        match *command {
-            "USER" => {
-                check_arguments(&parts, 1)?;
+/// A feed the current user is subscribed to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subscription {
+    pub id: i64,
+    pub url: String,
+    /// URL of the feed's icon
+    ///
+    /// Only sent from protocol version 2.
+    pub icon: Option<String>,
+    /// Position in the user's ordering, lowest first
+    ///
+    /// Only sent from protocol version 2.
+    pub order: Option<i64>,
+}
 
-                let username: String = at_position(&parts, "username", 1)?;
+/// A single entry of a subscribed feed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub id: i64,
+    pub feed_id: i64,
+    /// Shared between entries decoded through an Interner
+    pub feed_url: Arc<str>,
+    pub title: String,
+    pub url: String,
+    /// Publication time in seconds since the Unix epoch
+    ///
+    /// Only sent from protocol version 2.
+    pub published: Option<i64>,
+    /// Stable identifier from the feed, for deduplicating
+    /// entries whose URL changes or that are syndicated by
+    /// several feeds
+    ///
+    /// Sent as a single token from protocol version 2.
+    pub guid: Option<String>,
+    /// When the current user marked the entry read, in seconds
+    /// since the Unix epoch
+    ///
+    /// Only sent from protocol version 2.
+    pub read_at: Option<i64>,
+    /// Story the entry belongs to, shared by near-duplicate
+    /// entries the server has grouped together across feeds
+    ///
+    /// Only sent from protocol version 2.
+    pub group_id: Option<i64>,
+    /// Whether the server considers the entry a probable
+    /// duplicate of one the user has already seen
+    ///
+    /// Sent as `1` or `0` from protocol version 2.
+    pub duplicate: bool,
+    /// Whether the current user read, starred or saved the entry
+    ///
+    /// Only sent from protocol version 2.
+    pub flags: EntryFlags,
+    /// Language of the entry's content
+    ///
+    /// Only sent from protocol version 2.
+    pub language: Option<Language>,
+    /// The start of the entry's content, when the list was
+    /// requested with `SUMMARY`
+    ///
+    /// Sent percent-encoded as a single token from protocol
+    /// version 2. An empty summary is sent as absent.
+    pub summary: Option<String>,
+}
 
-                Ok(Command::User { username })
-            }
-            "LISTSUBSCRIPTIONS" => {
-                check_arguments(&parts, 0)?;
+/// A tag of feeds shared between two users
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Share {
+    pub tag: String,
+    /// The user whose feeds are shared
+    pub owner: String,
+    /// The user the feeds are shared with
+    pub member: String,
+}
 
-                Ok(Command::ListSubscriptions)
-            }
-            "SUBSCRIBE" => {
-                check_arguments(&parts, 1)?;
+impl Share {
+    /// Whether the given user owns the shared feeds
+    pub fn is_owned_by(&self, username: &str) -> bool {
+        self.owner == username
+    }
+}
 
-                let url: String = at_position(&parts, "url", 1)?;
+/// A feed category curated by the server, such as news or
+/// podcasts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Category {
+    /// Identifier sent with Subscribe, a single word
+    pub slug: String,
+    /// Human-readable name
+    pub name: String,
+}
 
-                Ok(Command::Subscribe { url })
-            }
-            "UNSUBSCRIBE" => {
-                check_arguments(&parts, 1)?;
+/// What a Session command does to the named session
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SessionAction {
+    /// Create the session and select it
+    Open,
+    Select,
+    /// End the session, forgetting its User state
+    Close,
+}
 
-                let id: i64 = at_position(&parts, "id", 1)?;
+impl SessionAction {
+    /// The keyword sent on the wire
+    pub fn keyword(self) -> &'static str {
+        match self {
+            SessionAction::Open => "OPEN",
+            SessionAction::Select => "SELECT",
+            SessionAction::Close => "CLOSE",
+        }
+    }
+}
 
-                Ok(Command::Unsubscribe { id })
-            }
-            "LISTUNREAD" => {
-                check_arguments(&parts, 0)?;
+impl fmt::Display for SessionAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.keyword())
+    }
+}
 
-                Ok(Command::ListUnread)
-            }
-            "MARKREAD" => {
-                check_arguments(&parts, 1)?;
+/// What an Auth command proves its user with
+///
+/// Debug output leaves out passwords and tokens.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum Credentials {
+    Password {
+        username: String,
+        password: String,
+    },
+    /// A token issued to a user out of band
+    Token(String),
+}
 
-                let id: i64 = at_position(&parts, "id", 1)?;
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Credentials::Password { username, .. } => f
+                .debug_struct("Password")
+                .field("username", username)
+                .field("password", &format_args!("{}", REDACTED))
+                .finish(),
+            Credentials::Token(_) => f
+                .debug_tuple("Token")
+                .field(&format_args!("{}", REDACTED))
+                .finish(),
+        }
+    }
+}
 
-                Ok(Command::MarkRead { id })
+impl fmt::Display for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Credentials::Password { username, password } => {
+                write!(f, "PASSWORD {} {}", Token(username), Token(password))
             }
-            _ => Err(ParseMessageError::UnknownType(command.to_string())),
+            Credentials::Token(token) => write!(f, "TOKEN {}", Token(token)),
         }
     }
 }
 
 /// Responses sent from seymour server
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Response {
     /// Acknowledgement for selecting current user
     AckUser { id: i64 },
@@ -181,7 +1059,7 @@ pub enum Response {
     ///
     /// Must be preceeded by one StartSubscriptionList and
     /// followed by one EndList.
-    Subscription { id: i64, url: String },
+    Subscription(Subscription),
 
     /// Beginning of a list of feed entries
     ///
@@ -193,13 +1071,7 @@ pub enum Response {
     ///
     /// Must be preceeded by one StartEntryList and
     /// followed by one EndList.
-    Entry {
-        id: i64,
-        feed_id: i64,
-        feed_url: String,
-        title: String,
-        url: String,
-    },
+    Entry(Entry),
 
     /// Ends a list sent by the server
     ///
@@ -209,15 +1081,74 @@ pub enum Response {
 
     /// Acknowledgement for subscribing the current user
     /// to a new feed
-    AckSubscribe,
+    ///
+    /// Echoes the idempotency key the command carried.
+    AckSubscribe { key: Option<String> },
 
     /// Acknowledgement for unsubscribing the current user
     /// from a feed
-    AckUnsubscribe,
+    ///
+    /// From protocol version 2, carries the subscription's new
+    /// revision if the server tracks revisions. Echoes the
+    /// idempotency key the command carried.
+    AckUnsubscribe {
+        revision: Option<i64>,
+        key: Option<String>,
+    },
 
     /// Acknowledgement for marking a feed entry as read
     /// by the current user
-    AckMarkRead,
+    ///
+    /// From protocol version 2, carries the read state's new
+    /// revision if the server tracks revisions. Echoes the
+    /// idempotency key the command carried.
+    AckMarkRead {
+        revision: Option<i64>,
+        key: Option<String>,
+    },
+
+    /// Reply to a Ping
+    Pong,
+
+    /// The server's capabilities, in reply to Capabilities
+    ///
+    /// Both sides switch to the highest protocol version they
+    /// share once this is received.
+    Capabilities(Capabilities),
+
+    /// A user's quota and usage, in reply to SetQuota or
+    /// GetQuota
+    Quota(QuotaUsage),
+
+    /// Acknowledgement for sharing a tag
+    AckShare,
+
+    /// Acknowledgement for no longer sharing a tag
+    AckUnshare,
+
+    /// Beginning of a list of shares
+    ///
+    /// Must be followed by zero or more Share lines and
+    /// one EndList.
+    StartShareList,
+
+    /// A single share, owned by or shared with the current user
+    ///
+    /// Must be preceeded by one StartShareList and
+    /// followed by one EndList.
+    Share(Share),
+
+    /// Acknowledgement for setting a subscription's position
+    AckSetOrder,
+
+    /// How a feed is being refreshed, in reply to FeedStatus
+    FeedStatus(FeedStatus),
+
+    /// Acknowledgement for setting a feed's refresh interval
+    AckSetInterval,
+
+    /// Acknowledgement for registering a webhook
+    AckAddWebhook { id: i64 },
 
     /// Error stating that the specified resource was
     /// not found
@@ -230,10 +1161,183 @@ pub enum Response {
     /// selected user, but no user has been selected
     NeedUser(String),
 
+    /// Error stating that the client has sent too many
+    /// commands and must slow down before retrying
+    RateLimited(String),
+
+    /// Error stating that the selected user is not allowed
+    /// to issue the command
+    Forbidden(String),
+
+    /// Error stating that the resource changed since the
+    /// revision the command expected
+    Conflict(String),
+
     /// Error stating that the seymour server hit an
     /// internal problem while attempting to serve
     /// the request
     InternalError(String),
+
+    /// Announcement that the seymour server is going down
+    ///
+    /// May be sent at any time. Clients should finish the
+    /// command in flight, stop sending new commands, and
+    /// reconnect after a delay.
+    ShuttingDown(String),
+
+    /// Error stating that the server is too busy to serve the
+    /// command right now
+    Overloaded(String),
+
+    /// Error stating that a service the server depends on,
+    /// such as a feed's host or the database, did not answer
+    /// in time
+    UpstreamTimeout(String),
+
+    /// Error stating that the server is down for maintenance
+    Maintenance(String),
+
+    /// Beginning of a list of webhooks
+    ///
+    /// Must be followed by zero or more Webhook lines and
+    /// one EndList.
+    StartWebhookList,
+
+    /// A single webhook registered by the current user
+    ///
+    /// Must be preceeded by one StartWebhookList and
+    /// followed by one EndList.
+    Webhook(Webhook),
+
+    /// Acknowledgement for deleting a webhook
+    AckDeleteWebhook,
+
+    /// End of one page of a list, in place of EndList
+    ///
+    /// More items follow; send the list command again with
+    /// `AFTER <cursor>` to continue.
+    EndPage(Cursor),
+
+    /// Acknowledgement for marking every unread entry as read,
+    /// with the number of entries that were marked
+    AckMarkAllRead { count: u32 },
+
+    /// Acknowledgement for an Undo, naming the action that
+    /// was reversed
+    AckUndo(Undone),
+
+    /// Beginning of an entry's content, in the format the
+    /// server chose
+    ///
+    /// Must be followed by zero or more PayloadLine lines and
+    /// one EndList.
+    StartContent {
+        entry_id: i64,
+        format: ContentFormat,
+    },
+
+    /// A single line of a document, sent verbatim
+    ///
+    /// Must be preceeded by one StartContent and followed by
+    /// one EndList.
+    PayloadLine(String),
+
+    /// Beginning of the changes answering a Sync
+    ///
+    /// Must be followed by zero or more Subscription, Entry,
+    /// ReadStateChange and Unsubscribed lines and one EndSync.
+    StartSync,
+
+    /// An entry was marked read at `read_at`, or unread again
+    /// if None
+    ///
+    /// Must be preceeded by one StartSync and followed by one
+    /// EndSync.
+    ReadStateChange { entry_id: i64, read_at: Option<i64> },
+
+    /// The current user unsubscribed from a feed
+    ///
+    /// Must be preceeded by one StartSync and followed by one
+    /// EndSync.
+    Unsubscribed { feed_id: i64 },
+
+    /// End of the changes answering a Sync, with the token to
+    /// send with the next one
+    EndSync(SyncToken),
+
+    /// Acknowledgement for marking a feed entry as unread again
+    ///
+    /// Carries the read state's new revision if the server
+    /// tracks revisions.
+    AckMarkUnread { revision: Option<i64> },
+
+    /// Beginning of the results of an Import
+    ///
+    /// Must be followed by zero or more ImportResult lines and
+    /// one EndList.
+    StartImport,
+
+    /// The result of importing one URL
+    ///
+    /// Must be preceeded by one StartImport and followed by one
+    /// EndList.
+    ImportResult(ImportResult),
+
+    /// Beginning of the JSON Feed document answering an Export
+    ///
+    /// Must be followed by zero or more PayloadLine lines and
+    /// one EndList.
+    StartExport,
+
+    /// Beginning of the upstream document answering a
+    /// GetRawFeed, with the media type it was served as, if
+    /// known
+    ///
+    /// Must be followed by zero or more PayloadLine lines and
+    /// one EndList.
+    StartRawFeed {
+        feed_id: i64,
+        media_type: Option<String>,
+    },
+
+    /// The number of entries a list command with COUNT would
+    /// have listed
+    Count(u32),
+
+    /// Beginning of a list of categories
+    ///
+    /// Must be followed by zero or more Category lines and one
+    /// EndList.
+    StartCategoryList,
+
+    /// A single category known to the server
+    ///
+    /// Must be preceeded by one StartCategoryList and followed
+    /// by one EndList.
+    Category(Category),
+
+    /// Acknowledgement for saving a search
+    AckSaveSearch,
+
+    /// Beginning of a list of saved searches
+    ///
+    /// Must be followed by zero or more SavedSearch lines and
+    /// one EndList.
+    StartSavedSearchList,
+
+    /// A single saved search
+    ///
+    /// Must be preceeded by one StartSavedSearchList and
+    /// followed by one EndList.
+    SavedSearch(SavedSearch),
+
+    /// Acknowledgement for opening, selecting or closing a
+    /// session
+    AckSession,
+
+    /// A token resuming the session until `expires`, in
+    /// seconds since the Unix epoch
+    ResumeToken { token: String, expires: i64 },
 }
 
 impl From<ParseMessageError> for Response {
@@ -242,171 +1346,747 @@ impl From<ParseMessageError> for Response {
     }
 }
 
-impl fmt::Display for Response {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+/// Constructors that clean free text the way encoding would,
+/// so the responses parse back unchanged
+///
+/// Single-word fields are kept as given, since they are
+/// percent-encoded on the wire and decoded when parsed.
+impl Response {
+    /// An entry carrying none of the fields added after
+    /// protocol version 1
+    pub fn entry(
+        id: i64,
+        feed_id: i64,
+        feed_url: &str,
+        title: impl Into<String>,
+        url: impl Into<String>,
+    ) -> Response {
+        Response::Entry(Entry {
+            id,
+            feed_id,
+            feed_url: feed_url.into(),
+            title: clean_text(title.into()),
+            url: url.into(),
+            published: None,
+            guid: None,
+            read_at: None,
+            group_id: None,
+            duplicate: false,
+            flags: EntryFlags::empty(),
+            language: None,
+            summary: None,
+        })
+    }
+
+    /// A subscription without an icon or position
+    pub fn subscription(id: i64, url: impl Into<String>) -> Response {
+        Response::Subscription(Subscription {
+            id,
+            url: url.into(),
+            icon: None,
+            order: None,
+        })
+    }
+
+    pub fn category(slug: impl Into<String>, name: impl Into<String>) -> Response {
+        Response::Category(Category {
+            slug: slug.into(),
+            name: clean_text(name.into()),
+        })
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Response {
+        Response::ResourceNotFound(clean_text(message.into()))
+    }
+
+    pub fn bad_command(message: impl Into<String>) -> Response {
+        Response::BadCommand(clean_text(message.into()))
+    }
+
+    pub fn need_user(message: impl Into<String>) -> Response {
+        Response::NeedUser(clean_text(message.into()))
+    }
+
+    pub fn rate_limited(message: impl Into<String>) -> Response {
+        Response::RateLimited(clean_text(message.into()))
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Response {
+        Response::Forbidden(clean_text(message.into()))
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Response {
+        Response::Conflict(clean_text(message.into()))
+    }
+
+    pub fn internal_error(message: impl Into<String>) -> Response {
+        Response::InternalError(clean_text(message.into()))
+    }
+
+    pub fn shutting_down(message: impl Into<String>) -> Response {
+        Response::ShuttingDown(clean_text(message.into()))
+    }
+
+    pub fn overloaded(message: impl Into<String>) -> Response {
+        Response::Overloaded(clean_text(message.into()))
+    }
+
+    pub fn upstream_timeout(message: impl Into<String>) -> Response {
+        Response::UpstreamTimeout(clean_text(message.into()))
+    }
+
+    pub fn maintenance(message: impl Into<String>) -> Response {
+        Response::Maintenance(clean_text(message.into()))
+    }
+}
+
+impl Response {
+    /// Encode the response for a protocol version
+    ///
+    /// Fields the version does not carry are left out.
+    pub fn encode(&self, version: ProtocolVersion) -> EncodedResponse<'_> {
+        EncodedResponse {
+            response: self,
+            version,
+        }
+    }
+
+    /// Encode the response for a protocol version, refusing
+    /// fields that would have to be escaped
+    ///
+    /// `encode` replaces line breaks and other control
+    /// characters in free text with spaces and percent-encodes
+    /// whitespace in single-word fields, so a hostile feed
+    /// cannot forge protocol lines. Single-word fields are
+    /// decoded when parsed, but free text stays altered. Servers
+    /// that would rather reject such content than send it
+    /// altered use this instead. A `%` is escaped too, yet never
+    /// refused, as URLs are full of them.
+    pub fn encode_strict(
+        &self,
+        version: ProtocolVersion,
+    ) -> Result<EncodedResponse<'_>, UnencodableField> {
+        let text = |field, value: &str| {
+            if is_clean_text(value) {
+                Ok(())
+            } else {
+                Err(UnencodableField { field })
+            }
+        };
+        let token = |field, value: &str| {
+            if is_clean_token(value) {
+                Ok(())
+            } else {
+                Err(UnencodableField { field })
+            }
+        };
+
         match self {
-            Response::AckUser { id } => write!(f, "20 {}", id),
-            Response::StartSubscriptionList => write!(f, "21"),
-            Response::Subscription { id, url } => write!(f, "22 {} {}", id, url),
-            Response::StartEntryList => write!(f, "23"),
-            Response::Entry {
-                id,
-                feed_id,
-                feed_url,
-                title,
-                url,
-            } => write!(f, "24 {} {} {} {} {}", id, feed_id, feed_url, url, title),
-            Response::EndList => write!(f, "25"),
-            Response::AckSubscribe => write!(f, "26"),
-            Response::AckUnsubscribe => write!(f, "27"),
-            Response::AckMarkRead => write!(f, "28"),
+            Response::Subscription(subscription) => {
+                token("url", &subscription.url)?;
+                subscription
+                    .icon
+                    .as_deref()
+                    .map_or(Ok(()), |icon| token("icon", icon))?;
+            }
+            Response::Entry(entry) => {
+                token("feed_url", &entry.feed_url)?;
+                token("url", &entry.url)?;
+                entry
+                    .guid
+                    .as_deref()
+                    .map_or(Ok(()), |guid| token("guid", guid))?;
+                text("title", &entry.title)?;
+            }
+            Response::FeedStatus(status) => {
+                status
+                    .etag
+                    .as_deref()
+                    .map_or(Ok(()), |etag| token("etag", etag))?;
+            }
+            Response::ResourceNotFound(message)
+            | Response::BadCommand(message)
+            | Response::NeedUser(message)
+            | Response::RateLimited(message)
+            | Response::Forbidden(message)
+            | Response::Conflict(message)
+            | Response::InternalError(message)
+            | Response::ShuttingDown(message)
+            | Response::Overloaded(message)
+            | Response::UpstreamTimeout(message)
+            | Response::Maintenance(message) => text("message", message)?,
+            Response::PayloadLine(line) => text("line", line)?,
+            Response::AckSubscribe { key: Some(key) }
+            | Response::AckUnsubscribe { key: Some(key), .. }
+            | Response::AckMarkRead { key: Some(key), .. } => token("key", key)?,
+            Response::ImportResult(result) => {
+                token("url", &result.url)?;
+
+                if let ImportOutcome::Failed(message) = &result.outcome {
+                    text("message", message)?;
+                }
+            }
+            Response::Category(category) => {
+                token("slug", &category.slug)?;
+                text("name", &category.name)?;
+            }
+            Response::Share(share) => {
+                token("tag", &share.tag)?;
+                token("owner", &share.owner)?;
+                token("member", &share.member)?;
+            }
+            Response::Webhook(webhook) => token("url", &webhook.url)?,
+            Response::SavedSearch(search) => token("name", &search.name)?,
+            Response::StartRawFeed {
+                media_type: Some(media_type),
+                ..
+            } => text("media_type", media_type)?,
+            Response::ResumeToken { token: value, .. } => token("token", value)?,
+            _ => {}
+        }
+
+        Ok(self.encode(version))
+    }
+
+    /// The numeric code sent on the wire for this response
+    pub fn code(&self) -> u8 {
+        self.descriptor().code
+    }
+
+    /// Whether the response reports an error (codes 40 to 59)
+    ///
+    /// ShuttingDown counts as an error even though it is not
+    /// a reply to any command. Codes from 60 are successes.
+    pub fn is_error(&self) -> bool {
+        (40..60).contains(&self.code())
+    }
+
+    /// Whether the response reports a failure that may not
+    /// happen again, so the command can be retried later
+    ///
+    /// Clients should back off before retrying; other errors
+    /// will fail the same way every time.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Response::RateLimited(_)
+                | Response::ShuttingDown(_)
+                | Response::Overloaded(_)
+                | Response::UpstreamTimeout(_)
+                | Response::Maintenance(_)
+        )
+    }
+
+    /// Whether the response is part of a successful reply
+    pub fn is_success(&self) -> bool {
+        !self.is_error()
+    }
+
+    /// Whether the response is an item inside a list
+    pub fn is_list_item(&self) -> bool {
+        matches!(
+            self,
+            Response::Subscription(_)
+                | Response::Entry(_)
+                | Response::Share(_)
+                | Response::Webhook(_)
+                | Response::PayloadLine(_)
+                | Response::ReadStateChange { .. }
+                | Response::Unsubscribed { .. }
+                | Response::ImportResult(_)
+                | Response::Category(_)
+                | Response::SavedSearch(_)
+        )
+    }
 
-            Response::ResourceNotFound(message) => write!(f, "40 {}", message),
-            Response::BadCommand(message) => write!(f, "41 {}", message),
-            Response::NeedUser(message) => write!(f, "42 {}", message),
+    /// Whether the response is the last one of a reply
+    ///
+    /// True for acknowledgements, the end of a list and error
+    /// replies. List starts and items are followed by more
+    /// responses, and ShuttingDown may arrive at any time
+    /// without ending the reply in flight.
+    pub fn terminates_command(&self) -> bool {
+        !matches!(
+            self,
+            Response::StartSubscriptionList
+                | Response::Subscription(_)
+                | Response::StartEntryList
+                | Response::Entry(_)
+                | Response::StartShareList
+                | Response::Share(_)
+                | Response::StartWebhookList
+                | Response::Webhook(_)
+                | Response::StartContent { .. }
+                | Response::PayloadLine(_)
+                | Response::StartSync
+                | Response::ReadStateChange { .. }
+                | Response::Unsubscribed { .. }
+                | Response::StartImport
+                | Response::ImportResult(_)
+                | Response::StartExport
+                | Response::StartRawFeed { .. }
+                | Response::StartCategoryList
+                | Response::Category(_)
+                | Response::StartSavedSearchList
+                | Response::SavedSearch(_)
+                | Response::ShuttingDown(_)
+        )
+    }
+}
+
+/// An argument that may be absent, sent as `-`
+struct Optional<'a, T>(&'a Option<T>);
 
-            Response::InternalError(message) => write!(f, "51 {}", message),
+/// A trailing `KEY <key>` argument, if there is a key
+struct IdempotencyKey<'a>(&'a Option<String>);
+
+impl<'a> fmt::Display for IdempotencyKey<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(key) => write!(f, " KEY {}", Token(key)),
+            None => Ok(()),
         }
     }
 }
 
-impl FromStr for Response {
-    type Err = ParseMessageError;
+impl<'a, T: fmt::Display> fmt::Display for Optional<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(value) => write!(f, "{}", value),
+            None => write!(f, "-"),
+        }
+    }
+}
 
-    fn from_str(value: &str) -> Result<Self, Self::Err> {
-        let parts: Vec<&str> = value.split(' ').collect();
+/// A summary percent-encoded as a single token
+///
+/// `-` is escaped so it cannot be mistaken for an absent
+/// summary.
+struct EncodedSummary<'a>(&'a Option<String>);
 
-        let response = parts.get(0).ok_or(ParseMessageError::EmptyMessage)?;
+impl<'a> fmt::Display for EncodedSummary<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.as_deref() {
+            None | Some("") => write!(f, "-"),
+            Some("-") => write!(f, "%2D"),
+            Some(summary) => write!(f, "{}", form::encode(summary)),
+        }
+    }
+}
 
-        match *response {
-            "20" => {
-                check_arguments(&parts, 1)?;
+/// The arguments of a Subscription line
+pub(crate) struct EncodedSubscription<'a>(pub &'a Subscription, pub ProtocolVersion);
 
-                let id: i64 = at_position(&parts, "id", 1)?;
+impl<'a> fmt::Display for EncodedSubscription<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Subscription {
+            id,
+            url,
+            icon,
+            order,
+        } = self.0;
 
-                Ok(Response::AckUser { id })
-            }
-            "21" => {
-                check_arguments(&parts, 0)?;
+        write!(f, " {} {}", id, Token(url))?;
 
-                Ok(Response::StartSubscriptionList)
-            }
-            "22" => {
-                check_arguments(&parts, 2)?;
+        if self.1 >= ProtocolVersion::V2 {
+            write!(f, " {} {}", OptionalToken(icon), Optional(order))?;
+        }
 
-                let id: i64 = at_position(&parts, "id", 1)?;
-                let url: String = at_position(&parts, "url", 2)?;
+        Ok(())
+    }
+}
 
-                Ok(Response::Subscription { id, url })
-            }
-            "23" => {
-                check_arguments(&parts, 0)?;
+/// The arguments of an Entry line
+pub(crate) struct EncodedEntry<'a>(pub &'a Entry, pub ProtocolVersion);
+
+impl<'a> fmt::Display for EncodedEntry<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Entry {
+            id,
+            feed_id,
+            feed_url,
+            title,
+            url,
+            published,
+            guid,
+            read_at,
+            group_id,
+            duplicate,
+            flags,
+            language,
+            summary,
+        } = self.0;
+
+        write!(f, " {} {} {} {}", id, feed_id, Token(feed_url), Token(url))?;
+
+        if self.1 >= ProtocolVersion::V2 {
+            write!(
+                f,
+                " {} {} {} {} {} {} {} {}",
+                Optional(published),
+                OptionalToken(guid),
+                Optional(read_at),
+                Optional(group_id),
+                u8::from(*duplicate),
+                flags,
+                Optional(language),
+                EncodedSummary(summary)
+            )?;
+        }
+
+        write!(f, " {}", Text(title))
+    }
+}
 
-                Ok(Response::StartEntryList)
+/// A response encoded for a specific protocol version
+///
+/// Created by `Response::encode`.
+#[derive(Debug, Clone, Copy)]
+pub struct EncodedResponse<'a> {
+    response: &'a Response,
+    version: ProtocolVersion,
+}
+
+impl<'a> fmt::Display for EncodedResponse<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.response.code())?;
+
+        match self.response {
+            Response::AckUser { id } => write!(f, " {}", id),
+            Response::Subscription(subscription) => {
+                write!(f, "{}", EncodedSubscription(subscription, self.version))
             }
-            "24" => {
-                let index = value
-                    .find(' ')
-                    .ok_or_else(|| ParseMessageError::MissingArgument("code".to_string()))?;
-
-                let line = &value[index + 1..];
-
-                let index = line
-                    .find(' ')
-                    .ok_or_else(|| ParseMessageError::MissingArgument("id".to_string()))?;
-
-                let id: i64 = line[..index].parse().map_err(|_| {
-                    ParseMessageError::InvalidIntegerArgument {
-                        argument: "id".to_string(),
-                        value: line[..index].to_string(),
-                    }
-                })?;
-
-                let line = &line[index + 1..];
-                let index = line
-                    .find(' ')
-                    .ok_or_else(|| ParseMessageError::MissingArgument("feed_id".to_string()))?;
-
-                let feed_id: i64 = line[..index].parse().map_err(|_| {
-                    ParseMessageError::InvalidIntegerArgument {
-                        argument: "feed_id".to_string(),
-                        value: line[..index].to_string(),
-                    }
-                })?;
-
-                let line = &line[index + 1..];
-                let index = line
-                    .find(' ')
-                    .ok_or_else(|| ParseMessageError::MissingArgument("feed_url".to_string()))?;
-                let feed_url = line[..index].to_string();
-
-                let line = &line[index + 1..];
-                let index = line
-                    .find(' ')
-                    .ok_or_else(|| ParseMessageError::MissingArgument("url".to_string()))?;
-                let url = line[..index].to_string();
-
-                let title = line[index + 1..].to_string();
-
-                Ok(Response::Entry {
-                    id,
+            Response::Entry(entry) => write!(f, "{}", EncodedEntry(entry, self.version)),
+            Response::AckSubscribe { key } => write!(f, "{}", IdempotencyKey(key)),
+            Response::AckUnsubscribe { revision, key }
+            | Response::AckMarkRead { revision, key } => {
+                if self.version >= ProtocolVersion::V2 {
+                    write!(f, " {}", Optional(revision))?;
+                }
+
+                write!(f, "{}", IdempotencyKey(key))
+            }
+            Response::Capabilities(capabilities) if capabilities.is_empty() => Ok(()),
+            Response::Capabilities(capabilities) => write!(f, " {}", capabilities),
+            Response::Quota(QuotaUsage {
+                quota,
+                feeds,
+                entries,
+            }) => write!(
+                f,
+                " {} {} {} {}",
+                Optional(&quota.max_feeds),
+                Optional(&quota.max_entries),
+                feeds,
+                entries
+            ),
+            Response::Share(Share { tag, owner, member }) => {
+                write!(f, " {} {} {}", Token(tag), Token(owner), Token(member))
+            }
+            Response::FeedStatus(FeedStatus {
+                feed_id,
+                interval,
+                last_fetched,
+                etag,
+                last_modified,
+                backoff,
+            }) => {
+                write!(
+                    f,
+                    " {} {} {} {} {}",
                     feed_id,
-                    feed_url,
-                    title,
-                    url,
-                })
+                    interval,
+                    Optional(last_fetched),
+                    OptionalToken(etag),
+                    Optional(last_modified)
+                )?;
+
+                match backoff {
+                    Some(Backoff {
+                        failures,
+                        failing_since,
+                        next_retry,
+                    }) => write!(f, " {} {} {}", failures, failing_since, next_retry),
+                    None => write!(f, " 0 - -"),
+                }
             }
-            "25" => {
-                check_arguments(&parts, 0)?;
-
-                Ok(Response::EndList)
+            Response::AckAddWebhook { id } => write!(f, " {}", id),
+
+            Response::ResourceNotFound(message) => write!(f, " {}", Text(message)),
+            Response::BadCommand(message) => write!(f, " {}", Text(message)),
+            Response::NeedUser(message) => write!(f, " {}", Text(message)),
+            Response::RateLimited(message) => write!(f, " {}", Text(message)),
+            Response::Forbidden(message) => write!(f, " {}", Text(message)),
+            Response::Conflict(message) => write!(f, " {}", Text(message)),
+
+            Response::InternalError(message) => write!(f, " {}", Text(message)),
+            Response::ShuttingDown(message) => write!(f, " {}", Text(message)),
+            Response::Overloaded(message) => write!(f, " {}", Text(message)),
+            Response::UpstreamTimeout(message) => write!(f, " {}", Text(message)),
+            Response::Maintenance(message) => write!(f, " {}", Text(message)),
+            Response::Webhook(Webhook { id, url }) => write!(f, " {} {}", id, Token(url)),
+            Response::EndPage(cursor) => write!(f, " {}", cursor),
+            Response::AckMarkAllRead { count } => write!(f, " {}", count),
+            Response::AckUndo(undone) => write!(f, " {}", undone),
+            Response::StartContent { entry_id, format } => write!(f, " {} {}", entry_id, format),
+            Response::PayloadLine(line) => write!(f, " {}", Text(line)),
+            Response::ReadStateChange { entry_id, read_at } => {
+                write!(f, " {} {}", entry_id, Optional(read_at))
             }
-            "26" => {
-                check_arguments(&parts, 0)?;
-
-                Ok(Response::AckSubscribe)
+            Response::Unsubscribed { feed_id } => write!(f, " {}", feed_id),
+            Response::EndSync(token) => write!(f, " {}", token),
+            Response::ResumeToken { token, expires } => {
+                write!(f, " {} {}", Token(token), expires)
             }
-            "27" => {
-                check_arguments(&parts, 0)?;
-
-                Ok(Response::AckUnsubscribe)
+            Response::AckMarkUnread { revision } => write!(f, " {}", Optional(revision)),
+            Response::ImportResult(result) => write!(f, " {}", result),
+            Response::StartRawFeed {
+                feed_id,
+                media_type: Some(media_type),
+            } => write!(f, " {} {}", feed_id, Text(media_type)),
+            Response::StartRawFeed {
+                feed_id,
+                media_type: None,
+            } => write!(f, " {} -", feed_id),
+            Response::Count(count) => write!(f, " {}", count),
+            Response::Category(Category { slug, name }) => {
+                write!(f, " {} {}", Token(slug), Text(name))
             }
-            "28" => {
-                check_arguments(&parts, 0)?;
-
-                Ok(Response::AckMarkRead)
+            Response::SavedSearch(SavedSearch { name, query }) => {
+                write!(f, " {} {}", Token(name), query)
             }
+            Response::StartSubscriptionList
+            | Response::StartEntryList
+            | Response::EndList
+            | Response::Pong
+            | Response::AckShare
+            | Response::AckUnshare
+            | Response::StartShareList
+            | Response::AckSetOrder
+            | Response::AckSetInterval
+            | Response::StartWebhookList
+            | Response::AckDeleteWebhook
+            | Response::StartSync
+            | Response::StartImport
+            | Response::StartExport
+            | Response::StartCategoryList
+            | Response::AckSaveSearch
+            | Response::AckSession
+            | Response::StartSavedSearchList => Ok(()),
+        }
+    }
+}
 
-            "40" => {
-                check_arguments(&parts, 1)?;
+/// Encodes the response for protocol version 1
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.encode(ProtocolVersion::V1))
+    }
+}
 
-                let message: String = at_position(&parts, "message", 1)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                Ok(Response::ResourceNotFound(message))
-            }
-            "41" => {
-                check_arguments(&parts, 1)?;
+    const HOSTILE: &str = "a b\nc";
 
-                let message: String = at_position(&parts, "message", 1)?;
+    /// Encode a response, checking that it stays on one line
+    /// and that strict encoding refuses it
+    fn reparse(response: &Response) -> Response {
+        let line = response.to_string();
 
-                Ok(Response::BadCommand(message))
-            }
-            "42" => {
-                check_arguments(&parts, 1)?;
+        assert!(!line.contains('\n'), "{:?}", line);
+        assert!(response.encode_strict(ProtocolVersion::V1).is_err());
 
-                let message: String = at_position(&parts, "message", 1)?;
+        line.parse().unwrap()
+    }
 
-                Ok(Response::NeedUser(message))
-            }
+    #[test]
+    fn escapes_share_fields() {
+        let share = |value: &str| {
+            Response::Share(Share {
+                tag: value.to_string(),
+                owner: value.to_string(),
+                member: value.to_string(),
+            })
+        };
+
+        assert_eq!(reparse(&share(HOSTILE)), share(HOSTILE));
+    }
+
+    #[test]
+    fn escapes_webhook_url() {
+        let webhook = |url: &str| {
+            Response::Webhook(Webhook {
+                id: 1,
+                url: url.to_string(),
+            })
+        };
 
-            "50" => {
-                check_arguments(&parts, 1)?;
+        assert_eq!(reparse(&webhook(HOSTILE)), webhook(HOSTILE));
+    }
 
-                let message: String = at_position(&parts, "message", 1)?;
+    #[test]
+    fn escapes_saved_search_name() {
+        let search = |name: &str| {
+            Response::SavedSearch(SavedSearch {
+                name: name.to_string(),
+                query: "rust AND \"async io\"".parse().unwrap(),
+            })
+        };
 
-                Ok(Response::InternalError(message))
+        assert_eq!(reparse(&search(HOSTILE)), search(HOSTILE));
+    }
+
+    #[test]
+    fn search_phrases_round_trip() {
+        let query = || {
+            Query::Term(crate::search::Term {
+                field: None,
+                pattern: crate::search::Pattern::Phrase(format!("{}\r\u{1b}\"", HOSTILE)),
+            })
+        };
+
+        let saved = Response::SavedSearch(SavedSearch {
+            name: "hostile".to_string(),
+            query: query(),
+        });
+        let line = saved.to_string();
+
+        assert!(!line.contains('\n'), "{:?}", line);
+        assert!(saved.encode_strict(ProtocolVersion::V1).is_ok());
+        assert_eq!(line.parse::<Response>().unwrap(), saved);
+
+        let commands = [
+            Command::Search {
+                query: query(),
+                deadline: Some(5),
+            },
+            Command::SaveSearch {
+                name: "hostile".to_string(),
+                query: query(),
+            },
+        ];
+
+        for command in commands {
+            let line = command.to_string();
+
+            assert!(!line.contains('\n'), "{:?}", line);
+            assert_eq!(line.parse::<Command>().unwrap(), command);
+        }
+    }
+
+    #[test]
+    fn escapes_resume_token() {
+        let token = |token: &str| Response::ResumeToken {
+            token: token.to_string(),
+            expires: 1_700_000_000,
+        };
+
+        assert_eq!(reparse(&token(HOSTILE)), token(HOSTILE));
+    }
+
+    #[test]
+    fn tokens_round_trip() {
+        let hostile = || Some("a b%20\nc".to_string());
+
+        let subscription = Response::Subscription(Subscription {
+            id: 1,
+            url: "a b%20\nc".to_string(),
+            icon: Some("-".to_string()),
+            order: None,
+        });
+        let entry = Response::Entry(Entry {
+            guid: Some("-".to_string()),
+            ..match Response::entry(1, 2, "a%20b", "A title", "a b") {
+                Response::Entry(entry) => entry,
+                _ => unreachable!(),
             }
-            _ => Err(ParseMessageError::UnknownType(response.to_string())),
+        });
+        let status = Response::FeedStatus(FeedStatus {
+            feed_id: 1,
+            interval: 60,
+            last_fetched: None,
+            etag: Some("-".to_string()),
+            last_modified: None,
+            backoff: None,
+        });
+
+        for response in [subscription, entry, status] {
+            let line = response.encode(ProtocolVersion::V2).to_string();
+
+            assert!(!line.contains('\n'), "{:?}", line);
+            assert_eq!(
+                Response::parse_versioned(&line, ProtocolVersion::V2).ok(),
+                Some(response),
+                "{:?}",
+                line
+            );
+        }
+
+        let entry = Response::entry(1, 2, "a%20b", "A title", "a b");
+        assert_eq!(
+            crate::parse_any(entry.to_string().as_bytes()).ok(),
+            Some(crate::Message::Response(entry))
+        );
+
+        let commands = [
+            Command::User {
+                username: "a%20b".to_string(),
+            },
+            Command::Subscribe {
+                url: "gemini://example.com/a b\nUNSUBSCRIBE 1".to_string(),
+                category: hostile(),
+                key: hostile(),
+                deadline: None,
+            },
+            Command::Share {
+                tag: "a b".to_string(),
+                username: "bob".to_string(),
+            },
+            Command::Auth(Credentials::Password {
+                username: "bob".to_string(),
+                password: "100% secret".to_string(),
+            }),
+        ];
+
+        for command in commands {
+            let line = command.to_string();
+
+            assert!(!line.contains('\n'), "{:?}", line);
+            assert_eq!(line.parse::<Command>().ok(), Some(command), "{:?}", line);
         }
+
+        assert_ne!(
+            Command::User {
+                username: "a%20b".to_string()
+            }
+            .to_string(),
+            Command::User {
+                username: "a b".to_string()
+            }
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn sends_media_type_with_parameters() {
+        let raw_feed = |media_type: &str| Response::StartRawFeed {
+            feed_id: 3,
+            media_type: Some(media_type.to_string()),
+        };
+
+        let response = raw_feed("text/html; charset=utf-8");
+
+        assert!(response.encode_strict(ProtocolVersion::V1).is_ok());
+        assert_eq!(response.to_string().parse().ok(), Some(response));
+        assert_eq!(reparse(&raw_feed(HOSTILE)), raw_feed("a b c"));
+
+        let unknown = Response::StartRawFeed {
+            feed_id: 3,
+            media_type: None,
+        };
+
+        assert_eq!(unknown.to_string().parse().ok(), Some(unknown));
     }
 }
@@ -0,0 +1,575 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::io::Write;
+use std::iter::FromIterator;
+
+use crate::code;
+use crate::search::SavedSearch;
+use crate::{
+    Category, Change, Command, ContentFormat, Cursor, EncodedEntry, EncodedSubscription, Entry,
+    ImportResult, ProtocolVersion, ProtocolViolation, ReplyValidator, Response, Share,
+    Subscription, SyncToken, Webhook,
+};
+
+/// Lazily frames a sequence of list items as a complete
+/// list reply
+///
+/// Yields the list's start response, each item as it is
+/// produced by the underlying iterator, and then one EndList
+/// (or EndPage, for a page continued by `continued_at`, or
+/// EndSync for changes).
+/// No items are buffered, so servers can write each line as
+/// soon as it is yielded.
+#[derive(Debug)]
+pub struct ListFrame<I> {
+    start: Option<Response>,
+    items: I,
+    end: Option<Response>,
+}
+
+impl<I> ListFrame<I> {
+    fn new(start: Response, items: I) -> Self {
+        ListFrame {
+            start: Some(start),
+            items,
+            end: Some(Response::EndList),
+        }
+    }
+
+    /// End the list with EndPage instead of EndList, for a
+    /// page the client can continue from the cursor
+    pub fn continued_at(self, cursor: Cursor) -> Self {
+        ListFrame {
+            end: Some(Response::EndPage(cursor)),
+            ..self
+        }
+    }
+}
+
+impl<I> ListFrame<I>
+where
+    I: Iterator,
+    I::Item: Into<Response>,
+{
+    /// Append every line of the reply to a buffer, so it can be
+    /// written with one call
+    pub fn encode_into(self, version: ProtocolVersion, buf: &mut Vec<u8>) {
+        for response in self {
+            push_line(buf, response.encode(version));
+        }
+    }
+}
+
+/// Writing to a Vec cannot fail
+fn push_line(buf: &mut Vec<u8>, line: impl fmt::Display) {
+    let _ = writeln!(buf, "{}", line);
+}
+
+/// Append a complete subscription list reply to a buffer,
+/// each line ending with `\n`
+pub fn encode_subscription_list(
+    subscriptions: &[Subscription],
+    version: ProtocolVersion,
+    buf: &mut Vec<u8>,
+) {
+    push_line(buf, code::START_SUBSCRIPTION_LIST);
+
+    for subscription in subscriptions {
+        push_line(
+            buf,
+            format_args!(
+                "{}{}",
+                code::SUBSCRIPTION,
+                EncodedSubscription(subscription, version)
+            ),
+        );
+    }
+
+    push_line(buf, code::END_LIST);
+}
+
+/// Append a complete entry list reply to a buffer, each line
+/// ending with `\n`
+pub fn encode_entry_list(entries: &[Entry], version: ProtocolVersion, buf: &mut Vec<u8>) {
+    push_line(buf, code::START_ENTRY_LIST);
+
+    for entry in entries {
+        push_line(
+            buf,
+            format_args!("{}{}", code::ENTRY, EncodedEntry(entry, version)),
+        );
+    }
+
+    push_line(buf, code::END_LIST);
+}
+
+impl<I> Iterator for ListFrame<I>
+where
+    I: Iterator,
+    I::Item: Into<Response>,
+{
+    type Item = Response;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(start) = self.start.take() {
+            return Some(start);
+        }
+
+        self.end.as_ref()?;
+
+        match self.items.next() {
+            Some(item) => Some(item.into()),
+            None => self.end.take(),
+        }
+    }
+}
+
+impl Response {
+    /// Frame subscriptions as a subscription list
+    pub fn subscription_list<I>(subscriptions: I) -> ListFrame<I::IntoIter>
+    where
+        I: IntoIterator<Item = Subscription>,
+    {
+        ListFrame::new(Response::StartSubscriptionList, subscriptions.into_iter())
+    }
+
+    /// Frame entries as an entry list
+    pub fn entry_list<I>(entries: I) -> ListFrame<I::IntoIter>
+    where
+        I: IntoIterator<Item = Entry>,
+    {
+        ListFrame::new(Response::StartEntryList, entries.into_iter())
+    }
+
+    /// Frame shares as a share list
+    pub fn share_list<I>(shares: I) -> ListFrame<I::IntoIter>
+    where
+        I: IntoIterator<Item = Share>,
+    {
+        ListFrame::new(Response::StartShareList, shares.into_iter())
+    }
+
+    /// Frame categories as a category list
+    pub fn category_list<I>(categories: I) -> ListFrame<I::IntoIter>
+    where
+        I: IntoIterator<Item = Category>,
+    {
+        ListFrame::new(Response::StartCategoryList, categories.into_iter())
+    }
+
+    /// Frame saved searches as a saved search list
+    pub fn saved_search_list<I>(searches: I) -> ListFrame<I::IntoIter>
+    where
+        I: IntoIterator<Item = SavedSearch>,
+    {
+        ListFrame::new(Response::StartSavedSearchList, searches.into_iter())
+    }
+
+    /// Frame webhooks as a webhook list
+    pub fn webhook_list<I>(webhooks: I) -> ListFrame<I::IntoIter>
+    where
+        I: IntoIterator<Item = Webhook>,
+    {
+        ListFrame::new(Response::StartWebhookList, webhooks.into_iter())
+    }
+
+    /// Frame changes as the reply to a Sync, ending with the
+    /// token for the next one
+    pub fn sync<I>(changes: I, token: SyncToken) -> ListFrame<I::IntoIter>
+    where
+        I: IntoIterator<Item = Change>,
+    {
+        ListFrame {
+            start: Some(Response::StartSync),
+            items: changes.into_iter(),
+            end: Some(Response::EndSync(token)),
+        }
+    }
+
+    /// Frame a document as an entry's content, one PayloadLine
+    /// per line
+    pub fn content(
+        entry_id: i64,
+        format: ContentFormat,
+        text: &str,
+    ) -> ListFrame<std::vec::IntoIter<Response>> {
+        ListFrame::new(
+            Response::StartContent { entry_id, format },
+            payload_lines(text),
+        )
+    }
+
+    /// Frame a feed's upstream document as the reply to a
+    /// GetRawFeed, one PayloadLine per line
+    pub fn raw_feed(
+        feed_id: i64,
+        media_type: Option<String>,
+        document: &str,
+    ) -> ListFrame<std::vec::IntoIter<Response>> {
+        ListFrame::new(
+            Response::StartRawFeed {
+                feed_id,
+                media_type,
+            },
+            payload_lines(document),
+        )
+    }
+
+    /// Frame a JSON Feed document as the reply to an Export, one
+    /// PayloadLine per line
+    pub fn export(document: &str) -> ListFrame<std::vec::IntoIter<Response>> {
+        ListFrame::new(Response::StartExport, payload_lines(document))
+    }
+
+    /// Frame the results of an Import, one per URL
+    pub fn import_results<I>(results: I) -> ListFrame<I::IntoIter>
+    where
+        I: IntoIterator<Item = ImportResult>,
+    {
+        ListFrame::new(Response::StartImport, results.into_iter())
+    }
+
+    /// The lines a client sends after Import for a list of URLs:
+    /// one PayloadLine per line and one EndList
+    pub fn import_payload(text: &str) -> Vec<Response> {
+        let mut payload: Vec<Response> = payload_lines(text).collect();
+        payload.push(Response::EndList);
+
+        payload
+    }
+
+    /// Join the PayloadLine responses of a reply back into a
+    /// document, ending each line with `\n`
+    pub fn payload_text(responses: &[Response]) -> String {
+        let mut text = String::new();
+
+        for response in responses {
+            if let Response::PayloadLine(line) = response {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+}
+
+/// Split a document into PayloadLine responses, accepting both
+/// `\n` and `\r\n` line endings
+fn payload_lines(text: &str) -> std::vec::IntoIter<Response> {
+    text.lines()
+        .map(|line| Response::PayloadLine(line.to_string()))
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+impl From<Subscription> for Response {
+    fn from(subscription: Subscription) -> Response {
+        Response::Subscription(subscription)
+    }
+}
+
+impl From<Entry> for Response {
+    fn from(entry: Entry) -> Response {
+        Response::Entry(entry)
+    }
+}
+
+impl From<Share> for Response {
+    fn from(share: Share) -> Response {
+        Response::Share(share)
+    }
+}
+
+impl From<Category> for Response {
+    fn from(category: Category) -> Response {
+        Response::Category(category)
+    }
+}
+
+impl From<SavedSearch> for Response {
+    fn from(search: SavedSearch) -> Response {
+        Response::SavedSearch(search)
+    }
+}
+
+impl From<Webhook> for Response {
+    fn from(webhook: Webhook) -> Response {
+        Response::Webhook(webhook)
+    }
+}
+
+impl From<ImportResult> for Response {
+    fn from(result: ImportResult) -> Response {
+        Response::ImportResult(result)
+    }
+}
+
+/// Orders entries oldest first, with undated entries after
+/// dated ones and ties broken by id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EntrySortKey {
+    pub undated: bool,
+    pub published: i64,
+    pub id: i64,
+}
+
+/// Orders subscriptions by URL ignoring case, with ties broken
+/// by id
+///
+/// Subscriptions carry no name on the wire, only the feed's URL,
+/// so the URL is what they sort by where a client would show a
+/// name.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubscriptionSortKey {
+    pub url: String,
+    pub id: i64,
+}
+
+impl Entry {
+    pub fn sort_key(&self) -> EntrySortKey {
+        EntrySortKey {
+            undated: self.published.is_none(),
+            published: self.published.unwrap_or_default(),
+            id: self.id,
+        }
+    }
+}
+
+impl Subscription {
+    pub fn sort_key(&self) -> SubscriptionSortKey {
+        SubscriptionSortKey {
+            url: self.url.to_lowercase(),
+            id: self.id,
+        }
+    }
+}
+
+/// A complete list of subscriptions
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SubscriptionList(pub Vec<Subscription>);
+
+impl SubscriptionList {
+    /// Encode the list as a complete reply
+    pub fn into_responses(self) -> ListFrame<std::vec::IntoIter<Subscription>> {
+        Response::subscription_list(self.0)
+    }
+
+    /// Sort by each subscription's position, with subscriptions
+    /// lacking one placed last and ties broken by id
+    pub fn sort_by_order(&mut self) {
+        self.0.sort_by_key(|subscription| {
+            (
+                subscription.order.is_none(),
+                subscription.order,
+                subscription.id,
+            )
+        });
+    }
+
+    /// Sort by URL ignoring case, with ties broken by id
+    pub fn sort_by_url(&mut self) {
+        self.0.sort_by_cached_key(Subscription::sort_key);
+    }
+}
+
+impl FromIterator<Subscription> for SubscriptionList {
+    fn from_iter<I: IntoIterator<Item = Subscription>>(iter: I) -> Self {
+        SubscriptionList(iter.into_iter().collect())
+    }
+}
+
+/// A complete list of feed entries
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryList(pub Vec<Entry>);
+
+impl EntryList {
+    /// Encode the list as a complete reply
+    pub fn into_responses(self) -> ListFrame<std::vec::IntoIter<Entry>> {
+        Response::entry_list(self.0)
+    }
+
+    /// Sort by id, which servers assign in the order entries
+    /// arrive
+    pub fn sort_by_id(&mut self) {
+        self.0.sort_by_key(|entry| entry.id);
+    }
+
+    /// Sort oldest first by publication time, with undated
+    /// entries last and ties broken by id
+    pub fn sort_by_published(&mut self) {
+        self.0.sort_by_key(Entry::sort_key);
+    }
+
+    /// Keep only the first entry of each group, collapsing
+    /// near-duplicate stories; ungrouped entries are all kept
+    pub fn collapse_groups(&mut self) {
+        let mut seen = HashSet::new();
+
+        self.0.retain(|entry| match entry.group_id {
+            Some(group_id) => seen.insert(group_id),
+            None => true,
+        });
+    }
+}
+
+impl FromIterator<Entry> for EntryList {
+    fn from_iter<I: IntoIterator<Item = Entry>>(iter: I) -> Self {
+        EntryList(iter.into_iter().collect())
+    }
+}
+
+/// Yields the items of a list reply as they are received
+///
+/// Responses are checked with a ReplyValidator as they are
+/// pulled from the underlying iterator. The start response and
+/// EndList are consumed; list items, the EndPage ending a
+/// partial list, the EndSync carrying the next sync token and
+/// ShuttingDown announcements are yielded, as are StartContent
+/// and StartRawFeed, which name the document's format.
+/// If the server answers with an error response instead of a
+/// list, that response is yielded and iteration ends.
+#[derive(Debug)]
+pub struct ListItems<I> {
+    validator: ReplyValidator,
+    responses: I,
+    done: bool,
+}
+
+impl<I> ListItems<I> {
+    pub fn new<R>(command: &Command, responses: R) -> Self
+    where
+        R: IntoIterator<IntoIter = I>,
+    {
+        ListItems {
+            validator: ReplyValidator::new(command),
+            responses: responses.into_iter(),
+            done: false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Response>> Iterator for ListItems<I> {
+    type Item = Result<Response, ProtocolViolation>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let response = match self.responses.next() {
+                Some(response) => response,
+                None => {
+                    self.done = true;
+                    return self.validator.finish().err().map(Err);
+                }
+            };
+
+            if let Err(e) = self.validator.accept(&response) {
+                self.done = true;
+                return Some(Err(e));
+            }
+
+            self.done = self.validator.is_complete();
+
+            match response {
+                Response::StartSubscriptionList
+                | Response::StartEntryList
+                | Response::StartShareList
+                | Response::StartCategoryList
+                | Response::StartSavedSearchList
+                | Response::StartWebhookList
+                | Response::StartSync
+                | Response::StartImport
+                | Response::StartExport
+                | Response::EndList => continue,
+                response => return Some(Ok(response)),
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: i64, published: Option<i64>) -> Entry {
+        match Response::entry(id, 1, "https://example.com", "Title", "https://example.com") {
+            Response::Entry(entry) => Entry { published, ..entry },
+            _ => unreachable!(),
+        }
+    }
+
+    fn subscription(id: i64, url: &str, order: Option<i64>) -> Subscription {
+        Subscription {
+            id,
+            url: url.to_string(),
+            icon: None,
+            order,
+        }
+    }
+
+    fn entry_ids(entries: &EntryList) -> Vec<i64> {
+        entries.0.iter().map(|entry| entry.id).collect()
+    }
+
+    fn subscription_ids(subscriptions: &SubscriptionList) -> Vec<i64> {
+        subscriptions
+            .0
+            .iter()
+            .map(|subscription| subscription.id)
+            .collect()
+    }
+
+    #[test]
+    fn entries_sort_oldest_first() {
+        let mut entries = EntryList(vec![
+            entry(5, None),
+            entry(4, Some(20)),
+            entry(1, None),
+            entry(3, Some(10)),
+            entry(2, Some(20)),
+        ]);
+
+        entries.sort_by_published();
+        assert_eq!(entry_ids(&entries), [3, 2, 4, 1, 5]);
+
+        entries.sort_by_id();
+        assert_eq!(entry_ids(&entries), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn subscriptions_sort_by_url_ignoring_case() {
+        let mut subscriptions = SubscriptionList(vec![
+            subscription(1, "https://c.example", None),
+            subscription(4, "https://A.example", None),
+            subscription(2, "https://b.example", None),
+            subscription(3, "https://a.example", None),
+        ]);
+
+        subscriptions.sort_by_url();
+        assert_eq!(subscription_ids(&subscriptions), [3, 4, 2, 1]);
+    }
+
+    #[test]
+    fn subscriptions_sort_by_order() {
+        let mut subscriptions = SubscriptionList(vec![
+            subscription(1, "https://a.example", None),
+            subscription(2, "https://b.example", Some(2)),
+            subscription(3, "https://c.example", Some(1)),
+            subscription(4, "https://d.example", Some(2)),
+        ]);
+
+        subscriptions.sort_by_order();
+        assert_eq!(subscription_ids(&subscriptions), [3, 2, 4, 1]);
+    }
+
+    #[test]
+    fn sort_keys() {
+        assert!(entry(2, Some(10)).sort_key() < entry(1, Some(20)).sort_key());
+        assert!(entry(2, Some(10)).sort_key() < entry(1, None).sort_key());
+        assert!(
+            subscription(2, "https://A.example", None).sort_key()
+                < subscription(1, "https://b.example", None).sort_key()
+        );
+    }
+}
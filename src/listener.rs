@@ -0,0 +1,163 @@
+//! Admitting connections to a server
+//!
+//! A server's accept loop asks a ConnectionLimiter before serving
+//! each new socket. Past its limits a connection is refused, and
+//! the refusal converts into an Overloaded response to write
+//! before closing the socket, so clients back off and retry
+//! rather than wait on a connection the server has no room to
+//! serve. Admitted connections hold a ConnectionPermit until
+//! they close.
+//!
+//! IPv6 clients are counted by their /64 prefix, since a single
+//! host usually has a whole prefix to draw addresses from.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use thiserror::Error;
+
+use crate::catalog::ErrorMessage;
+use crate::Response;
+
+/// How many connections a server serves at once
+///
+/// A limit of None means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    pub max_connections: Option<usize>,
+    /// Connections from each client address
+    pub max_per_address: Option<usize>,
+}
+
+/// Why a connection was not admitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum ConnectionRefused {
+    #[error("server full: {max} connections open")]
+    ServerFull { max: usize },
+    #[error("{max} connections already open from the address")]
+    TooManyFromAddress { max: usize },
+}
+
+/// The response to write before closing a refused connection
+impl From<ConnectionRefused> for Response {
+    fn from(refused: ConnectionRefused) -> Response {
+        let message = match refused {
+            ConnectionRefused::ServerFull { max } => {
+                ErrorMessage::new("server-full", "server full").param("max", max)
+            }
+            ConnectionRefused::TooManyFromAddress { max } => {
+                ErrorMessage::new("too-many-connections", "too many connections").param("max", max)
+            }
+        };
+
+        Response::Overloaded(message.to_string())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counts {
+    total: usize,
+    by_address: HashMap<IpAddr, usize>,
+}
+
+/// The address connections are counted under
+fn address_key(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V4(_) => address,
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => {
+                let prefix = u128::from(v6) & !(u128::MAX >> 64);
+                IpAddr::V6(Ipv6Addr::from(prefix))
+            }
+        },
+    }
+}
+
+/// Counts a server's open connections against its limits
+#[derive(Debug, Default)]
+pub struct ConnectionLimiter {
+    limits: ConnectionLimits,
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(limits: ConnectionLimits) -> ConnectionLimiter {
+        ConnectionLimiter {
+            limits,
+            counts: Arc::default(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Counts> {
+        self.counts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Admit a connection from `address`
+    pub fn admit(&self, address: IpAddr) -> Result<ConnectionPermit, ConnectionRefused> {
+        let key = address_key(address);
+        let mut counts = self.lock();
+
+        if let Some(max) = self.limits.max_connections {
+            if counts.total >= max {
+                return Err(ConnectionRefused::ServerFull { max });
+            }
+        }
+
+        let from_address = counts.by_address.get(&key).copied().unwrap_or_default();
+
+        if let Some(max) = self.limits.max_per_address {
+            if from_address >= max {
+                return Err(ConnectionRefused::TooManyFromAddress { max });
+            }
+        }
+
+        counts.total = counts.total.saturating_add(1);
+        counts
+            .by_address
+            .insert(key, from_address.saturating_add(1));
+
+        Ok(ConnectionPermit {
+            address: key,
+            counts: Arc::clone(&self.counts),
+        })
+    }
+
+    /// Connections admitted and still open
+    pub fn connections(&self) -> usize {
+        self.lock().total
+    }
+
+    /// Connections open from an address, or from its /64 prefix
+    /// for IPv6
+    pub fn connections_from(&self, address: IpAddr) -> usize {
+        self.lock()
+            .by_address
+            .get(&address_key(address))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// An admitted connection, counted until dropped
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    address: IpAddr,
+    counts: Arc<Mutex<Counts>>,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut counts = self.counts.lock().unwrap_or_else(PoisonError::into_inner);
+        counts.total = counts.total.saturating_sub(1);
+
+        if let Some(count) = counts.by_address.get_mut(&self.address) {
+            *count = count.saturating_sub(1);
+
+            if *count == 0 {
+                counts.by_address.remove(&self.address);
+            }
+        }
+    }
+}
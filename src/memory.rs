@@ -0,0 +1,517 @@
+//! Storage kept in memory
+//!
+//! MemoryStorage implements Storage without a database, for tests
+//! of servers and clients. Users and entries may be added
+//! directly, standing in for account creation and feed fetching. Every
+//! change to a subscription or read state takes the next revision
+//! of a single counter.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::feed::FeedItem;
+use crate::{
+    Cursor, Entry, EntryPage, HandlerError, Quota, Response, Role, ServerError, Storage,
+    Subscription,
+};
+
+/// Entries per page of the unread list
+const PAGE_SIZE: usize = 100;
+
+#[derive(Debug)]
+struct StoredSubscription {
+    id: i64,
+    user: i64,
+    feed: i64,
+    revision: i64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    users: Vec<String>,
+    feeds: Vec<String>,
+    subscriptions: Vec<StoredSubscription>,
+    entries: Vec<Entry>,
+    /// The revision and whether the entry is read, by user and
+    /// entry
+    read: HashMap<(i64, i64), (i64, bool)>,
+    roles: HashMap<i64, Role>,
+    quotas: HashMap<i64, Quota>,
+    next_subscription: i64,
+    revision: i64,
+}
+
+/// The id of the row at an index, for tables whose ids start
+/// at 1
+fn id_at(index: usize) -> i64 {
+    i64::try_from(index).unwrap_or(i64::MAX).saturating_add(1)
+}
+
+impl State {
+    fn next_revision(&mut self) -> i64 {
+        self.revision = self.revision.saturating_add(1);
+        self.revision
+    }
+
+    fn feed(&mut self, url: &str) -> i64 {
+        match self.feeds.iter().position(|feed| feed == url) {
+            Some(index) => id_at(index),
+            None => {
+                let id = id_at(self.feeds.len());
+                self.feeds.push(url.to_string());
+                id
+            }
+        }
+    }
+
+    fn is_subscribed(&self, user: i64, feed: i64) -> bool {
+        self.subscriptions
+            .iter()
+            .any(|subscription| subscription.user == user && subscription.feed == feed)
+    }
+
+    fn is_unread(&self, user: i64, entry: &Entry) -> bool {
+        self.is_subscribed(user, entry.feed_id)
+            && !matches!(self.read.get(&(user, entry.id)), Some((_, true)))
+    }
+
+    fn set_read(
+        &mut self,
+        user: i64,
+        entry: i64,
+        expected: Option<i64>,
+        read: bool,
+    ) -> Result<Option<i64>, HandlerError> {
+        if !self
+            .entries
+            .iter()
+            .any(|stored| stored.id == entry && self.is_subscribed(user, stored.feed_id))
+        {
+            return Err(HandlerError::NotFound(format!("entry {}", entry)));
+        }
+
+        let current = self
+            .read
+            .get(&(user, entry))
+            .map_or(0, |(revision, _)| *revision);
+
+        if expected.is_some_and(|expected| expected != current) {
+            return Err(HandlerError::Conflict(format!(
+                "entry {} is at revision {}",
+                entry, current
+            )));
+        }
+
+        let revision = self.next_revision();
+        self.read.insert((user, entry), (revision, read));
+
+        Ok(Some(revision))
+    }
+}
+
+/// Storage in memory, lost when dropped
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    state: Mutex<State>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Add a user, returning its id, or the id it already has
+    pub fn add_user(&self, username: &str) -> i64 {
+        let mut state = self.lock();
+
+        match state.users.iter().position(|user| user == username) {
+            Some(index) => id_at(index),
+            None => {
+                let id = id_at(state.users.len());
+                state.users.push(username.to_string());
+                id
+            }
+        }
+    }
+
+    /// Add an entry to a feed, as if it had been fetched,
+    /// returning the entry's id
+    pub fn add_entry(&self, feed_url: &str, title: &str, url: &str) -> i64 {
+        let mut state = self.lock();
+        let feed = state.feed(feed_url);
+        let id = id_at(state.entries.len());
+
+        if let Response::Entry(entry) = Response::entry(id, feed, feed_url, title, url) {
+            state.entries.push(entry);
+        }
+
+        id
+    }
+
+    pub fn set_role(&self, user: i64, role: Role) {
+        self.lock().roles.insert(user, role);
+    }
+
+    /// Add a feed no one subscribes to yet, returning its id,
+    /// or the id it already has
+    pub fn add_feed(&self, url: &str) -> i64 {
+        self.lock().feed(url)
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn user(&self, username: &str) -> Result<i64, HandlerError> {
+        let state = self.lock();
+
+        state
+            .users
+            .iter()
+            .position(|user| user == username)
+            .map(id_at)
+            .ok_or_else(|| HandlerError::NotFound(format!("user {}", username)))
+    }
+
+    fn subscriptions(&self, user: i64) -> Result<Vec<Subscription>, HandlerError> {
+        let state = self.lock();
+
+        Ok(state
+            .subscriptions
+            .iter()
+            .filter(|subscription| subscription.user == user)
+            .filter_map(|subscription| {
+                let url = usize::try_from(subscription.feed.saturating_sub(1))
+                    .ok()
+                    .and_then(|index| state.feeds.get(index))?;
+
+                match Response::subscription(subscription.id, url.as_str()) {
+                    Response::Subscription(subscription) => Some(subscription),
+                    _ => None,
+                }
+            })
+            .collect())
+    }
+
+    fn subscribe(&self, user: i64, url: &str, _category: Option<&str>) -> Result<(), HandlerError> {
+        let mut state = self.lock();
+        let feed = state.feed(url);
+
+        if state.is_subscribed(user, feed) {
+            return Ok(());
+        }
+
+        state.next_subscription = state.next_subscription.saturating_add(1);
+        let id = state.next_subscription;
+        let revision = state.next_revision();
+
+        state.subscriptions.push(StoredSubscription {
+            id,
+            user,
+            feed,
+            revision,
+        });
+
+        Ok(())
+    }
+
+    fn unsubscribe(
+        &self,
+        user: i64,
+        subscription: i64,
+        expected: Option<i64>,
+    ) -> Result<Option<i64>, HandlerError> {
+        let mut state = self.lock();
+
+        let index = state
+            .subscriptions
+            .iter()
+            .position(|stored| stored.id == subscription && stored.user == user)
+            .ok_or_else(|| HandlerError::NotFound(format!("subscription {}", subscription)))?;
+        let current = state
+            .subscriptions
+            .get(index)
+            .map_or(0, |stored| stored.revision);
+
+        if expected.is_some_and(|expected| expected != current) {
+            return Err(HandlerError::Conflict(format!(
+                "subscription {} is at revision {}",
+                subscription, current
+            )));
+        }
+
+        state.subscriptions.remove(index);
+
+        Ok(Some(state.next_revision()))
+    }
+
+    /// Summaries are not stored, so entries carry none
+    fn unread(
+        &self,
+        user: i64,
+        after: Option<&Cursor>,
+        _summary: Option<u32>,
+    ) -> Result<EntryPage, HandlerError> {
+        let state = self.lock();
+
+        let after = match after {
+            Some(cursor) => <[u8; 8]>::try_from(cursor.to_bytes().as_slice())
+                .map(i64::from_be_bytes)
+                .map_err(|_| ServerError::BadCommand("invalid cursor".to_string()))?,
+            None => 0,
+        };
+
+        let mut unread = state
+            .entries
+            .iter()
+            .filter(|entry| entry.id > after && state.is_unread(user, entry));
+        let entries: Vec<Entry> = unread.by_ref().take(PAGE_SIZE).cloned().collect();
+
+        let next = match (unread.next(), entries.last()) {
+            (Some(_), Some(last)) => Cursor::from_bytes(&last.id.to_be_bytes()).ok(),
+            _ => None,
+        };
+
+        Ok(EntryPage { entries, next })
+    }
+
+    fn unread_count(&self, user: i64) -> Result<u32, HandlerError> {
+        let state = self.lock();
+        let count = state
+            .entries
+            .iter()
+            .filter(|entry| state.is_unread(user, entry))
+            .count();
+
+        Ok(u32::try_from(count).unwrap_or(u32::MAX))
+    }
+
+    fn mark_read(
+        &self,
+        user: i64,
+        entry: i64,
+        expected: Option<i64>,
+    ) -> Result<Option<i64>, HandlerError> {
+        self.lock().set_read(user, entry, expected, true)
+    }
+
+    fn mark_unread(
+        &self,
+        user: i64,
+        entry: i64,
+        expected: Option<i64>,
+    ) -> Result<Option<i64>, HandlerError> {
+        self.lock().set_read(user, entry, expected, false)
+    }
+
+    fn mark_all_read(&self, user: i64) -> Result<u32, HandlerError> {
+        let mut state = self.lock();
+        let unread: Vec<i64> = state
+            .entries
+            .iter()
+            .filter(|entry| state.is_unread(user, entry))
+            .map(|entry| entry.id)
+            .collect();
+
+        for entry in &unread {
+            let revision = state.next_revision();
+            state.read.insert((user, *entry), (revision, true));
+        }
+
+        Ok(u32::try_from(unread.len()).unwrap_or(u32::MAX))
+    }
+
+    fn store_entries(
+        &self,
+        feed_id: i64,
+        items: Vec<FeedItem>,
+    ) -> Result<Vec<Entry>, HandlerError> {
+        let mut state = self.lock();
+
+        let feed_url = usize::try_from(feed_id.saturating_sub(1))
+            .ok()
+            .and_then(|index| state.feeds.get(index))
+            .cloned()
+            .ok_or_else(|| HandlerError::NotFound(format!("feed {}", feed_id)))?;
+
+        let mut added = Vec::new();
+
+        for item in items {
+            let stored = state.entries.iter().any(|entry| {
+                entry.feed_id == feed_id
+                    && match (&entry.guid, &item.guid) {
+                        (Some(stored), Some(guid)) => stored == guid,
+                        _ => entry.url == item.url,
+                    }
+            });
+
+            if stored {
+                continue;
+            }
+
+            if let Some(entry) = item.into_entry(id_at(state.entries.len()), feed_id, &feed_url) {
+                state.entries.push(entry.clone());
+                added.push(entry);
+            }
+        }
+
+        Ok(added)
+    }
+
+    fn role(&self, user: i64) -> Result<Role, HandlerError> {
+        Ok(self.lock().roles.get(&user).copied().unwrap_or_default())
+    }
+
+    fn quota(&self, user: i64) -> Result<Quota, HandlerError> {
+        Ok(self.lock().quotas.get(&user).copied().unwrap_or_default())
+    }
+
+    fn set_quota(&self, user: i64, quota: Quota) -> Result<(), HandlerError> {
+        self.lock().quotas.insert(user, quota);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(guid: Option<&str>, title: &str, url: &str) -> FeedItem {
+        FeedItem {
+            guid: guid.map(str::to_string),
+            title: title.to_string(),
+            url: url.to_string(),
+            published: None,
+        }
+    }
+
+    #[test]
+    fn keeps_subscriptions_and_read_state() {
+        let storage = MemoryStorage::new();
+        let bob = storage.add_user("bob");
+        let feed_url = "gemini://example.com/feed";
+
+        assert_eq!(storage.add_user("bob"), bob);
+        assert_eq!(storage.user("bob").unwrap(), bob);
+        assert!(matches!(
+            storage.user("alice"),
+            Err(HandlerError::NotFound(_))
+        ));
+
+        storage.subscribe(bob, feed_url, None).unwrap();
+        storage.subscribe(bob, feed_url, None).unwrap();
+
+        let subscriptions = storage.subscriptions(bob).unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].url, feed_url);
+
+        let feed = storage.add_feed(feed_url);
+        let added = storage
+            .store_entries(
+                feed,
+                vec![
+                    item(Some("1"), "First", "gemini://example.com/1"),
+                    item(None, "Second", "gemini://example.com/2"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(added.len(), 2);
+
+        let again = storage
+            .store_entries(
+                feed,
+                vec![
+                    item(Some("1"), "First, moved", "gemini://example.com/moved"),
+                    item(None, "Second", "gemini://example.com/2"),
+                ],
+            )
+            .unwrap();
+        assert!(again.is_empty());
+        assert!(matches!(
+            storage.store_entries(feed + 1, Vec::new()),
+            Err(HandlerError::NotFound(_))
+        ));
+
+        assert_eq!(storage.unread_count(bob).unwrap(), 2);
+
+        let first = added[0].id;
+        let revision = storage.mark_read(bob, first, None).unwrap();
+        assert!(matches!(
+            storage.mark_unread(bob, first, Some(0)),
+            Err(HandlerError::Conflict(_))
+        ));
+        storage.mark_unread(bob, first, revision).unwrap();
+        storage.mark_read(bob, first, None).unwrap();
+
+        let page = storage.unread(bob, None, None).unwrap();
+        assert_eq!(page.entries, added[1..]);
+        assert_eq!(page.next, None);
+
+        assert_eq!(storage.mark_all_read(bob).unwrap(), 1);
+        assert_eq!(storage.unread_count(bob).unwrap(), 0);
+
+        let alice = storage.add_user("alice");
+        assert!(matches!(
+            storage.mark_read(alice, first, None),
+            Err(HandlerError::NotFound(_))
+        ));
+
+        let subscription = subscriptions[0].id;
+        assert!(matches!(
+            storage.unsubscribe(bob, subscription, Some(0)),
+            Err(HandlerError::Conflict(_))
+        ));
+        storage.unsubscribe(bob, subscription, None).unwrap();
+        assert!(storage.subscriptions(bob).unwrap().is_empty());
+        assert!(matches!(
+            storage.unsubscribe(bob, subscription, None),
+            Err(HandlerError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn pages_the_unread_list() {
+        let storage = MemoryStorage::new();
+        let bob = storage.add_user("bob");
+        let feed_url = "gemini://example.com/feed";
+
+        storage.subscribe(bob, feed_url, None).unwrap();
+
+        for n in 0..=PAGE_SIZE {
+            storage.add_entry(feed_url, "Entry", &format!("gemini://example.com/{}", n));
+        }
+
+        let first = storage.unread(bob, None, None).unwrap();
+        assert_eq!(first.entries.len(), PAGE_SIZE);
+
+        let second = storage.unread(bob, first.next.as_ref(), None).unwrap();
+        assert_eq!(second.entries.len(), 1);
+        assert_eq!(second.next, None);
+
+        let invalid = Cursor::from_bytes(b"bad").unwrap();
+        assert!(storage.unread(bob, Some(&invalid), None).is_err());
+    }
+
+    #[test]
+    fn keeps_roles_and_quotas() {
+        let storage = MemoryStorage::new();
+        let bob = storage.add_user("bob");
+
+        assert_eq!(storage.role(bob).unwrap(), Role::User);
+        storage.set_role(bob, Role::Admin);
+        assert_eq!(storage.role(bob).unwrap(), Role::Admin);
+
+        assert_eq!(storage.quota(bob).unwrap(), Quota::unlimited());
+
+        let quota = Quota {
+            max_feeds: Some(10),
+            max_entries: None,
+        };
+        storage.set_quota(bob, quota).unwrap();
+        assert_eq!(storage.quota(bob).unwrap(), quota);
+    }
+}
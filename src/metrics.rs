@@ -0,0 +1,158 @@
+//! Metrics for monitoring servers
+//!
+//! With the `metrics` feature, servers record what they do
+//! through the metrics crate, so whichever exporter the
+//! application installs, such as a Prometheus one, publishes it:
+//!
+//! - `seymour_commands_total`, commands answered, by `verb`
+//! - `seymour_responses_total`, response lines sent, by `code`
+//! - `seymour_parse_errors_total`, lines that failed to decode
+//! - `seymour_connections_open`, connections being served
+//! - `seymour_connection_duration_seconds`, how long each
+//!   connection lasted, recorded as it closes
+//!
+//! `serve` records the last three itself. Commands and responses
+//! are counted by the CommandMetrics layer, so they include the
+//! answers layers give in place of the Service, such as
+//! RateLimited. `describe` sets the metrics' descriptions and
+//! units, for exporters that show them.
+
+use std::time::Duration;
+
+use crate::{Call, Layer, Response};
+
+pub const COMMANDS: &str = "seymour_commands_total";
+pub const RESPONSES: &str = "seymour_responses_total";
+pub const PARSE_ERRORS: &str = "seymour_parse_errors_total";
+pub const CONNECTIONS_OPEN: &str = "seymour_connections_open";
+pub const CONNECTION_DURATION: &str = "seymour_connection_duration_seconds";
+
+/// Describe every metric to the installed recorder
+pub fn describe() {
+    ::metrics::describe_counter!(COMMANDS, "Commands answered, by verb");
+    ::metrics::describe_counter!(RESPONSES, "Response lines sent, by code");
+    ::metrics::describe_counter!(PARSE_ERRORS, "Lines that failed to decode");
+    ::metrics::describe_gauge!(CONNECTIONS_OPEN, "Connections being served");
+    ::metrics::describe_histogram!(
+        CONNECTION_DURATION,
+        ::metrics::Unit::Seconds,
+        "How long connections lasted"
+    );
+}
+
+/// Counts every command and the response lines answering it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommandMetrics;
+
+impl Layer for CommandMetrics {
+    fn after(&self, call: &Call<'_>, reply: &[Response]) {
+        ::metrics::counter!(COMMANDS, "verb" => call.command.verb()).increment(1);
+
+        for response in reply {
+            ::metrics::counter!(RESPONSES, "code" => response.code().to_string()).increment(1);
+        }
+    }
+}
+
+pub(crate) fn parse_error() {
+    ::metrics::counter!(PARSE_ERRORS).increment(1);
+}
+
+/// A connection being served, counted until dropped
+pub(crate) struct OpenConnection;
+
+impl OpenConnection {
+    pub(crate) fn open() -> OpenConnection {
+        ::metrics::gauge!(CONNECTIONS_OPEN).increment(1);
+        OpenConnection
+    }
+
+    pub(crate) fn close(self, duration: Duration) {
+        ::metrics::histogram!(CONNECTION_DURATION).record(duration.as_secs_f64());
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        ::metrics::gauge!(CONNECTIONS_OPEN).decrement(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::SocketAddr;
+
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    use crate::{serve, Handler, Layered, MemoryStorage, ServeOptions};
+
+    #[test]
+    fn counts_commands_responses_and_connections() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let storage = MemoryStorage::new();
+        storage.add_user("bob");
+
+        ::metrics::with_local_recorder(&recorder, || {
+            let mut output = Vec::new();
+            let socket = SocketAddr::from(([192, 0, 2, 1], 40000));
+
+            serve(
+                b"USER bob\nUSER alice\nBOGUS\n".as_slice(),
+                &mut output,
+                socket,
+                &ServeOptions::default(),
+                |_| Layered::new(Handler::new(storage)).layer(CommandMetrics),
+            )
+            .unwrap();
+        });
+
+        let mut values: Vec<(String, Vec<String>, DebugValue)> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let key = key.key();
+                let labels = key
+                    .labels()
+                    .map(|label| format!("{}={}", label.key(), label.value()))
+                    .collect();
+
+                (key.name().to_string(), labels, value)
+            })
+            .filter(|(name, _, _)| name != CONNECTION_DURATION)
+            .collect();
+        values.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+
+        let not_found = Response::ResourceNotFound(String::new()).code();
+
+        assert_eq!(
+            values,
+            [
+                (
+                    COMMANDS.to_string(),
+                    vec!["verb=USER".to_string()],
+                    DebugValue::Counter(2)
+                ),
+                (
+                    CONNECTIONS_OPEN.to_string(),
+                    vec![],
+                    DebugValue::Gauge(0.0.into())
+                ),
+                (PARSE_ERRORS.to_string(), vec![], DebugValue::Counter(1)),
+                (
+                    RESPONSES.to_string(),
+                    vec!["code=20".to_string()],
+                    DebugValue::Counter(1)
+                ),
+                (
+                    RESPONSES.to_string(),
+                    vec![format!("code={}", not_found)],
+                    DebugValue::Counter(1)
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,850 @@
+//! Layers wrapping a Handler
+//!
+//! Concerns that cut across commands, such as logging, rate
+//! limits and quotas, are written once as a Layer instead of in
+//! every handler. A Layered service runs its layers around the
+//! Service it wraps, usually a Handler:
+//!
+//! ```text
+//! let mut service = Layered::new(handler)
+//!     .layer(RoleCheck)
+//!     .layer(AuthCheck::new(Arc::clone(&auth)))
+//!     .layer(CommandLog::new(|call, record, reply| ...))
+//!     .layer(Arc::clone(&rate_limiter))
+//!     .layer(QuotaPolicy { max_feeds: Some(100), ..QuotaPolicy::default() });
+//! ```
+//!
+//! Layers check a command in the order they were added, and the
+//! first to refuse it answers instead of the wrapped service, as
+//! if the handler had failed with its HandlerError. Every layer
+//! then sees the reply, whether or not the command ran.
+//!
+//! Checking who may run a command is a layer too: RoleCheck
+//! refuses commands the selected user's role does not allow and
+//! AuthCheck lets USER select only the user the connection
+//! authenticated as. A Handler used without them checks neither,
+//! so servers not replacing them with their own serve
+//! `Handler::checked`, which adds both.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::catalog::ErrorMessage;
+use crate::{AuditRecord, AuthProvider, Command, Handler, HandlerError, Response, Role, Storage};
+
+/// Answers the commands of one connection
+pub trait Service {
+    /// The complete reply to a command
+    fn call(&mut self, command: &Command) -> Vec<Response>;
+
+    /// The id of the selected user
+    fn user(&self) -> Option<i64>;
+
+    /// The user the connection authenticated as
+    fn authenticated(&self) -> Option<&str> {
+        None
+    }
+
+    /// The client's address
+    fn peer(&self) -> Option<IpAddr> {
+        None
+    }
+
+    fn storage(&self) -> &dyn Storage;
+}
+
+impl<S: Storage> Service for Handler<S> {
+    fn call(&mut self, command: &Command) -> Vec<Response> {
+        self.handle(command)
+    }
+
+    fn user(&self) -> Option<i64> {
+        Handler::<S>::user(self)
+    }
+
+    fn authenticated(&self) -> Option<&str> {
+        Handler::<S>::authenticated(self)
+    }
+
+    fn peer(&self) -> Option<IpAddr> {
+        Handler::<S>::peer(self)
+    }
+
+    fn storage(&self) -> &dyn Storage {
+        Handler::<S>::storage(self)
+    }
+}
+
+/// A command along with the connection it came from, as a
+/// Layer sees it
+#[derive(Clone, Copy)]
+pub struct Call<'a> {
+    pub command: &'a Command,
+    /// The id of the selected user
+    pub user: Option<i64>,
+    /// The user the connection authenticated as
+    pub authenticated: Option<&'a str>,
+    /// The client's address
+    pub peer: Option<IpAddr>,
+    pub storage: &'a dyn Storage,
+}
+
+impl fmt::Debug for Call<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Call")
+            .field("command", &self.command.to_audit_record())
+            .field("user", &self.user)
+            .field("authenticated", &self.authenticated)
+            .field("peer", &self.peer)
+            .finish()
+    }
+}
+
+impl<'a> Call<'a> {
+    /// A command sent to `service` in its current state
+    pub fn new<S: Service + ?Sized>(command: &'a Command, service: &'a S) -> Call<'a> {
+        Call {
+            command,
+            user: service.user(),
+            authenticated: service.authenticated(),
+            peer: service.peer(),
+            storage: service.storage(),
+        }
+    }
+}
+
+/// A concern run around every command of a Service
+///
+/// Layers are shared between connections, so they take `&self`
+/// and keep any counts behind a lock.
+pub trait Layer: Send + Sync {
+    /// Refuse a command before it runs
+    fn check(&self, call: &Call<'_>) -> Result<(), HandlerError> {
+        let _ = call;
+        Ok(())
+    }
+
+    /// See the reply to a command, with the user selected once
+    /// it was answered
+    fn after(&self, call: &Call<'_>, reply: &[Response]) {
+        let _ = (call, reply);
+    }
+}
+
+impl<L: Layer + ?Sized> Layer for Arc<L> {
+    fn check(&self, call: &Call<'_>) -> Result<(), HandlerError> {
+        (**self).check(call)
+    }
+
+    fn after(&self, call: &Call<'_>, reply: &[Response]) {
+        (**self).after(call, reply)
+    }
+}
+
+/// A Service with layers run around it
+pub struct Layered<S> {
+    inner: S,
+    layers: Vec<Box<dyn Layer>>,
+}
+
+impl<S: fmt::Debug> fmt::Debug for Layered<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Layered")
+            .field("inner", &self.inner)
+            .field("layers", &self.layers.len())
+            .finish()
+    }
+}
+
+impl<S> Layered<S> {
+    /// A service with no layers yet
+    pub fn new(inner: S) -> Layered<S> {
+        Layered {
+            inner,
+            layers: Vec::new(),
+        }
+    }
+
+    /// Run `layer` after the layers already added
+    pub fn layer<L: Layer + 'static>(mut self, layer: L) -> Layered<S> {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: Service> Service for Layered<S> {
+    fn call(&mut self, command: &Command) -> Vec<Response> {
+        let call = Call::new(command, &self.inner);
+
+        let refused = self
+            .layers
+            .iter()
+            .find_map(|layer| layer.check(&call).err());
+
+        let reply = match refused {
+            Some(error) => vec![Response::from(error)],
+            None => self.inner.call(command),
+        };
+
+        let call = Call::new(command, &self.inner);
+
+        for layer in &self.layers {
+            layer.after(&call, &reply);
+        }
+
+        reply
+    }
+
+    fn user(&self) -> Option<i64> {
+        self.inner.user()
+    }
+
+    fn authenticated(&self) -> Option<&str> {
+        self.inner.authenticated()
+    }
+
+    fn peer(&self) -> Option<IpAddr> {
+        self.inner.peer()
+    }
+
+    fn storage(&self) -> &dyn Storage {
+        self.inner.storage()
+    }
+}
+
+/// Refuses commands the selected user's role does not allow,
+/// answered with PermissionDenied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RoleCheck;
+
+impl Layer for RoleCheck {
+    fn check(&self, call: &Call<'_>) -> Result<(), HandlerError> {
+        let required = Role::required_for(call.command);
+
+        if required == Role::User {
+            return Ok(());
+        }
+
+        let user = call.user.ok_or(HandlerError::NeedUser)?;
+
+        if call.storage.role(user)? < required {
+            return Err(HandlerError::PermissionDenied(required));
+        }
+
+        Ok(())
+    }
+}
+
+/// Lets USER select only the user the connection authenticated
+/// as, refusing anyone else with Forbidden
+///
+/// Checked against the provider the Handler authenticates with,
+/// so a user removed since signing in cannot be selected again.
+pub struct AuthCheck {
+    auth: Arc<dyn AuthProvider>,
+}
+
+impl fmt::Debug for AuthCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AuthCheck").finish()
+    }
+}
+
+impl AuthCheck {
+    pub fn new(auth: Arc<dyn AuthProvider>) -> AuthCheck {
+        AuthCheck { auth }
+    }
+}
+
+impl Layer for AuthCheck {
+    fn check(&self, call: &Call<'_>) -> Result<(), HandlerError> {
+        let username = match call.command {
+            Command::User { username } => username,
+            _ => return Ok(()),
+        };
+
+        if call.authenticated != Some(username.as_str()) || !self.auth.lookup_user(username)? {
+            return Err(HandlerError::Forbidden(
+                ErrorMessage::new("auth-required", "authenticate first").to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Hands every command and its reply to a function
+///
+/// Commands are given as audit records, so secret arguments
+/// such as AUTH credentials never reach the log.
+pub struct CommandLog<F> {
+    log: F,
+}
+
+impl<F> fmt::Debug for CommandLog<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CommandLog").finish()
+    }
+}
+
+impl<F> CommandLog<F>
+where
+    F: Fn(&Call<'_>, &AuditRecord, &[Response]) + Send + Sync,
+{
+    pub fn new(log: F) -> CommandLog<F> {
+        CommandLog { log }
+    }
+}
+
+impl<F> Layer for CommandLog<F>
+where
+    F: Fn(&Call<'_>, &AuditRecord, &[Response]) + Send + Sync,
+{
+    fn after(&self, call: &Call<'_>, reply: &[Response]) {
+        (self.log)(call, &call.command.to_audit_record(), reply)
+    }
+}
+
+fn quota_exceeded(limit: &str, max: u32) -> HandlerError {
+    HandlerError::Forbidden(
+        ErrorMessage::new("quota-exceeded", "quota exceeded")
+            .param("limit", limit)
+            .param("max", max)
+            .to_string(),
+    )
+}
+
+/// Limits every user is held to, along with their own Quota
+///
+/// A limit of None means unlimited. A user whose Quota, read
+/// from Storage, is lower is held to that instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaPolicy {
+    /// Subscriptions a user may hold
+    pub max_feeds: Option<u32>,
+    /// Unread entries beyond which a user may not subscribe to
+    /// more feeds
+    pub max_pending_entries: Option<u32>,
+}
+
+/// The lower of two limits, either of which may be unlimited
+fn lowest(policy: Option<u32>, quota: Option<u32>) -> Option<u32> {
+    match (policy, quota) {
+        (Some(policy), Some(quota)) => Some(policy.min(quota)),
+        (policy, quota) => policy.or(quota),
+    }
+}
+
+/// Refuses SUBSCRIBE with Forbidden once a limit is reached
+impl Layer for QuotaPolicy {
+    fn check(&self, call: &Call<'_>) -> Result<(), HandlerError> {
+        let (storage, user) = match (call.command, call.user) {
+            (Command::Subscribe { .. }, Some(user)) => (call.storage, user),
+            _ => return Ok(()),
+        };
+
+        let usage = storage.quota_usage(user)?;
+
+        if let Some(max) = lowest(self.max_feeds, usage.quota.max_feeds) {
+            if usage.feeds >= max {
+                return Err(quota_exceeded("feeds", max));
+            }
+        }
+
+        let max_pending_entries = lowest(self.max_pending_entries, usage.quota.max_entries);
+
+        if let Some(max) = max_pending_entries {
+            if usage.entries >= max {
+                return Err(quota_exceeded("pending_entries", max));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What a RateLimiter counts commands against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateKey {
+    /// The selected user, across all their connections
+    User(i64),
+    /// The client's address, whether or not a user is selected
+    Peer(IpAddr),
+    /// Connections with neither, counted together
+    Unidentified,
+}
+
+impl RateKey {
+    /// The keys a call is counted against: the selected user
+    /// and the client's address
+    pub fn for_call(call: &Call<'_>) -> Vec<RateKey> {
+        let keys: Vec<RateKey> = call
+            .user
+            .map(RateKey::User)
+            .into_iter()
+            .chain(call.peer.map(RateKey::Peer))
+            .collect();
+
+        if keys.is_empty() {
+            vec![RateKey::Unidentified]
+        } else {
+            keys
+        }
+    }
+}
+
+/// Limits how many commands each user and client address sends
+///
+/// Allows `rate` commands per `window` for each key, up to
+/// `burst` of them at once, with the rest spread out evenly over
+/// the window. A burst of `rate`, the default, lets a client send
+/// the window's commands as fast as it likes.
+///
+/// Shared between connections behind an Arc, so a user's
+/// commands are counted across all of them, as are those of
+/// every connection from one address, including connections
+/// that never select a user.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: u32,
+    window: Duration,
+    burst: u32,
+    /// When each key next has a command to spare, as the
+    /// generic cell rate algorithm's theoretical arrival time
+    arrivals: Mutex<HashMap<RateKey, Instant>>,
+}
+
+impl RateLimiter {
+    /// Allow `rate` commands per `window`
+    pub fn new(rate: u32, window: Duration) -> RateLimiter {
+        RateLimiter {
+            rate,
+            window,
+            burst: rate,
+            arrivals: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Allow `rate` commands a minute
+    pub fn per_minute(rate: u32) -> RateLimiter {
+        RateLimiter::new(rate, Duration::from_secs(60))
+    }
+
+    /// Allow no more than `burst` commands at once
+    ///
+    /// Bursts are held between one command and the rate.
+    pub fn with_burst(self, burst: u32) -> RateLimiter {
+        RateLimiter {
+            burst: burst.clamp(1, self.rate.max(1)),
+            ..self
+        }
+    }
+
+    /// How often a key earns another command
+    fn interval(&self) -> Option<Duration> {
+        self.window.checked_div(self.rate)
+    }
+
+    /// How far ahead of `now` a key may run before it is refused
+    fn tolerance(&self, interval: Duration) -> Duration {
+        interval
+            .checked_mul(self.burst.saturating_sub(1))
+            .unwrap_or(Duration::MAX)
+    }
+
+    /// Count a command from a key, failing with how long until
+    /// it may send another
+    pub fn check(&self, key: RateKey, now: Instant) -> Result<(), Duration> {
+        self.check_all(&[key], now)
+    }
+
+    /// Count a command against every key, failing with the
+    /// longest wait if any of them is over the limit, in which
+    /// case none of them is counted
+    pub fn check_all(&self, keys: &[RateKey], now: Instant) -> Result<(), Duration> {
+        let interval = match self.interval() {
+            Some(interval) => interval,
+            None => return Err(self.window),
+        };
+        let tolerance = self.tolerance(interval);
+        let mut arrivals = self.arrivals.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let wait = keys
+            .iter()
+            .filter_map(|key| arrivals.get(key))
+            .map(|arrival| {
+                arrival
+                    .saturating_duration_since(now)
+                    .saturating_sub(tolerance)
+            })
+            .max()
+            .unwrap_or_default();
+
+        if wait > Duration::ZERO {
+            return Err(wait);
+        }
+
+        for key in keys {
+            let arrival = arrivals.entry(*key).or_insert(now);
+            let start = (*arrival).max(now);
+
+            *arrival = start.checked_add(interval).unwrap_or(start);
+        }
+
+        Ok(())
+    }
+
+    /// Forget keys that have caught up with `now`
+    pub fn prune(&self, now: Instant) {
+        self.arrivals
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|_, arrival| *arrival > now);
+    }
+}
+
+/// Counts commands against the selected user and the client's
+/// address, refusing them with RateLimited past the limit
+impl Layer for RateLimiter {
+    fn check(&self, call: &Call<'_>) -> Result<(), HandlerError> {
+        self.check_all(&RateKey::for_call(call), Instant::now())
+            .map_err(|retry_after| {
+                HandlerError::RateLimited(
+                    ErrorMessage::new("rate-limited", "too many commands")
+                        .param("retry_after", retry_after.as_secs().saturating_add(1))
+                        .to_string(),
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::feed::FeedItem;
+    use crate::{Credentials, Cursor, Entry, EntryPage, MemoryStorage, Quota, Subscription};
+
+    /// One user, `bob`, with a number of unread entries
+    struct Unread(u32);
+
+    impl Storage for Unread {
+        fn user(&self, username: &str) -> Result<i64, HandlerError> {
+            match username {
+                "bob" => Ok(1),
+                _ => Err(HandlerError::NotFound(username.to_string())),
+            }
+        }
+
+        fn subscriptions(&self, _user: i64) -> Result<Vec<Subscription>, HandlerError> {
+            Ok(Vec::new())
+        }
+
+        fn subscribe(
+            &self,
+            _user: i64,
+            _url: &str,
+            _category: Option<&str>,
+        ) -> Result<(), HandlerError> {
+            Ok(())
+        }
+
+        fn unsubscribe(
+            &self,
+            _user: i64,
+            _subscription: i64,
+            _expected: Option<i64>,
+        ) -> Result<Option<i64>, HandlerError> {
+            Ok(None)
+        }
+
+        fn unread(
+            &self,
+            _user: i64,
+            _after: Option<&Cursor>,
+            _summary: Option<u32>,
+        ) -> Result<EntryPage, HandlerError> {
+            Ok(EntryPage {
+                entries: Vec::new(),
+                next: None,
+            })
+        }
+
+        fn unread_count(&self, _user: i64) -> Result<u32, HandlerError> {
+            Ok(self.0)
+        }
+
+        fn mark_read(
+            &self,
+            _user: i64,
+            _entry: i64,
+            _expected: Option<i64>,
+        ) -> Result<Option<i64>, HandlerError> {
+            Ok(None)
+        }
+
+        fn mark_unread(
+            &self,
+            _user: i64,
+            _entry: i64,
+            _expected: Option<i64>,
+        ) -> Result<Option<i64>, HandlerError> {
+            Ok(None)
+        }
+
+        fn mark_all_read(&self, _user: i64) -> Result<u32, HandlerError> {
+            Ok(0)
+        }
+
+        fn store_entries(
+            &self,
+            _feed_id: i64,
+            _items: Vec<FeedItem>,
+        ) -> Result<Vec<Entry>, HandlerError> {
+            Ok(Vec::new())
+        }
+    }
+
+    /// Knows `bob`, whose password is `hunter2`
+    struct Passwords;
+
+    impl AuthProvider for Passwords {
+        fn lookup_user(&self, username: &str) -> Result<bool, HandlerError> {
+            Ok(username == "bob")
+        }
+
+        fn verify_password(&self, username: &str, password: &str) -> Result<bool, HandlerError> {
+            Ok(username == "bob" && password == "hunter2")
+        }
+
+        fn verify_token(&self, _token: &str) -> Result<Option<String>, HandlerError> {
+            Ok(None)
+        }
+    }
+
+    fn user(username: &str) -> Command {
+        Command::User {
+            username: username.to_string(),
+        }
+    }
+
+    fn subscribe() -> Command {
+        Command::Subscribe {
+            url: "gemini://example.org/feed.gmi".to_string(),
+            category: None,
+            key: None,
+            deadline: None,
+        }
+    }
+
+    #[test]
+    fn layers_refuse_before_the_handler() {
+        let mut service = Layered::new(Handler::new(Unread(10))).layer(QuotaPolicy {
+            max_pending_entries: Some(10),
+            ..QuotaPolicy::default()
+        });
+
+        assert_eq!(
+            service.call(&user("bob")),
+            vec![Response::AckUser { id: 1 }]
+        );
+        assert!(matches!(
+            service.call(&subscribe()).as_slice(),
+            [Response::Forbidden(message)] if message.starts_with("[quota-exceeded")
+        ));
+    }
+
+    #[test]
+    fn quota_policy_honors_user_quotas() {
+        let storage = MemoryStorage::new();
+        let bob = storage.add_user("bob");
+        storage
+            .subscribe(bob, "gemini://example.org/other.gmi", None)
+            .unwrap();
+        storage
+            .set_quota(
+                bob,
+                Quota {
+                    max_feeds: Some(1),
+                    max_entries: None,
+                },
+            )
+            .unwrap();
+
+        let mut service = Layered::new(Handler::new(storage)).layer(QuotaPolicy {
+            max_feeds: Some(100),
+            ..QuotaPolicy::default()
+        });
+
+        service.call(&user("bob"));
+
+        assert_eq!(
+            service.call(&subscribe()),
+            vec![Response::from(quota_exceeded("feeds", 1))]
+        );
+    }
+
+    #[test]
+    fn role_check_refuses_administrative_commands() {
+        let get_quota = Command::GetQuota {
+            username: Some("alice".to_string()),
+        };
+        let mut service = Handler::new(Unread(0)).checked();
+
+        assert_eq!(
+            service.call(&get_quota),
+            vec![Response::from(HandlerError::NeedUser)]
+        );
+
+        service.call(&user("bob"));
+
+        assert_eq!(
+            service.call(&get_quota),
+            vec![Response::from(HandlerError::PermissionDenied(Role::Admin))]
+        );
+        assert!(!matches!(
+            service
+                .call(&Command::GetQuota { username: None })
+                .as_slice(),
+            [Response::Forbidden(_)]
+        ));
+    }
+
+    #[test]
+    fn auth_check_selects_only_the_authenticated_user() {
+        let mut service = Handler::new(Unread(0))
+            .with_auth_provider(Arc::new(Passwords))
+            .checked();
+
+        assert!(matches!(
+            service.call(&user("bob")).as_slice(),
+            [Response::Forbidden(message)] if message.starts_with("[auth-required")
+        ));
+
+        let auth = Command::Auth(Credentials::Password {
+            username: "bob".to_string(),
+            password: "hunter2".to_string(),
+        });
+
+        assert_eq!(service.call(&auth), vec![Response::AckUser { id: 1 }]);
+        assert_eq!(service.authenticated(), Some("bob"));
+        assert_eq!(
+            service.call(&user("bob")),
+            vec![Response::AckUser { id: 1 }]
+        );
+
+        let unchecked = Handler::new(Unread(0)).with_auth_provider(Arc::new(Passwords));
+
+        assert_eq!(
+            Layered::new(unchecked).call(&user("bob")),
+            vec![Response::AckUser { id: 1 }]
+        );
+    }
+
+    #[test]
+    fn rate_limiter_counts_selected_user() {
+        let rate_limiter = Arc::new(RateLimiter::per_minute(1));
+        let mut service = Layered::new(Handler::new(Unread(0))).layer(Arc::clone(&rate_limiter));
+
+        service.call(&user("bob"));
+
+        assert_eq!(service.call(&Command::Ping), vec![Response::Pong]);
+        assert!(matches!(
+            service.call(&Command::Ping).as_slice(),
+            [Response::RateLimited(_)]
+        ));
+    }
+
+    #[test]
+    fn rate_limiter_spreads_commands_past_the_burst() {
+        let rate_limiter = RateLimiter::new(4, Duration::from_secs(40)).with_burst(2);
+        let key = RateKey::User(1);
+        let now = Instant::now();
+
+        assert_eq!(rate_limiter.check(key, now), Ok(()));
+        assert_eq!(rate_limiter.check(key, now), Ok(()));
+        assert_eq!(rate_limiter.check(key, now), Err(Duration::from_secs(10)));
+        assert_eq!(rate_limiter.check(RateKey::User(2), now), Ok(()));
+
+        let later = now + Duration::from_secs(10);
+
+        assert_eq!(rate_limiter.check(key, later), Ok(()));
+        assert_eq!(rate_limiter.check(key, later), Err(Duration::from_secs(10)));
+
+        rate_limiter.prune(now + Duration::from_secs(30));
+
+        assert!(rate_limiter.arrivals.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rate_limiter_counts_unauthenticated_peers() {
+        let rate_limiter = Arc::new(RateLimiter::per_minute(2));
+        let connection = |peer: [u8; 4]| {
+            Layered::new(Handler::new(Unread(0)).with_peer(IpAddr::from(peer)))
+                .layer(Arc::clone(&rate_limiter))
+        };
+        let mut first = connection([192, 0, 2, 1]);
+        let mut second = connection([192, 0, 2, 1]);
+        let mut elsewhere = connection([198, 51, 100, 1]);
+
+        assert_eq!(first.call(&Command::Ping), vec![Response::Pong]);
+        assert_eq!(second.call(&Command::Ping), vec![Response::Pong]);
+        assert!(matches!(
+            first.call(&Command::Ping).as_slice(),
+            [Response::RateLimited(message)] if message.contains("retry_after")
+        ));
+        assert_eq!(elsewhere.call(&Command::Ping), vec![Response::Pong]);
+    }
+
+    #[test]
+    fn command_log_sees_every_reply() {
+        let logged = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&logged);
+        let peer = IpAddr::from([192, 0, 2, 1]);
+        let mut service = Layered::new(Handler::new(Unread(0)).with_peer(peer))
+            .layer(RateLimiter::per_minute(1))
+            .layer(CommandLog::new(
+                move |call: &Call<'_>, record: &AuditRecord, reply: &[Response]| {
+                    log.lock().unwrap().push((
+                        call.peer,
+                        call.user,
+                        record.verb,
+                        reply.first().map(Response::code),
+                    ));
+                },
+            ));
+
+        service.call(&user("bob"));
+        service.call(&Command::Ping);
+
+        assert_eq!(
+            *logged.lock().unwrap(),
+            vec![
+                (
+                    Some(peer),
+                    Some(1),
+                    "USER",
+                    Some(Response::AckUser { id: 1 }.code())
+                ),
+                (
+                    Some(peer),
+                    Some(1),
+                    "PING",
+                    Some(Response::RateLimited(String::new()).code())
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,209 @@
+//! Unicode normalization of usernames
+//!
+//! Platforms disagree on whether "é" is typed as one character
+//! or as "e" followed by a combining accent. Composing both to
+//! the same form keeps them from naming different accounts.
+//!
+//! Only compositions into the Latin-1 Supplement and Latin
+//! Extended-A blocks are known, the subset of NFC that covers
+//! accented Latin letters. Other text is left as it is.
+
+/// Base letter, combining mark and their composition, sorted
+/// by base letter and then mark
+const COMPOSITIONS: &[(char, char, char)] = &[
+    ('A', '\u{300}', '\u{c0}'),
+    ('A', '\u{301}', '\u{c1}'),
+    ('A', '\u{302}', '\u{c2}'),
+    ('A', '\u{303}', '\u{c3}'),
+    ('A', '\u{304}', '\u{100}'),
+    ('A', '\u{306}', '\u{102}'),
+    ('A', '\u{308}', '\u{c4}'),
+    ('A', '\u{30a}', '\u{c5}'),
+    ('A', '\u{328}', '\u{104}'),
+    ('C', '\u{301}', '\u{106}'),
+    ('C', '\u{302}', '\u{108}'),
+    ('C', '\u{307}', '\u{10a}'),
+    ('C', '\u{30c}', '\u{10c}'),
+    ('C', '\u{327}', '\u{c7}'),
+    ('D', '\u{30c}', '\u{10e}'),
+    ('E', '\u{300}', '\u{c8}'),
+    ('E', '\u{301}', '\u{c9}'),
+    ('E', '\u{302}', '\u{ca}'),
+    ('E', '\u{304}', '\u{112}'),
+    ('E', '\u{306}', '\u{114}'),
+    ('E', '\u{307}', '\u{116}'),
+    ('E', '\u{308}', '\u{cb}'),
+    ('E', '\u{30c}', '\u{11a}'),
+    ('E', '\u{328}', '\u{118}'),
+    ('G', '\u{302}', '\u{11c}'),
+    ('G', '\u{306}', '\u{11e}'),
+    ('G', '\u{307}', '\u{120}'),
+    ('G', '\u{327}', '\u{122}'),
+    ('H', '\u{302}', '\u{124}'),
+    ('I', '\u{300}', '\u{cc}'),
+    ('I', '\u{301}', '\u{cd}'),
+    ('I', '\u{302}', '\u{ce}'),
+    ('I', '\u{303}', '\u{128}'),
+    ('I', '\u{304}', '\u{12a}'),
+    ('I', '\u{306}', '\u{12c}'),
+    ('I', '\u{307}', '\u{130}'),
+    ('I', '\u{308}', '\u{cf}'),
+    ('I', '\u{328}', '\u{12e}'),
+    ('J', '\u{302}', '\u{134}'),
+    ('K', '\u{327}', '\u{136}'),
+    ('L', '\u{301}', '\u{139}'),
+    ('L', '\u{30c}', '\u{13d}'),
+    ('L', '\u{327}', '\u{13b}'),
+    ('N', '\u{301}', '\u{143}'),
+    ('N', '\u{303}', '\u{d1}'),
+    ('N', '\u{30c}', '\u{147}'),
+    ('N', '\u{327}', '\u{145}'),
+    ('O', '\u{300}', '\u{d2}'),
+    ('O', '\u{301}', '\u{d3}'),
+    ('O', '\u{302}', '\u{d4}'),
+    ('O', '\u{303}', '\u{d5}'),
+    ('O', '\u{304}', '\u{14c}'),
+    ('O', '\u{306}', '\u{14e}'),
+    ('O', '\u{308}', '\u{d6}'),
+    ('O', '\u{30b}', '\u{150}'),
+    ('R', '\u{301}', '\u{154}'),
+    ('R', '\u{30c}', '\u{158}'),
+    ('R', '\u{327}', '\u{156}'),
+    ('S', '\u{301}', '\u{15a}'),
+    ('S', '\u{302}', '\u{15c}'),
+    ('S', '\u{30c}', '\u{160}'),
+    ('S', '\u{327}', '\u{15e}'),
+    ('T', '\u{30c}', '\u{164}'),
+    ('T', '\u{327}', '\u{162}'),
+    ('U', '\u{300}', '\u{d9}'),
+    ('U', '\u{301}', '\u{da}'),
+    ('U', '\u{302}', '\u{db}'),
+    ('U', '\u{303}', '\u{168}'),
+    ('U', '\u{304}', '\u{16a}'),
+    ('U', '\u{306}', '\u{16c}'),
+    ('U', '\u{308}', '\u{dc}'),
+    ('U', '\u{30a}', '\u{16e}'),
+    ('U', '\u{30b}', '\u{170}'),
+    ('U', '\u{328}', '\u{172}'),
+    ('W', '\u{302}', '\u{174}'),
+    ('Y', '\u{301}', '\u{dd}'),
+    ('Y', '\u{302}', '\u{176}'),
+    ('Y', '\u{308}', '\u{178}'),
+    ('Z', '\u{301}', '\u{179}'),
+    ('Z', '\u{307}', '\u{17b}'),
+    ('Z', '\u{30c}', '\u{17d}'),
+    ('a', '\u{300}', '\u{e0}'),
+    ('a', '\u{301}', '\u{e1}'),
+    ('a', '\u{302}', '\u{e2}'),
+    ('a', '\u{303}', '\u{e3}'),
+    ('a', '\u{304}', '\u{101}'),
+    ('a', '\u{306}', '\u{103}'),
+    ('a', '\u{308}', '\u{e4}'),
+    ('a', '\u{30a}', '\u{e5}'),
+    ('a', '\u{328}', '\u{105}'),
+    ('c', '\u{301}', '\u{107}'),
+    ('c', '\u{302}', '\u{109}'),
+    ('c', '\u{307}', '\u{10b}'),
+    ('c', '\u{30c}', '\u{10d}'),
+    ('c', '\u{327}', '\u{e7}'),
+    ('d', '\u{30c}', '\u{10f}'),
+    ('e', '\u{300}', '\u{e8}'),
+    ('e', '\u{301}', '\u{e9}'),
+    ('e', '\u{302}', '\u{ea}'),
+    ('e', '\u{304}', '\u{113}'),
+    ('e', '\u{306}', '\u{115}'),
+    ('e', '\u{307}', '\u{117}'),
+    ('e', '\u{308}', '\u{eb}'),
+    ('e', '\u{30c}', '\u{11b}'),
+    ('e', '\u{328}', '\u{119}'),
+    ('g', '\u{302}', '\u{11d}'),
+    ('g', '\u{306}', '\u{11f}'),
+    ('g', '\u{307}', '\u{121}'),
+    ('g', '\u{327}', '\u{123}'),
+    ('h', '\u{302}', '\u{125}'),
+    ('i', '\u{300}', '\u{ec}'),
+    ('i', '\u{301}', '\u{ed}'),
+    ('i', '\u{302}', '\u{ee}'),
+    ('i', '\u{303}', '\u{129}'),
+    ('i', '\u{304}', '\u{12b}'),
+    ('i', '\u{306}', '\u{12d}'),
+    ('i', '\u{308}', '\u{ef}'),
+    ('i', '\u{328}', '\u{12f}'),
+    ('j', '\u{302}', '\u{135}'),
+    ('k', '\u{327}', '\u{137}'),
+    ('l', '\u{301}', '\u{13a}'),
+    ('l', '\u{30c}', '\u{13e}'),
+    ('l', '\u{327}', '\u{13c}'),
+    ('n', '\u{301}', '\u{144}'),
+    ('n', '\u{303}', '\u{f1}'),
+    ('n', '\u{30c}', '\u{148}'),
+    ('n', '\u{327}', '\u{146}'),
+    ('o', '\u{300}', '\u{f2}'),
+    ('o', '\u{301}', '\u{f3}'),
+    ('o', '\u{302}', '\u{f4}'),
+    ('o', '\u{303}', '\u{f5}'),
+    ('o', '\u{304}', '\u{14d}'),
+    ('o', '\u{306}', '\u{14f}'),
+    ('o', '\u{308}', '\u{f6}'),
+    ('o', '\u{30b}', '\u{151}'),
+    ('r', '\u{301}', '\u{155}'),
+    ('r', '\u{30c}', '\u{159}'),
+    ('r', '\u{327}', '\u{157}'),
+    ('s', '\u{301}', '\u{15b}'),
+    ('s', '\u{302}', '\u{15d}'),
+    ('s', '\u{30c}', '\u{161}'),
+    ('s', '\u{327}', '\u{15f}'),
+    ('t', '\u{30c}', '\u{165}'),
+    ('t', '\u{327}', '\u{163}'),
+    ('u', '\u{300}', '\u{f9}'),
+    ('u', '\u{301}', '\u{fa}'),
+    ('u', '\u{302}', '\u{fb}'),
+    ('u', '\u{303}', '\u{169}'),
+    ('u', '\u{304}', '\u{16b}'),
+    ('u', '\u{306}', '\u{16d}'),
+    ('u', '\u{308}', '\u{fc}'),
+    ('u', '\u{30a}', '\u{16f}'),
+    ('u', '\u{30b}', '\u{171}'),
+    ('u', '\u{328}', '\u{173}'),
+    ('w', '\u{302}', '\u{175}'),
+    ('y', '\u{301}', '\u{fd}'),
+    ('y', '\u{302}', '\u{177}'),
+    ('y', '\u{308}', '\u{ff}'),
+    ('z', '\u{301}', '\u{17a}'),
+    ('z', '\u{307}', '\u{17c}'),
+    ('z', '\u{30c}', '\u{17e}'),
+];
+
+fn compose_pair(base: char, mark: char) -> Option<char> {
+    COMPOSITIONS
+        .binary_search_by(|&(candidate, candidate_mark, _)| {
+            (candidate, candidate_mark).cmp(&(base, mark))
+        })
+        .ok()
+        .and_then(|index| COMPOSITIONS.get(index))
+        .map(|&(_, _, composed)| composed)
+}
+
+/// Compose accented Latin letters written as a base letter and
+/// a combining mark
+pub(crate) fn compose(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut pending: Option<char> = None;
+
+    for c in text.chars() {
+        pending = match pending {
+            Some(base) => match compose_pair(base, c) {
+                Some(composed) => Some(composed),
+                None => {
+                    out.push(base);
+                    Some(c)
+                }
+            },
+            None => Some(c),
+        };
+    }
+
+    out.extend(pending);
+
+    out
+}
@@ -0,0 +1,1574 @@
+//! Parsing of protocol lines
+//!
+//! Parsing must never panic, whatever the input. The lints
+//! below enforce that no code in this module indexes, unwraps
+//! or performs unchecked arithmetic.
+
+#![deny(
+    clippy::indexing_slicing,
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::arithmetic_side_effects
+)]
+
+use std::str::FromStr;
+
+use crate::code;
+use crate::escape::decode_token;
+use crate::form;
+use crate::normalize;
+use crate::search::SavedSearch;
+use crate::webhook::validate_webhook_url;
+use crate::{
+    Backoff, Capabilities, Category, Command, Credentials, Cursor, Entry, EntryFlags, FeedStatus,
+    ImportOutcome, ImportResult, Language, Limits, ParseMessageError, ProtocolVersion, Quota,
+    QuotaUsage, Response, SessionAction, Share, Subscription, SyncToken, Undone, UsernameError,
+    UsernamePolicy, Webhook,
+};
+
+fn parse_argument<T: FromStr>(value: &str, argument_name: &str) -> Result<T, ParseMessageError> {
+    value
+        .parse()
+        .map_err(|_| ParseMessageError::InvalidIntegerArgument {
+            argument: argument_name.to_string(),
+            value: value.to_string(),
+        })
+}
+
+/// Parse a boolean argument, sent as `1` or `0`
+fn flag_argument(value: &str, argument_name: &str) -> Result<bool, ParseMessageError> {
+    match value {
+        "1" => Ok(true),
+        "0" => Ok(false),
+        _ => Err(ParseMessageError::InvalidIntegerArgument {
+            argument: argument_name.to_string(),
+            value: value.to_string(),
+        }),
+    }
+}
+
+/// Parse an argument that may be absent, sent as `-`
+fn optional_argument<T: FromStr>(
+    value: &str,
+    argument_name: &str,
+) -> Result<Option<T>, ParseMessageError> {
+    match value {
+        "-" => Ok(None),
+        value => parse_argument(value, argument_name).map(Some),
+    }
+}
+
+/// A single-word argument, decoded
+fn token(value: &str) -> String {
+    decode_token(value).into_owned()
+}
+
+/// A single-word argument that may be absent, sent as `-`
+fn optional_token(value: &str) -> Option<String> {
+    match value {
+        "-" => None,
+        value => Some(token(value)),
+    }
+}
+
+/// Options for parsing commands
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Require arguments to be separated by exactly one space
+    ///
+    /// By default runs of spaces and tabs count as a single
+    /// separator, and whitespace around the line is ignored.
+    pub strict_whitespace: bool,
+
+    /// Compose accented letters in usernames, so the same name
+    /// typed on different platforms names the same account
+    pub normalize_usernames: bool,
+
+    /// Lowercase usernames, making them case-insensitive
+    pub fold_username_case: bool,
+
+    /// Reject usernames the policy does not accept, after
+    /// normalizing them
+    pub username_policy: Option<UsernamePolicy>,
+}
+
+impl ParseOptions {
+    /// A username as these options normalize it
+    ///
+    /// Parsing applies this to every username argument. Servers
+    /// can apply it to usernames arriving by other means, such
+    /// as the REST mapping, to look up the same accounts.
+    pub fn username(&self, username: &str) -> String {
+        let username = if self.normalize_usernames {
+            normalize::compose(username)
+        } else {
+            username.to_string()
+        };
+
+        if self.fold_username_case {
+            username.to_lowercase()
+        } else {
+            username
+        }
+    }
+
+    /// Normalize a username argument in place, then check it
+    /// against the policy
+    pub(crate) fn apply_to_username(&self, username: &mut String) -> Result<(), UsernameError> {
+        if self.normalize_usernames || self.fold_username_case {
+            *username = self.username(username);
+        }
+
+        match &self.username_policy {
+            Some(policy) => policy.check(username),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The space-separated arguments following a verb or code
+///
+/// Arguments are borrowed from the line one at a time, so
+/// parsing a message does not allocate beyond the values it
+/// returns.
+#[derive(Clone, Copy)]
+struct Arguments<'a> {
+    remaining: Option<&'a str>,
+    taken: usize,
+    separators: &'static [char],
+    lenient: bool,
+}
+
+impl<'a> Arguments<'a> {
+    /// Split a line into its verb or code and its arguments,
+    /// separated by exactly one space each
+    fn split(line: &'a str) -> (&'a str, Self) {
+        let mut arguments = Arguments {
+            remaining: Some(line),
+            taken: 0,
+            separators: &[' '],
+            lenient: false,
+        };
+        let verb = arguments.take();
+
+        (verb.unwrap_or_default(), arguments)
+    }
+
+    /// Split a line into its verb and arguments, separated by
+    /// runs of spaces and tabs
+    fn split_lenient(line: &'a str) -> (&'a str, Self) {
+        let separators: &[char] = &[' ', '\t'];
+        let mut arguments = Arguments {
+            remaining: Some(line.trim_matches(separators)).filter(|line| !line.is_empty()),
+            taken: 0,
+            separators,
+            lenient: true,
+        };
+        let verb = arguments.take();
+
+        (verb.unwrap_or_default(), arguments)
+    }
+
+    /// Remove the next argument from the remaining line
+    fn take(&mut self) -> Option<&'a str> {
+        let remaining = self.remaining?;
+
+        let (argument, rest) = match remaining.split_once(self.separators) {
+            Some((argument, rest)) if self.lenient => {
+                (argument, Some(rest.trim_start_matches(self.separators)))
+            }
+            Some((argument, rest)) => (argument, Some(rest)),
+            None => (remaining, None),
+        };
+
+        self.remaining = rest;
+
+        Some(argument)
+    }
+
+    /// The next argument, without taking it
+    fn peek(&self) -> Option<&'a str> {
+        self.remaining
+            .and_then(|remaining| remaining.split(self.separators).next())
+    }
+
+    /// Take the next argument
+    fn next(&mut self, argument_name: &str) -> Result<&'a str, ParseMessageError> {
+        let argument = self
+            .take()
+            .ok_or_else(|| ParseMessageError::MissingArgument(argument_name.to_string()))?;
+
+        self.taken = self.taken.saturating_add(1);
+
+        Ok(argument)
+    }
+
+    /// Take the rest of the line as a single argument
+    fn rest(self, argument_name: &str) -> Result<&'a str, ParseMessageError> {
+        self.remaining
+            .ok_or_else(|| ParseMessageError::MissingArgument(argument_name.to_string()))
+    }
+
+    /// Take a keyword argument, such as `SINCE <timestamp>`, if
+    /// it is the next argument
+    fn keyword(
+        &mut self,
+        keyword: &str,
+        argument_name: &str,
+    ) -> Result<Option<&'a str>, ParseMessageError> {
+        match self.peek() {
+            Some(candidate) if candidate == keyword => {
+                self.next(argument_name)?;
+                self.next(argument_name).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Take a keyword standing alone, such as `COUNT`, if it is
+    /// the next argument
+    fn flag(&mut self, keyword: &str) -> Result<bool, ParseMessageError> {
+        match self.peek() {
+            Some(candidate) if candidate == keyword => self.next(keyword).map(|_| true),
+            _ => Ok(false),
+        }
+    }
+
+    /// Check that every argument has been taken by keyword
+    /// arguments
+    fn finish_keywords(self) -> Result<(), ParseMessageError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(candidate) => Err(ParseMessageError::UnknownKeyword(candidate.to_string())),
+        }
+    }
+
+    /// Check that every argument has been taken
+    fn finish(self) -> Result<(), ParseMessageError> {
+        match self.remaining {
+            None => Ok(()),
+            Some(rest) => Err(ParseMessageError::TooManyArguments {
+                expected: self.taken,
+                actual: self.taken.saturating_add(
+                    rest.split(self.separators)
+                        .filter(|argument| !self.lenient || !argument.is_empty())
+                        .count(),
+                ),
+            }),
+        }
+    }
+}
+
+fn parse_cursor(value: &str) -> Result<Cursor, ParseMessageError> {
+    value
+        .parse()
+        .map_err(|error| ParseMessageError::InvalidCursor {
+            value: value.to_string(),
+            error,
+        })
+}
+
+/// Parse the revision carried by acknowledgements from
+/// protocol version 2, and the idempotency key they echo
+fn parse_keyed_revision(
+    mut arguments: Arguments,
+    version: ProtocolVersion,
+) -> Result<(Option<i64>, Option<String>), ParseMessageError> {
+    let revision = if version >= ProtocolVersion::V2 {
+        optional_argument(arguments.next("revision")?, "revision")?
+    } else {
+        None
+    };
+    let key = arguments.keyword("KEY", "key")?;
+    arguments.finish_keywords()?;
+
+    Ok((revision, key.map(token)))
+}
+
+fn parse_sync_token(value: &str) -> Result<SyncToken, ParseMessageError> {
+    value
+        .parse()
+        .map_err(|error| ParseMessageError::InvalidSyncToken {
+            value: value.to_string(),
+            error,
+        })
+}
+
+fn parse_language(value: &str) -> Result<Language, ParseMessageError> {
+    value
+        .parse()
+        .map_err(|error| ParseMessageError::InvalidLanguage {
+            value: value.to_string(),
+            error,
+        })
+}
+
+/// Take the deadline written before a SEARCH query
+///
+/// A query may itself start with the word DEADLINE, so it is
+/// only read as the keyword when a number follows. Queries are
+/// sent with explicit operators, so a query starting with the
+/// word is never followed by one.
+fn parse_search_deadline(arguments: &mut Arguments) -> Result<Option<u32>, ParseMessageError> {
+    if arguments.peek() != Some("DEADLINE") {
+        return Ok(None);
+    }
+
+    let mut lookahead = *arguments;
+    lookahead.next("deadline")?;
+
+    match lookahead.take().map(str::parse) {
+        Some(Ok(deadline)) => {
+            *arguments = lookahead;
+            Ok(Some(deadline))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Parse capability tokens
+///
+/// Parsing is lenient so that peers can advertise capabilities
+/// added after this crate: unknown tokens become extensions and
+/// unknown version numbers are skipped.
+fn parse_capabilities(arguments: Arguments) -> Capabilities {
+    let mut capabilities = Capabilities::default();
+
+    for token in arguments
+        .remaining
+        .unwrap_or_default()
+        .split(arguments.separators)
+    {
+        match token.strip_prefix("VERSIONS=") {
+            Some(versions) => capabilities.versions.extend(
+                versions
+                    .split(',')
+                    .filter_map(|number| number.parse().ok())
+                    .filter_map(ProtocolVersion::from_number),
+            ),
+            None if token.is_empty() => {}
+            None if token == "ANONYMOUS" => capabilities.anonymous = true,
+            None => match parse_limit(&mut capabilities.limits, token) {
+                Some(()) => {}
+                None => capabilities.extensions.push(token.to_string()),
+            },
+        }
+    }
+
+    capabilities
+}
+
+/// Set the limit a token such as `MAXLINE=8192` advertises
+///
+/// Tokens that are not a valid limit are left to be kept as
+/// extensions.
+fn parse_limit(limits: &mut Limits, token: &str) -> Option<()> {
+    let key = Limits::TOKENS.iter().find(|key| token.starts_with(*key))?;
+    let value = token.get(key.len()..)?.parse().ok()?;
+
+    *limits.get_mut(key)? = Some(value);
+
+    Some(())
+}
+
+/// Parse a command from its verb and arguments
+///
+/// Legacy aliases are resolved only once the verb fails to
+/// match, keeping current verbs on the fast path.
+fn parse_command(command: &str, mut arguments: Arguments) -> Result<Command, ParseMessageError> {
+    match command {
+        "USER" => {
+            let username = token(arguments.next("username")?);
+            arguments.finish()?;
+
+            Ok(Command::User { username })
+        }
+        "LISTSUBSCRIPTIONS" => {
+            arguments.finish()?;
+
+            Ok(Command::ListSubscriptions)
+        }
+        "SUBSCRIBE" => {
+            let url = token(arguments.next("url")?);
+            let category = arguments.keyword("CATEGORY", "category")?;
+            let key = arguments.keyword("KEY", "key")?;
+            let deadline = arguments.keyword("DEADLINE", "deadline")?;
+            arguments.finish_keywords()?;
+
+            Ok(Command::Subscribe {
+                url,
+                category: category.map(token),
+                key: key.map(token),
+                deadline: deadline
+                    .map(|deadline| parse_argument(deadline, "deadline"))
+                    .transpose()?,
+            })
+        }
+        "UNSUBSCRIBE" => {
+            let id = arguments.next("id")?;
+            let expected = arguments.keyword("IF", "revision")?;
+            let key = arguments.keyword("KEY", "key")?;
+            arguments.finish_keywords()?;
+
+            Ok(Command::Unsubscribe {
+                id: parse_argument(id, "id")?,
+                expected: expected
+                    .map(|expected| parse_argument(expected, "revision"))
+                    .transpose()?,
+                key: key.map(token),
+            })
+        }
+        "LISTUNREAD" => {
+            let after = arguments.keyword("AFTER", "after")?;
+            let summary = arguments.keyword("SUMMARY", "summary")?;
+            let count = arguments.flag("COUNT")?;
+            arguments.finish_keywords()?;
+
+            Ok(Command::ListUnread {
+                after: after.map(parse_cursor).transpose()?,
+                summary: summary
+                    .map(|summary| parse_argument(summary, "summary"))
+                    .transpose()?,
+                count,
+            })
+        }
+        "MARKUNREAD" => {
+            let id = arguments.next("id")?;
+            let expected = arguments.keyword("IF", "revision")?;
+            arguments.finish_keywords()?;
+
+            Ok(Command::MarkUnread {
+                id: parse_argument(id, "id")?,
+                expected: expected
+                    .map(|expected| parse_argument(expected, "revision"))
+                    .transpose()?,
+            })
+        }
+        "MARKREAD" => {
+            let id = arguments.next("id")?;
+
+            if id == "ALL" {
+                arguments.finish()?;
+
+                return Ok(Command::MarkAllRead);
+            }
+
+            let expected = arguments.keyword("IF", "revision")?;
+            let key = arguments.keyword("KEY", "key")?;
+            arguments.finish_keywords()?;
+
+            Ok(Command::MarkRead {
+                id: parse_argument(id, "id")?,
+                expected: expected
+                    .map(|expected| parse_argument(expected, "revision"))
+                    .transpose()?,
+                key: key.map(token),
+            })
+        }
+        "SETORDER" => {
+            let id = arguments.next("id")?;
+            let order = arguments.next("order")?;
+            arguments.finish()?;
+
+            Ok(Command::SetOrder {
+                id: parse_argument(id, "id")?,
+                order: parse_argument(order, "order")?,
+            })
+        }
+        "SETINTERVAL" => {
+            let feed_id = arguments.next("feed_id")?;
+            let seconds = arguments.next("seconds")?;
+            arguments.finish()?;
+
+            Ok(Command::SetInterval {
+                feed_id: parse_argument(feed_id, "feed_id")?,
+                seconds: parse_argument(seconds, "seconds")?,
+            })
+        }
+        "FEEDSTATUS" => {
+            let feed_id = arguments.next("feed_id")?;
+            arguments.finish()?;
+
+            Ok(Command::FeedStatus {
+                feed_id: parse_argument(feed_id, "feed_id")?,
+            })
+        }
+        "ADDWEBHOOK" => {
+            let url = token(arguments.next("url")?);
+            arguments.finish()?;
+
+            validate_webhook_url(&url).map_err(|error| ParseMessageError::InvalidWebhookUrl {
+                url: url.clone(),
+                error,
+            })?;
+
+            Ok(Command::AddWebhook { url })
+        }
+        "LISTWEBHOOKS" => {
+            arguments.finish()?;
+
+            Ok(Command::ListWebhooks)
+        }
+        "DELETEWEBHOOK" => {
+            let id = arguments.next("id")?;
+            arguments.finish()?;
+
+            Ok(Command::DeleteWebhook {
+                id: parse_argument(id, "id")?,
+            })
+        }
+        "SEARCH" => {
+            let deadline = parse_search_deadline(&mut arguments)?;
+            let query = arguments.rest("query")?.parse()?;
+
+            Ok(Command::Search { query, deadline })
+        }
+        "SAVESEARCH" => {
+            let name = token(arguments.next("name")?);
+            let query = arguments.rest("query")?.parse()?;
+
+            Ok(Command::SaveSearch { name, query })
+        }
+        "LISTSEARCHES" => {
+            arguments.finish()?;
+
+            Ok(Command::ListSavedSearches)
+        }
+        "RUNSEARCH" => {
+            let name = token(arguments.next("name")?);
+            let after = arguments.keyword("AFTER", "after")?;
+            let deadline = arguments.keyword("DEADLINE", "deadline")?;
+            arguments.finish_keywords()?;
+
+            Ok(Command::RunSearch {
+                name,
+                after: after.map(parse_cursor).transpose()?,
+                deadline: deadline
+                    .map(|deadline| parse_argument(deadline, "deadline"))
+                    .transpose()?,
+            })
+        }
+        "SESSION" => {
+            let action = match arguments.next("action")? {
+                "OPEN" => SessionAction::Open,
+                "SELECT" => SessionAction::Select,
+                "CLOSE" => SessionAction::Close,
+                action => return Err(ParseMessageError::UnknownKeyword(action.to_string())),
+            };
+            let name = token(arguments.next("name")?);
+            arguments.finish()?;
+
+            Ok(Command::Session { action, name })
+        }
+        "AUTH" => {
+            let credentials = match arguments.next("method")? {
+                "PASSWORD" => Credentials::Password {
+                    username: token(arguments.next("username")?),
+                    password: token(arguments.next("password")?),
+                },
+                "TOKEN" => Credentials::Token(token(arguments.next("token")?)),
+                method => return Err(ParseMessageError::UnknownKeyword(method.to_string())),
+            };
+            arguments.finish()?;
+
+            Ok(Command::Auth(credentials))
+        }
+        "RESUMETOKEN" => {
+            arguments.finish()?;
+
+            Ok(Command::ResumeToken)
+        }
+        "RESUME" => {
+            let token = token(arguments.next("token")?);
+            arguments.finish()?;
+
+            Ok(Command::Resume { token })
+        }
+        "LISTREAD" => {
+            let since = arguments.keyword("SINCE", "since")?;
+            let after = arguments.keyword("AFTER", "after")?;
+            let summary = arguments.keyword("SUMMARY", "summary")?;
+            let count = arguments.flag("COUNT")?;
+            arguments.finish_keywords()?;
+
+            let since = since
+                .map(|since| parse_argument(since, "since"))
+                .transpose()?;
+
+            Ok(Command::ListRead {
+                since,
+                after: after.map(parse_cursor).transpose()?,
+                summary: summary
+                    .map(|summary| parse_argument(summary, "summary"))
+                    .transpose()?,
+                count,
+            })
+        }
+        "SETQUOTA" => {
+            let username = token(arguments.next("username")?);
+            let max_feeds = arguments.next("max_feeds")?;
+            let max_entries = arguments.next("max_entries")?;
+            arguments.finish()?;
+
+            Ok(Command::SetQuota {
+                username,
+                quota: Quota {
+                    max_feeds: optional_argument(max_feeds, "max_feeds")?,
+                    max_entries: optional_argument(max_entries, "max_entries")?,
+                },
+            })
+        }
+        "GETQUOTA" => {
+            let username = match arguments.remaining {
+                Some(_) => Some(token(arguments.next("username")?)),
+                None => None,
+            };
+            arguments.finish()?;
+
+            Ok(Command::GetQuota { username })
+        }
+        "SHARE" => {
+            let tag = token(arguments.next("tag")?);
+            let username = token(arguments.next("username")?);
+            arguments.finish()?;
+
+            Ok(Command::Share { tag, username })
+        }
+        "UNSHARE" => {
+            let tag = token(arguments.next("tag")?);
+            let username = token(arguments.next("username")?);
+            arguments.finish()?;
+
+            Ok(Command::Unshare { tag, username })
+        }
+        "LISTCATEGORIES" => {
+            arguments.finish()?;
+
+            Ok(Command::ListCategories)
+        }
+        "LISTSHARES" => {
+            arguments.finish()?;
+
+            Ok(Command::ListShares)
+        }
+        "GETCONTENT" => {
+            let id = arguments.next("id")?;
+            let format = arguments.keyword("FORMAT", "format")?;
+            arguments.finish_keywords()?;
+
+            Ok(Command::GetContent {
+                id: parse_argument(id, "id")?,
+                format: format.map(str::parse).transpose()?,
+            })
+        }
+        "SYNC" => {
+            let token = match arguments.remaining {
+                Some(_) => Some(parse_sync_token(arguments.next("token")?)?),
+                None => None,
+            };
+            arguments.finish()?;
+
+            Ok(Command::Sync { token })
+        }
+        "GETRAWFEED" => {
+            let feed_id = arguments.next("feed_id")?;
+            arguments.finish()?;
+
+            Ok(Command::GetRawFeed {
+                feed_id: parse_argument(feed_id, "feed_id")?,
+            })
+        }
+        "EXPORT" => {
+            let feed_id = arguments.keyword("FEED", "feed_id")?;
+            arguments.finish_keywords()?;
+
+            Ok(Command::Export {
+                feed_id: feed_id
+                    .map(|feed_id| parse_argument(feed_id, "feed_id"))
+                    .transpose()?,
+            })
+        }
+        "IMPORT" => {
+            arguments.finish()?;
+
+            Ok(Command::Import)
+        }
+        "UNDO" => {
+            arguments.finish()?;
+
+            Ok(Command::Undo)
+        }
+        "PING" => {
+            arguments.finish()?;
+
+            Ok(Command::Ping)
+        }
+        "CAPABILITIES" => Ok(Command::Capabilities(parse_capabilities(arguments))),
+        _ => match Command::descriptor_for(command) {
+            Some(descriptor) if descriptor.verb != command => {
+                parse_command(descriptor.verb, arguments)
+            }
+            _ => Err(ParseMessageError::UnknownType(command.to_string())),
+        },
+    }
+}
+
+/// Parsing never panics, whatever the input
+///
+/// Legacy verbs such as LISTFEEDS are accepted as aliases, and
+/// runs of spaces and tabs between arguments are accepted as
+/// one separator.
+impl FromStr for Command {
+    type Err = ParseMessageError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Command::parse_with(value, &ParseOptions::default())
+    }
+}
+
+impl Command {
+    /// Parse a command, choosing how strictly it must be
+    /// formatted
+    ///
+    /// Like the FromStr impl, this never panics.
+    pub fn parse_with(value: &str, options: &ParseOptions) -> Result<Command, ParseMessageError> {
+        let (command, arguments) = if options.strict_whitespace {
+            Arguments::split(value)
+        } else {
+            Arguments::split_lenient(value)
+        };
+
+        let mut command = parse_command(command, arguments)?;
+
+        if let Some(username) = command.username_mut() {
+            options.apply_to_username(username).map_err(|error| {
+                ParseMessageError::InvalidUsername {
+                    value: username.clone(),
+                    error,
+                }
+            })?;
+        }
+
+        Ok(command)
+    }
+
+    /// The username argument, for commands that take one
+    pub(crate) fn username_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Command::User { username }
+            | Command::Auth(Credentials::Password { username, .. })
+            | Command::SetQuota { username, .. }
+            | Command::GetQuota {
+                username: Some(username),
+            }
+            | Command::Share { username, .. }
+            | Command::Unshare { username, .. } => Some(username),
+            _ => None,
+        }
+    }
+}
+
+/// Parsing never panics, whatever the input
+///
+/// Lines are parsed as protocol version 1.
+impl FromStr for Response {
+    type Err = ParseMessageError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Response::parse_versioned(value, ProtocolVersion::V1)
+    }
+}
+
+impl Response {
+    /// Parse a response sent with a specific protocol version
+    ///
+    /// Like the FromStr impl, this never panics.
+    pub fn parse_versioned(
+        value: &str,
+        version: ProtocolVersion,
+    ) -> Result<Self, ParseMessageError> {
+        let (response, mut arguments) = Arguments::split(value);
+        let code = response
+            .parse::<u8>()
+            .ok()
+            .filter(|_| response.len() == 2 && response.bytes().all(|b| b.is_ascii_digit()))
+            .ok_or_else(|| ParseMessageError::UnknownType(response.to_string()))?;
+
+        match code {
+            code::ACK_USER => {
+                let id = arguments.next("id")?;
+                arguments.finish()?;
+
+                let id = parse_argument(id, "id")?;
+
+                Ok(Response::AckUser { id })
+            }
+            code::START_SUBSCRIPTION_LIST => {
+                arguments.finish()?;
+
+                Ok(Response::StartSubscriptionList)
+            }
+            code::SUBSCRIPTION => {
+                let id = arguments.next("id")?;
+                let url = arguments.next("url")?;
+                let (icon, order) = if version >= ProtocolVersion::V2 {
+                    (
+                        optional_token(arguments.next("icon")?),
+                        optional_argument(arguments.next("order")?, "order")?,
+                    )
+                } else {
+                    (None, None)
+                };
+                arguments.finish()?;
+
+                Ok(Response::Subscription(Subscription {
+                    id: parse_argument(id, "id")?,
+                    url: token(url),
+                    icon,
+                    order,
+                }))
+            }
+            code::START_ENTRY_LIST => {
+                arguments.finish()?;
+
+                Ok(Response::StartEntryList)
+            }
+            code::ENTRY => {
+                let id = arguments.next("id")?;
+                let feed_id = arguments.next("feed_id")?;
+                let feed_url = arguments.next("feed_url")?;
+                let url = arguments.next("url")?;
+                let (published, guid, read_at, group_id, duplicate, flags, language, summary) =
+                    if version >= ProtocolVersion::V2 {
+                        (
+                            optional_argument(arguments.next("published")?, "published")?,
+                            optional_token(arguments.next("guid")?),
+                            optional_argument(arguments.next("read_at")?, "read_at")?,
+                            optional_argument(arguments.next("group_id")?, "group_id")?,
+                            flag_argument(arguments.next("duplicate")?, "duplicate")?,
+                            arguments.next("flags")?.parse()?,
+                            match arguments.next("language")? {
+                                "-" => None,
+                                language => Some(parse_language(language)?),
+                            },
+                            match arguments.next("summary")? {
+                                "-" => None,
+                                summary => Some(form::decode(summary)),
+                            },
+                        )
+                    } else {
+                        (
+                            None,
+                            None,
+                            None,
+                            None,
+                            false,
+                            EntryFlags::empty(),
+                            None,
+                            None,
+                        )
+                    };
+                let title = arguments.rest("title")?;
+
+                Ok(Response::Entry(Entry {
+                    id: parse_argument(id, "id")?,
+                    feed_id: parse_argument(feed_id, "feed_id")?,
+                    feed_url: decode_token(feed_url).into(),
+                    title: title.to_string(),
+                    url: token(url),
+                    published,
+                    guid,
+                    read_at,
+                    group_id,
+                    duplicate,
+                    flags,
+                    language,
+                    summary,
+                }))
+            }
+            code::END_LIST => {
+                arguments.finish()?;
+
+                Ok(Response::EndList)
+            }
+            code::ACK_SUBSCRIBE => {
+                let key = arguments.keyword("KEY", "key")?;
+                arguments.finish_keywords()?;
+
+                Ok(Response::AckSubscribe {
+                    key: key.map(token),
+                })
+            }
+            code::ACK_UNSUBSCRIBE => {
+                let (revision, key) = parse_keyed_revision(arguments, version)?;
+
+                Ok(Response::AckUnsubscribe { revision, key })
+            }
+            code::ACK_MARK_READ => {
+                let (revision, key) = parse_keyed_revision(arguments, version)?;
+
+                Ok(Response::AckMarkRead { revision, key })
+            }
+            code::PONG => {
+                arguments.finish()?;
+
+                Ok(Response::Pong)
+            }
+            code::CAPABILITIES => Ok(Response::Capabilities(parse_capabilities(arguments))),
+            code::QUOTA => {
+                let max_feeds = arguments.next("max_feeds")?;
+                let max_entries = arguments.next("max_entries")?;
+                let feeds = arguments.next("feeds")?;
+                let entries = arguments.next("entries")?;
+                arguments.finish()?;
+
+                Ok(Response::Quota(QuotaUsage {
+                    quota: Quota {
+                        max_feeds: optional_argument(max_feeds, "max_feeds")?,
+                        max_entries: optional_argument(max_entries, "max_entries")?,
+                    },
+                    feeds: parse_argument(feeds, "feeds")?,
+                    entries: parse_argument(entries, "entries")?,
+                }))
+            }
+            code::ACK_SHARE => {
+                arguments.finish()?;
+
+                Ok(Response::AckShare)
+            }
+            code::ACK_UNSHARE => {
+                arguments.finish()?;
+
+                Ok(Response::AckUnshare)
+            }
+            code::START_SHARE_LIST => {
+                arguments.finish()?;
+
+                Ok(Response::StartShareList)
+            }
+            code::SHARE => {
+                let tag = token(arguments.next("tag")?);
+                let owner = token(arguments.next("owner")?);
+                let member = token(arguments.next("member")?);
+                arguments.finish()?;
+
+                Ok(Response::Share(Share { tag, owner, member }))
+            }
+            code::ACK_SET_ORDER => {
+                arguments.finish()?;
+
+                Ok(Response::AckSetOrder)
+            }
+            code::FEED_STATUS => {
+                let feed_id = arguments.next("feed_id")?;
+                let interval = arguments.next("interval")?;
+                let last_fetched = arguments.next("last_fetched")?;
+                let etag = arguments.next("etag")?;
+                let last_modified = arguments.next("last_modified")?;
+                let failures = arguments.next("failures")?;
+                let failing_since = arguments.next("failing_since")?;
+                let next_retry = arguments.next("next_retry")?;
+                arguments.finish()?;
+
+                // A feed without failures has no backoff to report
+                let backoff = match parse_argument(failures, "failures")? {
+                    0 => None,
+                    failures => Some(Backoff {
+                        failures,
+                        failing_since: parse_argument(failing_since, "failing_since")?,
+                        next_retry: parse_argument(next_retry, "next_retry")?,
+                    }),
+                };
+
+                Ok(Response::FeedStatus(FeedStatus {
+                    feed_id: parse_argument(feed_id, "feed_id")?,
+                    interval: parse_argument(interval, "interval")?,
+                    last_fetched: optional_argument(last_fetched, "last_fetched")?,
+                    etag: optional_token(etag),
+                    last_modified: optional_argument(last_modified, "last_modified")?,
+                    backoff,
+                }))
+            }
+            code::ACK_SET_INTERVAL => {
+                arguments.finish()?;
+
+                Ok(Response::AckSetInterval)
+            }
+            code::ACK_ADD_WEBHOOK => {
+                let id = arguments.next("id")?;
+                arguments.finish()?;
+
+                Ok(Response::AckAddWebhook {
+                    id: parse_argument(id, "id")?,
+                })
+            }
+            code::RESOURCE_NOT_FOUND => {
+                let message = arguments.rest("message")?.to_string();
+
+                Ok(Response::ResourceNotFound(message))
+            }
+            code::BAD_COMMAND => {
+                let message = arguments.rest("message")?.to_string();
+
+                Ok(Response::BadCommand(message))
+            }
+            code::NEED_USER => {
+                let message = arguments.rest("message")?.to_string();
+
+                Ok(Response::NeedUser(message))
+            }
+            code::RATE_LIMITED => {
+                let message = arguments.rest("message")?.to_string();
+
+                Ok(Response::RateLimited(message))
+            }
+            code::FORBIDDEN => {
+                let message = arguments.rest("message")?.to_string();
+
+                Ok(Response::Forbidden(message))
+            }
+            code::CONFLICT => {
+                let message = arguments.rest("message")?.to_string();
+
+                Ok(Response::Conflict(message))
+            }
+
+            code::INTERNAL_ERROR_LEGACY | code::INTERNAL_ERROR => {
+                let message = arguments.rest("message")?.to_string();
+
+                Ok(Response::InternalError(message))
+            }
+            code::SHUTTING_DOWN => {
+                let message = arguments.rest("message")?.to_string();
+
+                Ok(Response::ShuttingDown(message))
+            }
+            code::OVERLOADED => {
+                let message = arguments.rest("message")?.to_string();
+
+                Ok(Response::Overloaded(message))
+            }
+            code::UPSTREAM_TIMEOUT => {
+                let message = arguments.rest("message")?.to_string();
+
+                Ok(Response::UpstreamTimeout(message))
+            }
+            code::MAINTENANCE => {
+                let message = arguments.rest("message")?.to_string();
+
+                Ok(Response::Maintenance(message))
+            }
+            code::START_WEBHOOK_LIST => {
+                arguments.finish()?;
+
+                Ok(Response::StartWebhookList)
+            }
+            code::WEBHOOK => {
+                let id = arguments.next("id")?;
+                let url = arguments.next("url")?;
+                arguments.finish()?;
+
+                Ok(Response::Webhook(Webhook {
+                    id: parse_argument(id, "id")?,
+                    url: token(url),
+                }))
+            }
+            code::ACK_DELETE_WEBHOOK => {
+                arguments.finish()?;
+
+                Ok(Response::AckDeleteWebhook)
+            }
+            code::END_PAGE => {
+                let cursor = arguments.next("cursor")?;
+                arguments.finish()?;
+
+                Ok(Response::EndPage(parse_cursor(cursor)?))
+            }
+            code::ACK_MARK_ALL_READ => {
+                let count = arguments.next("count")?;
+                arguments.finish()?;
+
+                Ok(Response::AckMarkAllRead {
+                    count: parse_argument(count, "count")?,
+                })
+            }
+            code::ACK_UNDO => {
+                let command: Command = arguments.rest("command")?.parse()?;
+
+                Undone::from_command(&command)
+                    .map(Response::AckUndo)
+                    .ok_or_else(|| ParseMessageError::NotUndoable(command.verb().to_string()))
+            }
+            code::START_CONTENT => {
+                let entry_id = arguments.next("entry_id")?;
+                let format = arguments.next("format")?;
+                arguments.finish()?;
+
+                Ok(Response::StartContent {
+                    entry_id: parse_argument(entry_id, "entry_id")?,
+                    format: format.parse()?,
+                })
+            }
+            // Empty lines may arrive with or without the space
+            // after the code
+            code::PAYLOAD_LINE => Ok(Response::PayloadLine(
+                arguments.remaining.unwrap_or_default().to_string(),
+            )),
+            code::START_SYNC => {
+                arguments.finish()?;
+
+                Ok(Response::StartSync)
+            }
+            code::READ_STATE_CHANGE => {
+                let entry_id = arguments.next("entry_id")?;
+                let read_at = arguments.next("read_at")?;
+                arguments.finish()?;
+
+                Ok(Response::ReadStateChange {
+                    entry_id: parse_argument(entry_id, "entry_id")?,
+                    read_at: optional_argument(read_at, "read_at")?,
+                })
+            }
+            code::UNSUBSCRIBED => {
+                let feed_id = arguments.next("feed_id")?;
+                arguments.finish()?;
+
+                Ok(Response::Unsubscribed {
+                    feed_id: parse_argument(feed_id, "feed_id")?,
+                })
+            }
+            code::END_SYNC => {
+                let token = arguments.next("token")?;
+                arguments.finish()?;
+
+                Ok(Response::EndSync(parse_sync_token(token)?))
+            }
+            code::ACK_MARK_UNREAD => {
+                let revision = arguments.next("revision")?;
+                arguments.finish()?;
+
+                Ok(Response::AckMarkUnread {
+                    revision: optional_argument(revision, "revision")?,
+                })
+            }
+            code::START_IMPORT => {
+                arguments.finish()?;
+
+                Ok(Response::StartImport)
+            }
+            code::IMPORT_RESULT => {
+                let url = token(arguments.next("url")?);
+                let outcome = match arguments.next("status")? {
+                    "SUBSCRIBED" => ImportOutcome::Subscribed {
+                        feed_id: parse_argument(arguments.rest("feed_id")?, "feed_id")?,
+                    },
+                    "EXISTING" => ImportOutcome::AlreadySubscribed {
+                        feed_id: parse_argument(arguments.rest("feed_id")?, "feed_id")?,
+                    },
+                    "FAILED" => ImportOutcome::Failed(arguments.rest("message")?.to_string()),
+                    status => return Err(ParseMessageError::UnknownKeyword(status.to_string())),
+                };
+
+                Ok(Response::ImportResult(ImportResult { url, outcome }))
+            }
+            code::START_EXPORT => {
+                arguments.finish()?;
+
+                Ok(Response::StartExport)
+            }
+            code::START_RAW_FEED => {
+                let feed_id = arguments.next("feed_id")?;
+                let media_type = match arguments.rest("media_type")? {
+                    "-" => None,
+                    media_type => Some(media_type.to_string()),
+                };
+
+                Ok(Response::StartRawFeed {
+                    feed_id: parse_argument(feed_id, "feed_id")?,
+                    media_type,
+                })
+            }
+            code::COUNT => {
+                let count = arguments.next("count")?;
+                arguments.finish()?;
+
+                Ok(Response::Count(parse_argument(count, "count")?))
+            }
+            code::START_CATEGORY_LIST => {
+                arguments.finish()?;
+
+                Ok(Response::StartCategoryList)
+            }
+            code::CATEGORY => {
+                let slug = token(arguments.next("slug")?);
+                let name = arguments.rest("name")?.to_string();
+
+                Ok(Response::Category(Category { slug, name }))
+            }
+            code::ACK_SAVE_SEARCH => {
+                arguments.finish()?;
+
+                Ok(Response::AckSaveSearch)
+            }
+            code::START_SAVED_SEARCH_LIST => {
+                arguments.finish()?;
+
+                Ok(Response::StartSavedSearchList)
+            }
+            code::SAVED_SEARCH => {
+                let name = token(arguments.next("name")?);
+                let query = arguments.rest("query")?.parse()?;
+
+                Ok(Response::SavedSearch(SavedSearch { name, query }))
+            }
+            code::ACK_SESSION => {
+                arguments.finish()?;
+
+                Ok(Response::AckSession)
+            }
+            code::RESUME_TOKEN => {
+                let token = token(arguments.next("token")?);
+                let expires = arguments.next("expires")?;
+                arguments.finish()?;
+
+                Ok(Response::ResumeToken {
+                    token,
+                    expires: parse_argument(expires, "expires")?,
+                })
+            }
+
+            _ => Err(ParseMessageError::UnknownType(response.to_string())),
+        }
+    }
+}
+
+/// Find the next space in a line
+#[cfg(feature = "memchr")]
+fn find_space(bytes: &[u8]) -> Option<usize> {
+    memchr::memchr(b' ', bytes)
+}
+
+/// Find the next space in a line
+#[cfg(not(feature = "memchr"))]
+fn find_space(bytes: &[u8]) -> Option<usize> {
+    bytes.iter().position(|&byte| byte == b' ')
+}
+
+fn split_space(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let index = find_space(bytes)?;
+
+    Some((bytes.get(..index)?, bytes.get(index.checked_add(1)?..)?))
+}
+
+/// Parse a decimal integer directly from bytes
+///
+/// Only an optional minus sign followed by digits is accepted.
+/// Anything else, including overflow, is left to the general
+/// parser.
+fn integer(bytes: &[u8]) -> Option<i64> {
+    let (negative, digits) = match bytes.split_first() {
+        Some((b'-', digits)) => (true, digits),
+        _ => (false, bytes),
+    };
+
+    if digits.is_empty() {
+        return None;
+    }
+
+    // Accumulate negatively so that i64::MIN fits
+    let mut value: i64 = 0;
+
+    for &byte in digits {
+        if !byte.is_ascii_digit() {
+            return None;
+        }
+
+        value = value
+            .checked_mul(10)?
+            .checked_sub(i64::from(byte.wrapping_sub(b'0')))?;
+    }
+
+    if negative {
+        Some(value)
+    } else {
+        value.checked_neg()
+    }
+}
+
+/// Parse an entry line without the general tokenizer
+///
+/// Entries make up almost every line of a sync, so they get a
+/// fast path that finds delimiters with memchr (when the
+/// feature is enabled) and reads numeric fields without UTF-8
+/// validation. Only the version 1 layout is read here; version 2
+/// entries carry optional fields that need the general parser.
+/// Returns None for anything but a well-formed entry, leaving
+/// the general parser to produce the error.
+fn parse_entry(bytes: &[u8], version: ProtocolVersion) -> Option<Response> {
+    if version >= ProtocolVersion::V2 {
+        return None;
+    }
+
+    let rest = bytes.strip_prefix(b"24 ")?;
+    let (id, rest) = split_space(rest)?;
+    let (feed_id, rest) = split_space(rest)?;
+    let (feed_url, rest) = split_space(rest)?;
+    let (url, title) = split_space(rest)?;
+
+    Some(Response::Entry(Entry {
+        id: integer(id)?,
+        feed_id: integer(feed_id)?,
+        feed_url: decode_token(std::str::from_utf8(feed_url).ok()?).into(),
+        title: std::str::from_utf8(title).ok()?.to_string(),
+        url: token(std::str::from_utf8(url).ok()?),
+        published: None,
+        guid: None,
+        read_at: None,
+        group_id: None,
+        duplicate: false,
+        flags: EntryFlags::empty(),
+        language: None,
+        summary: None,
+    }))
+}
+
+/// A line parsed as either a command or a response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Command(Command),
+    Response(Response),
+}
+
+/// Parse raw bytes as a command or a response
+///
+/// Both parsers are run on every input, which makes this a
+/// suitable fuzzing entry point. Like the FromStr impls, it
+/// never panics. If neither parser accepts the line, the error
+/// from the parser matching its first character (a digit for
+/// responses) is returned.
+///
+/// Responses are read as protocol version 1, as with FromStr;
+/// `parse_any_versioned` reads them in a negotiated version.
+///
+/// Entry lines are decoded on a fast path, making this the
+/// preferred entry point for clients reading raw lines.
+pub fn parse_any(bytes: &[u8]) -> Result<Message, ParseMessageError> {
+    parse_any_versioned(bytes, ProtocolVersion::V1)
+}
+
+/// Parse raw bytes as a command or a response sent with a
+/// specific protocol version
+///
+/// Gives the same result as `Response::parse_versioned` for
+/// every response.
+pub fn parse_any_versioned(
+    bytes: &[u8],
+    version: ProtocolVersion,
+) -> Result<Message, ParseMessageError> {
+    if let Some(response) = parse_entry(bytes, version) {
+        return Ok(Message::Response(response));
+    }
+
+    let line = std::str::from_utf8(bytes).map_err(|_| ParseMessageError::InvalidUtf8)?;
+
+    let command = line.parse::<Command>();
+    let response = Response::parse_versioned(line, version);
+
+    match (command, response) {
+        (Ok(command), _) => Ok(Message::Command(command)),
+        (_, Ok(response)) => Ok(Message::Response(response)),
+        (Err(command), Err(response)) => {
+            if line.starts_with(|c: char| c.is_ascii_digit()) {
+                Err(response)
+            } else {
+                Err(command)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every argument remaining
+    fn arguments(mut arguments: Arguments<'_>) -> Vec<&str> {
+        std::iter::from_fn(|| arguments.take()).collect()
+    }
+
+    #[test]
+    fn splits_on_single_spaces() {
+        let (verb, rest) = Arguments::split("MARKREAD 12 IF 3");
+
+        assert_eq!(verb, "MARKREAD");
+        assert_eq!(arguments(rest), vec!["12", "IF", "3"]);
+    }
+
+    #[test]
+    fn strict_keeps_empty_arguments() {
+        let (verb, rest) = Arguments::split("USER  bob");
+
+        assert_eq!(verb, "USER");
+        assert_eq!(arguments(rest), vec!["", "bob"]);
+
+        let (verb, rest) = Arguments::split("PING ");
+
+        assert_eq!(verb, "PING");
+        assert!(matches!(
+            rest.finish(),
+            Err(ParseMessageError::TooManyArguments {
+                expected: 0,
+                actual: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn strict_does_not_split_on_tabs() {
+        let (verb, rest) = Arguments::split("USER\tbob");
+
+        assert_eq!(verb, "USER\tbob");
+        assert!(rest.finish().is_ok());
+    }
+
+    #[test]
+    fn lenient_collapses_runs_of_separators() {
+        let (verb, rest) = Arguments::split_lenient(" \tUSER \t bob  ");
+
+        assert_eq!(verb, "USER");
+        assert_eq!(arguments(rest), vec!["bob"]);
+
+        let (_, rest) = Arguments::split_lenient("PING \t ");
+
+        assert!(rest.finish().is_ok());
+    }
+
+    #[test]
+    fn empty_lines_have_an_empty_verb() {
+        for line in &["", " ", "\t"] {
+            let (verb, rest) = Arguments::split_lenient(line);
+
+            assert_eq!(verb, "");
+            assert!(rest.finish().is_ok());
+        }
+
+        let (verb, rest) = Arguments::split("");
+
+        assert_eq!(verb, "");
+        assert!(rest.finish().is_ok());
+    }
+
+    #[test]
+    fn rest_keeps_inner_spaces() {
+        let (_, rest) = Arguments::split("41 bad  command ");
+
+        assert_eq!(rest.peek(), Some("bad"));
+        assert_eq!(rest.rest("message").ok(), Some("bad  command "));
+
+        let (_, rest) = Arguments::split("41");
+
+        assert!(matches!(
+            rest.rest("message"),
+            Err(ParseMessageError::MissingArgument(name)) if name == "message"
+        ));
+    }
+
+    #[test]
+    fn keywords_and_flags() {
+        let (_, mut rest) = Arguments::split("LISTUNREAD AFTER abc COUNT");
+
+        assert_eq!(rest.keyword("SINCE", "since").ok(), Some(None));
+        assert_eq!(rest.keyword("AFTER", "after").ok(), Some(Some("abc")));
+        assert_eq!(rest.flag("SUMMARY").ok(), Some(false));
+        assert_eq!(rest.flag("COUNT").ok(), Some(true));
+        assert!(rest.finish_keywords().is_ok());
+
+        let (_, mut rest) = Arguments::split("LISTUNREAD AFTER");
+
+        assert!(matches!(
+            rest.keyword("AFTER", "after"),
+            Err(ParseMessageError::MissingArgument(name)) if name == "after"
+        ));
+
+        let (_, rest) = Arguments::split("LISTUNREAD BEFORE 3");
+
+        assert!(matches!(
+            rest.finish_keywords(),
+            Err(ParseMessageError::UnknownKeyword(keyword)) if keyword == "BEFORE"
+        ));
+    }
+
+    #[test]
+    fn counts_extra_arguments() {
+        let (_, mut rest) = Arguments::split("USER bob alice carol");
+
+        assert_eq!(rest.next("username").ok(), Some("bob"));
+        assert!(matches!(
+            rest.finish(),
+            Err(ParseMessageError::TooManyArguments {
+                expected: 1,
+                actual: 3
+            })
+        ));
+
+        let (_, mut rest) = Arguments::split_lenient("USER bob  alice\t carol ");
+
+        assert_eq!(rest.next("username").ok(), Some("bob"));
+        assert!(matches!(
+            rest.finish(),
+            Err(ParseMessageError::TooManyArguments {
+                expected: 1,
+                actual: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn parse_any_matches_parse_versioned() {
+        let entry = Entry {
+            published: Some(1_600_000_000),
+            guid: Some("tag:example.com,2020:1".to_string()),
+            read_at: None,
+            group_id: Some(9),
+            duplicate: true,
+            flags: EntryFlags::STARRED,
+            ..match Response::entry(
+                1,
+                2,
+                "gemini://example.com/feed",
+                "A title",
+                "gemini://example.com/1",
+            ) {
+                Response::Entry(entry) => entry,
+                _ => return,
+            }
+        };
+        let response = Response::Entry(entry);
+
+        for &version in ProtocolVersion::ALL {
+            let line = response.encode(version).to_string();
+            let expected = Response::parse_versioned(&line, version).ok();
+
+            assert!(expected.is_some(), "{}", line);
+            assert_eq!(
+                parse_any_versioned(line.as_bytes(), version).ok(),
+                expected.map(Message::Response),
+                "{}",
+                line
+            );
+        }
+
+        // A version 2 line is not read with the version 1 layout
+        let line = response.encode(ProtocolVersion::V2).to_string();
+
+        assert_eq!(
+            parse_any_versioned(line.as_bytes(), ProtocolVersion::V2).ok(),
+            Some(Message::Response(response))
+        );
+        assert_eq!(
+            parse_any(line.as_bytes()).ok(),
+            line.parse::<Response>().ok().map(Message::Response)
+        );
+    }
+}
@@ -0,0 +1,414 @@
+//! HAProxy PROXY protocol headers
+//!
+//! Load balancers speaking the PROXY protocol send a header
+//! ahead of the client's first line naming the real client
+//! address. Servers accepting connections from one parse the
+//! header with `parse` before handing the rest of the stream
+//! to a Decoder, as `serve` does when its options ask for a
+//! PROXY header. Both the text form of version 1 and the
+//! binary form of version 2 are read.
+//!
+//! Headers arrive from the network, so the same lints as the
+//! line parser apply here.
+
+#![deny(
+    clippy::indexing_slicing,
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::arithmetic_side_effects
+)]
+
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::Range;
+
+use thiserror::Error;
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Longest version 1 header, line ending included
+const V1_MAX_LENGTH: usize = 107;
+
+/// Length of the fixed part of a version 2 header
+const V2_HEADER_LENGTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyVersion {
+    V1,
+    V2,
+}
+
+/// The addresses of a proxied connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyHeader {
+    pub version: ProxyVersion,
+    /// The client's address, or None for connections the proxy
+    /// opened itself, such as health checks, and for families
+    /// other than TCP and UDP over IP
+    pub source: Option<SocketAddr>,
+    /// The address the client connected to
+    pub destination: Option<SocketAddr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ProxyError {
+    #[error("connection does not start with a PROXY header")]
+    Missing,
+    #[error("malformed PROXY header: {0}")]
+    Malformed(&'static str),
+    #[error("unsupported PROXY protocol version {0}")]
+    UnsupportedVersion(u8),
+}
+
+/// Parse the PROXY header at the start of a connection
+///
+/// Returns the header and its length in bytes, or None if more
+/// bytes are needed to tell.
+pub fn parse(bytes: &[u8]) -> Result<Option<(ProxyHeader, usize)>, ProxyError> {
+    if starts_with(bytes, V2_SIGNATURE) {
+        if bytes.len() < V2_SIGNATURE.len() {
+            return Ok(None);
+        }
+
+        return parse_v2(bytes);
+    }
+
+    if starts_with(bytes, V1_PREFIX) {
+        if bytes.len() < V1_PREFIX.len() {
+            return Ok(None);
+        }
+
+        return parse_v1(bytes);
+    }
+
+    Err(ProxyError::Missing)
+}
+
+/// Whether `bytes` is a prefix of `prefix` or starts with it
+fn starts_with(bytes: &[u8], prefix: &[u8]) -> bool {
+    bytes
+        .iter()
+        .zip(prefix)
+        .all(|(byte, expected)| byte == expected)
+}
+
+fn parse_v1(bytes: &[u8]) -> Result<Option<(ProxyHeader, usize)>, ProxyError> {
+    let end = match bytes.windows(2).position(|window| window == b"\r\n") {
+        Some(end) => end,
+        None if bytes.len() >= V1_MAX_LENGTH => {
+            return Err(ProxyError::Malformed("header line too long"))
+        }
+        None => return Ok(None),
+    };
+    let length = end
+        .checked_add(2)
+        .ok_or(ProxyError::Malformed("header line too long"))?;
+
+    if length > V1_MAX_LENGTH {
+        return Err(ProxyError::Malformed("header line too long"));
+    }
+
+    let line = bytes
+        .get(..end)
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .ok_or(ProxyError::Malformed("header is not ASCII"))?;
+    let mut fields = line.split(' ').skip(1);
+
+    let header = match fields.next() {
+        Some("UNKNOWN") => ProxyHeader {
+            version: ProxyVersion::V1,
+            source: None,
+            destination: None,
+        },
+        Some(family @ "TCP4") | Some(family @ "TCP6") => {
+            let mut address = || {
+                fields
+                    .next()
+                    .and_then(|field| field.parse::<IpAddr>().ok())
+                    .filter(|address| address.is_ipv4() == (family == "TCP4"))
+                    .ok_or(ProxyError::Malformed("invalid address"))
+            };
+            let source = address()?;
+            let destination = address()?;
+
+            let mut port = || {
+                fields
+                    .next()
+                    .filter(|field| !field.starts_with('0') || *field == "0")
+                    .and_then(|field| field.parse::<u16>().ok())
+                    .ok_or(ProxyError::Malformed("invalid port"))
+            };
+            let source_port = port()?;
+            let destination_port = port()?;
+
+            if fields.next().is_some() {
+                return Err(ProxyError::Malformed("too many fields"));
+            }
+
+            ProxyHeader {
+                version: ProxyVersion::V1,
+                source: Some(SocketAddr::new(source, source_port)),
+                destination: Some(SocketAddr::new(destination, destination_port)),
+            }
+        }
+        _ => return Err(ProxyError::Malformed("unknown protocol family")),
+    };
+
+    Ok(Some((header, length)))
+}
+
+fn parse_v2(bytes: &[u8]) -> Result<Option<(ProxyHeader, usize)>, ProxyError> {
+    let fixed = match bytes.get(..V2_HEADER_LENGTH) {
+        Some(fixed) => fixed,
+        None => return Ok(None),
+    };
+
+    let (version_command, family, length) = match fixed.get(12..) {
+        Some([version_command, family, high, low]) => (
+            *version_command,
+            *family,
+            usize::from(u16::from_be_bytes([*high, *low])),
+        ),
+        _ => return Ok(None),
+    };
+
+    if version_command >> 4 != 2 {
+        return Err(ProxyError::UnsupportedVersion(version_command >> 4));
+    }
+
+    let total = V2_HEADER_LENGTH
+        .checked_add(length)
+        .ok_or(ProxyError::Malformed("header too long"))?;
+    let addresses = match bytes.get(V2_HEADER_LENGTH..total) {
+        Some(addresses) => addresses,
+        None => return Ok(None),
+    };
+
+    let (source, destination) = match version_command & 0x0f {
+        // LOCAL, sent by the proxy for its own connections
+        0x0 => (None, None),
+        // PROXY
+        0x1 => match family >> 4 {
+            0x1 => {
+                let ip = |range: Range<usize>| {
+                    let octets = <[u8; 4]>::try_from(addresses.get(range)?).ok()?;
+                    Some(IpAddr::V4(Ipv4Addr::from(octets)))
+                };
+
+                addresses_with_ports(ip(0..4), ip(4..8), addresses.get(8..12))?
+            }
+            0x2 => {
+                let ip = |range: Range<usize>| {
+                    let octets = <[u8; 16]>::try_from(addresses.get(range)?).ok()?;
+                    Some(IpAddr::V6(Ipv6Addr::from(octets)))
+                };
+
+                addresses_with_ports(ip(0..16), ip(16..32), addresses.get(32..36))?
+            }
+            // Unspecified and Unix socket families
+            _ => (None, None),
+        },
+        _ => return Err(ProxyError::Malformed("unknown command")),
+    };
+
+    Ok(Some((
+        ProxyHeader {
+            version: ProxyVersion::V2,
+            source,
+            destination,
+        },
+        total,
+    )))
+}
+
+type Addresses = (Option<SocketAddr>, Option<SocketAddr>);
+
+fn addresses_with_ports(
+    source: Option<IpAddr>,
+    destination: Option<IpAddr>,
+    ports: Option<&[u8]>,
+) -> Result<Addresses, ProxyError> {
+    match (source, destination, ports) {
+        (Some(source), Some(destination), Some([source_high, source_low, high, low])) => Ok((
+            Some(SocketAddr::new(
+                source,
+                u16::from_be_bytes([*source_high, *source_low]),
+            )),
+            Some(SocketAddr::new(
+                destination,
+                u16::from_be_bytes([*high, *low]),
+            )),
+        )),
+        _ => Err(ProxyError::Malformed("address block too short")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A version 2 header with the command, family and address
+    /// block given
+    fn v2(command: u8, family: u8, addresses: &[u8]) -> Result<Vec<u8>, ProxyError> {
+        let length = u16::try_from(addresses.len())
+            .map_err(|_| ProxyError::Malformed("test address block too long"))?;
+        let mut header = V2_SIGNATURE.to_vec();
+
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend_from_slice(&length.to_be_bytes());
+        header.extend_from_slice(addresses);
+
+        Ok(header)
+    }
+
+    fn socket(ip: impl Into<IpAddr>, port: u16) -> Option<SocketAddr> {
+        Some(SocketAddr::new(ip.into(), port))
+    }
+
+    #[test]
+    fn parses_version_1() -> Result<(), ProxyError> {
+        let line = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 1965\r\nUSER bob\n";
+
+        assert_eq!(
+            parse(line)?,
+            Some((
+                ProxyHeader {
+                    version: ProxyVersion::V1,
+                    source: socket([192, 0, 2, 1], 56324),
+                    destination: socket([198, 51, 100, 1], 1965),
+                },
+                46
+            ))
+        );
+
+        let line = b"PROXY TCP6 2001:db8::1 2001:db8::2 443 1965\r\n";
+        let (header, length) = parse(line)?.ok_or(ProxyError::Missing)?;
+
+        assert_eq!(length, line.len());
+        assert_eq!(
+            header.source,
+            socket(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 443)
+        );
+
+        assert_eq!(
+            parse(b"PROXY UNKNOWN ignored fields\r\n")?,
+            Some((
+                ProxyHeader {
+                    version: ProxyVersion::V1,
+                    source: None,
+                    destination: None,
+                },
+                30
+            ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parses_version_2() -> Result<(), ProxyError> {
+        let addresses = [192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x07, 0xad];
+        let header = v2(0x1, 0x11, &addresses)?;
+
+        assert_eq!(
+            parse(&header)?,
+            Some((
+                ProxyHeader {
+                    version: ProxyVersion::V2,
+                    source: socket([192, 0, 2, 1], 56324),
+                    destination: socket([198, 51, 100, 1], 1965),
+                },
+                28
+            ))
+        );
+
+        let mut addresses = [0; 36];
+        addresses[15] = 1;
+        addresses[31] = 2;
+        addresses[32..].copy_from_slice(&[0x01, 0xbb, 0x07, 0xad]);
+        let (header, _) = parse(&v2(0x1, 0x21, &addresses)?)?.ok_or(ProxyError::Missing)?;
+
+        assert_eq!(header.source, socket(Ipv6Addr::from(1), 443));
+        assert_eq!(header.destination, socket(Ipv6Addr::from(2), 1965));
+
+        Ok(())
+    }
+
+    #[test]
+    fn local_connections_have_no_addresses() -> Result<(), ProxyError> {
+        let mut header = v2(0x0, 0x11, &[0; 12])?;
+        header.extend_from_slice(b"PING\n");
+
+        assert_eq!(
+            parse(&header)?,
+            Some((
+                ProxyHeader {
+                    version: ProxyVersion::V2,
+                    source: None,
+                    destination: None,
+                },
+                28
+            ))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn waits_for_truncated_headers() -> Result<(), ProxyError> {
+        let line = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 1965\r\n";
+        let header = v2(0x1, 0x11, &[0; 12])?;
+
+        for length in 0..line.len() {
+            assert_eq!(parse(line.get(..length).unwrap_or_default())?, None);
+        }
+
+        for length in 0..header.len() {
+            assert_eq!(parse(header.get(..length).unwrap_or_default())?, None);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_malformed_headers() -> Result<(), ProxyError> {
+        assert_eq!(parse(b"USER bob\n"), Err(ProxyError::Missing));
+        assert_eq!(
+            parse(b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n"),
+            Err(ProxyError::Malformed("invalid address"))
+        );
+        assert_eq!(
+            parse(b"PROXY TCP4 192.0.2.1 192.0.2.2 01 2\r\n"),
+            Err(ProxyError::Malformed("invalid port"))
+        );
+        assert_eq!(
+            parse(b"PROXY UDP4 192.0.2.1 192.0.2.2 1 2\r\n"),
+            Err(ProxyError::Malformed("unknown protocol family"))
+        );
+        assert_eq!(
+            parse(&[b"PROXY ".as_slice(), &[b'x'; V1_MAX_LENGTH]].concat()),
+            Err(ProxyError::Malformed("header line too long"))
+        );
+        assert_eq!(
+            parse(&v2(0x1, 0x11, &[0; 4])?),
+            Err(ProxyError::Malformed("address block too short"))
+        );
+        assert_eq!(
+            parse(&v2(0xf, 0x11, &[0; 12])?),
+            Err(ProxyError::Malformed("unknown command"))
+        );
+
+        let mut header = v2(0x1, 0x11, &[0; 12])?;
+
+        if let Some(version_command) = header.get_mut(12) {
+            *version_command = 0x11;
+        }
+
+        assert_eq!(parse(&header), Err(ProxyError::UnsupportedVersion(1)));
+
+        Ok(())
+    }
+}
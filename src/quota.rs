@@ -0,0 +1,45 @@
+/// Per-user resource limits set by an administrator
+///
+/// A limit of None means unlimited, sent as `-`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    /// Maximum number of subscriptions
+    pub max_feeds: Option<u32>,
+    /// Maximum number of stored entries
+    pub max_entries: Option<u32>,
+}
+
+impl Quota {
+    /// A quota with no limits
+    pub fn unlimited() -> Quota {
+        Quota::default()
+    }
+}
+
+/// A user's quota along with their current usage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub quota: Quota,
+    /// Number of subscriptions held
+    pub feeds: u32,
+    /// Number of entries stored
+    pub entries: u32,
+}
+
+impl QuotaUsage {
+    /// Whether another subscription fits in the quota
+    pub fn can_add_feed(&self) -> bool {
+        match self.quota.max_feeds {
+            Some(max) => self.feeds < max,
+            None => true,
+        }
+    }
+
+    /// Whether another entry fits in the quota
+    pub fn can_add_entry(&self) -> bool {
+        match self.quota.max_entries {
+            Some(max) => self.entries < max,
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,424 @@
+//! Reading feeds without handling the protocol
+//!
+//! A FeedReader sits on a ClientHandle and offers what a reader
+//! application does: subscribe to a feed, list the unread
+//! entries, mark them read and unsubscribe. Each call sends its
+//! commands, follows the pages of long lists and turns the
+//! replies into Feeds and Entries, with every failure (a closed
+//! connection, a timeout, a server error or a reply that makes no
+//! sense) surfacing as a FeedReaderError.
+
+use std::convert::TryFrom;
+
+use thiserror::Error;
+
+use crate::{
+    ClientError, ClientHandle, Command, ConnectionClosed, Credentials, Cursor, Entry, Response,
+    ServerError, Subscription, TimeoutError,
+};
+
+/// A feed the user is subscribed to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feed {
+    pub id: i64,
+    pub url: String,
+    /// URL of the feed's icon
+    pub icon: Option<String>,
+}
+
+impl From<Subscription> for Feed {
+    fn from(subscription: Subscription) -> Feed {
+        Feed {
+            id: subscription.id,
+            url: subscription.url,
+            icon: subscription.icon,
+        }
+    }
+}
+
+/// Why a FeedReader call failed
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum FeedReaderError {
+    #[error(transparent)]
+    Closed(#[from] ConnectionClosed),
+    #[error(transparent)]
+    Timeout(#[from] TimeoutError),
+    /// The server refused the command
+    #[error(transparent)]
+    Server(#[from] ServerError),
+    /// The server accepted the subscription but does not list it
+    #[error("subscribed to {0}, but the server does not list it")]
+    MissingFeed(String),
+    /// The server answered with something the command does not
+    /// expect, a protocol failure. `response` is the line, empty
+    /// if there was none.
+    #[error("unexpected reply to {command}: {response}")]
+    UnexpectedReply { command: String, response: String },
+}
+
+impl From<ClientError> for FeedReaderError {
+    fn from(error: ClientError) -> FeedReaderError {
+        match error {
+            ClientError::Closed(closed) => closed.into(),
+            ClientError::Timeout(timeout) => timeout.into(),
+            ClientError::Server(error) => error.into(),
+        }
+    }
+}
+
+/// Feed reading operations over a shared connection
+#[derive(Debug, Clone)]
+pub struct FeedReader {
+    client: ClientHandle,
+}
+
+impl FeedReader {
+    pub fn new(client: ClientHandle) -> FeedReader {
+        FeedReader { client }
+    }
+
+    /// The connection the reader sends its commands over
+    pub fn client(&self) -> &ClientHandle {
+        &self.client
+    }
+
+    /// Select the user whose feeds to read
+    pub async fn user(&self, username: &str) -> Result<(), FeedReaderError> {
+        let command = Command::User {
+            username: username.to_string(),
+        };
+
+        match self.request(command).await? {
+            (_, Response::AckUser { .. }) => Ok(()),
+            (command, response) => Err(unexpected(&command, &response)),
+        }
+    }
+
+    /// Authenticate, selecting the user the credentials belong to
+    pub async fn authenticate(&self, credentials: Credentials) -> Result<(), FeedReaderError> {
+        match self.request(Command::Auth(credentials)).await? {
+            (_, Response::AckUser { .. }) => Ok(()),
+            (command, response) => Err(unexpected(&command, &response)),
+        }
+    }
+
+    /// Subscribe to the feed at `url`
+    pub async fn subscribe(&self, url: &str) -> Result<Feed, FeedReaderError> {
+        let command = Command::Subscribe {
+            url: url.to_string(),
+            category: None,
+            key: None,
+            deadline: None,
+        };
+
+        match self.request(command).await? {
+            (_, Response::AckSubscribe { .. }) => {}
+            (command, response) => return Err(unexpected(&command, &response)),
+        }
+
+        self.feeds()
+            .await?
+            .into_iter()
+            .find(|feed| feed.url == url)
+            .ok_or_else(|| FeedReaderError::MissingFeed(url.to_string()))
+    }
+
+    /// Unsubscribe from a feed by its id
+    pub async fn unsubscribe(&self, feed: i64) -> Result<(), FeedReaderError> {
+        let command = Command::Unsubscribe {
+            id: feed,
+            expected: None,
+            key: None,
+        };
+
+        match self.request(command).await? {
+            (_, Response::AckUnsubscribe { .. }) => Ok(()),
+            (command, response) => Err(unexpected(&command, &response)),
+        }
+    }
+
+    /// The feeds the user is subscribed to
+    pub async fn feeds(&self) -> Result<Vec<Feed>, FeedReaderError> {
+        let reply = self.client.request(Command::ListSubscriptions).await?;
+        let mut feeds = Vec::new();
+
+        for response in list(
+            &reply.command,
+            reply.responses,
+            Response::StartSubscriptionList,
+        )? {
+            match response {
+                Response::Subscription(subscription) => feeds.push(subscription.into()),
+                response => return Err(unexpected(&reply.command, &response)),
+            }
+        }
+
+        Ok(feeds)
+    }
+
+    /// Every unread entry, following the list across pages
+    pub async fn unread(&self) -> Result<Vec<Entry>, FeedReaderError> {
+        let mut entries = Vec::new();
+        let mut after: Option<Cursor> = None;
+
+        loop {
+            let command = Command::ListUnread {
+                after: after.take(),
+                summary: None,
+                count: false,
+            };
+            let reply = self.client.request(command).await?;
+            let mut responses = list(&reply.command, reply.responses, Response::StartEntryList)?;
+
+            if let Some(Response::EndPage(cursor)) = responses.last() {
+                after = Some(cursor.clone());
+                responses.pop();
+            }
+
+            for response in responses {
+                match response {
+                    Response::Entry(entry) => entries.push(entry),
+                    response => return Err(unexpected(&reply.command, &response)),
+                }
+            }
+
+            if after.is_none() {
+                return Ok(entries);
+            }
+        }
+    }
+
+    /// Mark an entry read
+    pub async fn mark_read(&self, entry: &Entry) -> Result<(), FeedReaderError> {
+        self.mark_read_id(entry.id).await
+    }
+
+    /// Mark an entry read by its id
+    pub async fn mark_read_id(&self, entry: i64) -> Result<(), FeedReaderError> {
+        let command = Command::MarkRead {
+            id: entry,
+            expected: None,
+            key: None,
+        };
+
+        match self.request(command).await? {
+            (_, Response::AckMarkRead { .. }) => Ok(()),
+            (command, response) => Err(unexpected(&command, &response)),
+        }
+    }
+
+    /// Send a command answered with a single response
+    async fn request(&self, command: Command) -> Result<(Command, Response), FeedReaderError> {
+        let mut reply = self.client.request(command).await?;
+
+        match (reply.responses.pop(), reply.responses.is_empty()) {
+            (Some(response), true) => match ServerError::try_from(response) {
+                Ok(error) => Err(error.into()),
+                Err(response) => Ok((reply.command, response)),
+            },
+            (Some(response), false) => Err(unexpected(&reply.command, &response)),
+            (None, _) => Err(missing(&reply.command)),
+        }
+    }
+}
+
+fn unexpected(command: &Command, response: &Response) -> FeedReaderError {
+    FeedReaderError::UnexpectedReply {
+        command: command.to_string(),
+        response: response.to_string(),
+    }
+}
+
+fn missing(command: &Command) -> FeedReaderError {
+    FeedReaderError::UnexpectedReply {
+        command: command.to_string(),
+        response: String::new(),
+    }
+}
+
+/// The items of a list reply, between `start` and EndList, with
+/// an EndPage ending the page left on
+fn list(
+    command: &Command,
+    responses: Vec<Response>,
+    start: Response,
+) -> Result<Vec<Response>, FeedReaderError> {
+    let mut responses = responses.into_iter();
+
+    match responses.next() {
+        Some(response) if response == start => {}
+        Some(response) => {
+            return Err(ServerError::try_from(response)
+                .map(FeedReaderError::from)
+                .unwrap_or_else(|response| unexpected(command, &response)))
+        }
+        None => return Err(missing(command)),
+    }
+
+    let mut items: Vec<Response> = responses.collect();
+
+    if items.last() == Some(&Response::EndList) {
+        items.pop();
+    }
+
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::future::Future;
+    use std::task::{Context, Poll, Waker};
+
+    use crate::ClientDriver;
+
+    /// Run a reader call, answering each line it writes
+    fn run<F: Future>(
+        driver: &mut ClientDriver,
+        future: F,
+        mut answer: impl FnMut(&str) -> Vec<Response>,
+    ) -> F::Output {
+        let mut future = Box::pin(future);
+        let mut cx = Context::from_waker(Waker::noop());
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+
+            let mut answered = false;
+
+            while let Poll::Ready(Some(line)) = driver.poll_line(&mut cx) {
+                for response in answer(&line) {
+                    driver.receive(response).unwrap();
+                }
+
+                answered = true;
+            }
+
+            assert!(answered, "the reader is waiting on nothing");
+        }
+    }
+
+    #[test]
+    fn subscribe_returns_the_feed() {
+        let (client, mut driver) = ClientHandle::new();
+        let reader = FeedReader::new(client);
+        let url = "https://example.com/feed";
+
+        let feed = run(&mut driver, reader.subscribe(url), |line| match line {
+            "LISTSUBSCRIPTIONS" => vec![
+                Response::StartSubscriptionList,
+                Response::Subscription(Subscription {
+                    id: 7,
+                    url: url.to_string(),
+                    icon: None,
+                    order: None,
+                }),
+                Response::EndList,
+            ],
+            _ => vec![Response::AckSubscribe { key: None }],
+        });
+
+        assert_eq!(
+            feed,
+            Ok(Feed {
+                id: 7,
+                url: url.to_string(),
+                icon: None,
+            })
+        );
+    }
+
+    #[test]
+    fn unread_follows_pages() {
+        let (client, mut driver) = ClientHandle::new();
+        let reader = FeedReader::new(client);
+        let cursor = Cursor::from_bytes(b"page 2").unwrap();
+        let feed = "https://example.com/feed";
+
+        let entries = run(&mut driver, reader.unread(), |line| {
+            if line.contains("AFTER") {
+                vec![
+                    Response::StartEntryList,
+                    Response::entry(2, 1, feed, "Second", "https://example.com/2"),
+                    Response::EndList,
+                ]
+            } else {
+                vec![
+                    Response::StartEntryList,
+                    Response::entry(1, 1, feed, "First", "https://example.com/1"),
+                    Response::EndPage(cursor.clone()),
+                ]
+            }
+        })
+        .unwrap();
+
+        let ids: Vec<_> = entries.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, [1, 2]);
+
+        let marked = run(&mut driver, reader.mark_read(&entries[0]), |_| {
+            vec![Response::AckMarkRead {
+                revision: None,
+                key: None,
+            }]
+        });
+        assert_eq!(marked, Ok(()));
+
+        let unsubscribed = run(&mut driver, reader.unsubscribe(1), |line| {
+            assert_eq!(line, "UNSUBSCRIBE 1");
+            vec![Response::AckUnsubscribe {
+                revision: None,
+                key: None,
+            }]
+        });
+        assert_eq!(unsubscribed, Ok(()));
+    }
+
+    #[test]
+    fn failures_are_typed() {
+        let (client, mut driver) = ClientHandle::new();
+        let reader = FeedReader::new(client);
+
+        let subscribed = run(&mut driver, reader.subscribe("https://example.com"), |_| {
+            vec![Response::ResourceNotFound("no such feed".to_string())]
+        });
+        assert_eq!(
+            subscribed,
+            Err(FeedReaderError::Server(ServerError::NotFound(
+                "no such feed".to_string()
+            )))
+        );
+
+        let unread = run(&mut driver, reader.unread(), |_| {
+            vec![Response::NeedUser("select a user".to_string())]
+        });
+        assert!(matches!(
+            unread,
+            Err(FeedReaderError::Server(ServerError::NeedUser(_)))
+        ));
+
+        let missing = run(
+            &mut driver,
+            reader.subscribe("https://example.com"),
+            |line| match line {
+                "LISTSUBSCRIPTIONS" => vec![Response::StartSubscriptionList, Response::EndList],
+                _ => vec![Response::AckSubscribe { key: None }],
+            },
+        );
+        assert_eq!(
+            missing,
+            Err(FeedReaderError::MissingFeed(
+                "https://example.com".to_string()
+            ))
+        );
+
+        driver.close();
+
+        assert_eq!(
+            run(&mut driver, reader.feeds(), |_| Vec::new()),
+            Err(FeedReaderError::Closed(ConnectionClosed))
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//! Recording and replaying session wire traffic
+//!
+//! A Recording is a list of lines with the direction they
+//! travelled and their offset from the start of the session.
+//! It is stored as text, one line per record, using the same
+//! arrows as the protocol description:
+//!
+//! ```text
+//! 0 > USER jay
+//! 3 < 20 1
+//! ```
+//!
+//! where the first field is the offset in milliseconds.
+//! Directions are seen from the client: lines it sent to the
+//! server are `>`, lines it received are `<`. Secrets are
+//! redacted as in the wire log, so a recording never holds
+//! credentials; replaying one that authenticated sends the
+//! redacted line.
+
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use crate::wirelog::{redact_secrets, Direction};
+
+/// A single line of recorded traffic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedLine {
+    pub offset: Duration,
+    /// Which way the line travelled, seen from the client
+    pub direction: Direction,
+    pub line: String,
+}
+
+impl fmt::Display for RecordedLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let arrow = match self.direction {
+            Direction::Sent => ">",
+            Direction::Received => "<",
+        };
+
+        write!(f, "{} {} {}", self.offset.as_millis(), arrow, self.line)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ParseRecordingError {
+    #[error("line {0}: missing offset")]
+    MissingOffset(usize),
+    #[error("line {line}: invalid offset \"{value}\"")]
+    InvalidOffset { line: usize, value: String },
+    #[error("line {line}: invalid direction \"{value}\"")]
+    InvalidDirection { line: usize, value: String },
+}
+
+impl FromStr for RecordedLine {
+    type Err = ParseRecordingError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        parse_record(value, 1)
+    }
+}
+
+fn parse_record(value: &str, number: usize) -> Result<RecordedLine, ParseRecordingError> {
+    let (offset, rest) = value
+        .split_once(' ')
+        .ok_or(ParseRecordingError::MissingOffset(number))?;
+
+    let offset = offset.parse().map(Duration::from_millis).map_err(|_| {
+        ParseRecordingError::InvalidOffset {
+            line: number,
+            value: offset.to_string(),
+        }
+    })?;
+
+    let (direction, line) = rest.split_once(' ').unwrap_or((rest, ""));
+
+    let direction = match direction {
+        ">" => Direction::Sent,
+        "<" => Direction::Received,
+        _ => {
+            return Err(ParseRecordingError::InvalidDirection {
+                line: number,
+                value: direction.to_string(),
+            })
+        }
+    };
+
+    Ok(RecordedLine {
+        offset,
+        direction,
+        line: line.to_string(),
+    })
+}
+
+/// The recorded traffic of one session
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    pub lines: Vec<RecordedLine>,
+}
+
+impl fmt::Display for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{}", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Recording {
+    type Err = ParseRecordingError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let lines = value
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(index, line)| parse_record(line, index + 1))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Recording { lines })
+    }
+}
+
+/// Records lines of a live session as they are sent
+/// and received
+#[derive(Debug)]
+pub struct Recorder {
+    start: Instant,
+    recording: Recording,
+}
+
+impl Default for Recorder {
+    fn default() -> Self {
+        Recorder::new()
+    }
+}
+
+impl Recorder {
+    /// Start recording, with offsets measured from now
+    pub fn new() -> Self {
+        Recorder {
+            start: Instant::now(),
+            recording: Recording::default(),
+        }
+    }
+
+    /// Record a line sent from the client to the server
+    pub fn record_to_server(&mut self, line: &str) {
+        self.push(Direction::Sent, line);
+    }
+
+    /// Record a line sent from the server to the client
+    pub fn record_to_client(&mut self, line: &str) {
+        self.push(Direction::Received, line);
+    }
+
+    pub fn finish(self) -> Recording {
+        self.recording
+    }
+
+    fn push(&mut self, direction: Direction, line: &str) {
+        self.recording.lines.push(RecordedLine {
+            offset: self.start.elapsed(),
+            direction,
+            line: redact_secrets(direction, line).unwrap_or_else(|| line.to_string()),
+        });
+    }
+}
+
+/// How recorded timing is reproduced during a replay
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pacing {
+    /// Wait the recorded time between lines
+    Preserve,
+
+    /// Divide the recorded waits by the given factor
+    Accelerate(f64),
+
+    /// Send lines without waiting
+    Immediate,
+}
+
+impl Pacing {
+    fn wait(&self, recorded: Duration) {
+        let wait = match self {
+            Pacing::Preserve => recorded,
+            Pacing::Accelerate(factor) if *factor > 0.0 => {
+                Duration::try_from_secs_f64(recorded.as_secs_f64() / factor).unwrap_or(recorded)
+            }
+            Pacing::Accelerate(_) | Pacing::Immediate => return,
+        };
+
+        thread::sleep(wait);
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ReplayError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("record {index}: connection closed, expected \"{expected}\"")]
+    ConnectionClosed { index: usize, expected: String },
+    #[error("record {index}: expected \"{expected}\", got \"{actual}\"")]
+    Mismatch {
+        index: usize,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl Recording {
+    /// Replay one side of the recording against the other
+    /// side under test
+    ///
+    /// Lines recorded in the direction `sends` are written to
+    /// `writer`: `Direction::Sent` plays the client against a
+    /// server under test, and `Direction::Received` plays the
+    /// server against a client. Every other line is read from
+    /// `reader` and must match the recording exactly.
+    pub fn replay<R: BufRead, W: Write>(
+        &self,
+        sends: Direction,
+        pacing: Pacing,
+        reader: &mut R,
+        writer: &mut W,
+    ) -> Result<(), ReplayError> {
+        let mut previous = Duration::from_millis(0);
+
+        for (index, record) in self.lines.iter().enumerate() {
+            if record.direction == sends {
+                pacing.wait(record.offset.checked_sub(previous).unwrap_or_default());
+
+                writeln!(writer, "{}", record.line)?;
+                writer.flush()?;
+            } else {
+                let mut line = String::new();
+
+                if reader.read_line(&mut line)? == 0 {
+                    return Err(ReplayError::ConnectionClosed {
+                        index,
+                        expected: record.line.clone(),
+                    });
+                }
+
+                let line = line.trim_end_matches(&['\r', '\n'][..]);
+
+                if line != record.line {
+                    return Err(ReplayError::Mismatch {
+                        index,
+                        expected: record.line.clone(),
+                        actual: line.to_string(),
+                    });
+                }
+            }
+
+            previous = record.offset;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_recording() {
+        let text = "0 > USER jay\n3 < 20 1\n";
+        let recording: Recording = text.parse().unwrap();
+
+        assert_eq!(recording.lines[0].direction, Direction::Sent);
+        assert_eq!(recording.lines[1].direction, Direction::Received);
+        assert_eq!(recording.to_string(), text);
+    }
+
+    #[test]
+    fn redacts_credentials() {
+        let mut recorder = Recorder::new();
+        recorder.record_to_server("AUTH PASSWORD bob hunter2");
+        recorder.record_to_client("84 0123456789abcdef 1700000000");
+
+        let recording = recorder.finish();
+
+        assert_eq!(recording.lines[0].line, "AUTH [redacted]");
+        assert_eq!(recording.lines[1].line, "84 [redacted]");
+    }
+
+    #[test]
+    fn replays_with_tiny_acceleration() {
+        let recording: Recording = "0 > PING\n1 < 61\n2 > PING\n".parse().unwrap();
+        let mut reader = &b"61\n"[..];
+        let mut written = Vec::new();
+
+        recording
+            .replay(
+                Direction::Sent,
+                Pacing::Accelerate(f64::MIN_POSITIVE),
+                &mut reader,
+                &mut written,
+            )
+            .unwrap();
+
+        assert_eq!(written, b"PING\nPING\n");
+    }
+}
@@ -0,0 +1,167 @@
+//! Request ids for tracing commands across logs
+//!
+//! A client may start a command line with `@<id> ` to give the
+//! command an id of its choosing. The server echoes the prefix
+//! on every line of its reply and records the id in its logs,
+//! so one user action can be followed from client logs through
+//! server logs to packet captures. Ids are only for people
+//! reading logs; replies are still matched to commands by
+//! their order.
+//!
+//! Ids arrive from the network, so the same lints as the line
+//! parser apply here.
+
+#![deny(
+    clippy::indexing_slicing,
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::arithmetic_side_effects
+)]
+
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::{AuditRecord, Command, ParseMessageError, ParseOptions, ProtocolVersion, Response};
+
+/// Longest request id accepted, in characters
+pub const MAX_REQUEST_ID_LENGTH: usize = 64;
+
+/// A client-chosen id of ASCII letters, digits, `-`, `_` and `.`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(String);
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RequestIdError {
+    #[error("empty request id")]
+    Empty,
+    #[error("request id longer than {MAX_REQUEST_ID_LENGTH} characters")]
+    TooLong,
+    #[error("request id contains a character other than letters, digits, '-', '_' and '.'")]
+    InvalidCharacter,
+}
+
+impl RequestId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for RequestId {
+    type Err = RequestIdError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if value.is_empty() {
+            return Err(RequestIdError::Empty);
+        }
+
+        if value.len() > MAX_REQUEST_ID_LENGTH {
+            return Err(RequestIdError::TooLong);
+        }
+
+        if !value
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"-_.".contains(&byte))
+        {
+            return Err(RequestIdError::InvalidCharacter);
+        }
+
+        Ok(RequestId(value.to_string()))
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A message and the request id its line carries
+///
+/// Displayed with the `@<id> ` prefix when there is an id, so
+/// servers echo a command's id by wrapping each response of the
+/// reply, encoded for the connection's version, with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identified<T> {
+    pub request_id: Option<RequestId>,
+    pub message: T,
+}
+
+impl<T> Identified<T> {
+    pub fn new(request_id: Option<RequestId>, message: T) -> Identified<T> {
+        Identified {
+            request_id,
+            message,
+        }
+    }
+}
+
+/// Split the request id prefix off a line
+fn split(line: &str) -> Result<(Option<RequestId>, &str), ParseMessageError> {
+    let prefixed = match line.strip_prefix('@') {
+        Some(prefixed) => prefixed,
+        None => return Ok((None, line)),
+    };
+
+    let (value, rest) = prefixed.split_once(' ').unwrap_or((prefixed, ""));
+    let request_id = value
+        .parse()
+        .map_err(|error| ParseMessageError::InvalidRequestId {
+            value: value.to_string(),
+            error,
+        })?;
+
+    Ok((Some(request_id), rest))
+}
+
+impl Identified<Command> {
+    /// Parse a command line that may carry a request id
+    pub fn parse_with(line: &str, options: &ParseOptions) -> Result<Self, ParseMessageError> {
+        let (request_id, line) = split(line)?;
+
+        Ok(Identified {
+            request_id,
+            message: Command::parse_with(line, options)?,
+        })
+    }
+
+    /// The command's audit record, with its request id
+    pub fn to_audit_record(&self) -> AuditRecord {
+        let mut record = self.message.to_audit_record();
+
+        if let Some(request_id) = &self.request_id {
+            record
+                .arguments
+                .insert(0, ("request_id", request_id.to_string()));
+        }
+
+        record
+    }
+}
+
+impl Identified<Response> {
+    /// Parse a response line that may carry a request id
+    pub fn parse_versioned(
+        line: &str,
+        version: ProtocolVersion,
+    ) -> Result<Self, ParseMessageError> {
+        let (request_id, line) = split(line)?;
+
+        Ok(Identified {
+            request_id,
+            message: Response::parse_versioned(line, version)?,
+        })
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Identified<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(request_id) = &self.request_id {
+            write!(f, "@{} ", request_id)?;
+        }
+
+        write!(f, "{}", self.message)
+    }
+}
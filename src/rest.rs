@@ -0,0 +1,1797 @@
+//! Canonical HTTP/REST mapping of the protocol
+//!
+//! Every Command has a method, path and optional JSON body,
+//! and every complete reply has a status code and optional
+//! JSON body, so an HTTP façade can be put in front of a
+//! seymour server without defining its own model:
+//!
+//! ```text
+//! USER <username>        PUT    /user                {"username": ...}
+//! AUTH PASSWORD <u> <p>  POST   /auth                {"username": ..., "password": ...}
+//! AUTH TOKEN <token>     POST   /auth                {"token": ...}
+//! RESUMETOKEN            POST   /session/token
+//! RESUME <token>         POST   /resume              {"token": ...}
+//! LISTSUBSCRIPTIONS      GET    /subscriptions
+//! SUBSCRIBE <url> [CATEGORY <c>] [KEY <k>]
+//!                        POST   /subscriptions[?key=<k>] {"url": ..., "category": ...}
+//! UNSUBSCRIBE <id> [IF <r>] [KEY <k>]
+//!                        DELETE /subscriptions/<id>[?if=<r>&key=<k>]
+//! SETORDER <id> <order>  PUT    /subscriptions/<id>/order {"order": ...}
+//! SETINTERVAL <id> <s>   PUT    /subscriptions/<id>/interval {"seconds": ...}
+//! FEEDSTATUS <id>        GET    /subscriptions/<id>/status
+//! GETRAWFEED <id>        GET    /subscriptions/<id>/raw
+//! LISTUNREAD [AFTER <c>] [SUMMARY <n>] [COUNT]
+//!                        GET    /entries/unread[?after=<c>&summary=<n>&count=1]
+//! MARKREAD <id> [IF <r>] [KEY <k>]
+//!                        POST   /entries/<id>/read[?if=<r>&key=<k>]
+//! MARKUNREAD <id> [IF <r>]
+//!                        DELETE /entries/<id>/read[?if=<r>]
+//! MARKREAD ALL           POST   /entries/read
+//! GETCONTENT <id> [FORMAT <f>]
+//!                        GET    /entries/<id>/content[?format=<f>]
+//! EXPORT [FEED <id>]     GET    /export[?feed=<id>]
+//! IMPORT + payload       POST   /import              {"urls": [...]}
+//! UNDO                   POST   /undo
+//! SYNC [<token>]         GET    /sync[?token=<token>]
+//! LISTREAD [SINCE <t>] [AFTER <c>] [SUMMARY <n>] [COUNT]
+//!                        GET    /entries/read[?since=<t>&after=<c>&summary=<n>&count=1]
+//! SEARCH <query>         GET    /entries/search?q=<query>
+//! SAVESEARCH <n> <query> PUT    /searches/<n>        {"query": ...}
+//! LISTSEARCHES           GET    /searches
+//! RUNSEARCH <n> [AFTER <c>]
+//!                        GET    /searches/<n>/entries[?after=<c>]
+//! SESSION OPEN <n>       POST   /sessions            {"name": ...}
+//! SESSION SELECT <n>     PUT    /session             {"name": ...}
+//! SESSION CLOSE <n>      DELETE /sessions/<n>
+//! PING                   GET    /ping
+//! CAPABILITIES [...]     POST   /capabilities        {"versions": [...], "anonymous": ..., "limits": {...}, "extensions": [...]}
+//! SETQUOTA <user> ...    PUT    /users/<user>/quota  {"max_feeds": ..., "max_entries": ...}
+//! GETQUOTA               GET    /quota
+//! GETQUOTA <user>        GET    /users/<user>/quota
+//! SHARE <tag> <user>     PUT    /shares/<tag>/<user>
+//! UNSHARE <tag> <user>   DELETE /shares/<tag>/<user>
+//! LISTSHARES             GET    /shares
+//! LISTCATEGORIES         GET    /categories
+//! ADDWEBHOOK <url>       POST   /webhooks            {"url": ...}
+//! LISTWEBHOOKS           GET    /webhooks
+//! DELETEWEBHOOK <id>     DELETE /webhooks/<id>
+//! ```
+//!
+//! Export replies carry the JSON Feed document itself as their
+//! body. The URL list following IMPORT travels in the request body,
+//! built by `RestRequest::import` and turned back into payload
+//! lines by `RestRequest::import_payload`.
+//!
+//! Counts are answered with `{"count": ...}`. Entry lists cut
+//! short by EndPage carry its cursor in a
+//! `"next"` field next to `"entries"`. Sync replies carry a
+//! `"changes"` array whose objects are tagged by `"type"`, and
+//! the next token in `"token"`.
+//!
+//! Acks carrying a revision answer `{"revision": ...}`.
+//! Idempotency keys are not repeated in HTTP replies, so
+//! `to_reply` echoes the key of the request's command. The
+//! DEADLINE of SUBSCRIBE, SEARCH and RUNSEARCH travels as a
+//! `deadline` query parameter.
+//! Successful acks without data are answered with 204, and
+//! error responses with a status matching their meaning and a
+//! `{"error": <message>, "code": <code>}` body. Overloaded and
+//! Maintenance share 503 with ShuttingDown, so the protocol
+//! code tells them apart.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
+use crate::escape::{is_clean_text, is_clean_token};
+use crate::form;
+use crate::json::Value;
+use crate::search::SavedSearch;
+use crate::webhook::validate_webhook_url;
+use crate::{
+    url_list, Backoff, Capabilities, Category, Change, Command, Credentials, Cursor, Entry,
+    EntryFlags, FeedStatus, ImportOutcome, ImportResult, Limits, ParseOptions, ProtocolVersion,
+    Quota, QuotaUsage, Response, SessionAction, Share, Subscription, SyncToken, Undone,
+    UsernameError, Webhook,
+};
+
+#[derive(Debug, Error)]
+pub enum RestError {
+    #[error("unknown method \"{0}\"")]
+    UnknownMethod(String),
+    #[error("no command for {method} {path}")]
+    UnknownRoute { method: Method, path: String },
+    #[error("invalid id \"{0}\" in path")]
+    InvalidPathId(String),
+    #[error("invalid query parameter \"{0}\"")]
+    InvalidQuery(String),
+    #[error("invalid JSON body: {0}")]
+    InvalidJson(String),
+    #[error("missing or invalid field \"{0}\" in body")]
+    InvalidField(String),
+    #[error("\"{0}\" contains whitespace or control characters")]
+    InvalidToken(String),
+    #[error("invalid username \"{value}\": {error}")]
+    InvalidUsername { value: String, error: UsernameError },
+    #[error("unexpected status {0}")]
+    UnexpectedStatus(u16),
+    #[error("reply is not a complete response sequence")]
+    MalformedReply,
+}
+
+/// HTTP methods used by the mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Delete,
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Method::Get => write!(f, "GET"),
+            Method::Post => write!(f, "POST"),
+            Method::Put => write!(f, "PUT"),
+            Method::Delete => write!(f, "DELETE"),
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = RestError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "GET" => Ok(Method::Get),
+            "POST" => Ok(Method::Post),
+            "PUT" => Ok(Method::Put),
+            "DELETE" => Ok(Method::Delete),
+            _ => Err(RestError::UnknownMethod(value.to_string())),
+        }
+    }
+}
+
+fn parse_body(body: Option<&str>) -> Result<Value, RestError> {
+    Value::parse(body.unwrap_or_default()).map_err(|e| RestError::InvalidJson(e.to_string()))
+}
+
+fn string_field(body: &Value, field: &str) -> Result<String, RestError> {
+    body.get(field)
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| RestError::InvalidField(field.to_string()))
+}
+
+/// A single-word argument, which must not be able to end the
+/// command line or split into several arguments
+fn token(name: &str, value: String) -> Result<String, RestError> {
+    if is_clean_token(&value) {
+        Ok(value)
+    } else {
+        Err(RestError::InvalidToken(name.to_string()))
+    }
+}
+
+fn token_field(body: &Value, field: &str) -> Result<String, RestError> {
+    token(field, string_field(body, field)?)
+}
+
+fn optional_token_field(body: &Value, field: &str) -> Result<Option<String>, RestError> {
+    body.get(field)
+        .and_then(Value::as_str)
+        .map(|value| token(field, value.to_string()))
+        .transpose()
+}
+
+fn query_token(query: &[(String, String)], key: &str) -> Result<Option<String>, RestError> {
+    form::get(query, key)
+        .map(|value| token(key, value.to_string()))
+        .transpose()
+}
+
+/// A percent-decoded path segment used as a single argument
+fn path_token(name: &str, segment: &str) -> Result<String, RestError> {
+    token(name, form::decode(segment))
+}
+
+fn integer_field(body: &Value, field: &str) -> Result<i64, RestError> {
+    body.get(field)
+        .and_then(Value::as_i64)
+        .ok_or_else(|| RestError::InvalidField(field.to_string()))
+}
+
+fn query_integer(query: &[(String, String)], key: &str) -> Result<Option<i64>, RestError> {
+    form::get(query, key)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| RestError::InvalidQuery(key.to_string()))
+        })
+        .transpose()
+}
+
+fn query_count(query: &[(String, String)], key: &str) -> Result<Option<u32>, RestError> {
+    form::get(query, key)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| RestError::InvalidQuery(key.to_string()))
+        })
+        .transpose()
+}
+
+fn query_cursor(query: &[(String, String)], key: &str) -> Result<Option<Cursor>, RestError> {
+    form::get(query, key)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| RestError::InvalidQuery(key.to_string()))
+        })
+        .transpose()
+}
+
+/// A parameter set to `1` when true and left out otherwise
+fn query_flag(query: &[(String, String)], key: &str) -> Result<bool, RestError> {
+    match form::get(query, key) {
+        None => Ok(false),
+        Some("1") => Ok(true),
+        Some(_) => Err(RestError::InvalidQuery(key.to_string())),
+    }
+}
+
+fn flag_parameter(flag: bool) -> Option<String> {
+    if flag {
+        Some("1".to_string())
+    } else {
+        None
+    }
+}
+
+/// Append the present query parameters to a path
+fn with_query(path: &str, parameters: &[(&str, Option<String>)]) -> String {
+    let mut separator = '?';
+    let mut path = path.to_string();
+
+    for (key, value) in parameters {
+        if let Some(value) = value {
+            path.push_str(&format!("{}{}={}", separator, key, form::encode(value)));
+            separator = '&';
+        }
+    }
+
+    path
+}
+
+fn count_field(body: &Value, field: &str) -> Result<u32, RestError> {
+    body.get(field)
+        .and_then(Value::as_i64)
+        .and_then(|count| u32::try_from(count).ok())
+        .ok_or_else(|| RestError::InvalidField(field.to_string()))
+}
+
+/// A count that may be null, meaning unlimited
+fn optional_count_field(body: &Value, field: &str) -> Result<Option<u32>, RestError> {
+    match body.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(_) => count_field(body, field).map(Some),
+    }
+}
+
+fn optional_count(count: Option<u32>) -> Value {
+    count.map_or(Value::Null, |count| Value::Integer(i64::from(count)))
+}
+
+fn path_id(segment: &str) -> Result<i64, RestError> {
+    segment
+        .parse()
+        .map_err(|_| RestError::InvalidPathId(segment.to_string()))
+}
+
+/// An HTTP request corresponding to a Command
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestRequest {
+    pub method: Method,
+    pub path: String,
+    pub body: Option<String>,
+}
+
+impl From<&Command> for RestRequest {
+    fn from(command: &Command) -> RestRequest {
+        let (method, path, body) = match command {
+            Command::User { username } => (
+                Method::Put,
+                "/user".to_string(),
+                Some(Value::object(vec![(
+                    "username",
+                    Value::from(username.as_str()),
+                )])),
+            ),
+            Command::Auth(credentials) => (
+                Method::Post,
+                "/auth".to_string(),
+                Some(match credentials {
+                    Credentials::Password { username, password } => Value::object(vec![
+                        ("username", Value::from(username.as_str())),
+                        ("password", Value::from(password.as_str())),
+                    ]),
+                    Credentials::Token(token) => {
+                        Value::object(vec![("token", Value::from(token.as_str()))])
+                    }
+                }),
+            ),
+            Command::ResumeToken => (Method::Post, "/session/token".to_string(), None),
+            Command::Resume { token } => (
+                Method::Post,
+                "/resume".to_string(),
+                Some(Value::object(vec![("token", Value::from(token.as_str()))])),
+            ),
+            Command::ListSubscriptions => (Method::Get, "/subscriptions".to_string(), None),
+            Command::Subscribe {
+                url,
+                category,
+                key,
+                deadline,
+            } => (
+                Method::Post,
+                with_query(
+                    "/subscriptions",
+                    &[
+                        ("key", key.clone()),
+                        ("deadline", deadline.map(|deadline| deadline.to_string())),
+                    ],
+                ),
+                Some(Value::object(vec![
+                    ("url", Value::from(url.as_str())),
+                    (
+                        "category",
+                        category.as_deref().map_or(Value::Null, Value::from),
+                    ),
+                ])),
+            ),
+            Command::Unsubscribe { id, expected, key } => (
+                Method::Delete,
+                with_query(
+                    &format!("/subscriptions/{}", id),
+                    &[
+                        ("if", expected.map(|expected| expected.to_string())),
+                        ("key", key.clone()),
+                    ],
+                ),
+                None,
+            ),
+            Command::SetOrder { id, order } => (
+                Method::Put,
+                format!("/subscriptions/{}/order", id),
+                Some(Value::object(vec![("order", Value::Integer(*order))])),
+            ),
+            Command::SetInterval { feed_id, seconds } => (
+                Method::Put,
+                format!("/subscriptions/{}/interval", feed_id),
+                Some(Value::object(vec![(
+                    "seconds",
+                    Value::Integer(i64::from(*seconds)),
+                )])),
+            ),
+            Command::FeedStatus { feed_id } => (
+                Method::Get,
+                format!("/subscriptions/{}/status", feed_id),
+                None,
+            ),
+            Command::ListUnread {
+                after,
+                summary,
+                count,
+            } => (
+                Method::Get,
+                with_query(
+                    "/entries/unread",
+                    &[
+                        ("after", after.as_ref().map(Cursor::to_string)),
+                        ("summary", summary.map(|summary| summary.to_string())),
+                        ("count", flag_parameter(*count)),
+                    ],
+                ),
+                None,
+            ),
+            Command::MarkRead { id, expected, key } => (
+                Method::Post,
+                with_query(
+                    &format!("/entries/{}/read", id),
+                    &[
+                        ("if", expected.map(|expected| expected.to_string())),
+                        ("key", key.clone()),
+                    ],
+                ),
+                None,
+            ),
+            Command::MarkUnread { id, expected } => (
+                Method::Delete,
+                with_query(
+                    &format!("/entries/{}/read", id),
+                    &[("if", expected.map(|expected| expected.to_string()))],
+                ),
+                None,
+            ),
+            Command::MarkAllRead => (Method::Post, "/entries/read".to_string(), None),
+            Command::GetContent { id, format } => (
+                Method::Get,
+                with_query(
+                    &format!("/entries/{}/content", id),
+                    &[("format", format.map(|format| format.to_string()))],
+                ),
+                None,
+            ),
+            Command::ListRead {
+                since,
+                after,
+                summary,
+                count,
+            } => (
+                Method::Get,
+                with_query(
+                    "/entries/read",
+                    &[
+                        ("since", since.map(|since| since.to_string())),
+                        ("after", after.as_ref().map(Cursor::to_string)),
+                        ("summary", summary.map(|summary| summary.to_string())),
+                        ("count", flag_parameter(*count)),
+                    ],
+                ),
+                None,
+            ),
+            Command::Search { query, deadline } => (
+                Method::Get,
+                with_query(
+                    "/entries/search",
+                    &[
+                        ("q", Some(query.to_string())),
+                        ("deadline", deadline.map(|deadline| deadline.to_string())),
+                    ],
+                ),
+                None,
+            ),
+            Command::SaveSearch { name, query } => (
+                Method::Put,
+                format!("/searches/{}", form::encode(name)),
+                Some(Value::object(vec![(
+                    "query",
+                    Value::from(query.to_string()),
+                )])),
+            ),
+            Command::ListSavedSearches => (Method::Get, "/searches".to_string(), None),
+            Command::RunSearch {
+                name,
+                after,
+                deadline,
+            } => (
+                Method::Get,
+                with_query(
+                    &format!("/searches/{}/entries", form::encode(name)),
+                    &[
+                        ("after", after.as_ref().map(Cursor::to_string)),
+                        ("deadline", deadline.map(|deadline| deadline.to_string())),
+                    ],
+                ),
+                None,
+            ),
+            Command::Session { action, name } => match action {
+                SessionAction::Open => (
+                    Method::Post,
+                    "/sessions".to_string(),
+                    Some(Value::object(vec![("name", Value::from(name.as_str()))])),
+                ),
+                SessionAction::Select => (
+                    Method::Put,
+                    "/session".to_string(),
+                    Some(Value::object(vec![("name", Value::from(name.as_str()))])),
+                ),
+                SessionAction::Close => (
+                    Method::Delete,
+                    format!("/sessions/{}", form::encode(name)),
+                    None,
+                ),
+            },
+            Command::GetRawFeed { feed_id } => {
+                (Method::Get, format!("/subscriptions/{}/raw", feed_id), None)
+            }
+            Command::Export { feed_id } => (
+                Method::Get,
+                with_query(
+                    "/export",
+                    &[("feed", feed_id.map(|feed_id| feed_id.to_string()))],
+                ),
+                None,
+            ),
+            Command::Import => (Method::Post, "/import".to_string(), None),
+            Command::Undo => (Method::Post, "/undo".to_string(), None),
+            Command::Sync { token } => (
+                Method::Get,
+                with_query(
+                    "/sync",
+                    &[("token", token.as_ref().map(SyncToken::to_string))],
+                ),
+                None,
+            ),
+            Command::Ping => (Method::Get, "/ping".to_string(), None),
+            Command::Capabilities(capabilities) => (
+                Method::Post,
+                "/capabilities".to_string(),
+                Some(capabilities_value(capabilities)),
+            ),
+            Command::SetQuota { username, quota } => (
+                Method::Put,
+                format!("/users/{}/quota", form::encode(username)),
+                Some(Value::object(vec![
+                    ("max_feeds", optional_count(quota.max_feeds)),
+                    ("max_entries", optional_count(quota.max_entries)),
+                ])),
+            ),
+            Command::GetQuota { username: None } => (Method::Get, "/quota".to_string(), None),
+            Command::GetQuota {
+                username: Some(username),
+            } => (
+                Method::Get,
+                format!("/users/{}/quota", form::encode(username)),
+                None,
+            ),
+            Command::Share { tag, username } => (
+                Method::Put,
+                format!("/shares/{}/{}", form::encode(tag), form::encode(username)),
+                None,
+            ),
+            Command::Unshare { tag, username } => (
+                Method::Delete,
+                format!("/shares/{}/{}", form::encode(tag), form::encode(username)),
+                None,
+            ),
+            Command::ListShares => (Method::Get, "/shares".to_string(), None),
+            Command::ListCategories => (Method::Get, "/categories".to_string(), None),
+            Command::AddWebhook { url } => (
+                Method::Post,
+                "/webhooks".to_string(),
+                Some(Value::object(vec![("url", Value::from(url.as_str()))])),
+            ),
+            Command::ListWebhooks => (Method::Get, "/webhooks".to_string(), None),
+            Command::DeleteWebhook { id } => (Method::Delete, format!("/webhooks/{}", id), None),
+        };
+
+        RestRequest {
+            method,
+            path,
+            body: body.map(|body| body.to_string()),
+        }
+    }
+}
+
+impl RestRequest {
+    /// The request importing the URLs of a plain-text list
+    pub fn import(text: &str) -> RestRequest {
+        let urls = url_list(text).map(Value::from).collect();
+
+        RestRequest {
+            method: Method::Post,
+            path: "/import".to_string(),
+            body: Some(Value::object(vec![("urls", Value::Array(urls))]).to_string()),
+        }
+    }
+
+    /// The payload lines to send after the Import command of an
+    /// import request
+    pub fn import_payload(&self) -> Result<Vec<Response>, RestError> {
+        let body = parse_body(self.body.as_deref())?;
+        let urls = list_items(&body, "urls")?
+            .iter()
+            .map(|url| {
+                url.as_str()
+                    .filter(|url| is_clean_token(url))
+                    .ok_or_else(|| RestError::InvalidField("urls".to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Response::import_payload(&urls.join("\n")))
+    }
+
+    /// Map the request onto the Command it represents
+    ///
+    /// Query parameters not used by the route are ignored.
+    /// Usernames are taken as given; use `to_command_with` to
+    /// normalize and check them as the server's parser does.
+    pub fn to_command(&self) -> Result<Command, RestError> {
+        self.to_command_with(&ParseOptions::default())
+    }
+
+    /// Map the request onto a Command, applying the username
+    /// normalization and policy of `options`
+    ///
+    /// Values sent as a single argument of the command must not
+    /// contain whitespace or control characters, so that a body
+    /// field or path segment cannot smuggle a second command
+    /// onto the line.
+    pub fn to_command_with(&self, options: &ParseOptions) -> Result<Command, RestError> {
+        let mut command = self.route()?;
+
+        if let Some(username) = command.username_mut() {
+            options
+                .apply_to_username(username)
+                .map_err(|error| RestError::InvalidUsername {
+                    value: username.clone(),
+                    error,
+                })?;
+        }
+
+        Ok(command)
+    }
+
+    fn route(&self) -> Result<Command, RestError> {
+        let (path, query) = self.path.split_once('?').unwrap_or((&self.path, ""));
+        let query = form::parse(query);
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let body = self.body.as_deref();
+
+        match (self.method, segments.as_slice()) {
+            (Method::Put, ["user"]) => Ok(Command::User {
+                username: token_field(&parse_body(body)?, "username")?,
+            }),
+            (Method::Post, ["auth"]) => {
+                let body = parse_body(body)?;
+
+                Ok(Command::Auth(
+                    match body.get("token").and_then(Value::as_str) {
+                        Some(value) => Credentials::Token(token("token", value.to_string())?),
+                        None => Credentials::Password {
+                            username: token_field(&body, "username")?,
+                            password: token_field(&body, "password")?,
+                        },
+                    },
+                ))
+            }
+            (Method::Post, ["session", "token"]) => Ok(Command::ResumeToken),
+            (Method::Post, ["resume"]) => Ok(Command::Resume {
+                token: token_field(&parse_body(body)?, "token")?,
+            }),
+            (Method::Get, ["subscriptions"]) => Ok(Command::ListSubscriptions),
+            (Method::Post, ["subscriptions"]) => {
+                let body = parse_body(body)?;
+
+                Ok(Command::Subscribe {
+                    url: token_field(&body, "url")?,
+                    category: optional_token_field(&body, "category")?,
+                    key: query_token(&query, "key")?,
+                    deadline: query_count(&query, "deadline")?,
+                })
+            }
+            (Method::Delete, ["subscriptions", id]) => Ok(Command::Unsubscribe {
+                id: path_id(id)?,
+                expected: query_integer(&query, "if")?,
+                key: query_token(&query, "key")?,
+            }),
+            (Method::Put, ["subscriptions", id, "order"]) => Ok(Command::SetOrder {
+                id: path_id(id)?,
+                order: integer_field(&parse_body(body)?, "order")?,
+            }),
+            (Method::Put, ["subscriptions", id, "interval"]) => Ok(Command::SetInterval {
+                feed_id: path_id(id)?,
+                seconds: count_field(&parse_body(body)?, "seconds")?,
+            }),
+            (Method::Get, ["subscriptions", id, "status"]) => Ok(Command::FeedStatus {
+                feed_id: path_id(id)?,
+            }),
+            (Method::Get, ["entries", "unread"]) => Ok(Command::ListUnread {
+                after: query_cursor(&query, "after")?,
+                summary: query_count(&query, "summary")?,
+                count: query_flag(&query, "count")?,
+            }),
+            (Method::Post, ["entries", id, "read"]) => Ok(Command::MarkRead {
+                id: path_id(id)?,
+                expected: query_integer(&query, "if")?,
+                key: query_token(&query, "key")?,
+            }),
+            (Method::Delete, ["entries", id, "read"]) => Ok(Command::MarkUnread {
+                id: path_id(id)?,
+                expected: query_integer(&query, "if")?,
+            }),
+            (Method::Post, ["entries", "read"]) => Ok(Command::MarkAllRead),
+            (Method::Get, ["entries", id, "content"]) => Ok(Command::GetContent {
+                id: path_id(id)?,
+                format: form::get(&query, "format")
+                    .map(|format| {
+                        format
+                            .parse()
+                            .map_err(|_| RestError::InvalidQuery("format".to_string()))
+                    })
+                    .transpose()?,
+            }),
+            (Method::Get, ["entries", "read"]) => Ok(Command::ListRead {
+                since: query_integer(&query, "since")?,
+                after: query_cursor(&query, "after")?,
+                summary: query_count(&query, "summary")?,
+                count: query_flag(&query, "count")?,
+            }),
+            (Method::Get, ["entries", "search"]) => {
+                let search = form::get(&query, "q")
+                    .filter(|search| is_clean_text(search))
+                    .ok_or_else(|| RestError::InvalidQuery("q".to_string()))?;
+
+                Ok(Command::Search {
+                    query: search
+                        .parse()
+                        .map_err(|_| RestError::InvalidQuery("q".to_string()))?,
+                    deadline: query_count(&query, "deadline")?,
+                })
+            }
+            (Method::Put, ["searches", name]) => {
+                let search = string_field(&parse_body(body)?, "query")?;
+
+                if !is_clean_text(&search) {
+                    return Err(RestError::InvalidField("query".to_string()));
+                }
+
+                Ok(Command::SaveSearch {
+                    name: path_token("name", name)?,
+                    query: search
+                        .parse()
+                        .map_err(|_| RestError::InvalidField("query".to_string()))?,
+                })
+            }
+            (Method::Get, ["searches"]) => Ok(Command::ListSavedSearches),
+            (Method::Get, ["searches", name, "entries"]) => Ok(Command::RunSearch {
+                name: path_token("name", name)?,
+                after: query_cursor(&query, "after")?,
+                deadline: query_count(&query, "deadline")?,
+            }),
+            (Method::Post, ["sessions"]) => Ok(Command::Session {
+                action: SessionAction::Open,
+                name: token_field(&parse_body(body)?, "name")?,
+            }),
+            (Method::Put, ["session"]) => Ok(Command::Session {
+                action: SessionAction::Select,
+                name: token_field(&parse_body(body)?, "name")?,
+            }),
+            (Method::Delete, ["sessions", name]) => Ok(Command::Session {
+                action: SessionAction::Close,
+                name: path_token("name", name)?,
+            }),
+            (Method::Get, ["subscriptions", id, "raw"]) => Ok(Command::GetRawFeed {
+                feed_id: path_id(id)?,
+            }),
+            (Method::Get, ["export"]) => Ok(Command::Export {
+                feed_id: query_integer(&query, "feed")?,
+            }),
+            (Method::Post, ["import"]) => Ok(Command::Import),
+            (Method::Post, ["undo"]) => Ok(Command::Undo),
+            (Method::Get, ["sync"]) => Ok(Command::Sync {
+                token: form::get(&query, "token")
+                    .map(|token| {
+                        token
+                            .parse()
+                            .map_err(|_| RestError::InvalidQuery("token".to_string()))
+                    })
+                    .transpose()?,
+            }),
+            (Method::Get, ["ping"]) => Ok(Command::Ping),
+            (Method::Post, ["capabilities"]) => Ok(Command::Capabilities(capabilities_from_value(
+                &parse_body(body)?,
+            ))),
+            (Method::Put, ["users", username, "quota"]) => {
+                let body = parse_body(body)?;
+
+                Ok(Command::SetQuota {
+                    username: path_token("username", username)?,
+                    quota: Quota {
+                        max_feeds: optional_count_field(&body, "max_feeds")?,
+                        max_entries: optional_count_field(&body, "max_entries")?,
+                    },
+                })
+            }
+            (Method::Get, ["quota"]) => Ok(Command::GetQuota { username: None }),
+            (Method::Get, ["users", username, "quota"]) => Ok(Command::GetQuota {
+                username: Some(path_token("username", username)?),
+            }),
+            (Method::Put, ["shares", tag, username]) => Ok(Command::Share {
+                tag: path_token("tag", tag)?,
+                username: path_token("username", username)?,
+            }),
+            (Method::Delete, ["shares", tag, username]) => Ok(Command::Unshare {
+                tag: path_token("tag", tag)?,
+                username: path_token("username", username)?,
+            }),
+            (Method::Get, ["shares"]) => Ok(Command::ListShares),
+            (Method::Get, ["categories"]) => Ok(Command::ListCategories),
+            (Method::Post, ["webhooks"]) => {
+                let url = token_field(&parse_body(body)?, "url")?;
+
+                validate_webhook_url(&url).map_err(|_| RestError::InvalidField("url".into()))?;
+
+                Ok(Command::AddWebhook { url })
+            }
+            (Method::Get, ["webhooks"]) => Ok(Command::ListWebhooks),
+            (Method::Delete, ["webhooks", id]) => Ok(Command::DeleteWebhook { id: path_id(id)? }),
+            _ => Err(RestError::UnknownRoute {
+                method: self.method,
+                path: self.path.clone(),
+            }),
+        }
+    }
+}
+
+/// An HTTP response corresponding to a complete reply
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestResponse {
+    pub status: u16,
+    pub body: Option<String>,
+}
+
+fn error_status(response: &Response) -> Option<(u16, &str)> {
+    match response {
+        Response::ResourceNotFound(message) => Some((404, message)),
+        Response::BadCommand(message) => Some((400, message)),
+        Response::NeedUser(message) => Some((401, message)),
+        Response::RateLimited(message) => Some((429, message)),
+        Response::Forbidden(message) => Some((403, message)),
+        Response::Conflict(message) => Some((409, message)),
+        Response::InternalError(message) => Some((500, message)),
+        Response::ShuttingDown(message) => Some((503, message)),
+        Response::Overloaded(message) => Some((503, message)),
+        Response::UpstreamTimeout(message) => Some((504, message)),
+        Response::Maintenance(message) => Some((503, message)),
+        _ => None,
+    }
+}
+
+/// The error response for a status, using the protocol code
+/// from the body where several responses share the status
+fn error_response(status: u16, code: Option<i64>, message: String) -> Option<Response> {
+    match (status, code) {
+        (404, _) => Some(Response::ResourceNotFound(message)),
+        (400, _) => Some(Response::BadCommand(message)),
+        (401, _) => Some(Response::NeedUser(message)),
+        (429, _) => Some(Response::RateLimited(message)),
+        (403, _) => Some(Response::Forbidden(message)),
+        (409, _) => Some(Response::Conflict(message)),
+        (500, _) => Some(Response::InternalError(message)),
+        (503, Some(53)) => Some(Response::Overloaded(message)),
+        (503, Some(55)) => Some(Response::Maintenance(message)),
+        (503, _) => Some(Response::ShuttingDown(message)),
+        (504, _) => Some(Response::UpstreamTimeout(message)),
+        _ => None,
+    }
+}
+
+fn subscription_value(subscription: &Subscription) -> Value {
+    Value::object(vec![
+        ("id", Value::Integer(subscription.id)),
+        ("url", Value::from(subscription.url.as_str())),
+        (
+            "icon",
+            subscription
+                .icon
+                .as_deref()
+                .map_or(Value::Null, Value::from),
+        ),
+        (
+            "order",
+            subscription.order.map_or(Value::Null, Value::Integer),
+        ),
+    ])
+}
+
+fn entry_value(entry: &Entry) -> Value {
+    Value::object(vec![
+        ("id", Value::Integer(entry.id)),
+        ("feed_id", Value::Integer(entry.feed_id)),
+        ("feed_url", Value::from(&*entry.feed_url)),
+        ("title", Value::from(entry.title.as_str())),
+        ("url", Value::from(entry.url.as_str())),
+        (
+            "published",
+            entry.published.map_or(Value::Null, Value::Integer),
+        ),
+        (
+            "guid",
+            entry.guid.as_deref().map_or(Value::Null, Value::from),
+        ),
+        ("read_at", entry.read_at.map_or(Value::Null, Value::Integer)),
+        (
+            "group_id",
+            entry.group_id.map_or(Value::Null, Value::Integer),
+        ),
+        ("duplicate", Value::Bool(entry.duplicate)),
+        ("flags", Value::from(entry.flags.to_string())),
+        (
+            "language",
+            entry
+                .language
+                .as_ref()
+                .map_or(Value::Null, |language| Value::from(language.as_str())),
+        ),
+        (
+            "summary",
+            entry.summary.as_deref().map_or(Value::Null, Value::from),
+        ),
+    ])
+}
+
+fn share_value(share: &Share) -> Value {
+    Value::object(vec![
+        ("tag", Value::from(share.tag.as_str())),
+        ("owner", Value::from(share.owner.as_str())),
+        ("member", Value::from(share.member.as_str())),
+    ])
+}
+
+fn capabilities_value(capabilities: &Capabilities) -> Value {
+    Value::object(vec![
+        (
+            "versions",
+            Value::Array(
+                capabilities
+                    .versions
+                    .iter()
+                    .map(|version| Value::Integer(i64::from(version.number())))
+                    .collect(),
+            ),
+        ),
+        ("anonymous", Value::Bool(capabilities.anonymous)),
+        ("limits", limits_value(&capabilities.limits)),
+        (
+            "extensions",
+            Value::Array(
+                capabilities
+                    .extensions
+                    .iter()
+                    .map(|extension| Value::from(extension.as_str()))
+                    .collect(),
+            ),
+        ),
+    ])
+}
+
+fn limits_value(limits: &Limits) -> Value {
+    let limit = |limit: Option<u32>| limit.map_or(Value::Null, |n| Value::Integer(i64::from(n)));
+
+    Value::object(vec![
+        ("max_line_length", limit(limits.max_line_length)),
+        ("max_subscriptions", limit(limits.max_subscriptions)),
+        ("max_batch_size", limit(limits.max_batch_size)),
+    ])
+}
+
+fn limits_from_value(value: Option<&Value>) -> Limits {
+    let limit = |field| {
+        value
+            .and_then(|value| value.get(field))
+            .and_then(Value::as_i64)
+            .and_then(|n| u32::try_from(n).ok())
+    };
+
+    Limits {
+        max_line_length: limit("max_line_length"),
+        max_subscriptions: limit("max_subscriptions"),
+        max_batch_size: limit("max_batch_size"),
+    }
+}
+
+fn subscription_from_value(value: &Value) -> Result<Subscription, RestError> {
+    Ok(Subscription {
+        id: integer_field(value, "id")?,
+        url: string_field(value, "url")?,
+        icon: value
+            .get("icon")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        order: value.get("order").and_then(Value::as_i64),
+    })
+}
+
+fn entry_from_value(value: &Value) -> Result<Entry, RestError> {
+    Ok(Entry {
+        id: integer_field(value, "id")?,
+        feed_id: integer_field(value, "feed_id")?,
+        feed_url: string_field(value, "feed_url")?.into(),
+        title: string_field(value, "title")?,
+        url: string_field(value, "url")?,
+        published: value.get("published").and_then(Value::as_i64),
+        guid: value
+            .get("guid")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        read_at: value.get("read_at").and_then(Value::as_i64),
+        group_id: value.get("group_id").and_then(Value::as_i64),
+        duplicate: value
+            .get("duplicate")
+            .and_then(Value::as_bool)
+            .unwrap_or_default(),
+        flags: match value.get("flags").and_then(Value::as_str) {
+            Some(flags) => flags
+                .parse()
+                .map_err(|_| RestError::InvalidField("flags".to_string()))?,
+            None => EntryFlags::empty(),
+        },
+        language: value
+            .get("language")
+            .and_then(Value::as_str)
+            .map(|language| {
+                language
+                    .parse()
+                    .map_err(|_| RestError::InvalidField("language".to_string()))
+            })
+            .transpose()?,
+        summary: value
+            .get("summary")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+    })
+}
+
+fn share_from_value(value: &Value) -> Result<Share, RestError> {
+    Ok(Share {
+        tag: string_field(value, "tag")?,
+        owner: string_field(value, "owner")?,
+        member: string_field(value, "member")?,
+    })
+}
+
+/// Like the wire format, unknown versions are skipped so peers
+/// can advertise versions added later
+fn capabilities_from_value(value: &Value) -> Capabilities {
+    let array = |field| {
+        value
+            .get(field)
+            .and_then(Value::as_array)
+            .unwrap_or_default()
+    };
+
+    Capabilities {
+        versions: array("versions")
+            .iter()
+            .filter_map(Value::as_i64)
+            .filter_map(|number| u8::try_from(number).ok())
+            .filter_map(ProtocolVersion::from_number)
+            .collect(),
+        anonymous: value
+            .get("anonymous")
+            .and_then(Value::as_bool)
+            .unwrap_or_default(),
+        limits: limits_from_value(value.get("limits")),
+        extensions: array("extensions")
+            .iter()
+            .filter_map(Value::as_str)
+            .filter(|extension| is_clean_token(extension))
+            .map(str::to_string)
+            .collect(),
+    }
+}
+
+fn change_value(change: &Response) -> Result<Value, RestError> {
+    match change {
+        Response::Subscription(subscription) => Ok(Value::object(vec![
+            ("type", Value::from("subscribed")),
+            ("subscription", subscription_value(subscription)),
+        ])),
+        Response::Entry(entry) => Ok(Value::object(vec![
+            ("type", Value::from("entry")),
+            ("entry", entry_value(entry)),
+        ])),
+        Response::ReadStateChange { entry_id, read_at } => Ok(Value::object(vec![
+            ("type", Value::from("read")),
+            ("entry_id", Value::Integer(*entry_id)),
+            ("read_at", read_at.map_or(Value::Null, Value::Integer)),
+        ])),
+        Response::Unsubscribed { feed_id } => Ok(Value::object(vec![
+            ("type", Value::from("unsubscribed")),
+            ("feed_id", Value::Integer(*feed_id)),
+        ])),
+        _ => Err(RestError::MalformedReply),
+    }
+}
+
+fn change_from_value(value: &Value) -> Result<Change, RestError> {
+    let field = |name: &str| {
+        value
+            .get(name)
+            .ok_or_else(|| RestError::InvalidField(name.to_string()))
+    };
+
+    match string_field(value, "type")?.as_str() {
+        "subscribed" => Ok(Change::Subscribed(subscription_from_value(field(
+            "subscription",
+        )?)?)),
+        "entry" => Ok(Change::Entry(entry_from_value(field("entry")?)?)),
+        "read" => Ok(Change::ReadState {
+            entry_id: integer_field(value, "entry_id")?,
+            read_at: value.get("read_at").and_then(Value::as_i64),
+        }),
+        "unsubscribed" => Ok(Change::Unsubscribed {
+            feed_id: integer_field(value, "feed_id")?,
+        }),
+        _ => Err(RestError::InvalidField("type".to_string())),
+    }
+}
+
+fn import_result_value(result: &ImportResult) -> Value {
+    let url = ("url", Value::from(result.url.as_str()));
+
+    match &result.outcome {
+        ImportOutcome::Subscribed { feed_id } => Value::object(vec![
+            url,
+            ("status", Value::from("subscribed")),
+            ("feed_id", Value::Integer(*feed_id)),
+        ]),
+        ImportOutcome::AlreadySubscribed { feed_id } => Value::object(vec![
+            url,
+            ("status", Value::from("existing")),
+            ("feed_id", Value::Integer(*feed_id)),
+        ]),
+        ImportOutcome::Failed(message) => Value::object(vec![
+            url,
+            ("status", Value::from("failed")),
+            ("error", Value::from(message.as_str())),
+        ]),
+    }
+}
+
+fn import_result_from_value(value: &Value) -> Result<ImportResult, RestError> {
+    let outcome = match string_field(value, "status")?.as_str() {
+        "subscribed" => ImportOutcome::Subscribed {
+            feed_id: integer_field(value, "feed_id")?,
+        },
+        "existing" => ImportOutcome::AlreadySubscribed {
+            feed_id: integer_field(value, "feed_id")?,
+        },
+        "failed" => ImportOutcome::Failed(string_field(value, "error")?),
+        _ => return Err(RestError::InvalidField("status".to_string())),
+    };
+
+    Ok(ImportResult {
+        url: string_field(value, "url")?,
+        outcome,
+    })
+}
+
+fn list_items<'a>(body: &'a Value, field: &str) -> Result<&'a [Value], RestError> {
+    body.get(field)
+        .and_then(Value::as_array)
+        .ok_or_else(|| RestError::InvalidField(field.to_string()))
+}
+
+impl RestResponse {
+    fn json(status: u16, body: Value) -> RestResponse {
+        RestResponse {
+            status,
+            body: Some(body.to_string()),
+        }
+    }
+
+    fn no_content() -> RestResponse {
+        RestResponse {
+            status: 204,
+            body: None,
+        }
+    }
+
+    /// Encode a complete reply as a single HTTP response
+    pub fn from_reply(responses: &[Response]) -> Result<RestResponse, RestError> {
+        let (first, rest) = responses.split_first().ok_or(RestError::MalformedReply)?;
+
+        if let Some((status, message)) = error_status(first) {
+            return Ok(RestResponse::json(
+                status,
+                Value::object(vec![
+                    ("error", Value::from(message)),
+                    ("code", Value::Integer(i64::from(first.code()))),
+                ]),
+            ));
+        }
+
+        let (items, next) = match rest.split_last() {
+            Some((Response::EndList, items)) => (Some(items), None),
+            Some((Response::EndPage(cursor), items)) => (Some(items), Some(cursor)),
+            _ => (None, None),
+        };
+
+        match (first, items) {
+            (Response::StartSync, None) => match rest.split_last() {
+                Some((Response::EndSync(token), changes)) => {
+                    let changes = changes.iter().map(change_value).collect::<Result<_, _>>()?;
+
+                    Ok(RestResponse::json(
+                        200,
+                        Value::object(vec![
+                            ("changes", Value::Array(changes)),
+                            ("token", Value::from(token.as_str())),
+                        ]),
+                    ))
+                }
+                _ => Err(RestError::MalformedReply),
+            },
+            (Response::AckUser { id }, None) if rest.is_empty() => Ok(RestResponse::json(
+                200,
+                Value::object(vec![("user_id", Value::Integer(*id))]),
+            )),
+            (Response::AckUnsubscribe { revision, .. }, None)
+            | (Response::AckMarkRead { revision, .. }, None)
+            | (Response::AckMarkUnread { revision }, None)
+                if rest.is_empty() =>
+            {
+                Ok(match revision {
+                    Some(revision) => RestResponse::json(
+                        200,
+                        Value::object(vec![("revision", Value::Integer(*revision))]),
+                    ),
+                    None => RestResponse::no_content(),
+                })
+            }
+            (Response::AckSubscribe { .. }, None)
+            | (Response::Pong, None)
+            | (Response::AckShare, None)
+            | (Response::AckUnshare, None)
+            | (Response::AckSaveSearch, None)
+            | (Response::AckSession, None)
+            | (Response::AckSetOrder, None)
+            | (Response::AckSetInterval, None)
+            | (Response::AckDeleteWebhook, None)
+                if rest.is_empty() =>
+            {
+                Ok(RestResponse::no_content())
+            }
+            (Response::Capabilities(capabilities), None) if rest.is_empty() => {
+                Ok(RestResponse::json(200, capabilities_value(capabilities)))
+            }
+            (Response::Quota(usage), None) if rest.is_empty() => Ok(RestResponse::json(
+                200,
+                Value::object(vec![
+                    ("max_feeds", optional_count(usage.quota.max_feeds)),
+                    ("max_entries", optional_count(usage.quota.max_entries)),
+                    ("feeds", Value::Integer(i64::from(usage.feeds))),
+                    ("entries", Value::Integer(i64::from(usage.entries))),
+                ]),
+            )),
+            (Response::AckUndo(undone), None) if rest.is_empty() => Ok(RestResponse::json(
+                200,
+                Value::object(vec![("undone", Value::from(undone.to_string()))]),
+            )),
+            (Response::AckMarkAllRead { count }, None) | (Response::Count(count), None)
+                if rest.is_empty() =>
+            {
+                Ok(RestResponse::json(
+                    200,
+                    Value::object(vec![("count", Value::Integer(i64::from(*count)))]),
+                ))
+            }
+            (Response::ResumeToken { token, expires }, None) if rest.is_empty() => {
+                Ok(RestResponse::json(
+                    200,
+                    Value::object(vec![
+                        ("token", Value::from(token.as_str())),
+                        ("expires", Value::Integer(*expires)),
+                    ]),
+                ))
+            }
+            (Response::AckAddWebhook { id }, None) if rest.is_empty() => Ok(RestResponse::json(
+                200,
+                Value::object(vec![("id", Value::Integer(*id))]),
+            )),
+            (Response::FeedStatus(status), None) if rest.is_empty() => Ok(RestResponse::json(
+                200,
+                Value::object(vec![
+                    ("feed_id", Value::Integer(status.feed_id)),
+                    ("interval", Value::Integer(i64::from(status.interval))),
+                    (
+                        "last_fetched",
+                        status.last_fetched.map_or(Value::Null, Value::Integer),
+                    ),
+                    (
+                        "etag",
+                        status.etag.as_deref().map_or(Value::Null, Value::from),
+                    ),
+                    (
+                        "last_modified",
+                        status.last_modified.map_or(Value::Null, Value::Integer),
+                    ),
+                    (
+                        "backoff",
+                        status.backoff.map_or(Value::Null, |backoff| {
+                            Value::object(vec![
+                                ("failures", Value::Integer(i64::from(backoff.failures))),
+                                ("failing_since", Value::Integer(backoff.failing_since)),
+                                ("next_retry", Value::Integer(backoff.next_retry)),
+                            ])
+                        }),
+                    ),
+                ]),
+            )),
+            (Response::StartSubscriptionList, Some(items)) if next.is_none() => {
+                let subscriptions = items
+                    .iter()
+                    .map(|item| match item {
+                        Response::Subscription(subscription) => {
+                            Ok(subscription_value(subscription))
+                        }
+                        _ => Err(RestError::MalformedReply),
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                Ok(RestResponse::json(
+                    200,
+                    Value::object(vec![("subscriptions", Value::Array(subscriptions))]),
+                ))
+            }
+            (Response::StartEntryList, Some(items)) => {
+                let entries = items
+                    .iter()
+                    .map(|item| match item {
+                        Response::Entry(entry) => Ok(entry_value(entry)),
+                        _ => Err(RestError::MalformedReply),
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                let mut fields = vec![("entries", Value::Array(entries))];
+
+                if let Some(cursor) = next {
+                    fields.push(("next", Value::from(cursor.as_str())));
+                }
+
+                Ok(RestResponse::json(200, Value::object(fields)))
+            }
+            (Response::StartShareList, Some(items)) if next.is_none() => {
+                let shares = items
+                    .iter()
+                    .map(|item| match item {
+                        Response::Share(share) => Ok(share_value(share)),
+                        _ => Err(RestError::MalformedReply),
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                Ok(RestResponse::json(
+                    200,
+                    Value::object(vec![("shares", Value::Array(shares))]),
+                ))
+            }
+            (Response::StartCategoryList, Some(items)) if next.is_none() => {
+                let categories = items
+                    .iter()
+                    .map(|item| match item {
+                        Response::Category(category) => Ok(Value::object(vec![
+                            ("slug", Value::from(category.slug.as_str())),
+                            ("name", Value::from(category.name.as_str())),
+                        ])),
+                        _ => Err(RestError::MalformedReply),
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                Ok(RestResponse::json(
+                    200,
+                    Value::object(vec![("categories", Value::Array(categories))]),
+                ))
+            }
+            (Response::StartSavedSearchList, Some(items)) if next.is_none() => {
+                let searches = items
+                    .iter()
+                    .map(|item| match item {
+                        Response::SavedSearch(search) => Ok(Value::object(vec![
+                            ("name", Value::from(search.name.as_str())),
+                            ("query", Value::from(search.query.to_string())),
+                        ])),
+                        _ => Err(RestError::MalformedReply),
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                Ok(RestResponse::json(
+                    200,
+                    Value::object(vec![("searches", Value::Array(searches))]),
+                ))
+            }
+            (Response::StartWebhookList, Some(items)) if next.is_none() => {
+                let webhooks = items
+                    .iter()
+                    .map(|item| match item {
+                        Response::Webhook(webhook) => Ok(Value::object(vec![
+                            ("id", Value::Integer(webhook.id)),
+                            ("url", Value::from(webhook.url.as_str())),
+                        ])),
+                        _ => Err(RestError::MalformedReply),
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                Ok(RestResponse::json(
+                    200,
+                    Value::object(vec![("webhooks", Value::Array(webhooks))]),
+                ))
+            }
+            (
+                Response::StartRawFeed {
+                    feed_id,
+                    media_type,
+                },
+                Some(items),
+            ) if next.is_none() => Ok(RestResponse::json(
+                200,
+                Value::object(vec![
+                    ("feed_id", Value::Integer(*feed_id)),
+                    (
+                        "media_type",
+                        media_type.as_deref().map_or(Value::Null, Value::from),
+                    ),
+                    ("content", Value::from(Response::payload_text(items))),
+                ]),
+            )),
+            (Response::StartExport, Some(items)) if next.is_none() => Ok(RestResponse {
+                status: 200,
+                body: Some(Response::payload_text(items)),
+            }),
+            (Response::StartImport, Some(items)) if next.is_none() => {
+                let results = items
+                    .iter()
+                    .map(|item| match item {
+                        Response::ImportResult(result) => Ok(import_result_value(result)),
+                        _ => Err(RestError::MalformedReply),
+                    })
+                    .collect::<Result<_, _>>()?;
+
+                Ok(RestResponse::json(
+                    200,
+                    Value::object(vec![("results", Value::Array(results))]),
+                ))
+            }
+            (Response::StartContent { entry_id, format }, Some(items)) if next.is_none() => {
+                Ok(RestResponse::json(
+                    200,
+                    Value::object(vec![
+                        ("entry_id", Value::Integer(*entry_id)),
+                        ("format", Value::from(format.name())),
+                        ("content", Value::from(Response::payload_text(items))),
+                    ]),
+                ))
+            }
+            _ => Err(RestError::MalformedReply),
+        }
+    }
+
+    /// Decode the HTTP response to a command back into the
+    /// responses of a complete reply
+    pub fn to_reply(&self, command: &Command) -> Result<Vec<Response>, RestError> {
+        let body = self.body.as_deref();
+
+        if self.status >= 400 {
+            let body = parse_body(body)?;
+            let message = string_field(&body, "error")?;
+            let code = body.get("code").and_then(Value::as_i64);
+
+            return error_response(self.status, code, message)
+                .map(|response| vec![response])
+                .ok_or(RestError::UnexpectedStatus(self.status));
+        }
+
+        match (command, self.status) {
+            (Command::User { .. } | Command::Auth(_) | Command::Resume { .. }, 200) => {
+                Ok(vec![Response::AckUser {
+                    id: integer_field(&parse_body(body)?, "user_id")?,
+                }])
+            }
+            (Command::Sync { .. }, 200) => {
+                let body = parse_body(body)?;
+                let token = string_field(&body, "token")?
+                    .parse()
+                    .map_err(|_| RestError::InvalidField("token".to_string()))?;
+                let changes = list_items(&body, "changes")?
+                    .iter()
+                    .map(change_from_value)
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                Ok(Response::sync(changes, token).collect())
+            }
+            (Command::ListSubscriptions, 200) => {
+                let body = parse_body(body)?;
+                let mut responses = vec![Response::StartSubscriptionList];
+
+                for item in list_items(&body, "subscriptions")? {
+                    responses.push(Response::Subscription(subscription_from_value(item)?));
+                }
+
+                responses.push(Response::EndList);
+
+                Ok(responses)
+            }
+            (Command::ListUnread { count: true, .. }, 200)
+            | (Command::ListRead { count: true, .. }, 200) => Ok(vec![Response::Count(
+                count_field(&parse_body(body)?, "count")?,
+            )]),
+            (Command::ListUnread { .. }, 200)
+            | (Command::ListRead { .. }, 200)
+            | (Command::Search { .. }, 200)
+            | (Command::RunSearch { .. }, 200) => {
+                let body = parse_body(body)?;
+                let mut responses = vec![Response::StartEntryList];
+
+                for item in list_items(&body, "entries")? {
+                    responses.push(Response::Entry(entry_from_value(item)?));
+                }
+
+                responses.push(match body.get("next").and_then(Value::as_str) {
+                    Some(cursor) => Response::EndPage(
+                        cursor
+                            .parse()
+                            .map_err(|_| RestError::InvalidField("next".to_string()))?,
+                    ),
+                    None => Response::EndList,
+                });
+
+                Ok(responses)
+            }
+            (Command::ListShares, 200) => {
+                let body = parse_body(body)?;
+                let mut responses = vec![Response::StartShareList];
+
+                for item in list_items(&body, "shares")? {
+                    responses.push(Response::Share(share_from_value(item)?));
+                }
+
+                responses.push(Response::EndList);
+
+                Ok(responses)
+            }
+            (Command::ListCategories, 200) => {
+                let body = parse_body(body)?;
+                let mut responses = vec![Response::StartCategoryList];
+
+                for item in list_items(&body, "categories")? {
+                    responses.push(Response::Category(Category {
+                        slug: string_field(item, "slug")?,
+                        name: string_field(item, "name")?,
+                    }));
+                }
+
+                responses.push(Response::EndList);
+
+                Ok(responses)
+            }
+            (Command::ListSavedSearches, 200) => {
+                let body = parse_body(body)?;
+                let mut responses = vec![Response::StartSavedSearchList];
+
+                for item in list_items(&body, "searches")? {
+                    responses.push(Response::SavedSearch(SavedSearch {
+                        name: string_field(item, "name")?,
+                        query: string_field(item, "query")?
+                            .parse()
+                            .map_err(|_| RestError::InvalidField("query".to_string()))?,
+                    }));
+                }
+
+                responses.push(Response::EndList);
+
+                Ok(responses)
+            }
+            (Command::GetRawFeed { .. }, 200) => {
+                let body = parse_body(body)?;
+
+                Ok(Response::raw_feed(
+                    integer_field(&body, "feed_id")?,
+                    body.get("media_type")
+                        .and_then(Value::as_str)
+                        .map(str::to_string),
+                    &string_field(&body, "content")?,
+                )
+                .collect())
+            }
+            (Command::Export { .. }, 200) => {
+                Ok(Response::export(body.unwrap_or_default()).collect())
+            }
+            (Command::Import, 200) => {
+                let body = parse_body(body)?;
+                let mut responses = vec![Response::StartImport];
+
+                for item in list_items(&body, "results")? {
+                    responses.push(Response::ImportResult(import_result_from_value(item)?));
+                }
+
+                responses.push(Response::EndList);
+
+                Ok(responses)
+            }
+            (Command::ListWebhooks, 200) => {
+                let body = parse_body(body)?;
+                let mut responses = vec![Response::StartWebhookList];
+
+                for item in list_items(&body, "webhooks")? {
+                    responses.push(Response::Webhook(Webhook {
+                        id: integer_field(item, "id")?,
+                        url: string_field(item, "url")?,
+                    }));
+                }
+
+                responses.push(Response::EndList);
+
+                Ok(responses)
+            }
+            (Command::Subscribe { key, .. }, 204) => {
+                Ok(vec![Response::AckSubscribe { key: key.clone() }])
+            }
+            (Command::Unsubscribe { key, .. }, 204) => Ok(vec![Response::AckUnsubscribe {
+                revision: None,
+                key: key.clone(),
+            }]),
+            (Command::Unsubscribe { key, .. }, 200) => Ok(vec![Response::AckUnsubscribe {
+                revision: Some(integer_field(&parse_body(body)?, "revision")?),
+                key: key.clone(),
+            }]),
+            (Command::MarkRead { key, .. }, 204) => Ok(vec![Response::AckMarkRead {
+                revision: None,
+                key: key.clone(),
+            }]),
+            (Command::MarkRead { key, .. }, 200) => Ok(vec![Response::AckMarkRead {
+                revision: Some(integer_field(&parse_body(body)?, "revision")?),
+                key: key.clone(),
+            }]),
+            (Command::MarkUnread { .. }, 204) => {
+                Ok(vec![Response::AckMarkUnread { revision: None }])
+            }
+            (Command::MarkUnread { .. }, 200) => Ok(vec![Response::AckMarkUnread {
+                revision: Some(integer_field(&parse_body(body)?, "revision")?),
+            }]),
+            (Command::GetContent { .. }, 200) => {
+                let body = parse_body(body)?;
+                let format = string_field(&body, "format")?
+                    .parse()
+                    .map_err(|_| RestError::InvalidField("format".to_string()))?;
+
+                Ok(Response::content(
+                    integer_field(&body, "entry_id")?,
+                    format,
+                    &string_field(&body, "content")?,
+                )
+                .collect())
+            }
+            (Command::MarkAllRead, 200) => Ok(vec![Response::AckMarkAllRead {
+                count: count_field(&parse_body(body)?, "count")?,
+            }]),
+            (Command::Undo, 200) => {
+                let undone = string_field(&parse_body(body)?, "undone")?;
+
+                undone
+                    .parse()
+                    .ok()
+                    .as_ref()
+                    .and_then(Undone::from_command)
+                    .map(|undone| vec![Response::AckUndo(undone)])
+                    .ok_or_else(|| RestError::InvalidField("undone".to_string()))
+            }
+            (Command::Ping, 204) => Ok(vec![Response::Pong]),
+            (Command::Share { .. }, 204) => Ok(vec![Response::AckShare]),
+            (Command::Unshare { .. }, 204) => Ok(vec![Response::AckUnshare]),
+            (Command::SaveSearch { .. }, 204) => Ok(vec![Response::AckSaveSearch]),
+            (Command::Session { .. }, 204) => Ok(vec![Response::AckSession]),
+            (Command::ResumeToken, 200) => {
+                let body = parse_body(body)?;
+
+                Ok(vec![Response::ResumeToken {
+                    token: string_field(&body, "token")?,
+                    expires: integer_field(&body, "expires")?,
+                }])
+            }
+            (Command::SetOrder { .. }, 204) => Ok(vec![Response::AckSetOrder]),
+            (Command::SetInterval { .. }, 204) => Ok(vec![Response::AckSetInterval]),
+            (Command::AddWebhook { .. }, 200) => Ok(vec![Response::AckAddWebhook {
+                id: integer_field(&parse_body(body)?, "id")?,
+            }]),
+            (Command::DeleteWebhook { .. }, 204) => Ok(vec![Response::AckDeleteWebhook]),
+            (Command::FeedStatus { .. }, 200) => {
+                let body = parse_body(body)?;
+
+                Ok(vec![Response::FeedStatus(FeedStatus {
+                    feed_id: integer_field(&body, "feed_id")?,
+                    interval: count_field(&body, "interval")?,
+                    last_fetched: body.get("last_fetched").and_then(Value::as_i64),
+                    etag: body.get("etag").and_then(Value::as_str).map(str::to_string),
+                    last_modified: body.get("last_modified").and_then(Value::as_i64),
+                    backoff: match body.get("backoff") {
+                        Some(backoff @ Value::Object(_)) => Some(Backoff {
+                            failures: count_field(backoff, "failures")?,
+                            failing_since: integer_field(backoff, "failing_since")?,
+                            next_retry: integer_field(backoff, "next_retry")?,
+                        }),
+                        _ => None,
+                    },
+                })])
+            }
+            (Command::SetQuota { .. }, 200) | (Command::GetQuota { .. }, 200) => {
+                let body = parse_body(body)?;
+
+                Ok(vec![Response::Quota(QuotaUsage {
+                    quota: Quota {
+                        max_feeds: optional_count_field(&body, "max_feeds")?,
+                        max_entries: optional_count_field(&body, "max_entries")?,
+                    },
+                    feeds: count_field(&body, "feeds")?,
+                    entries: count_field(&body, "entries")?,
+                })])
+            }
+            (Command::Capabilities(_), 200) => Ok(vec![Response::Capabilities(
+                capabilities_from_value(&parse_body(body)?),
+            )]),
+            _ => Err(RestError::UnexpectedStatus(self.status)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::UsernamePolicy;
+
+    fn request(method: Method, path: &str, body: Option<&str>) -> RestRequest {
+        RestRequest {
+            method,
+            path: path.to_string(),
+            body: body.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn rejects_line_break_in_body_field() {
+        let request = request(
+            Method::Put,
+            "/user",
+            Some(r#"{"username": "bob\nSESSION SELECT alice"}"#),
+        );
+
+        assert!(matches!(
+            request.to_command(),
+            Err(RestError::InvalidToken(field)) if field == "username"
+        ));
+    }
+
+    #[test]
+    fn rejects_line_break_in_path_segment() {
+        let request = request(Method::Put, "/shares/a%0ASETQUOTA%20x%20-%20-/bob", None);
+
+        assert!(matches!(
+            request.to_command(),
+            Err(RestError::InvalidToken(field)) if field == "tag"
+        ));
+    }
+
+    #[test]
+    fn rejects_space_in_url() {
+        let request = request(
+            Method::Post,
+            "/subscriptions",
+            Some(r#"{"url": "https://example.com/a feed"}"#),
+        );
+
+        assert!(matches!(
+            request.to_command(),
+            Err(RestError::InvalidToken(field)) if field == "url"
+        ));
+    }
+
+    #[test]
+    fn applies_username_options() {
+        let options = ParseOptions {
+            fold_username_case: true,
+            username_policy: Some(UsernamePolicy::default()),
+            ..ParseOptions::default()
+        };
+
+        let share = request(Method::Put, "/shares/tech/Bob", None);
+
+        assert_eq!(
+            share.to_command_with(&options).ok(),
+            Some(Command::Share {
+                tag: "tech".to_string(),
+                username: "bob".to_string(),
+            })
+        );
+
+        let user = request(Method::Put, "/user", Some(r#"{"username": "bob!"}"#));
+
+        assert!(matches!(
+            user.to_command_with(&options),
+            Err(RestError::InvalidUsername { .. })
+        ));
+    }
+}
@@ -0,0 +1,243 @@
+//! Deciding when to refresh each feed
+//!
+//! RefreshScheduler keeps every feed's FeedStatus and the time
+//! its next refresh is due. The server sleeps until `next_wake`,
+//! takes the refreshes that are due with `poll`, fetches them with
+//! its HTTP client, and reports each outcome to `complete`, which
+//! records it and schedules the next refresh. New items go to
+//! `Storage::store_entries`, whose return value tells the server
+//! which entries to announce.
+//!
+//! Requests to a host are admitted through a HostLimiter. After
+//! `shutdown` no new refreshes start, and the server may exit
+//! once `in_flight` reaches zero.
+
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{Admission, FeedStatus, FetchOutcome, HostLimiter};
+
+/// Seconds between refreshes of feeds added with an interval of
+/// zero
+pub const DEFAULT_REFRESH_INTERVAL: u32 = 60 * 60;
+
+/// A refresh to make now
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refresh {
+    pub feed_id: i64,
+    pub url: String,
+    /// Conditional request headers, as names and values
+    pub headers: Vec<(&'static str, String)>,
+}
+
+#[derive(Debug, Clone)]
+struct ScheduledFeed {
+    url: String,
+    status: FeedStatus,
+    due: Option<i64>,
+    in_flight: bool,
+}
+
+/// Tracks when each feed is due for a refresh
+#[derive(Debug, Clone)]
+pub struct RefreshScheduler {
+    feeds: HashMap<i64, ScheduledFeed>,
+    /// Due times and feed ids, earliest first
+    queue: BTreeSet<(i64, i64)>,
+    limiter: HostLimiter,
+    in_flight: usize,
+    shutting_down: bool,
+}
+
+impl RefreshScheduler {
+    /// A scheduler admitting requests through `limiter`
+    pub fn new(limiter: HostLimiter) -> RefreshScheduler {
+        RefreshScheduler {
+            feeds: HashMap::new(),
+            queue: BTreeSet::new(),
+            limiter,
+            in_flight: 0,
+            shutting_down: false,
+        }
+    }
+
+    fn schedule(&mut self, feed_id: i64, at: i64) {
+        if let Some(feed) = self.feeds.get_mut(&feed_id) {
+            if let Some(due) = feed.due.replace(at) {
+                self.queue.remove(&(due, feed_id));
+            }
+
+            self.queue.insert((at, feed_id));
+        }
+    }
+
+    fn unschedule(&mut self, feed_id: i64) {
+        if let Some(due) = self
+            .feeds
+            .get_mut(&feed_id)
+            .and_then(|feed| feed.due.take())
+        {
+            self.queue.remove(&(due, feed_id));
+        }
+    }
+
+    /// Start tracking a feed, from the status stored for it
+    ///
+    /// A feed never fetched is due at once. Adding a feed
+    /// already tracked replaces its status.
+    pub fn add_feed(&mut self, url: &str, mut status: FeedStatus, now: i64) {
+        let feed_id = status.feed_id;
+
+        if status.interval == 0 {
+            status.interval = DEFAULT_REFRESH_INTERVAL;
+        }
+
+        let due = status.next_fetch().unwrap_or(now);
+
+        self.unschedule(feed_id);
+        self.feeds.insert(
+            feed_id,
+            ScheduledFeed {
+                url: url.to_string(),
+                status,
+                due: None,
+                in_flight: false,
+            },
+        );
+        self.schedule(feed_id, due);
+    }
+
+    /// Stop tracking a feed, such as once no one subscribes to
+    /// it
+    pub fn remove_feed(&mut self, feed_id: i64) {
+        self.unschedule(feed_id);
+
+        if let Some(feed) = self.feeds.remove(&feed_id) {
+            if feed.in_flight {
+                self.in_flight = self.in_flight.saturating_sub(1);
+                self.limiter.finish(&feed.url);
+            }
+        }
+    }
+
+    /// Change how often a feed is refreshed, as SETINTERVAL
+    /// asks
+    ///
+    /// Returns false if the feed is not tracked.
+    pub fn set_interval(&mut self, feed_id: i64, seconds: u32, now: i64) -> bool {
+        let feed = match self.feeds.get_mut(&feed_id) {
+            Some(feed) => feed,
+            None => return false,
+        };
+
+        feed.status.interval = seconds;
+
+        if !feed.in_flight {
+            let due = feed.status.next_fetch().unwrap_or(now);
+            self.schedule(feed_id, due);
+        }
+
+        true
+    }
+
+    /// A feed's refresh state, as FEEDSTATUS reports it
+    pub fn status(&self, feed_id: i64) -> Option<&FeedStatus> {
+        self.feeds.get(&feed_id).map(|feed| &feed.status)
+    }
+
+    /// When the next refresh is due, or None if nothing is
+    /// scheduled
+    pub fn next_wake(&self) -> Option<i64> {
+        if self.shutting_down {
+            return None;
+        }
+
+        self.queue.iter().next().map(|(at, _)| *at)
+    }
+
+    /// The refreshes to start at `now`
+    ///
+    /// Feeds whose host is busy or was requested too recently
+    /// stay scheduled for when the host allows another request.
+    pub fn poll(&mut self, now: i64) -> Vec<Refresh> {
+        let mut refreshes = Vec::new();
+
+        if self.shutting_down {
+            return refreshes;
+        }
+
+        let due: Vec<i64> = self
+            .queue
+            .iter()
+            .take_while(|(at, _)| *at <= now)
+            .map(|(_, feed_id)| *feed_id)
+            .collect();
+
+        for feed_id in due {
+            self.unschedule(feed_id);
+
+            let feed = match self.feeds.get_mut(&feed_id) {
+                Some(feed) => feed,
+                None => continue,
+            };
+
+            match self.limiter.admit(&feed.url, now) {
+                Admission::Start => {
+                    feed.in_flight = true;
+                    self.in_flight = self.in_flight.saturating_add(1);
+
+                    refreshes.push(Refresh {
+                        feed_id,
+                        url: feed.url.clone(),
+                        headers: feed.status.conditional_headers(),
+                    });
+                }
+                Admission::WaitUntil(at) => self.schedule(feed_id, at),
+                Admission::Busy => self.schedule(feed_id, now.saturating_add(1)),
+            }
+        }
+
+        refreshes
+    }
+
+    /// Record how a refresh started by `poll` went, scheduling
+    /// the feed's next one
+    ///
+    /// Returns the feed's updated status, to be stored, or None
+    /// if the feed is no longer tracked.
+    pub fn complete(
+        &mut self,
+        feed_id: i64,
+        outcome: FetchOutcome,
+        now: i64,
+    ) -> Option<&FeedStatus> {
+        let feed = self.feeds.get_mut(&feed_id)?;
+
+        if feed.in_flight {
+            feed.in_flight = false;
+            self.in_flight = self.in_flight.saturating_sub(1);
+            self.limiter.finish(&feed.url);
+        }
+
+        feed.status.record_fetch(outcome, now);
+        let due = feed.status.next_fetch().unwrap_or(now);
+        self.schedule(feed_id, due);
+
+        self.status(feed_id)
+    }
+
+    /// Refreshes started and not yet completed
+    pub fn in_flight(&self) -> usize {
+        self.in_flight
+    }
+
+    /// Start no more refreshes
+    ///
+    /// Refreshes in flight may still be completed.
+    pub fn shutdown(&mut self) {
+        self.shutting_down = true;
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down
+    }
+}
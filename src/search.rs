@@ -0,0 +1,614 @@
+//! The SEARCH query language
+//!
+//! A query is a sequence of terms. Terms are bare words or
+//! double-quoted phrases, optionally prefixed by a field such
+//! as `title:` to restrict where they match. Terms next to each
+//! other must all match; `AND` may be written explicitly, `OR`
+//! binds looser than `AND`, and parentheses group:
+//!
+//! ```text
+//! rust title:"release notes" OR (feed:example.org AND async)
+//! ```
+//!
+//! Inside a phrase, a backslash makes the next character
+//! literal, so `\"` and `\\` stand for a quote and a backslash,
+//! except that `\u{1B}` stands for the character with that hex
+//! code. Control characters are written that way, so a query
+//! fits on one protocol line; outside phrases they separate
+//! terms like spaces.
+//!
+//! Words and phrases match case-insensitively anywhere in the
+//! field. Terms without a field match the title, URL or feed
+//! URL of an entry.
+//!
+//! Parsing must never panic, whatever the input, so the same
+//! lints as the line parser apply here.
+
+#![deny(
+    clippy::indexing_slicing,
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::arithmetic_side_effects
+)]
+
+use std::fmt;
+use std::iter::Peekable;
+use std::str::{CharIndices, FromStr};
+
+use thiserror::Error;
+
+use crate::Entry;
+
+/// Deepest nesting of parentheses accepted, bounding the
+/// recursion of the parser
+const MAX_DEPTH: usize = 32;
+
+/// The entry field a term is restricted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    /// `title:`
+    Title,
+    /// `url:`, the entry's link
+    Url,
+    /// `feed:`, the URL of the entry's feed
+    Feed,
+    /// `guid:`
+    Guid,
+}
+
+impl Field {
+    /// The name written before the colon
+    pub fn name(self) -> &'static str {
+        match self {
+            Field::Title => "title",
+            Field::Url => "url",
+            Field::Feed => "feed",
+            Field::Guid => "guid",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Field> {
+        [Field::Title, Field::Url, Field::Feed, Field::Guid]
+            .iter()
+            .copied()
+            .find(|field| field.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// The text a term matches
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Pattern {
+    /// A single word without spaces, quotes or parentheses
+    Word(String),
+    /// A quoted phrase, which may contain anything
+    Phrase(String),
+}
+
+impl Pattern {
+    /// The text to match
+    pub fn text(&self) -> &str {
+        match self {
+            Pattern::Word(text) | Pattern::Phrase(text) => text,
+        }
+    }
+}
+
+/// A single search term
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Term {
+    /// Restricts the term to one field; None matches any
+    pub field: Option<Field>,
+    pub pattern: Pattern,
+}
+
+/// A parsed search query
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Query {
+    Term(Term),
+    /// Every subquery must match
+    And(Vec<Query>),
+    /// At least one subquery must match
+    Or(Vec<Query>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum QueryError {
+    #[error("empty query")]
+    Empty,
+    #[error("empty term")]
+    EmptyTerm,
+    #[error("unterminated phrase")]
+    UnterminatedPhrase,
+    #[error("invalid escape in phrase")]
+    InvalidEscape,
+    #[error("unbalanced parenthesis")]
+    UnbalancedParenthesis,
+    #[error("operator is missing an operand")]
+    MissingOperand,
+    #[error("query nested more than {MAX_DEPTH} levels deep")]
+    TooDeep,
+}
+
+/// A query the server stores under a name for the current
+/// user, run with RUNSEARCH
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SavedSearch {
+    /// A single word, unique per user
+    pub name: String,
+    pub query: Query,
+}
+
+impl Query {
+    /// Whether an entry matches the query
+    pub fn matches(&self, entry: &Entry) -> bool {
+        match self {
+            Query::Term(term) => term.matches(entry),
+            Query::And(queries) => queries.iter().all(|query| query.matches(entry)),
+            Query::Or(queries) => queries.iter().any(|query| query.matches(entry)),
+        }
+    }
+}
+
+impl Term {
+    /// Whether an entry matches the term
+    pub fn matches(&self, entry: &Entry) -> bool {
+        let needle = self.pattern.text().to_lowercase();
+        let contains = |haystack: &str| haystack.to_lowercase().contains(&needle);
+
+        match self.field {
+            Some(Field::Title) => contains(&entry.title),
+            Some(Field::Url) => contains(&entry.url),
+            Some(Field::Feed) => contains(&entry.feed_url),
+            Some(Field::Guid) => match &entry.guid {
+                Some(guid) => contains(guid),
+                None => false,
+            },
+            None => contains(&entry.title) || contains(&entry.url) || contains(&entry.feed_url),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Term(Term),
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || c.is_control()
+}
+
+fn is_word_char(c: char) -> bool {
+    !is_separator(c) && c != '"' && c != '(' && c != ')'
+}
+
+struct Tokenizer<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(input: &'a str) -> Self {
+        Tokenizer {
+            input,
+            chars: input.char_indices().peekable(),
+        }
+    }
+
+    /// Read a phrase after its opening quote
+    fn phrase(&mut self) -> Result<String, QueryError> {
+        let mut phrase = String::new();
+
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => break,
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, 'u')) if self.chars.next_if(|&(_, c)| c == '{').is_some() => {
+                        phrase.push(self.code()?)
+                    }
+                    Some((_, c)) => phrase.push(c),
+                    None => return Err(QueryError::UnterminatedPhrase),
+                },
+                Some((_, c)) => phrase.push(c),
+                None => return Err(QueryError::UnterminatedPhrase),
+            }
+        }
+
+        if phrase.is_empty() {
+            return Err(QueryError::EmptyTerm);
+        }
+
+        Ok(phrase)
+    }
+
+    /// Read the hex code and closing brace of a `\u{...}` escape
+    fn code(&mut self) -> Result<char, QueryError> {
+        let mut code = String::new();
+
+        loop {
+            match self.chars.next() {
+                Some((_, '}')) => break,
+                Some((_, c)) if c.is_ascii_hexdigit() && code.len() < 6 => code.push(c),
+                Some(_) => return Err(QueryError::InvalidEscape),
+                None => return Err(QueryError::UnterminatedPhrase),
+            }
+        }
+
+        u32::from_str_radix(&code, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or(QueryError::InvalidEscape)
+    }
+
+    /// Read a word, and the phrase following it if it names a
+    /// field and ends in a colon
+    fn word(&mut self, start: usize) -> Result<Token, QueryError> {
+        let mut end = self.input.len();
+
+        while let Some(&(index, c)) = self.chars.peek() {
+            if !is_word_char(c) {
+                end = index;
+                break;
+            }
+
+            self.chars.next();
+        }
+
+        let word = self.input.get(start..end).unwrap_or_default();
+
+        match word {
+            "AND" => return Ok(Token::And),
+            "OR" => return Ok(Token::Or),
+            _ => {}
+        }
+
+        let (field, value) = match word.split_once(':') {
+            Some((name, value)) => match Field::from_name(name) {
+                Some(field) => (Some(field), value),
+                None => (None, word),
+            },
+            None => (None, word),
+        };
+
+        let pattern = match (value, self.chars.peek()) {
+            ("", Some((_, '"'))) => {
+                self.chars.next();
+                Pattern::Phrase(self.phrase()?)
+            }
+            ("", _) => return Err(QueryError::EmptyTerm),
+            (value, _) => Pattern::Word(value.to_string()),
+        };
+
+        Ok(Token::Term(Term { field, pattern }))
+    }
+}
+
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Result<Token, QueryError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(&(index, c)) = self.chars.peek() {
+            if !is_separator(c) {
+                self.chars.next();
+
+                return Some(match c {
+                    '(' => Ok(Token::Open),
+                    ')' => Ok(Token::Close),
+                    '"' => self.phrase().map(|phrase| {
+                        Token::Term(Term {
+                            field: None,
+                            pattern: Pattern::Phrase(phrase),
+                        })
+                    }),
+                    _ => self.word(index),
+                });
+            }
+
+            self.chars.next();
+        }
+
+        None
+    }
+}
+
+struct Parser<I: Iterator<Item = Token>> {
+    tokens: Peekable<I>,
+    depth: usize,
+}
+
+/// Build a compound query, unwrapping single subqueries
+fn compound(mut queries: Vec<Query>, build: fn(Vec<Query>) -> Query) -> Query {
+    match queries.pop() {
+        Some(query) if queries.is_empty() => query,
+        Some(query) => {
+            queries.push(query);
+            build(queries)
+        }
+        None => build(queries),
+    }
+}
+
+impl<I: Iterator<Item = Token>> Parser<I> {
+    fn or(&mut self) -> Result<Query, QueryError> {
+        let mut queries = vec![self.and()?];
+
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            queries.push(self.and()?);
+        }
+
+        Ok(compound(queries, Query::Or))
+    }
+
+    fn and(&mut self) -> Result<Query, QueryError> {
+        let mut queries = vec![self.primary()?];
+
+        loop {
+            match self.tokens.peek() {
+                Some(Token::And) => {
+                    self.tokens.next();
+                }
+                Some(Token::Term(_)) | Some(Token::Open) => {}
+                _ => break,
+            }
+
+            queries.push(self.primary()?);
+        }
+
+        Ok(compound(queries, Query::And))
+    }
+
+    fn primary(&mut self) -> Result<Query, QueryError> {
+        match self.tokens.next() {
+            Some(Token::Term(term)) => Ok(Query::Term(term)),
+            Some(Token::Open) => {
+                if self.depth >= MAX_DEPTH {
+                    return Err(QueryError::TooDeep);
+                }
+
+                self.depth = self.depth.saturating_add(1);
+                let query = self.or()?;
+                self.depth = self.depth.saturating_sub(1);
+
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(query),
+                    _ => Err(QueryError::UnbalancedParenthesis),
+                }
+            }
+            Some(Token::Close) => Err(QueryError::UnbalancedParenthesis),
+            Some(Token::And) | Some(Token::Or) | None => Err(QueryError::MissingOperand),
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let tokens = Tokenizer::new(value).collect::<Result<Vec<_>, _>>()?;
+
+        if tokens.is_empty() {
+            return Err(QueryError::Empty);
+        }
+
+        let mut parser = Parser {
+            tokens: tokens.into_iter().peekable(),
+            depth: 0,
+        };
+
+        let query = parser.or()?;
+
+        match parser.tokens.next() {
+            None => Ok(query),
+            Some(_) => Err(QueryError::UnbalancedParenthesis),
+        }
+    }
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(field) = self.field {
+            write!(f, "{}:", field.name())?;
+        }
+
+        match &self.pattern {
+            Pattern::Word(word) => write!(f, "{}", word),
+            Pattern::Phrase(phrase) => {
+                write!(f, "\"")?;
+
+                for c in phrase.chars() {
+                    match c {
+                        '"' | '\\' => write!(f, "\\{}", c)?,
+                        c if c.is_control() => write!(f, "\\u{{{:X}}}", u32::from(c))?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+
+                write!(f, "\"")
+            }
+        }
+    }
+}
+
+/// Canonical form, with explicit operators and every nested
+/// compound query parenthesized
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (queries, operator) = match self {
+            Query::Term(term) => return write!(f, "{}", term),
+            Query::And(queries) => (queries, " AND "),
+            Query::Or(queries) => (queries, " OR "),
+        };
+
+        let mut separator = "";
+
+        for query in queries {
+            match query {
+                Query::Term(term) => write!(f, "{}{}", separator, term)?,
+                query => write!(f, "{}({})", separator, query)?,
+            }
+
+            separator = operator;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str) -> Result<Query, QueryError> {
+        query.parse()
+    }
+
+    fn word(field: Option<Field>, word: &str) -> Query {
+        Query::Term(Term {
+            field,
+            pattern: Pattern::Word(word.to_string()),
+        })
+    }
+
+    fn phrase(field: Option<Field>, phrase: &str) -> Query {
+        Query::Term(Term {
+            field,
+            pattern: Pattern::Phrase(phrase.to_string()),
+        })
+    }
+
+    #[test]
+    fn or_binds_looser_than_and() {
+        let query = Query::Or(vec![
+            Query::And(vec![word(None, "a"), word(None, "b")]),
+            Query::And(vec![word(None, "c"), word(None, "d")]),
+        ]);
+
+        assert_eq!(parse("a b OR c AND d"), Ok(query.clone()));
+        assert_eq!(query.to_string(), "(a AND b) OR (c AND d)");
+    }
+
+    #[test]
+    fn parentheses_group() {
+        assert_eq!(
+            parse(r#"rust title:"release notes" OR (feed:example.org AND async)"#),
+            Ok(Query::Or(vec![
+                Query::And(vec![
+                    word(None, "rust"),
+                    phrase(Some(Field::Title), "release notes"),
+                ]),
+                Query::And(vec![
+                    word(Some(Field::Feed), "example.org"),
+                    word(None, "async"),
+                ]),
+            ]))
+        );
+        assert_eq!(
+            parse("a (b OR c)"),
+            Ok(Query::And(vec![
+                word(None, "a"),
+                Query::Or(vec![word(None, "b"), word(None, "c")]),
+            ]))
+        );
+        assert_eq!(parse("((a))"), Ok(word(None, "a")));
+    }
+
+    #[test]
+    fn reads_fields_and_words() {
+        assert_eq!(parse("TITLE:rust"), Ok(word(Some(Field::Title), "rust")));
+        assert_eq!(parse("guid:a:b"), Ok(word(Some(Field::Guid), "a:b")));
+        assert_eq!(
+            parse("https://example.org"),
+            Ok(word(None, "https://example.org"))
+        );
+        assert_eq!(
+            parse("and or"),
+            Ok(Query::And(vec![word(None, "and"), word(None, "or")]))
+        );
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = |depth: usize| format!("{}a{}", "(".repeat(depth), ")".repeat(depth));
+
+        assert_eq!(parse(&nested(MAX_DEPTH)), Ok(word(None, "a")));
+        assert_eq!(
+            parse(&nested(MAX_DEPTH.saturating_add(1))),
+            Err(QueryError::TooDeep)
+        );
+
+        // Depth counts open parentheses, not parentheses seen
+        assert!(parse(&vec![nested(MAX_DEPTH); 3].join(" OR ")).is_ok());
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        let cases = [
+            ("", QueryError::Empty),
+            ("   ", QueryError::Empty),
+            (r#""""#, QueryError::EmptyTerm),
+            ("title:", QueryError::EmptyTerm),
+            (r#""open"#, QueryError::UnterminatedPhrase),
+            (r#""open\"#, QueryError::UnterminatedPhrase),
+            (r#""\u{1B"#, QueryError::UnterminatedPhrase),
+            (r#""\u{zz}""#, QueryError::InvalidEscape),
+            (r#""\u{D800}""#, QueryError::InvalidEscape),
+            (r#""\u{1234567}""#, QueryError::InvalidEscape),
+            ("(a", QueryError::UnbalancedParenthesis),
+            ("a)", QueryError::UnbalancedParenthesis),
+            (")", QueryError::UnbalancedParenthesis),
+            ("()", QueryError::UnbalancedParenthesis),
+            ("a OR", QueryError::MissingOperand),
+            ("AND a", QueryError::MissingOperand),
+            ("a AND OR b", QueryError::MissingOperand),
+        ];
+
+        for (query, error) in cases.iter() {
+            assert_eq!(parse(query), Err(error.clone()), "{:?}", query);
+        }
+    }
+
+    #[test]
+    fn escapes_phrases() {
+        let query = phrase(None, "say \"hi\" \\ \u{1b}[2J\r\nOK");
+        let encoded = query.to_string();
+
+        assert_eq!(encoded, r#""say \"hi\" \\ \u{1B}[2J\u{D}\u{A}OK""#);
+        assert_eq!(parse(&encoded), Ok(query));
+
+        // Any other escaped character stands for itself
+        assert_eq!(parse(r#""\a\u""#), Ok(phrase(None, "au")));
+    }
+
+    #[test]
+    fn control_characters_separate_words() {
+        assert_eq!(
+            parse("a\u{1b}b\0"),
+            Ok(Query::And(vec![word(None, "a"), word(None, "b")]))
+        );
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        let queries = [
+            "a",
+            "a b c",
+            "a OR b OR c",
+            "a (b OR (c d)) OR e",
+            r#"title:"x (y) OR z" url:"a\"b""#,
+            r#"(("\u{0}" OR guid:x) AND feed:"\u{7F}")"#,
+        ];
+
+        for query in queries.iter() {
+            let encoded = parse(query).map(|query| query.to_string());
+            let reparsed = encoded.clone().and_then(|encoded| parse(&encoded));
+
+            assert!(encoded.is_ok(), "{:?}", query);
+            assert_eq!(reparsed, parse(query));
+            assert_eq!(reparsed.map(|query| query.to_string()), encoded);
+        }
+    }
+}
@@ -0,0 +1,1100 @@
+//! Answering commands from a storage backend
+//!
+//! Storage is the interface between the protocol and wherever a
+//! server keeps its users, feeds, subscriptions, entries and read
+//! state, be it SQLite, Postgres or memory. A Handler holds one
+//! connection's selected user and turns each command into calls
+//! on the backend and the responses to send, so backends only
+//! deal in rows. Backends fail with HandlerError, whose Storage
+//! variant keeps their own errors out of the responses.
+//!
+//! Commands beyond users, subscriptions, read state and quotas
+//! are answered with BadCommand, for servers to handle before passing
+//! the rest to a Handler.
+//!
+//! Quotas, rate limits and logging are middleware: Layers run
+//! around a Handler by a Layered service.
+//!
+//! Administrative commands, such as SETQUOTA, need the Admin
+//! role, which Storage reports for each user. The RoleCheck layer
+//! refuses anyone else with PermissionDenied, sent as Forbidden.
+//!
+//! A Handler given an AuthProvider answers AUTH, checking the
+//! credentials against the provider, and the AuthCheck layer only
+//! lets USER select the user the connection authenticated as.
+//! Without one, USER selects any user Storage knows and AUTH is
+//! not supported. `Handler::checked` runs a Handler behind both
+//! layers.
+//!
+//! A LoginThrottle shared by every connection counts failed AUTH
+//! and RESUME attempts for each user and client address. After a
+//! few failures it refuses further attempts for a lockout that
+//! doubles with each failure, answered with RateLimited and a
+//! too-many-attempts key carrying how many seconds to wait.
+//!
+//! A Handler given ResumeTokens answers RESUMETOKEN with a token
+//! that RESUME accepts on a later connection until it expires,
+//! selecting the same user without authenticating again.
+//!
+//! With the `tracing` feature, a Handler records an event for
+//! each command it answers, naming the verb, the selected user
+//! and the response code, and an error event when Storage fails,
+//! since the client is only told of an internal error.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::catalog::ErrorMessage;
+use crate::feed::FeedItem;
+use crate::middleware::{AuthCheck, Layered, RoleCheck};
+use crate::{
+    Command, Credentials, Cursor, Entry, HandlerError, Quota, QuotaUsage, Response, ServerError,
+    Subscription,
+};
+
+/// What a user is allowed to do
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Role {
+    #[default]
+    User,
+    /// May also run administrative commands
+    Admin,
+}
+
+impl Role {
+    pub fn keyword(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+
+    /// The role a user needs to run a command
+    ///
+    /// Reading one's own quota needs no more than User.
+    pub fn required_for(command: &Command) -> Role {
+        match command {
+            Command::SetQuota { .. }
+            | Command::GetQuota {
+                username: Some(_), ..
+            } => Role::Admin,
+            _ => Role::User,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.keyword())
+    }
+}
+
+/// One page of unread entries
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntryPage {
+    pub entries: Vec<Entry>,
+    /// Where the next page starts, if there is one
+    pub next: Option<Cursor>,
+}
+
+/// Where a server keeps its state
+///
+/// Shared by every connection, so methods take `&self`.
+/// `expected` arguments carry the revision a client expects;
+/// backends tracking revisions fail with Conflict when it does
+/// not match and return the new revision on success.
+pub trait Storage: Send + Sync {
+    /// The id of a user, or NotFound if there is no such user
+    fn user(&self, username: &str) -> Result<i64, HandlerError>;
+
+    fn subscriptions(&self, user: i64) -> Result<Vec<Subscription>, HandlerError>;
+
+    /// Subscribe a user to a feed, adding the feed if no one is
+    /// subscribed to it yet
+    fn subscribe(&self, user: i64, url: &str, category: Option<&str>) -> Result<(), HandlerError>;
+
+    fn unsubscribe(
+        &self,
+        user: i64,
+        subscription: i64,
+        expected: Option<i64>,
+    ) -> Result<Option<i64>, HandlerError>;
+
+    /// A page of a user's unread entries, continuing after
+    /// `after`, with summaries of up to `summary` characters
+    fn unread(
+        &self,
+        user: i64,
+        after: Option<&Cursor>,
+        summary: Option<u32>,
+    ) -> Result<EntryPage, HandlerError>;
+
+    fn unread_count(&self, user: i64) -> Result<u32, HandlerError>;
+
+    fn mark_read(
+        &self,
+        user: i64,
+        entry: i64,
+        expected: Option<i64>,
+    ) -> Result<Option<i64>, HandlerError>;
+
+    fn mark_unread(
+        &self,
+        user: i64,
+        entry: i64,
+        expected: Option<i64>,
+    ) -> Result<Option<i64>, HandlerError>;
+
+    /// Mark every unread entry read, returning how many there
+    /// were
+    fn mark_all_read(&self, user: i64) -> Result<u32, HandlerError>;
+
+    /// Store the items of a refreshed feed, returning the
+    /// entries that are new
+    ///
+    /// Items already stored, matched by guid or else by URL, are
+    /// skipped.
+    fn store_entries(&self, feed_id: i64, items: Vec<FeedItem>)
+        -> Result<Vec<Entry>, HandlerError>;
+
+    /// A user's role, User unless the backend keeps roles
+    fn role(&self, user: i64) -> Result<Role, HandlerError> {
+        let _ = user;
+
+        Ok(Role::User)
+    }
+
+    /// A user's quota, unlimited unless the backend keeps quotas
+    fn quota(&self, user: i64) -> Result<Quota, HandlerError> {
+        let _ = user;
+
+        Ok(Quota::unlimited())
+    }
+
+    /// Set a user's quota, refused with BadCommand unless the
+    /// backend keeps quotas
+    fn set_quota(&self, user: i64, quota: Quota) -> Result<(), HandlerError> {
+        let _ = (user, quota);
+
+        Err(ServerError::BadCommand("SETQUOTA is not supported".to_string()).into())
+    }
+
+    /// A user's quota along with their subscriptions and unread
+    /// entries
+    fn quota_usage(&self, user: i64) -> Result<QuotaUsage, HandlerError> {
+        Ok(QuotaUsage {
+            quota: self.quota(user)?,
+            feeds: u32::try_from(self.subscriptions(user)?.len()).unwrap_or(u32::MAX),
+            entries: self.unread_count(user)?,
+        })
+    }
+}
+
+macro_rules! forward_storage {
+    ($($pointer:ty),*) => {
+        $(
+            impl<S: Storage + ?Sized> Storage for $pointer {
+                fn user(&self, username: &str) -> Result<i64, HandlerError> {
+                    (**self).user(username)
+                }
+
+                fn subscriptions(&self, user: i64) -> Result<Vec<Subscription>, HandlerError> {
+                    (**self).subscriptions(user)
+                }
+
+                fn subscribe(
+                    &self,
+                    user: i64,
+                    url: &str,
+                    category: Option<&str>,
+                ) -> Result<(), HandlerError> {
+                    (**self).subscribe(user, url, category)
+                }
+
+                fn unsubscribe(
+                    &self,
+                    user: i64,
+                    subscription: i64,
+                    expected: Option<i64>,
+                ) -> Result<Option<i64>, HandlerError> {
+                    (**self).unsubscribe(user, subscription, expected)
+                }
+
+                fn unread(
+                    &self,
+                    user: i64,
+                    after: Option<&Cursor>,
+                    summary: Option<u32>,
+                ) -> Result<EntryPage, HandlerError> {
+                    (**self).unread(user, after, summary)
+                }
+
+                fn unread_count(&self, user: i64) -> Result<u32, HandlerError> {
+                    (**self).unread_count(user)
+                }
+
+                fn mark_read(
+                    &self,
+                    user: i64,
+                    entry: i64,
+                    expected: Option<i64>,
+                ) -> Result<Option<i64>, HandlerError> {
+                    (**self).mark_read(user, entry, expected)
+                }
+
+                fn mark_unread(
+                    &self,
+                    user: i64,
+                    entry: i64,
+                    expected: Option<i64>,
+                ) -> Result<Option<i64>, HandlerError> {
+                    (**self).mark_unread(user, entry, expected)
+                }
+
+                fn mark_all_read(&self, user: i64) -> Result<u32, HandlerError> {
+                    (**self).mark_all_read(user)
+                }
+
+                fn store_entries(
+                    &self,
+                    feed_id: i64,
+                    items: Vec<FeedItem>,
+                ) -> Result<Vec<Entry>, HandlerError> {
+                    (**self).store_entries(feed_id, items)
+                }
+
+                fn role(&self, user: i64) -> Result<Role, HandlerError> {
+                    (**self).role(user)
+                }
+
+                fn quota(&self, user: i64) -> Result<Quota, HandlerError> {
+                    (**self).quota(user)
+                }
+
+                fn set_quota(&self, user: i64, quota: Quota) -> Result<(), HandlerError> {
+                    (**self).set_quota(user, quota)
+                }
+
+                fn quota_usage(&self, user: i64) -> Result<QuotaUsage, HandlerError> {
+                    (**self).quota_usage(user)
+                }
+            }
+        )*
+    };
+}
+
+forward_storage!(&S, Box<S>, Arc<S>);
+
+/// Where a server checks who its users are
+///
+/// Shared by every connection, so methods take `&self`. The
+/// usernames a provider accepts are looked up in Storage to
+/// select the user.
+pub trait AuthProvider: Send + Sync {
+    /// Whether the user may still sign in
+    fn lookup_user(&self, username: &str) -> Result<bool, HandlerError>;
+
+    fn verify_password(&self, username: &str, password: &str) -> Result<bool, HandlerError>;
+
+    /// The user a token was issued to, or None if the token is
+    /// not valid
+    fn verify_token(&self, token: &str) -> Result<Option<String>, HandlerError>;
+}
+
+fn unsupported(command: &Command) -> Response {
+    Response::BadCommand(format!("{} is not supported", command.descriptor().verb))
+}
+
+fn auth_failed() -> HandlerError {
+    HandlerError::Forbidden(ErrorMessage::new("auth-failed", "invalid credentials").to_string())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum LoginKey {
+    User(String),
+    Address(IpAddr),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Locks out users and addresses after repeated failed logins
+///
+/// Shared between connections behind an Arc, so attempts are
+/// counted across all of them. Failures are forgotten once none
+/// has happened for the longest lockout.
+#[derive(Debug)]
+pub struct LoginThrottle {
+    free_attempts: u32,
+    lockout: Duration,
+    max_lockout: Duration,
+    failures: Mutex<HashMap<LoginKey, Failures>>,
+}
+
+fn login_keys(username: Option<&str>, address: Option<IpAddr>) -> Vec<LoginKey> {
+    username
+        .map(|username| LoginKey::User(username.to_string()))
+        .into_iter()
+        .chain(address.map(LoginKey::Address))
+        .collect()
+}
+
+impl LoginThrottle {
+    /// Allow `free_attempts` failures before locking out for
+    /// `lockout`, doubled with each further failure up to
+    /// `max_lockout`
+    ///
+    /// Lockouts are capped at `u32::MAX` seconds, so adding one
+    /// to the current time cannot overflow.
+    pub fn new(free_attempts: u32, lockout: Duration, max_lockout: Duration) -> LoginThrottle {
+        let longest = Duration::from_secs(u64::from(u32::MAX));
+
+        LoginThrottle {
+            free_attempts,
+            lockout: lockout.min(longest),
+            max_lockout: max_lockout.min(longest),
+            failures: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// How long until none of `keys` is locked out
+    fn wait(failures: &HashMap<LoginKey, Failures>, keys: &[LoginKey], now: Instant) -> Duration {
+        keys.iter()
+            .filter_map(|key| failures.get(key)?.locked_until)
+            .map(|until| until.saturating_duration_since(now))
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn fail(&self, failures: &mut HashMap<LoginKey, Failures>, keys: Vec<LoginKey>, now: Instant) {
+        for key in keys {
+            let entry = failures.entry(key).or_insert(Failures {
+                count: 0,
+                last: now,
+                locked_until: None,
+            });
+
+            if now.saturating_duration_since(entry.last) >= self.max_lockout {
+                entry.count = 0;
+            }
+
+            entry.count = entry.count.saturating_add(1);
+            entry.last = now;
+
+            if let Some(excess) = entry
+                .count
+                .checked_sub(self.free_attempts.saturating_add(1))
+            {
+                let lockout = self
+                    .lockout
+                    .checked_mul(1 << excess.min(20))
+                    .unwrap_or(self.max_lockout)
+                    .min(self.max_lockout);
+
+                if let Some(until) = now.checked_add(lockout) {
+                    entry.locked_until = Some(until);
+                }
+            }
+        }
+    }
+
+    /// Whether a login may be attempted, failing with how long
+    /// until the user and address are no longer locked out
+    ///
+    /// Logins checked concurrently all pass; use `reserve` to
+    /// check and count an attempt at once.
+    pub fn check(
+        &self,
+        username: Option<&str>,
+        address: Option<IpAddr>,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        let wait = LoginThrottle::wait(&failures, &login_keys(username, address), now);
+
+        if wait > Duration::from_secs(0) {
+            return Err(wait);
+        }
+
+        Ok(())
+    }
+
+    /// Count a failed login, locking out once the free attempts
+    /// are used up
+    pub fn record_failure(&self, username: Option<&str>, address: Option<IpAddr>, now: Instant) {
+        let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+
+        self.fail(&mut failures, login_keys(username, address), now);
+    }
+
+    /// Check whether a login may be attempted and, if so, count
+    /// it as failed until `release` says otherwise
+    ///
+    /// Checking and counting under one lock means parallel
+    /// guesses cannot all slip in before the first failure is
+    /// recorded.
+    pub fn reserve(
+        &self,
+        username: Option<&str>,
+        address: Option<IpAddr>,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let keys = login_keys(username, address);
+        let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+        let wait = LoginThrottle::wait(&failures, &keys, now);
+
+        if wait > Duration::from_secs(0) {
+            return Err(wait);
+        }
+
+        self.fail(&mut failures, keys, now);
+
+        Ok(())
+    }
+
+    /// Take back an attempt counted by `reserve` that did not
+    /// fail
+    pub fn release(&self, username: Option<&str>, address: Option<IpAddr>) {
+        let mut failures = self.failures.lock().unwrap_or_else(PoisonError::into_inner);
+
+        for key in login_keys(username, address) {
+            if let Some(entry) = failures.get_mut(&key) {
+                entry.count = entry.count.saturating_sub(1);
+
+                if entry.count <= self.free_attempts {
+                    entry.locked_until = None;
+                }
+
+                if entry.count == 0 {
+                    failures.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Forget a user's failures after a successful login
+    ///
+    /// Failures from the address still count, so one working
+    /// account does not reset an address guessing at others.
+    pub fn record_success(&self, username: &str) {
+        self.failures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&LoginKey::User(username.to_string()));
+    }
+
+    /// Forget failures older than the longest lockout
+    pub fn prune(&self, now: Instant) {
+        let max_lockout = self.max_lockout;
+
+        self.failures
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|_, failures| now.saturating_duration_since(failures.last) < max_lockout);
+    }
+}
+
+/// What a connection resuming a session picks up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResumedSession {
+    pub user: i64,
+    /// The user the session authenticated as, if it did
+    pub authenticated: Option<String>,
+    /// When the token stops being accepted, in seconds since
+    /// the Unix epoch
+    pub expires: i64,
+}
+
+/// Bytes of randomness in a resume token
+const TOKEN_BYTES: usize = 16;
+
+/// Fills a buffer with random bytes for a resume token
+pub type TokenGenerator = Box<dyn Fn(&mut [u8]) -> io::Result<()> + Send + Sync>;
+
+/// The operating system's random source, through getrandom
+fn os_random(bytes: &mut [u8]) -> io::Result<()> {
+    getrandom::fill(bytes).map_err(io::Error::from)
+}
+
+/// Tokens resuming sessions on later connections
+///
+/// A token is a bearer credential: anyone presenting it before
+/// it expires resumes the session as its user, with no password.
+/// Tokens are therefore 128 bits from the operating system's
+/// random source, read through getrandom on every platform it
+/// supports, so they can be neither guessed nor predicted from
+/// tokens seen earlier, and RESUME attempts count against the
+/// LoginThrottle. They are only as safe as the connections
+/// carrying them, which should be encrypted; wire logs redact
+/// them.
+///
+/// There is no weaker fallback: where the random source cannot
+/// be read, issuing fails and RESUMETOKEN is answered with an
+/// internal error. Platforms getrandom does not cover can supply
+/// their own source with `with_generator`.
+///
+/// Shared between connections behind an Arc. Tokens are kept in
+/// memory, so they do not outlive the process.
+pub struct ResumeTokens {
+    lifetime: i64,
+    generate: TokenGenerator,
+    sessions: Mutex<HashMap<String, ResumedSession>>,
+}
+
+impl fmt::Debug for ResumeTokens {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ResumeTokens")
+            .field("lifetime", &self.lifetime)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ResumeTokens {
+    /// Tokens accepted for `lifetime` seconds after being
+    /// issued
+    pub fn new(lifetime: u32) -> ResumeTokens {
+        ResumeTokens {
+            lifetime: i64::from(lifetime),
+            generate: Box::new(os_random),
+            sessions: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Draw token bytes from `generate` instead of the operating
+    /// system, which must be a cryptographically secure source
+    pub fn with_generator<F>(self, generate: F) -> ResumeTokens
+    where
+        F: Fn(&mut [u8]) -> io::Result<()> + Send + Sync + 'static,
+    {
+        ResumeTokens {
+            generate: Box::new(generate),
+            ..self
+        }
+    }
+
+    fn generate(&self) -> io::Result<String> {
+        let mut bytes = [0; TOKEN_BYTES];
+        (self.generate)(&mut bytes)?;
+
+        Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+
+    /// Issue a token for a session at `now`, returning it and
+    /// when it expires
+    ///
+    /// Fails if the random source cannot be read.
+    pub fn issue(
+        &self,
+        user: i64,
+        authenticated: Option<String>,
+        now: i64,
+    ) -> io::Result<(String, i64)> {
+        let token = self.generate()?;
+        let expires = now.saturating_add(self.lifetime);
+
+        self.sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(
+                token.clone(),
+                ResumedSession {
+                    user,
+                    authenticated,
+                    expires,
+                },
+            );
+
+        Ok((token, expires))
+    }
+
+    /// The session a token resumes, if it has not expired
+    ///
+    /// Tokens stay valid until they expire or are revoked, so a
+    /// client may resume again after another disconnect.
+    pub fn redeem(&self, token: &str, now: i64) -> Option<ResumedSession> {
+        self.sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(token)
+            .filter(|session| session.expires > now)
+            .cloned()
+    }
+
+    pub fn revoke(&self, token: &str) {
+        self.sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(token);
+    }
+
+    /// Forget tokens expired by `now`
+    pub fn prune(&self, now: i64) {
+        self.sessions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|_, session| session.expires > now);
+    }
+}
+
+/// Seconds since the Unix epoch
+fn unix_now() -> i64 {
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    i64::try_from(since_epoch.as_secs()).unwrap_or(i64::MAX)
+}
+
+/// One connection's view of a server
+pub struct Handler<S> {
+    storage: S,
+    user: Option<i64>,
+    auth: Option<Arc<dyn AuthProvider>>,
+    /// The user the connection authenticated as
+    authenticated: Option<String>,
+    resume_tokens: Option<Arc<ResumeTokens>>,
+    login_throttle: Option<Arc<LoginThrottle>>,
+    /// The client's address, counted by the login throttle and
+    /// rate limits
+    peer: Option<IpAddr>,
+}
+
+impl<S: fmt::Debug> fmt::Debug for Handler<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Handler")
+            .field("storage", &self.storage)
+            .field("user", &self.user)
+            .field("auth", &self.auth.is_some())
+            .field("authenticated", &self.authenticated)
+            .field("resume_tokens", &self.resume_tokens)
+            .field("login_throttle", &self.login_throttle)
+            .field("peer", &self.peer)
+            .finish()
+    }
+}
+
+impl<S: Storage> Handler<S> {
+    /// A handler with no user selected and no limits
+    pub fn new(storage: S) -> Handler<S> {
+        Handler {
+            storage,
+            user: None,
+            auth: None,
+            authenticated: None,
+            resume_tokens: None,
+            login_throttle: None,
+            peer: None,
+        }
+    }
+
+    /// Require AUTH before USER, checking credentials against
+    /// a provider
+    pub fn with_auth_provider(self, auth: Arc<dyn AuthProvider>) -> Handler<S> {
+        Handler {
+            auth: Some(auth),
+            ..self
+        }
+    }
+
+    /// Issue and accept tokens resuming sessions
+    pub fn with_resume_tokens(self, resume_tokens: Arc<ResumeTokens>) -> Handler<S> {
+        Handler {
+            resume_tokens: Some(resume_tokens),
+            ..self
+        }
+    }
+
+    /// Lock out users and addresses after failed logins
+    pub fn with_login_throttle(self, login_throttle: Arc<LoginThrottle>) -> Handler<S> {
+        Handler {
+            login_throttle: Some(login_throttle),
+            ..self
+        }
+    }
+
+    /// The address of the client, as `serve` reads it from a
+    /// PROXY header, so failed logins and commands from it are
+    /// counted together and logged with it
+    pub fn with_peer(self, peer: IpAddr) -> Handler<S> {
+        Handler {
+            peer: Some(peer),
+            ..self
+        }
+    }
+
+    /// The handler behind RoleCheck and, given an AuthProvider,
+    /// AuthCheck
+    pub fn checked(self) -> Layered<Handler<S>> {
+        let auth = self.auth.clone();
+        let checked = Layered::new(self).layer(RoleCheck);
+
+        match auth {
+            Some(auth) => checked.layer(AuthCheck::new(auth)),
+            None => checked,
+        }
+    }
+
+    /// The id of the selected user
+    pub fn user(&self) -> Option<i64> {
+        self.user
+    }
+
+    /// The user the connection authenticated as
+    pub fn authenticated(&self) -> Option<&str> {
+        self.authenticated.as_deref()
+    }
+
+    /// The client's address
+    pub fn peer(&self) -> Option<IpAddr> {
+        self.peer
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    /// The complete reply to a command
+    pub fn handle(&mut self, command: &Command) -> Vec<Response> {
+        let handled = self.try_handle(command);
+
+        #[cfg(feature = "tracing")]
+        match &handled {
+            Ok(reply) => tracing::debug!(
+                verb = command.verb(),
+                user = ?self.user,
+                code = ?reply.first().map(Response::code),
+                "command answered"
+            ),
+            Err(HandlerError::Storage(error)) => tracing::error!(
+                verb = command.verb(),
+                user = ?self.user,
+                %error,
+                "storage failed"
+            ),
+            Err(error) => tracing::debug!(
+                verb = command.verb(),
+                user = ?self.user,
+                %error,
+                "command refused"
+            ),
+        }
+
+        handled.unwrap_or_else(|error| vec![Response::from(error)])
+    }
+
+    fn selected(&self) -> Result<i64, HandlerError> {
+        self.user.ok_or(HandlerError::NeedUser)
+    }
+
+    /// The user credentials belong to
+    fn authenticate(
+        auth: &dyn AuthProvider,
+        credentials: &Credentials,
+    ) -> Result<String, HandlerError> {
+        let username = match credentials {
+            Credentials::Password { username, password } => {
+                if !auth.verify_password(username, password)? {
+                    return Err(auth_failed());
+                }
+
+                username.clone()
+            }
+            Credentials::Token(token) => auth.verify_token(token)?.ok_or_else(auth_failed)?,
+        };
+
+        if !auth.lookup_user(&username)? {
+            return Err(auth_failed());
+        }
+
+        Ok(username)
+    }
+
+    /// Make a login attempt unless the throttle refuses it,
+    /// counting a Forbidden answer as a failure
+    fn throttled<T>(
+        &self,
+        username: Option<&str>,
+        attempt: impl FnOnce() -> Result<T, HandlerError>,
+    ) -> Result<T, HandlerError> {
+        let login_throttle = match &self.login_throttle {
+            Some(login_throttle) => login_throttle,
+            None => return attempt(),
+        };
+
+        login_throttle
+            .reserve(username, self.peer, Instant::now())
+            .map_err(HandlerError::TooManyAttempts)?;
+
+        let result = attempt();
+
+        match (&result, username) {
+            (Err(HandlerError::Forbidden(_)), _) => {}
+            (Ok(_), Some(username)) => {
+                login_throttle.release(Some(username), self.peer);
+                login_throttle.record_success(username);
+            }
+            _ => login_throttle.release(username, self.peer),
+        }
+
+        result
+    }
+
+    /// The session a token resumes, if its user may still sign
+    /// in
+    fn resume(
+        &self,
+        resume_tokens: &ResumeTokens,
+        token: &str,
+    ) -> Result<ResumedSession, HandlerError> {
+        let resume_failed = || {
+            HandlerError::Forbidden(
+                ErrorMessage::new("resume-failed", "unknown or expired token").to_string(),
+            )
+        };
+
+        let session = resume_tokens
+            .redeem(token, unix_now())
+            .ok_or_else(resume_failed)?;
+
+        if let Some(auth) = &self.auth {
+            match &session.authenticated {
+                Some(username) if auth.lookup_user(username)? => {}
+                _ => return Err(resume_failed()),
+            }
+        }
+
+        Ok(session)
+    }
+
+    fn try_handle(&mut self, command: &Command) -> Result<Vec<Response>, HandlerError> {
+        let response = match command {
+            Command::User { username } => {
+                let id = self.storage.user(username)?;
+                self.user = Some(id);
+
+                Response::AckUser { id }
+            }
+            Command::Auth(credentials) => {
+                if let Some(auth) = &self.auth {
+                    let claimed = match credentials {
+                        Credentials::Password { username, .. } => Some(username.as_str()),
+                        Credentials::Token(_) => None,
+                    };
+                    let username =
+                        self.throttled(claimed, || Self::authenticate(auth.as_ref(), credentials))?;
+                    let id = self.storage.user(&username)?;
+
+                    self.authenticated = Some(username);
+                    self.user = Some(id);
+
+                    Response::AckUser { id }
+                } else {
+                    unsupported(command)
+                }
+            }
+            Command::ResumeToken => {
+                if let Some(resume_tokens) = &self.resume_tokens {
+                    let (token, expires) = resume_tokens
+                        .issue(self.selected()?, self.authenticated.clone(), unix_now())
+                        .map_err(HandlerError::storage)?;
+
+                    Response::ResumeToken { token, expires }
+                } else {
+                    unsupported(command)
+                }
+            }
+            Command::Resume { token } => {
+                if let Some(resume_tokens) = &self.resume_tokens {
+                    let session = self.throttled(None, || self.resume(resume_tokens, token))?;
+
+                    self.authenticated = session.authenticated;
+                    self.user = Some(session.user);
+
+                    Response::AckUser { id: session.user }
+                } else {
+                    unsupported(command)
+                }
+            }
+            Command::Ping => Response::Pong,
+            Command::ListSubscriptions => {
+                let subscriptions = self.storage.subscriptions(self.selected()?)?;
+
+                return Ok(Response::subscription_list(subscriptions).collect());
+            }
+            Command::Subscribe {
+                url, category, key, ..
+            } => {
+                self.storage
+                    .subscribe(self.selected()?, url, category.as_deref())?;
+
+                Response::AckSubscribe { key: key.clone() }
+            }
+            Command::Unsubscribe { id, expected, key } => Response::AckUnsubscribe {
+                revision: self.storage.unsubscribe(self.selected()?, *id, *expected)?,
+                key: key.clone(),
+            },
+            Command::ListUnread { count: true, .. } => {
+                Response::Count(self.storage.unread_count(self.selected()?)?)
+            }
+            Command::ListUnread { after, summary, .. } => {
+                let page = self
+                    .storage
+                    .unread(self.selected()?, after.as_ref(), *summary)?;
+                let list = Response::entry_list(page.entries);
+
+                return Ok(match page.next {
+                    Some(cursor) => list.continued_at(cursor).collect(),
+                    None => list.collect(),
+                });
+            }
+            Command::MarkRead { id, expected, key } => Response::AckMarkRead {
+                revision: self.storage.mark_read(self.selected()?, *id, *expected)?,
+                key: key.clone(),
+            },
+            Command::MarkUnread { id, expected } => Response::AckMarkUnread {
+                revision: self.storage.mark_unread(self.selected()?, *id, *expected)?,
+            },
+            Command::MarkAllRead => Response::AckMarkAllRead {
+                count: self.storage.mark_all_read(self.selected()?)?,
+            },
+            Command::SetQuota { username, quota } => {
+                let user = self.storage.user(username)?;
+                self.storage.set_quota(user, *quota)?;
+
+                Response::Quota(self.storage.quota_usage(user)?)
+            }
+            Command::GetQuota { username } => {
+                let user = match username {
+                    Some(username) => self.storage.user(username)?,
+                    None => self.selected()?,
+                };
+
+                Response::Quota(self.storage.quota_usage(user)?)
+            }
+            command => unsupported(command),
+        };
+
+        Ok(vec![response])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStorage;
+
+    #[test]
+    fn resume_tokens_are_random() {
+        let resume_tokens = ResumeTokens::new(60);
+        let (first, expires) = resume_tokens.issue(1, None, 1000).unwrap();
+        let (second, _) = resume_tokens.issue(1, None, 1000).unwrap();
+
+        assert_eq!(expires, 1060);
+        assert_eq!(first.len(), 2 * TOKEN_BYTES);
+        assert!(first.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(first, second);
+        assert_eq!(resume_tokens.redeem(&first, 1059).map(|s| s.user), Some(1));
+        assert_eq!(resume_tokens.redeem(&first, 1060), None);
+    }
+
+    #[test]
+    fn resume_tokens_use_the_generator() {
+        let resume_tokens = ResumeTokens::new(60).with_generator(|bytes| {
+            bytes.fill(0xab);
+            Ok(())
+        });
+        let (token, _) = resume_tokens.issue(1, None, 0).unwrap();
+
+        assert_eq!(token, "ab".repeat(TOKEN_BYTES));
+
+        let failing = ResumeTokens::new(60)
+            .with_generator(|_| Err(io::Error::new(io::ErrorKind::Unsupported, "no source")));
+
+        assert!(failing.issue(1, None, 0).is_err());
+    }
+
+    #[test]
+    fn quota_commands_reach_storage() {
+        let storage = MemoryStorage::new();
+        let admin = storage.add_user("admin");
+        let bob = storage.add_user("bob");
+        storage.set_role(admin, Role::Admin);
+        storage
+            .subscribe(bob, "gemini://example.org/feed.gmi", None)
+            .unwrap();
+
+        let quota = Quota {
+            max_feeds: Some(10),
+            max_entries: None,
+        };
+        let mut handler = Handler::new(storage);
+
+        handler.handle(&Command::User {
+            username: "admin".to_string(),
+        });
+
+        assert_eq!(
+            handler.handle(&Command::SetQuota {
+                username: "bob".to_string(),
+                quota,
+            }),
+            vec![Response::Quota(QuotaUsage {
+                quota,
+                feeds: 1,
+                entries: 0,
+            })]
+        );
+
+        handler.handle(&Command::User {
+            username: "bob".to_string(),
+        });
+
+        assert_eq!(
+            handler.handle(&Command::GetQuota { username: None }),
+            vec![Response::Quota(QuotaUsage {
+                quota,
+                feeds: 1,
+                entries: 0,
+            })]
+        );
+    }
+
+    #[test]
+    fn login_throttle_reserves_attempts() {
+        let throttle = LoginThrottle::new(2, Duration::from_secs(10), Duration::from_secs(60));
+        let address = Some(IpAddr::from([192, 0, 2, 1]));
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(throttle.reserve(Some("bob"), address, now), Ok(()));
+        }
+
+        assert_eq!(
+            throttle.reserve(Some("bob"), address, now),
+            Err(Duration::from_secs(10))
+        );
+
+        throttle.release(Some("bob"), address);
+
+        assert_eq!(throttle.check(Some("bob"), address, now), Ok(()));
+    }
+
+    #[test]
+    fn login_throttle_caps_lockout() {
+        let throttle = LoginThrottle::new(0, Duration::MAX, Duration::MAX);
+        let now = Instant::now();
+
+        throttle.record_failure(Some("bob"), None, now);
+
+        assert!(throttle.check(Some("bob"), None, now).is_err());
+    }
+}
@@ -0,0 +1,409 @@
+//! SOCKS5 proxying of client connections
+//!
+//! Clients that reach their server only through a proxy, Tor
+//! among them, connect to the proxy and run a Socks5Handshake
+//! over the socket before speaking the protocol. Host names are
+//! passed to the proxy unresolved, so lookups happen on the far
+//! side. Username and password authentication (RFC 1929) is
+//! offered when the proxy has credentials.
+//!
+//! Replies arrive from the network, so the same lints as the
+//! line parser apply here.
+
+#![deny(
+    clippy::indexing_slicing,
+    clippy::unwrap_used,
+    clippy::expect_used,
+    clippy::panic,
+    clippy::arithmetic_side_effects
+)]
+
+use std::convert::TryFrom;
+use std::net::IpAddr;
+
+use thiserror::Error;
+
+/// The port SOCKS proxies listen on unless configured otherwise
+pub const DEFAULT_PORT: u16 = 1080;
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0x00;
+const USERNAME_PASSWORD: u8 = 0x02;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const AUTHENTICATION_VERSION: u8 = 1;
+const CONNECT: u8 = 0x01;
+const IPV4: u8 = 0x01;
+const DOMAIN_NAME: u8 = 0x03;
+const IPV6: u8 = 0x04;
+
+/// A SOCKS5 proxy to connect through
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    pub host: String,
+    pub port: u16,
+    /// Username and password, if the proxy requires them
+    pub credentials: Option<(String, String)>,
+}
+
+impl Socks5Proxy {
+    pub fn new(host: &str, port: u16) -> Socks5Proxy {
+        Socks5Proxy {
+            host: host.to_string(),
+            port,
+            credentials: None,
+        }
+    }
+
+    pub fn with_credentials(self, username: &str, password: &str) -> Socks5Proxy {
+        Socks5Proxy {
+            credentials: Some((username.to_string(), password.to_string())),
+            ..self
+        }
+    }
+
+    /// The host and port to open the socket to
+    pub fn address(&self) -> (&str, u16) {
+        (&self.host, self.port)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum Socks5Error {
+    #[error("malformed SOCKS5 reply: {0}")]
+    Malformed(&'static str),
+    #[error("unsupported SOCKS version {0}")]
+    UnsupportedVersion(u8),
+    #[error("proxy accepts none of the offered authentication methods")]
+    NoAcceptableMethod,
+    #[error("proxy rejected the credentials")]
+    AuthenticationFailed,
+    #[error("proxy refused the connection (reply {0})")]
+    Refused(u8),
+    #[error("{0} longer than 255 bytes")]
+    TooLong(&'static str),
+}
+
+/// What to do after receiving bytes from the proxy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Socks5Step {
+    /// Write these bytes to the proxy, then read its reply
+    Send(Vec<u8>),
+    /// Read more bytes; the reply is incomplete
+    NeedMore,
+    /// The tunnel is open and the protocol can start
+    Connected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Method,
+    Authentication,
+    Connect,
+    Connected,
+}
+
+/// The client side of the SOCKS5 handshake
+#[derive(Debug, Clone)]
+pub struct Socks5Handshake {
+    state: State,
+    credentials: Option<(String, String)>,
+    request: Vec<u8>,
+}
+
+/// Append a length-prefixed field
+fn push_field(bytes: &mut Vec<u8>, field: &str, name: &'static str) -> Result<(), Socks5Error> {
+    let length = u8::try_from(field.len()).map_err(|_| Socks5Error::TooLong(name))?;
+
+    bytes.push(length);
+    bytes.extend_from_slice(field.as_bytes());
+
+    Ok(())
+}
+
+impl Socks5Handshake {
+    /// Start a handshake opening a tunnel to `host` and `port`
+    ///
+    /// Returns the handshake and the greeting to write to the
+    /// proxy first.
+    pub fn new(
+        proxy: &Socks5Proxy,
+        host: &str,
+        port: u16,
+    ) -> Result<(Socks5Handshake, Vec<u8>), Socks5Error> {
+        let mut request = vec![VERSION, CONNECT, 0];
+
+        match host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+        {
+            Ok(IpAddr::V4(address)) => {
+                request.push(IPV4);
+                request.extend_from_slice(&address.octets());
+            }
+            Ok(IpAddr::V6(address)) => {
+                request.push(IPV6);
+                request.extend_from_slice(&address.octets());
+            }
+            Err(_) => {
+                request.push(DOMAIN_NAME);
+                push_field(&mut request, host, "host name")?;
+            }
+        }
+
+        request.extend_from_slice(&port.to_be_bytes());
+
+        let greeting = match proxy.credentials {
+            Some(_) => vec![VERSION, 2, NO_AUTHENTICATION, USERNAME_PASSWORD],
+            None => vec![VERSION, 1, NO_AUTHENTICATION],
+        };
+
+        Ok((
+            Socks5Handshake {
+                state: State::Method,
+                credentials: proxy.credentials.clone(),
+                request,
+            },
+            greeting,
+        ))
+    }
+
+    /// Whether the tunnel is open
+    pub fn is_connected(&self) -> bool {
+        self.state == State::Connected
+    }
+
+    /// Handle bytes read from the proxy
+    ///
+    /// Returns the next step and how many of the bytes it used.
+    /// Bytes left over once connected belong to the server.
+    pub fn receive(&mut self, bytes: &[u8]) -> Result<(Socks5Step, usize), Socks5Error> {
+        match self.state {
+            State::Method => {
+                let (version, method) = match bytes {
+                    [version, method, ..] => (*version, *method),
+                    _ => return Ok((Socks5Step::NeedMore, 0)),
+                };
+
+                if version != VERSION {
+                    return Err(Socks5Error::UnsupportedVersion(version));
+                }
+
+                match (method, &self.credentials) {
+                    (NO_AUTHENTICATION, _) => {
+                        self.state = State::Connect;
+                        Ok((Socks5Step::Send(self.request.clone()), 2))
+                    }
+                    (USERNAME_PASSWORD, Some((username, password))) => {
+                        let mut request = vec![AUTHENTICATION_VERSION];
+                        push_field(&mut request, username, "username")?;
+                        push_field(&mut request, password, "password")?;
+
+                        self.state = State::Authentication;
+                        Ok((Socks5Step::Send(request), 2))
+                    }
+                    (NO_ACCEPTABLE_METHOD, _) => Err(Socks5Error::NoAcceptableMethod),
+                    _ => Err(Socks5Error::Malformed("method was not offered")),
+                }
+            }
+            State::Authentication => match bytes {
+                [_, 0, ..] => {
+                    self.state = State::Connect;
+                    Ok((Socks5Step::Send(self.request.clone()), 2))
+                }
+                [_, _, ..] => Err(Socks5Error::AuthenticationFailed),
+                _ => Ok((Socks5Step::NeedMore, 0)),
+            },
+            State::Connect => {
+                let (version, reply, address_type) = match bytes {
+                    [version, reply, _, address_type, ..] => (*version, *reply, *address_type),
+                    _ => return Ok((Socks5Step::NeedMore, 0)),
+                };
+
+                if version != VERSION {
+                    return Err(Socks5Error::UnsupportedVersion(version));
+                }
+
+                if reply != 0 {
+                    return Err(Socks5Error::Refused(reply));
+                }
+
+                // The bound address, which clients have no use for
+                let address_length = match address_type {
+                    IPV4 => 4,
+                    IPV6 => 16,
+                    DOMAIN_NAME => match bytes.get(4) {
+                        Some(length) => usize::from(*length).saturating_add(1),
+                        None => return Ok((Socks5Step::NeedMore, 0)),
+                    },
+                    _ => return Err(Socks5Error::Malformed("unknown address type")),
+                };
+                let length = address_length.saturating_add(6);
+
+                if bytes.len() < length {
+                    return Ok((Socks5Step::NeedMore, 0));
+                }
+
+                self.state = State::Connected;
+                Ok((Socks5Step::Connected, length))
+            }
+            State::Connected => Ok((Socks5Step::Connected, 0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONNECTED_IPV4: [u8; 10] = [5, 0, 0, IPV4, 192, 0, 2, 1, 0x1f, 0x90];
+
+    #[test]
+    fn connects_without_authentication() -> Result<(), Socks5Error> {
+        let proxy = Socks5Proxy::new("proxy.example", DEFAULT_PORT);
+        let (mut handshake, greeting) = Socks5Handshake::new(&proxy, "example.com", 1965)?;
+
+        assert_eq!(greeting, vec![5, 1, NO_AUTHENTICATION]);
+        assert_eq!(handshake.receive(&[5])?, (Socks5Step::NeedMore, 0));
+
+        let mut request = vec![5, CONNECT, 0, DOMAIN_NAME, 11];
+        request.extend_from_slice(b"example.com");
+        request.extend_from_slice(&1965u16.to_be_bytes());
+
+        assert_eq!(
+            handshake.receive(&[5, NO_AUTHENTICATION])?,
+            (Socks5Step::Send(request), 2)
+        );
+        assert!(!handshake.is_connected());
+
+        // Bytes after the reply belong to the server
+        let mut reply = CONNECTED_IPV4.to_vec();
+        reply.extend_from_slice(b"30 VERSIONS=1,2");
+
+        assert_eq!(handshake.receive(&reply)?, (Socks5Step::Connected, 10));
+        assert!(handshake.is_connected());
+
+        Ok(())
+    }
+
+    #[test]
+    fn authenticates_with_username_and_password() -> Result<(), Socks5Error> {
+        let proxy = Socks5Proxy::new("proxy.example", DEFAULT_PORT).with_credentials("bob", "pw");
+        let (mut handshake, greeting) = Socks5Handshake::new(&proxy, "192.0.2.7", 1965)?;
+
+        assert_eq!(greeting, vec![5, 2, NO_AUTHENTICATION, USERNAME_PASSWORD]);
+        assert_eq!(
+            handshake.receive(&[5, USERNAME_PASSWORD])?,
+            (
+                Socks5Step::Send(vec![
+                    AUTHENTICATION_VERSION,
+                    3,
+                    b'b',
+                    b'o',
+                    b'b',
+                    2,
+                    b'p',
+                    b'w'
+                ]),
+                2
+            )
+        );
+        assert_eq!(handshake.receive(&[1])?, (Socks5Step::NeedMore, 0));
+        assert_eq!(
+            handshake.receive(&[1, 0])?,
+            (
+                Socks5Step::Send(vec![5, CONNECT, 0, IPV4, 192, 0, 2, 7, 0x07, 0xad]),
+                2
+            )
+        );
+        assert_eq!(
+            handshake.receive(&CONNECTED_IPV4)?,
+            (Socks5Step::Connected, 10)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn addresses_ipv6_hosts() -> Result<(), Socks5Error> {
+        let proxy = Socks5Proxy::new("proxy.example", DEFAULT_PORT);
+        let (mut handshake, _) = Socks5Handshake::new(&proxy, "[2001:db8::1]", 1965)?;
+
+        let mut request = vec![5, CONNECT, 0, IPV6, 0x20, 0x01, 0x0d, 0xb8];
+        request.extend_from_slice(&[0; 11]);
+        request.extend_from_slice(&[1, 0x07, 0xad]);
+
+        assert_eq!(
+            handshake.receive(&[5, NO_AUTHENTICATION])?,
+            (Socks5Step::Send(request), 2)
+        );
+
+        // Replies may bind an IPv6 address or a domain name
+        let mut reply = vec![5, 0, 0, IPV6];
+        reply.extend_from_slice(&[0; 18]);
+
+        assert_eq!(
+            handshake
+                .clone()
+                .receive(reply.get(..21).unwrap_or_default())?,
+            (Socks5Step::NeedMore, 0)
+        );
+        assert_eq!(
+            handshake.clone().receive(&reply)?,
+            (Socks5Step::Connected, 22)
+        );
+        assert_eq!(
+            handshake.receive(&[5, 0, 0, DOMAIN_NAME, 1, b'x', 0, 0])?,
+            (Socks5Step::Connected, 8)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn failures() -> Result<(), Socks5Error> {
+        let proxy = Socks5Proxy::new("proxy.example", DEFAULT_PORT);
+        let with_credentials = proxy.clone().with_credentials("bob", "pw");
+        let start =
+            |proxy: &Socks5Proxy| Socks5Handshake::new(proxy, "example.com", 1965).map(|(h, _)| h);
+
+        assert_eq!(
+            start(&proxy)?.receive(&[4, 0]),
+            Err(Socks5Error::UnsupportedVersion(4))
+        );
+        assert_eq!(
+            start(&proxy)?.receive(&[5, NO_ACCEPTABLE_METHOD]),
+            Err(Socks5Error::NoAcceptableMethod)
+        );
+        assert_eq!(
+            start(&proxy)?.receive(&[5, USERNAME_PASSWORD]),
+            Err(Socks5Error::Malformed("method was not offered"))
+        );
+
+        let mut handshake = start(&with_credentials)?;
+        handshake.receive(&[5, USERNAME_PASSWORD])?;
+        assert_eq!(
+            handshake.receive(&[1, 1]),
+            Err(Socks5Error::AuthenticationFailed)
+        );
+
+        let mut handshake = start(&proxy)?;
+        handshake.receive(&[5, NO_AUTHENTICATION])?;
+        assert_eq!(
+            handshake.clone().receive(&[5, 5, 0, IPV4]),
+            Err(Socks5Error::Refused(5))
+        );
+        assert_eq!(
+            handshake.receive(&[5, 0, 0, 9]),
+            Err(Socks5Error::Malformed("unknown address type"))
+        );
+
+        let long = "a".repeat(256);
+        assert!(matches!(
+            Socks5Handshake::new(&proxy, &long, 1965),
+            Err(Socks5Error::TooLong("host name"))
+        ));
+
+        Ok(())
+    }
+}
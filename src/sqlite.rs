@@ -0,0 +1,733 @@
+//! Storage kept in SQLite
+//!
+//! SqliteStorage implements Storage on a single SQLite database,
+//! for small deployments such as the seymour-server binary. The
+//! schema is created when the database is opened. Like
+//! MemoryStorage, users and feeds are added directly, and every
+//! change to a subscription or read state takes the next revision
+//! of a single counter.
+//!
+//! The connection is shared behind a lock, so commands from every
+//! connection are answered one at a time. Database errors surface
+//! as HandlerError::Storage.
+
+use std::convert::TryFrom;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use crate::feed::FeedItem;
+use crate::{
+    Cursor, Entry, EntryPage, HandlerError, Quota, Response, Role, ServerError, Storage,
+    Subscription,
+};
+
+/// Entries per page of the unread list
+const PAGE_SIZE: usize = 100;
+
+const SCHEMA: &str = "
+    PRAGMA foreign_keys = ON;
+
+    CREATE TABLE IF NOT EXISTS users (
+        id INTEGER PRIMARY KEY,
+        username TEXT NOT NULL UNIQUE,
+        role TEXT NOT NULL DEFAULT 'user',
+        max_feeds INTEGER,
+        max_entries INTEGER
+    );
+
+    CREATE TABLE IF NOT EXISTS feeds (
+        id INTEGER PRIMARY KEY,
+        url TEXT NOT NULL UNIQUE
+    );
+
+    CREATE TABLE IF NOT EXISTS subscriptions (
+        id INTEGER PRIMARY KEY,
+        user_id INTEGER NOT NULL REFERENCES users (id),
+        feed_id INTEGER NOT NULL REFERENCES feeds (id),
+        category TEXT,
+        revision INTEGER NOT NULL,
+        UNIQUE (user_id, feed_id)
+    );
+
+    CREATE TABLE IF NOT EXISTS entries (
+        id INTEGER PRIMARY KEY,
+        feed_id INTEGER NOT NULL REFERENCES feeds (id),
+        guid TEXT,
+        title TEXT NOT NULL,
+        url TEXT NOT NULL,
+        published INTEGER
+    );
+
+    CREATE INDEX IF NOT EXISTS entries_by_feed ON entries (feed_id);
+
+    CREATE TABLE IF NOT EXISTS read_state (
+        user_id INTEGER NOT NULL REFERENCES users (id),
+        entry_id INTEGER NOT NULL REFERENCES entries (id),
+        revision INTEGER NOT NULL,
+        read INTEGER NOT NULL,
+        PRIMARY KEY (user_id, entry_id)
+    );
+
+    CREATE TABLE IF NOT EXISTS revision (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        value INTEGER NOT NULL
+    );
+
+    INSERT OR IGNORE INTO revision (id, value) VALUES (1, 0);
+";
+
+/// The unread entries of a user, from the entry after `?2`
+const UNREAD: &str = "
+    SELECT entries.id, entries.feed_id, feeds.url, entries.guid, entries.title,
+        entries.url, entries.published
+    FROM entries
+    JOIN feeds ON feeds.id = entries.feed_id
+    JOIN subscriptions
+        ON subscriptions.feed_id = entries.feed_id AND subscriptions.user_id = ?1
+    LEFT JOIN read_state
+        ON read_state.user_id = ?1 AND read_state.entry_id = entries.id
+    WHERE entries.id > ?2 AND COALESCE(read_state.read, 0) = 0
+    ORDER BY entries.id
+";
+
+fn storage(error: rusqlite::Error) -> HandlerError {
+    HandlerError::storage(error)
+}
+
+fn count(count: i64) -> u32 {
+    u32::try_from(count).unwrap_or(u32::MAX)
+}
+
+fn limit(limit: Option<i64>) -> Option<u32> {
+    limit.map(|limit| u32::try_from(limit).unwrap_or(0))
+}
+
+fn next_revision(transaction: &Transaction<'_>) -> Result<i64, HandlerError> {
+    transaction
+        .query_row(
+            "UPDATE revision SET value = value + 1 WHERE id = 1 RETURNING value",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(storage)
+}
+
+fn feed(transaction: &Transaction<'_>, url: &str) -> Result<i64, HandlerError> {
+    transaction
+        .execute("INSERT OR IGNORE INTO feeds (url) VALUES (?1)", [url])
+        .map_err(storage)?;
+
+    transaction
+        .query_row("SELECT id FROM feeds WHERE url = ?1", [url], |row| {
+            row.get(0)
+        })
+        .map_err(storage)
+}
+
+fn set_read(
+    transaction: &Transaction<'_>,
+    user: i64,
+    entry: i64,
+    expected: Option<i64>,
+    read: bool,
+) -> Result<Option<i64>, HandlerError> {
+    let current: Option<i64> = transaction
+        .query_row(
+            "SELECT COALESCE(read_state.revision, 0)
+            FROM entries
+            JOIN subscriptions
+                ON subscriptions.feed_id = entries.feed_id AND subscriptions.user_id = ?1
+            LEFT JOIN read_state
+                ON read_state.user_id = ?1 AND read_state.entry_id = entries.id
+            WHERE entries.id = ?2",
+            params![user, entry],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(storage)?;
+
+    let current = current.ok_or_else(|| HandlerError::NotFound(format!("entry {}", entry)))?;
+
+    if expected.is_some_and(|expected| expected != current) {
+        return Err(HandlerError::Conflict(format!(
+            "entry {} is at revision {}",
+            entry, current
+        )));
+    }
+
+    let revision = next_revision(transaction)?;
+    transaction
+        .execute(
+            "INSERT INTO read_state (user_id, entry_id, revision, read) VALUES (?1, ?2, ?3, ?4)
+            ON CONFLICT (user_id, entry_id)
+            DO UPDATE SET revision = excluded.revision, read = excluded.read",
+            params![user, entry, revision, read],
+        )
+        .map_err(storage)?;
+
+    Ok(Some(revision))
+}
+
+/// Storage in a SQLite database
+#[derive(Debug)]
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// Open the database at `path`, creating it and its schema
+    /// as needed
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SqliteStorage, rusqlite::Error> {
+        SqliteStorage::from_connection(Connection::open(path)?)
+    }
+
+    /// A database in memory, lost when dropped
+    pub fn open_in_memory() -> Result<SqliteStorage, rusqlite::Error> {
+        SqliteStorage::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<SqliteStorage, rusqlite::Error> {
+        connection.execute_batch(SCHEMA)?;
+
+        Ok(SqliteStorage {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Run `f` in a transaction, committed if it succeeds
+    fn transaction<T, F>(&self, f: F) -> Result<T, HandlerError>
+    where
+        F: FnOnce(&Transaction<'_>) -> Result<T, HandlerError>,
+    {
+        let mut connection = self.lock();
+        let transaction = connection.transaction().map_err(storage)?;
+        let result = f(&transaction)?;
+        transaction.commit().map_err(storage)?;
+
+        Ok(result)
+    }
+
+    /// Add a user, returning its id, or the id it already has
+    pub fn add_user(&self, username: &str) -> Result<i64, HandlerError> {
+        self.transaction(|transaction| {
+            transaction
+                .execute(
+                    "INSERT OR IGNORE INTO users (username) VALUES (?1)",
+                    [username],
+                )
+                .map_err(storage)?;
+
+            transaction
+                .query_row(
+                    "SELECT id FROM users WHERE username = ?1",
+                    [username],
+                    |row| row.get(0),
+                )
+                .map_err(storage)
+        })
+    }
+
+    pub fn set_role(&self, user: i64, role: Role) -> Result<(), HandlerError> {
+        self.lock()
+            .execute(
+                "UPDATE users SET role = ?2 WHERE id = ?1",
+                params![user, role.keyword()],
+            )
+            .map_err(storage)?;
+
+        Ok(())
+    }
+
+    /// Add a feed no one subscribes to yet, returning its id,
+    /// or the id it already has
+    pub fn add_feed(&self, url: &str) -> Result<i64, HandlerError> {
+        self.transaction(|transaction| feed(transaction, url))
+    }
+
+    /// The id and URL of every feed someone subscribes to, for
+    /// fetchers to poll
+    pub fn feeds(&self) -> Result<Vec<(i64, String)>, HandlerError> {
+        let connection = self.lock();
+        let mut statement = connection
+            .prepare(
+                "SELECT DISTINCT feeds.id, feeds.url
+                FROM feeds JOIN subscriptions ON subscriptions.feed_id = feeds.id
+                ORDER BY feeds.id",
+            )
+            .map_err(storage)?;
+        let feeds = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(storage)?
+            .collect::<Result<_, _>>()
+            .map_err(storage)?;
+
+        Ok(feeds)
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn user(&self, username: &str) -> Result<i64, HandlerError> {
+        self.lock()
+            .query_row(
+                "SELECT id FROM users WHERE username = ?1",
+                [username],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(storage)?
+            .ok_or_else(|| HandlerError::NotFound(format!("user {}", username)))
+    }
+
+    fn subscriptions(&self, user: i64) -> Result<Vec<Subscription>, HandlerError> {
+        let connection = self.lock();
+        let mut statement = connection
+            .prepare(
+                "SELECT subscriptions.id, feeds.url
+                FROM subscriptions JOIN feeds ON feeds.id = subscriptions.feed_id
+                WHERE subscriptions.user_id = ?1
+                ORDER BY subscriptions.id",
+            )
+            .map_err(storage)?;
+        let rows = statement
+            .query_map([user], |row| Ok((row.get(0)?, row.get::<_, String>(1)?)))
+            .map_err(storage)?;
+
+        let mut subscriptions = Vec::new();
+
+        for row in rows {
+            let (id, url) = row.map_err(storage)?;
+
+            if let Response::Subscription(subscription) = Response::subscription(id, url) {
+                subscriptions.push(subscription);
+            }
+        }
+
+        Ok(subscriptions)
+    }
+
+    fn subscribe(&self, user: i64, url: &str, category: Option<&str>) -> Result<(), HandlerError> {
+        self.transaction(|transaction| {
+            let feed = feed(transaction, url)?;
+            let subscribed = transaction
+                .query_row(
+                    "SELECT 1 FROM subscriptions WHERE user_id = ?1 AND feed_id = ?2",
+                    [user, feed],
+                    |_| Ok(()),
+                )
+                .optional()
+                .map_err(storage)?
+                .is_some();
+
+            if subscribed {
+                return Ok(());
+            }
+
+            let revision = next_revision(transaction)?;
+            transaction
+                .execute(
+                    "INSERT INTO subscriptions (user_id, feed_id, category, revision)
+                    VALUES (?1, ?2, ?3, ?4)",
+                    params![user, feed, category, revision],
+                )
+                .map_err(storage)?;
+
+            Ok(())
+        })
+    }
+
+    fn unsubscribe(
+        &self,
+        user: i64,
+        subscription: i64,
+        expected: Option<i64>,
+    ) -> Result<Option<i64>, HandlerError> {
+        self.transaction(|transaction| {
+            let current: i64 = transaction
+                .query_row(
+                    "SELECT revision FROM subscriptions WHERE id = ?1 AND user_id = ?2",
+                    [subscription, user],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(storage)?
+                .ok_or_else(|| HandlerError::NotFound(format!("subscription {}", subscription)))?;
+
+            if expected.is_some_and(|expected| expected != current) {
+                return Err(HandlerError::Conflict(format!(
+                    "subscription {} is at revision {}",
+                    subscription, current
+                )));
+            }
+
+            transaction
+                .execute("DELETE FROM subscriptions WHERE id = ?1", [subscription])
+                .map_err(storage)?;
+
+            next_revision(transaction).map(Some)
+        })
+    }
+
+    /// Summaries are not stored, so entries carry none
+    fn unread(
+        &self,
+        user: i64,
+        after: Option<&Cursor>,
+        _summary: Option<u32>,
+    ) -> Result<EntryPage, HandlerError> {
+        let after = match after {
+            Some(cursor) => <[u8; 8]>::try_from(cursor.to_bytes().as_slice())
+                .map(i64::from_be_bytes)
+                .map_err(|_| ServerError::BadCommand("invalid cursor".to_string()))?,
+            None => 0,
+        };
+
+        let connection = self.lock();
+        let mut statement = connection
+            .prepare(&format!("{} LIMIT ?3", UNREAD))
+            .map_err(storage)?;
+        let rows = statement
+            .query_map(
+                params![
+                    user,
+                    after,
+                    i64::try_from(PAGE_SIZE)
+                        .unwrap_or(i64::MAX)
+                        .saturating_add(1)
+                ],
+                |row| {
+                    let item = FeedItem {
+                        guid: row.get(3)?,
+                        title: row.get(4)?,
+                        url: row.get(5)?,
+                        published: row.get(6)?,
+                    };
+
+                    Ok((row.get(0)?, row.get(1)?, row.get::<_, String>(2)?, item))
+                },
+            )
+            .map_err(storage)?;
+
+        let mut entries: Vec<Entry> = Vec::new();
+
+        for row in rows {
+            let (id, feed_id, feed_url, item) = row.map_err(storage)?;
+            entries.extend(item.into_entry(id, feed_id, &feed_url));
+        }
+
+        let next = if entries.len() > PAGE_SIZE {
+            entries.truncate(PAGE_SIZE);
+            entries
+                .last()
+                .and_then(|last| Cursor::from_bytes(&last.id.to_be_bytes()).ok())
+        } else {
+            None
+        };
+
+        Ok(EntryPage { entries, next })
+    }
+
+    fn unread_count(&self, user: i64) -> Result<u32, HandlerError> {
+        self.lock()
+            .query_row(
+                &format!("SELECT COUNT(*) FROM ({})", UNREAD),
+                params![user, 0],
+                |row| row.get(0),
+            )
+            .map(count)
+            .map_err(storage)
+    }
+
+    fn mark_read(
+        &self,
+        user: i64,
+        entry: i64,
+        expected: Option<i64>,
+    ) -> Result<Option<i64>, HandlerError> {
+        self.transaction(|transaction| set_read(transaction, user, entry, expected, true))
+    }
+
+    fn mark_unread(
+        &self,
+        user: i64,
+        entry: i64,
+        expected: Option<i64>,
+    ) -> Result<Option<i64>, HandlerError> {
+        self.transaction(|transaction| set_read(transaction, user, entry, expected, false))
+    }
+
+    fn mark_all_read(&self, user: i64) -> Result<u32, HandlerError> {
+        self.transaction(|transaction| {
+            let unread: Vec<i64> = {
+                let mut statement = transaction
+                    .prepare(&format!("SELECT id FROM ({})", UNREAD))
+                    .map_err(storage)?;
+                let ids = statement
+                    .query_map(params![user, 0], |row| row.get(0))
+                    .map_err(storage)?
+                    .collect::<Result<_, _>>()
+                    .map_err(storage)?;
+                ids
+            };
+
+            for entry in &unread {
+                let revision = next_revision(transaction)?;
+                transaction
+                    .execute(
+                        "INSERT INTO read_state (user_id, entry_id, revision, read)
+                        VALUES (?1, ?2, ?3, 1)
+                        ON CONFLICT (user_id, entry_id)
+                        DO UPDATE SET revision = excluded.revision, read = 1",
+                        params![user, entry, revision],
+                    )
+                    .map_err(storage)?;
+            }
+
+            Ok(u32::try_from(unread.len()).unwrap_or(u32::MAX))
+        })
+    }
+
+    fn store_entries(
+        &self,
+        feed_id: i64,
+        items: Vec<FeedItem>,
+    ) -> Result<Vec<Entry>, HandlerError> {
+        self.transaction(|transaction| {
+            let feed_url: String = transaction
+                .query_row("SELECT url FROM feeds WHERE id = ?1", [feed_id], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .map_err(storage)?
+                .ok_or_else(|| HandlerError::NotFound(format!("feed {}", feed_id)))?;
+
+            let mut added = Vec::new();
+
+            for item in items {
+                let stored = match &item.guid {
+                    Some(guid) => transaction.query_row(
+                        "SELECT 1 FROM entries WHERE feed_id = ?1 AND guid = ?2",
+                        params![feed_id, guid],
+                        |_| Ok(()),
+                    ),
+                    None => transaction.query_row(
+                        "SELECT 1 FROM entries WHERE feed_id = ?1 AND url = ?2",
+                        params![feed_id, item.url],
+                        |_| Ok(()),
+                    ),
+                }
+                .optional()
+                .map_err(storage)?
+                .is_some();
+
+                if stored {
+                    continue;
+                }
+
+                transaction
+                    .execute(
+                        "INSERT INTO entries (feed_id, guid, title, url, published)
+                        VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![feed_id, item.guid, item.title, item.url, item.published],
+                    )
+                    .map_err(storage)?;
+
+                let id = transaction.last_insert_rowid();
+                added.extend(item.into_entry(id, feed_id, &feed_url));
+            }
+
+            Ok(added)
+        })
+    }
+
+    fn role(&self, user: i64) -> Result<Role, HandlerError> {
+        let role: Option<String> = self
+            .lock()
+            .query_row("SELECT role FROM users WHERE id = ?1", [user], |row| {
+                row.get(0)
+            })
+            .optional()
+            .map_err(storage)?;
+
+        Ok(match role.as_deref() {
+            Some("admin") => Role::Admin,
+            _ => Role::User,
+        })
+    }
+
+    fn quota(&self, user: i64) -> Result<Quota, HandlerError> {
+        let quota = self
+            .lock()
+            .query_row(
+                "SELECT max_feeds, max_entries FROM users WHERE id = ?1",
+                [user],
+                |row| {
+                    Ok(Quota {
+                        max_feeds: limit(row.get(0)?),
+                        max_entries: limit(row.get(1)?),
+                    })
+                },
+            )
+            .optional()
+            .map_err(storage)?;
+
+        Ok(quota.unwrap_or_default())
+    }
+
+    fn set_quota(&self, user: i64, quota: Quota) -> Result<(), HandlerError> {
+        self.lock()
+            .execute(
+                "UPDATE users SET max_feeds = ?2, max_entries = ?3 WHERE id = ?1",
+                params![user, quota.max_feeds, quota.max_entries],
+            )
+            .map_err(storage)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(guid: Option<&str>, title: &str, url: &str) -> FeedItem {
+        FeedItem {
+            guid: guid.map(str::to_string),
+            title: title.to_string(),
+            url: url.to_string(),
+            published: Some(1_700_000_000),
+        }
+    }
+
+    #[test]
+    fn keeps_subscriptions_and_read_state() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let bob = storage.add_user("bob").unwrap();
+        let feed_url = "gemini://example.com/feed";
+
+        assert_eq!(storage.add_user("bob").unwrap(), bob);
+        assert_eq!(storage.user("bob").unwrap(), bob);
+        assert!(matches!(
+            storage.user("alice"),
+            Err(HandlerError::NotFound(_))
+        ));
+
+        storage.subscribe(bob, feed_url, None).unwrap();
+        storage.subscribe(bob, feed_url, None).unwrap();
+
+        let subscriptions = storage.subscriptions(bob).unwrap();
+        assert_eq!(subscriptions.len(), 1);
+        assert_eq!(subscriptions[0].url, feed_url);
+
+        let feed = storage.add_feed(feed_url).unwrap();
+        assert_eq!(storage.feeds().unwrap(), [(feed, feed_url.to_string())]);
+
+        let added = storage
+            .store_entries(
+                feed,
+                vec![
+                    item(Some("1"), "First", "gemini://example.com/1"),
+                    item(None, "Second", "gemini://example.com/2"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(added.len(), 2);
+        assert_eq!(added[0].guid.as_deref(), Some("1"));
+        assert_eq!(added[0].published, Some(1_700_000_000));
+
+        let again = storage
+            .store_entries(
+                feed,
+                vec![
+                    item(Some("1"), "First, moved", "gemini://example.com/moved"),
+                    item(None, "Second", "gemini://example.com/2"),
+                ],
+            )
+            .unwrap();
+        assert!(again.is_empty());
+
+        assert_eq!(storage.unread_count(bob).unwrap(), 2);
+
+        let first = added[0].id;
+        let revision = storage.mark_read(bob, first, None).unwrap();
+        assert!(matches!(
+            storage.mark_unread(bob, first, Some(0)),
+            Err(HandlerError::Conflict(_))
+        ));
+        storage.mark_unread(bob, first, revision).unwrap();
+        storage.mark_read(bob, first, None).unwrap();
+
+        let page = storage.unread(bob, None, None).unwrap();
+        assert_eq!(page.entries, added[1..]);
+        assert_eq!(page.next, None);
+
+        assert_eq!(storage.mark_all_read(bob).unwrap(), 1);
+        assert_eq!(storage.unread_count(bob).unwrap(), 0);
+
+        let alice = storage.add_user("alice").unwrap();
+        assert!(matches!(
+            storage.mark_read(alice, first, None),
+            Err(HandlerError::NotFound(_))
+        ));
+
+        let subscription = subscriptions[0].id;
+        assert!(matches!(
+            storage.unsubscribe(bob, subscription, Some(0)),
+            Err(HandlerError::Conflict(_))
+        ));
+        storage.unsubscribe(bob, subscription, None).unwrap();
+        assert!(storage.subscriptions(bob).unwrap().is_empty());
+        assert!(matches!(
+            storage.unsubscribe(bob, subscription, None),
+            Err(HandlerError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn pages_the_unread_list() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let bob = storage.add_user("bob").unwrap();
+        let feed_url = "gemini://example.com/feed";
+
+        storage.subscribe(bob, feed_url, None).unwrap();
+        let feed = storage.add_feed(feed_url).unwrap();
+
+        let items = (0..PAGE_SIZE + 1)
+            .map(|n| item(None, "Entry", &format!("gemini://example.com/{}", n)))
+            .collect();
+        storage.store_entries(feed, items).unwrap();
+
+        let first = storage.unread(bob, None, None).unwrap();
+        assert_eq!(first.entries.len(), PAGE_SIZE);
+
+        let second = storage.unread(bob, first.next.as_ref(), None).unwrap();
+        assert_eq!(second.entries.len(), 1);
+        assert_eq!(second.next, None);
+    }
+
+    #[test]
+    fn keeps_roles_and_quotas() {
+        let storage = SqliteStorage::open_in_memory().unwrap();
+        let bob = storage.add_user("bob").unwrap();
+
+        assert_eq!(storage.role(bob).unwrap(), Role::User);
+        storage.set_role(bob, Role::Admin).unwrap();
+        assert_eq!(storage.role(bob).unwrap(), Role::Admin);
+
+        assert_eq!(storage.quota(bob).unwrap(), Quota::unlimited());
+
+        let quota = Quota {
+            max_feeds: Some(10),
+            max_entries: None,
+        };
+        storage.set_quota(bob, quota).unwrap();
+        assert_eq!(storage.quota(bob).unwrap(), quota);
+    }
+}
@@ -0,0 +1,61 @@
+/// How the server is backing off a feed that keeps failing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Backoff {
+    /// Number of refreshes that have failed in a row, at
+    /// least one
+    pub failures: u32,
+    /// Unix timestamp of the first failure in the run
+    pub failing_since: i64,
+    /// Unix timestamp of the next attempt
+    pub next_retry: i64,
+}
+
+impl Backoff {
+    /// Seconds the feed has been failing for, as of `now`
+    pub fn failing_for(&self, now: i64) -> i64 {
+        now.saturating_sub(self.failing_since).max(0)
+    }
+}
+
+/// How the server is refreshing a subscribed feed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeedStatus {
+    pub feed_id: i64,
+    /// Seconds between refreshes
+    pub interval: u32,
+    /// Unix timestamp of the last refresh, if any
+    pub last_fetched: Option<i64>,
+    /// ETag the server sends in `If-None-Match`, as received
+    pub etag: Option<String>,
+    /// Last-Modified time the server sends in
+    /// `If-Modified-Since`, as a Unix timestamp
+    pub last_modified: Option<i64>,
+    /// Set while the feed's refreshes are failing
+    pub backoff: Option<Backoff>,
+}
+
+impl FeedStatus {
+    /// Unix timestamp after which the feed is due for a refresh
+    ///
+    /// Feeds that have never been fetched are due immediately,
+    /// and failing feeds are retried when their backoff ends.
+    pub fn next_fetch(&self) -> Option<i64> {
+        if let Some(backoff) = self.backoff {
+            return Some(backoff.next_retry);
+        }
+
+        self.last_fetched
+            .map(|last_fetched| last_fetched.saturating_add(i64::from(self.interval)))
+    }
+
+    /// Whether the feed's last refresh failed
+    pub fn is_failing(&self) -> bool {
+        self.backoff.is_some()
+    }
+
+    /// Whether refreshes are conditional requests, which the
+    /// feed's server can answer with 304 Not Modified
+    pub fn is_conditional(&self) -> bool {
+        self.etag.is_some() || self.last_modified.is_some()
+    }
+}
@@ -0,0 +1,246 @@
+//! Incremental sync
+//!
+//! SYNC answers with every change to the user's state since a
+//! sync token, then a new token to send with the next SYNC.
+//! Without a token the server sends the current state: every
+//! subscription and unread entry. Tokens are opaque to the
+//! client and use the same encoding as list cursors.
+//!
+//! SyncEngine merges the read state a client changed while
+//! offline with the changes SYNC reports.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Command, Cursor, CursorError, Entry, Response, Subscription};
+
+/// A point in the user's history of changes, handed out by the
+/// server
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SyncToken(Cursor);
+
+impl SyncToken {
+    /// Encode server-defined position data
+    ///
+    /// Fails under the same conditions as `Cursor::from_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SyncToken, CursorError> {
+        Cursor::from_bytes(bytes).map(SyncToken)
+    }
+
+    /// Decode the position data the server encoded
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.to_bytes()
+    }
+
+    /// The token as sent on the wire
+    pub fn as_str(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl FromStr for SyncToken {
+    type Err = CursorError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        value.parse().map(SyncToken)
+    }
+}
+
+impl fmt::Display for SyncToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A single change reported by SYNC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// The user subscribed to a feed
+    Subscribed(Subscription),
+    /// A new entry arrived
+    Entry(Entry),
+    /// An entry was marked read at the given time, or marked
+    /// unread again if None
+    ReadState { entry_id: i64, read_at: Option<i64> },
+    /// The user unsubscribed from a feed
+    Unsubscribed { feed_id: i64 },
+}
+
+impl From<Change> for Response {
+    fn from(change: Change) -> Response {
+        match change {
+            Change::Subscribed(subscription) => Response::Subscription(subscription),
+            Change::Entry(entry) => Response::Entry(entry),
+            Change::ReadState { entry_id, read_at } => {
+                Response::ReadStateChange { entry_id, read_at }
+            }
+            Change::Unsubscribed { feed_id } => Response::Unsubscribed { feed_id },
+        }
+    }
+}
+
+/// Fails with the response itself if it is not a change
+impl TryFrom<Response> for Change {
+    type Error = Response;
+
+    fn try_from(response: Response) -> Result<Self, Self::Error> {
+        match response {
+            Response::Subscription(subscription) => Ok(Change::Subscribed(subscription)),
+            Response::Entry(entry) => Ok(Change::Entry(entry)),
+            Response::ReadStateChange { entry_id, read_at } => {
+                Ok(Change::ReadState { entry_id, read_at })
+            }
+            Response::Unsubscribed { feed_id } => Ok(Change::Unsubscribed { feed_id }),
+            response => Err(response),
+        }
+    }
+}
+
+/// A read state set locally, at a time in seconds since the
+/// Unix epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Mark {
+    read: bool,
+    at: i64,
+}
+
+/// Reconciles a client's local read state journal with the
+/// server's
+///
+/// Clients record every mark made locally, merge the changes of
+/// each SYNC, and send the commands the engine asks for until
+/// they are acknowledged. The latest mark of an entry wins. The
+/// server reports when an entry was read but not when it was
+/// marked unread again, so an unread entry on the server never
+/// undoes a local mark.
+#[derive(Debug, Clone, Default)]
+pub struct SyncEngine {
+    token: Option<SyncToken>,
+    journal: BTreeMap<i64, Mark>,
+}
+
+impl SyncEngine {
+    /// Resume from the token of the last merged SYNC, or start
+    /// from the server's current state without one
+    pub fn new(token: Option<SyncToken>) -> SyncEngine {
+        SyncEngine {
+            token,
+            journal: BTreeMap::new(),
+        }
+    }
+
+    /// The token of the last merged SYNC
+    pub fn token(&self) -> Option<&SyncToken> {
+        self.token.as_ref()
+    }
+
+    /// Record that an entry was read locally
+    pub fn mark_read(&mut self, entry_id: i64, at: i64) {
+        self.record(entry_id, Mark { read: true, at });
+    }
+
+    /// Record that an entry was marked unread locally
+    pub fn mark_unread(&mut self, entry_id: i64, at: i64) {
+        self.record(entry_id, Mark { read: false, at });
+    }
+
+    fn record(&mut self, entry_id: i64, mark: Mark) {
+        let newer = match self.journal.get(&entry_id) {
+            Some(recorded) => mark.at >= recorded.at,
+            None => true,
+        };
+
+        if newer {
+            self.journal.insert(entry_id, mark);
+        }
+    }
+
+    /// Whether local marks are waiting to be sent
+    pub fn is_pending(&self) -> bool {
+        !self.journal.is_empty()
+    }
+
+    /// The Sync command fetching changes since the last merge
+    pub fn sync_command(&self) -> Command {
+        Command::Sync {
+            token: self.token.clone(),
+        }
+    }
+
+    /// Merge the changes answering a Sync
+    ///
+    /// Local marks the server already agrees with, or that an
+    /// earlier read on the server supersedes, are dropped.
+    pub fn merge<I>(&mut self, changes: I, token: SyncToken)
+    where
+        I: IntoIterator<Item = Change>,
+    {
+        for change in changes {
+            match change {
+                Change::ReadState { entry_id, read_at } => self.observe(entry_id, read_at),
+                Change::Entry(entry) => self.observe(entry.id, entry.read_at),
+                Change::Subscribed(_) | Change::Unsubscribed { .. } => {}
+            }
+        }
+
+        self.token = Some(token);
+    }
+
+    fn observe(&mut self, entry_id: i64, read_at: Option<i64>) {
+        let settled = match (self.journal.get(&entry_id), read_at) {
+            (Some(mark), Some(read_at)) => mark.read || read_at >= mark.at,
+            (Some(mark), None) => !mark.read,
+            (None, _) => false,
+        };
+
+        if settled {
+            self.journal.remove(&entry_id);
+        }
+    }
+
+    /// The commands converging the server on the local marks,
+    /// one per entry in entry id order
+    pub fn commands(&self) -> Vec<Command> {
+        self.journal
+            .iter()
+            .map(|(&id, mark)| {
+                if mark.read {
+                    Command::MarkRead {
+                        id,
+                        expected: None,
+                        key: None,
+                    }
+                } else {
+                    Command::MarkUnread { id, expected: None }
+                }
+            })
+            .collect()
+    }
+
+    /// Record the server's reply to one of the commands
+    ///
+    /// Acknowledged marks are dropped, as are marks of entries
+    /// the server no longer has. Marks changed again since the
+    /// command was sent, and marks refused with any other
+    /// error, are kept to be sent again.
+    pub fn acknowledge(&mut self, command: &Command, response: &Response) {
+        let (id, read) = match command {
+            Command::MarkRead { id, .. } => (*id, true),
+            Command::MarkUnread { id, .. } => (*id, false),
+            _ => return,
+        };
+
+        let done = matches!(
+            response,
+            Response::AckMarkRead { .. }
+                | Response::AckMarkUnread { .. }
+                | Response::ResourceNotFound(_)
+        );
+
+        if done && matches!(self.journal.get(&id), Some(mark) if mark.read == read) {
+            self.journal.remove(&id);
+        }
+    }
+}
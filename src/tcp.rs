@@ -0,0 +1,165 @@
+//! Socket options for client connections
+//!
+//! Interactive clients want each command on the wire at once,
+//! while batch jobs care more about riding out a server restart.
+//! TcpOptions holds the settings that differ between them, with
+//! presets for both.
+
+use std::io;
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::TimeoutError;
+
+/// How long to wait between attempts to connect
+///
+/// Delays start at `initial` and double after every failed
+/// attempt, up to `max`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectBackoff {
+    pub initial: Duration,
+    pub max: Duration,
+    /// Attempts after which to give up, or None to keep trying
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ConnectBackoff {
+    fn default() -> Self {
+        ConnectBackoff {
+            initial: Duration::from_millis(500),
+            max: Duration::from_secs(30),
+            max_attempts: Some(5),
+        }
+    }
+}
+
+impl ConnectBackoff {
+    /// The delay before retrying after `failures` failed
+    /// attempts, or None once the client should give up
+    pub fn delay(&self, failures: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| failures >= max) {
+            return None;
+        }
+
+        let factor = 2u32
+            .checked_pow(failures.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+
+        Some(
+            self.initial
+                .checked_mul(factor)
+                .map_or(self.max, |delay| delay.min(self.max)),
+        )
+    }
+}
+
+/// TCP settings for the connection to the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpOptions {
+    /// Send each line at once instead of coalescing small
+    /// writes (TCP_NODELAY)
+    pub nodelay: bool,
+    /// Idle time before keepalive probes start, or None to
+    /// leave keepalive off
+    pub keepalive: Option<Duration>,
+    /// Longest wait for one connection attempt
+    pub connect_timeout: Option<Duration>,
+    /// Longest wait for the server to send anything, or None to
+    /// wait as long as the connection stays up
+    pub read_timeout: Option<Duration>,
+    pub backoff: ConnectBackoff,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        TcpOptions::interactive()
+    }
+}
+
+impl TcpOptions {
+    /// For clients a person is waiting on: lines go out at
+    /// once and dead connections are noticed within a minute
+    pub fn interactive() -> TcpOptions {
+        TcpOptions {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(30)),
+            connect_timeout: Some(Duration::from_secs(10)),
+            read_timeout: Some(Duration::from_secs(60)),
+            backoff: ConnectBackoff::default(),
+        }
+    }
+
+    /// For unattended jobs such as syncs: writes are coalesced
+    /// and reconnecting keeps trying through a server restart
+    pub fn batch() -> TcpOptions {
+        TcpOptions {
+            nodelay: false,
+            keepalive: Some(Duration::from_secs(300)),
+            connect_timeout: Some(Duration::from_secs(30)),
+            read_timeout: None,
+            backoff: ConnectBackoff {
+                initial: Duration::from_secs(1),
+                max: Duration::from_secs(300),
+                max_attempts: None,
+            },
+        }
+    }
+
+    /// Apply the options std can set to a connected socket
+    ///
+    /// The standard library has no keepalive settings, so
+    /// clients set `keepalive` through their runtime or a crate
+    /// such as socket2.
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        stream.set_read_timeout(self.read_timeout)
+    }
+
+    /// The TimeoutError a failed connection attempt stands for,
+    /// if it failed by running out of time
+    pub fn connect_timed_out(&self, error: &io::Error) -> Option<TimeoutError> {
+        match (error.kind(), self.connect_timeout) {
+            (io::ErrorKind::TimedOut, Some(timeout)) => Some(TimeoutError::Connect(timeout)),
+            _ => None,
+        }
+    }
+
+    /// The TimeoutError a failed read stands for, if it failed
+    /// by running out of time
+    ///
+    /// Sockets report a read timeout as WouldBlock on Unix and
+    /// as TimedOut on Windows.
+    pub fn read_timed_out(&self, error: &io::Error) -> Option<TimeoutError> {
+        match (error.kind(), self.read_timeout) {
+            (io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut, Some(timeout)) => {
+                Some(TimeoutError::Read(timeout))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeouts() {
+        let options = TcpOptions::interactive();
+        let timed_out = io::Error::from(io::ErrorKind::TimedOut);
+        let would_block = io::Error::from(io::ErrorKind::WouldBlock);
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+
+        assert_eq!(
+            options.connect_timed_out(&timed_out),
+            Some(TimeoutError::Connect(Duration::from_secs(10)))
+        );
+        assert_eq!(options.connect_timed_out(&refused), None);
+        assert_eq!(
+            options.read_timed_out(&would_block),
+            Some(TimeoutError::Read(Duration::from_secs(60)))
+        );
+        assert_eq!(options.read_timed_out(&refused), None);
+        assert_eq!(TcpOptions::batch().read_timed_out(&would_block), None);
+    }
+}
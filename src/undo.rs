@@ -0,0 +1,56 @@
+use std::fmt;
+
+use crate::Command;
+
+/// An action reversed by an Undo command
+///
+/// Servers record the last undoable command of each session
+/// and report it back once it has been reversed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Undone {
+    /// The entry is unread again
+    MarkRead { id: i64 },
+    /// The entries marked read by `MARKREAD ALL` are unread
+    /// again
+    MarkAllRead,
+    /// The user is subscribed to the feed again, under the
+    /// same id
+    Unsubscribe { id: i64 },
+}
+
+impl Undone {
+    /// The action a command performs, if Undo can reverse it
+    pub fn from_command(command: &Command) -> Option<Undone> {
+        match command {
+            Command::MarkRead { id, .. } => Some(Undone::MarkRead { id: *id }),
+            Command::MarkAllRead => Some(Undone::MarkAllRead),
+            Command::Unsubscribe { id, .. } => Some(Undone::Unsubscribe { id: *id }),
+            _ => None,
+        }
+    }
+
+    /// The command that performed the action
+    pub fn command(&self) -> Command {
+        match *self {
+            Undone::MarkRead { id } => Command::MarkRead {
+                id,
+                expected: None,
+                key: None,
+            },
+            Undone::MarkAllRead => Command::MarkAllRead,
+            Undone::Unsubscribe { id } => Command::Unsubscribe {
+                id,
+                expected: None,
+                key: None,
+            },
+        }
+    }
+}
+
+/// Written as the command that performed the action, such as
+/// `MARKREAD 12`
+impl fmt::Display for Undone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.command())
+    }
+}
@@ -0,0 +1,88 @@
+//! Username validation
+//!
+//! The protocol itself accepts any single word as a username.
+//! Servers that store usernames usually want fewer surprises,
+//! and a `UsernamePolicy` in `ParseOptions` lets the parser
+//! reject the rest with a precise BadCommand.
+
+use thiserror::Error;
+
+/// Which usernames a server accepts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsernamePolicy {
+    /// Minimum length in characters
+    pub min_length: usize,
+    /// Maximum length in characters
+    pub max_length: usize,
+    /// Whether letters and digits outside ASCII are allowed
+    pub allow_unicode: bool,
+    /// Characters allowed besides letters and digits
+    pub punctuation: String,
+    /// Names no user may take, compared case-insensitively
+    pub reserved: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum UsernameError {
+    #[error("must be at least {0} characters long")]
+    TooShort(usize),
+    #[error("must be at most {0} characters long")]
+    TooLong(usize),
+    #[error("character '{0}' is not allowed")]
+    InvalidCharacter(char),
+    #[error("name is reserved")]
+    Reserved,
+}
+
+/// Letters and digits of any script, `-`, `_` and `.`, from 1
+/// to 32 characters, with no reserved names
+impl Default for UsernamePolicy {
+    fn default() -> Self {
+        UsernamePolicy {
+            min_length: 1,
+            max_length: 32,
+            allow_unicode: true,
+            punctuation: "-_.".to_string(),
+            reserved: Vec::new(),
+        }
+    }
+}
+
+impl UsernamePolicy {
+    fn allows(&self, c: char) -> bool {
+        if c.is_ascii_alphanumeric() || self.punctuation.contains(c) {
+            return true;
+        }
+
+        self.allow_unicode && !c.is_ascii() && c.is_alphanumeric()
+    }
+
+    /// Check a username against the policy
+    pub fn check(&self, username: &str) -> Result<(), UsernameError> {
+        let length = username.chars().count();
+
+        if length < self.min_length {
+            return Err(UsernameError::TooShort(self.min_length));
+        }
+
+        if length > self.max_length {
+            return Err(UsernameError::TooLong(self.max_length));
+        }
+
+        if let Some(c) = username.chars().find(|c| !self.allows(*c)) {
+            return Err(UsernameError::InvalidCharacter(c));
+        }
+
+        let folded = username.to_lowercase();
+
+        if self
+            .reserved
+            .iter()
+            .any(|reserved| reserved.to_lowercase() == folded)
+        {
+            return Err(UsernameError::Reserved);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,405 @@
+use thiserror::Error;
+
+use crate::wirelog::{redact_secrets, Direction};
+use crate::{Command, Response};
+
+/// Errors raised when a server's responses do not form
+/// a valid reply to the issued command
+///
+/// Commands are named by their audit record and responses are
+/// quoted with secret arguments redacted, so violations can be
+/// logged without leaking credentials or tokens.
+#[derive(Debug, Error)]
+pub enum ProtocolViolation {
+    #[error("list item \"{0}\" received outside of a list")]
+    ItemOutsideList(String),
+    #[error("end of list received with no open list")]
+    UnexpectedEndList,
+    #[error("\"{0}\" received before the open list was ended")]
+    UnterminatedList(String),
+    #[error("unexpected response \"{response}\" to command \"{command}\"")]
+    UnexpectedResponse { command: String, response: String },
+    #[error("response \"{0}\" received after the reply was complete")]
+    ReplyAlreadyComplete(String),
+    #[error("reply to command \"{0}\" ended before it was complete")]
+    IncompleteReply(String),
+}
+
+/// Progress of a reply after accepting a response
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyStatus {
+    /// More responses are needed to complete the reply
+    Incomplete,
+
+    /// The reply is complete
+    Complete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Expected {
+    AckUser,
+    AckSubscribe,
+    AckUnsubscribe,
+    AckMarkRead,
+    AckMarkUnread,
+    AckMarkAllRead,
+    AckUndo,
+    Pong,
+    Capabilities,
+    Quota,
+    AckShare,
+    AckUnshare,
+    AckSetOrder,
+    AckSetInterval,
+    FeedStatus,
+    AckAddWebhook,
+    AckDeleteWebhook,
+    AckSaveSearch,
+    AckSession,
+    ResumeToken,
+    SubscriptionList,
+    EntryList,
+    Count,
+    ShareList,
+    CategoryList,
+    SavedSearchList,
+    WebhookList,
+    Content,
+    RawFeed,
+    Export,
+    Import,
+    Sync,
+}
+
+impl Expected {
+    fn for_command(command: &Command) -> Self {
+        match command {
+            Command::User { .. } | Command::Auth(_) | Command::Resume { .. } => Expected::AckUser,
+            Command::ResumeToken => Expected::ResumeToken,
+            Command::ListSubscriptions => Expected::SubscriptionList,
+            Command::Subscribe { .. } => Expected::AckSubscribe,
+            Command::Unsubscribe { .. } => Expected::AckUnsubscribe,
+            Command::ListUnread { count: true, .. } | Command::ListRead { count: true, .. } => {
+                Expected::Count
+            }
+            Command::ListUnread { .. } => Expected::EntryList,
+            Command::MarkRead { .. } => Expected::AckMarkRead,
+            Command::MarkUnread { .. } => Expected::AckMarkUnread,
+            Command::MarkAllRead => Expected::AckMarkAllRead,
+            Command::GetContent { .. } => Expected::Content,
+            Command::GetRawFeed { .. } => Expected::RawFeed,
+            Command::Export { .. } => Expected::Export,
+            Command::Import => Expected::Import,
+            Command::Undo => Expected::AckUndo,
+            Command::Sync { .. } => Expected::Sync,
+            Command::ListRead { .. } => Expected::EntryList,
+            Command::Search { .. } | Command::RunSearch { .. } => Expected::EntryList,
+            Command::SaveSearch { .. } => Expected::AckSaveSearch,
+            Command::Session { .. } => Expected::AckSession,
+            Command::ListSavedSearches => Expected::SavedSearchList,
+            Command::Ping => Expected::Pong,
+            Command::Capabilities(_) => Expected::Capabilities,
+            Command::SetQuota { .. } | Command::GetQuota { .. } => Expected::Quota,
+            Command::Share { .. } => Expected::AckShare,
+            Command::Unshare { .. } => Expected::AckUnshare,
+            Command::ListShares => Expected::ShareList,
+            Command::ListCategories => Expected::CategoryList,
+            Command::SetOrder { .. } => Expected::AckSetOrder,
+            Command::SetInterval { .. } => Expected::AckSetInterval,
+            Command::FeedStatus { .. } => Expected::FeedStatus,
+            Command::AddWebhook { .. } => Expected::AckAddWebhook,
+            Command::ListWebhooks => Expected::WebhookList,
+            Command::DeleteWebhook { .. } => Expected::AckDeleteWebhook,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Waiting,
+    InList,
+    Complete,
+}
+
+/// Checks that the responses received for a single command
+/// form a well-formed reply
+///
+/// Feed each response to `accept` in the order it was
+/// received. Error responses are accepted as a complete reply
+/// to any command, as long as no list has been started.
+/// ShuttingDown announcements may arrive at any point and do
+/// not affect the reply.
+#[derive(Debug, Clone)]
+pub struct ReplyValidator {
+    command: String,
+    expected: Expected,
+    state: State,
+}
+
+impl ReplyValidator {
+    pub fn new(command: &Command) -> Self {
+        ReplyValidator {
+            command: command.to_audit_record().to_string(),
+            expected: Expected::for_command(command),
+            state: State::Waiting,
+        }
+    }
+
+    /// Whether a complete reply has been accepted
+    pub fn is_complete(&self) -> bool {
+        self.state == State::Complete
+    }
+
+    /// Accept the next response of the reply
+    pub fn accept(&mut self, response: &Response) -> Result<ReplyStatus, ProtocolViolation> {
+        if let Response::ShuttingDown(_) = response {
+            return Ok(self.status());
+        }
+
+        self.state = match self.state {
+            State::Complete => {
+                return Err(ProtocolViolation::ReplyAlreadyComplete(quote(response)))
+            }
+            State::Waiting => self.accept_first(response)?,
+            State::InList => self.accept_in_list(response)?,
+        };
+
+        Ok(self.status())
+    }
+
+    /// Check that the reply was completed
+    pub fn finish(&self) -> Result<(), ProtocolViolation> {
+        if self.is_complete() {
+            Ok(())
+        } else {
+            Err(ProtocolViolation::IncompleteReply(self.command.clone()))
+        }
+    }
+
+    fn status(&self) -> ReplyStatus {
+        if self.is_complete() {
+            ReplyStatus::Complete
+        } else {
+            ReplyStatus::Incomplete
+        }
+    }
+
+    fn accept_first(&self, response: &Response) -> Result<State, ProtocolViolation> {
+        match (self.expected, response) {
+            (_, response) if response.is_error() => Ok(State::Complete),
+
+            (Expected::AckUser, Response::AckUser { .. })
+            | (Expected::AckSubscribe, Response::AckSubscribe { .. })
+            | (Expected::AckUnsubscribe, Response::AckUnsubscribe { .. })
+            | (Expected::AckMarkRead, Response::AckMarkRead { .. })
+            | (Expected::AckMarkUnread, Response::AckMarkUnread { .. })
+            | (Expected::AckMarkAllRead, Response::AckMarkAllRead { .. })
+            | (Expected::AckUndo, Response::AckUndo(_))
+            | (Expected::Pong, Response::Pong)
+            | (Expected::Capabilities, Response::Capabilities(_))
+            | (Expected::Quota, Response::Quota(_))
+            | (Expected::AckShare, Response::AckShare)
+            | (Expected::AckUnshare, Response::AckUnshare)
+            | (Expected::AckSetOrder, Response::AckSetOrder)
+            | (Expected::AckSetInterval, Response::AckSetInterval)
+            | (Expected::FeedStatus, Response::FeedStatus(_))
+            | (Expected::AckAddWebhook, Response::AckAddWebhook { .. })
+            | (Expected::AckDeleteWebhook, Response::AckDeleteWebhook)
+            | (Expected::AckSaveSearch, Response::AckSaveSearch)
+            | (Expected::AckSession, Response::AckSession)
+            | (Expected::ResumeToken, Response::ResumeToken { .. })
+            | (Expected::Count, Response::Count(_)) => Ok(State::Complete),
+
+            (Expected::SubscriptionList, Response::StartSubscriptionList)
+            | (Expected::EntryList, Response::StartEntryList)
+            | (Expected::ShareList, Response::StartShareList)
+            | (Expected::CategoryList, Response::StartCategoryList)
+            | (Expected::SavedSearchList, Response::StartSavedSearchList)
+            | (Expected::WebhookList, Response::StartWebhookList)
+            | (Expected::Content, Response::StartContent { .. })
+            | (Expected::RawFeed, Response::StartRawFeed { .. })
+            | (Expected::Export, Response::StartExport)
+            | (Expected::Import, Response::StartImport)
+            | (Expected::Sync, Response::StartSync) => Ok(State::InList),
+
+            (_, response) if response.is_list_item() => {
+                Err(ProtocolViolation::ItemOutsideList(quote(response)))
+            }
+            (_, Response::EndList) | (_, Response::EndPage(_)) | (_, Response::EndSync(_)) => {
+                Err(ProtocolViolation::UnexpectedEndList)
+            }
+
+            _ => Err(self.unexpected(response)),
+        }
+    }
+
+    fn accept_in_list(&self, response: &Response) -> Result<State, ProtocolViolation> {
+        match (self.expected, response) {
+            // Changes always end with the next sync token
+            (Expected::Sync, Response::EndSync(_)) => Ok(State::Complete),
+            (Expected::Sync, Response::EndList) => Err(self.unexpected(response)),
+
+            (_, Response::EndList) => Ok(State::Complete),
+
+            // Only entry lists can be continued with AFTER
+            (Expected::EntryList, Response::EndPage(_)) => Ok(State::Complete),
+
+            (Expected::SubscriptionList, Response::Subscription(_))
+            | (Expected::EntryList, Response::Entry(_))
+            | (Expected::ShareList, Response::Share(_))
+            | (Expected::CategoryList, Response::Category(_))
+            | (Expected::SavedSearchList, Response::SavedSearch(_))
+            | (Expected::WebhookList, Response::Webhook(_))
+            | (Expected::Content, Response::PayloadLine(_))
+            | (Expected::RawFeed, Response::PayloadLine(_))
+            | (Expected::Export, Response::PayloadLine(_))
+            | (Expected::Import, Response::ImportResult(_))
+            | (Expected::Sync, Response::Subscription(_))
+            | (Expected::Sync, Response::Entry(_))
+            | (Expected::Sync, Response::ReadStateChange { .. })
+            | (Expected::Sync, Response::Unsubscribed { .. }) => Ok(State::InList),
+
+            (_, response) if response.is_list_item() => Err(self.unexpected(response)),
+
+            _ => Err(ProtocolViolation::UnterminatedList(quote(response))),
+        }
+    }
+
+    fn unexpected(&self, response: &Response) -> ProtocolViolation {
+        ProtocolViolation::UnexpectedResponse {
+            command: self.command.clone(),
+            response: quote(response),
+        }
+    }
+}
+
+/// A response as quoted in a violation
+fn quote(response: &Response) -> String {
+    let line = response.to_string();
+
+    redact_secrets(Direction::Received, &line).unwrap_or(line)
+}
+
+/// Validate a complete reply to a command
+pub fn validate_reply<'a, I>(command: &Command, responses: I) -> Result<(), ProtocolViolation>
+where
+    I: IntoIterator<Item = &'a Response>,
+{
+    let mut validator = ReplyValidator::new(command);
+
+    for response in responses {
+        validator.accept(response)?;
+    }
+
+    validator.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{Credentials, Cursor};
+
+    fn validate(command: &Command, responses: &[Response]) -> Result<(), ProtocolViolation> {
+        validate_reply(command, responses)
+    }
+
+    #[test]
+    fn complete_replies() {
+        assert!(validate(&Command::Ping, &[Response::Pong]).is_ok());
+        assert!(validate(
+            &Command::ListSubscriptions,
+            &[Response::StartSubscriptionList, Response::EndList]
+        )
+        .is_ok());
+        assert!(validate(
+            &Command::ListSubscriptions,
+            &[Response::NeedUser("select a user".to_string())]
+        )
+        .is_ok());
+
+        // Announcements do not take part in the reply
+        assert!(validate(
+            &Command::Ping,
+            &[
+                Response::ShuttingDown("restarting".to_string()),
+                Response::Pong
+            ]
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn violations() {
+        assert!(matches!(
+            validate(&Command::Ping, &[Response::EndList]),
+            Err(ProtocolViolation::UnexpectedEndList)
+        ));
+        assert!(matches!(
+            validate(&Command::Ping, &[Response::Pong, Response::Pong]),
+            Err(ProtocolViolation::ReplyAlreadyComplete(_))
+        ));
+        assert!(matches!(
+            validate(
+                &Command::ListSubscriptions,
+                &[Response::StartSubscriptionList]
+            ),
+            Err(ProtocolViolation::IncompleteReply(_))
+        ));
+        assert!(matches!(
+            validate(
+                &Command::ListSubscriptions,
+                &[Response::StartSubscriptionList, Response::Pong]
+            ),
+            Err(ProtocolViolation::UnterminatedList(_))
+        ));
+        assert!(matches!(
+            validate(&Command::Ping, &[Response::AckShare]),
+            Err(ProtocolViolation::UnexpectedResponse { .. })
+        ));
+
+        // Only entry lists can be paged
+        assert!(validate(
+            &Command::ListSubscriptions,
+            &[
+                Response::StartSubscriptionList,
+                Response::EndPage(Cursor::from_bytes(b"page").unwrap())
+            ]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn violations_hide_secrets() {
+        let auth = Command::Auth(Credentials::Password {
+            username: "alice".to_string(),
+            password: "hunter2".to_string(),
+        });
+        let resume = Command::Resume {
+            token: "s3cr3t".to_string(),
+        };
+        let token = Response::ResumeToken {
+            token: "t0k3n".to_string(),
+            expires: 0,
+        };
+
+        let errors = [
+            validate(&auth, &[Response::Pong]),
+            validate(&auth, &[]),
+            validate(&resume, std::slice::from_ref(&token)),
+            validate(&Command::Ping, &[Response::Pong, token]),
+        ];
+
+        for error in errors {
+            let message = error
+                .err()
+                .map(|error| error.to_string())
+                .unwrap_or_default();
+
+            assert!(!message.is_empty());
+            for secret in ["hunter2", "s3cr3t", "t0k3n"] {
+                assert!(!message.contains(secret), "{} leaks {}", message, secret);
+            }
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Protocol versions and capability negotiation
+//!
+//! Connections start out speaking version 1. A client that
+//! knows later versions sends CAPABILITIES listing the versions
+//! it speaks, the server answers with its own, and from then on
+//! both sides use the highest version they share. Servers that
+//! predate negotiation answer with BadCommand, leaving the
+//! connection on version 1.
+//!
+//! Version 2 adds the publication time, GUID, read time,
+//! group, duplicate flag, flags, language and summary to
+//! entries, the icon URL and position to subscriptions, and
+//! revisions to mark-read and unsubscribe acknowledgements.
+//! Optional fields are sent as `-` when absent.
+//!
+//! Servers advertising `ANONYMOUS` answer LISTSUBSCRIPTIONS
+//! and LISTUNREAD without a User command, from a public user
+//! chosen by the server operator.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A revision of the wire format
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProtocolVersion {
+    /// The original protocol
+    #[default]
+    V1,
+    /// Adds entry publication times, GUIDs, read times, groups,
+    /// duplicate flags, flags, languages and summaries,
+    /// subscription icons and positions, and ack revisions
+    V2,
+}
+
+impl ProtocolVersion {
+    /// The most recent version known to this crate
+    pub const LATEST: ProtocolVersion = ProtocolVersion::V2;
+
+    /// Every version known to this crate, oldest first
+    pub const ALL: &'static [ProtocolVersion] = &[ProtocolVersion::V1, ProtocolVersion::V2];
+
+    /// The number identifying the version on the wire
+    pub fn number(self) -> u8 {
+        match self {
+            ProtocolVersion::V1 => 1,
+            ProtocolVersion::V2 => 2,
+        }
+    }
+
+    /// Look up a version by its number
+    pub fn from_number(number: u8) -> Option<ProtocolVersion> {
+        ProtocolVersion::ALL
+            .iter()
+            .copied()
+            .find(|version| version.number() == number)
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.number())
+    }
+}
+
+/// What one side of a connection supports
+///
+/// Sent as space-separated tokens, such as `VERSIONS=1,2 ANONYMOUS`.
+/// Tokens this crate does not understand are kept as
+/// extensions, and unknown version numbers are ignored, so
+/// peers can advertise capabilities added later.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Protocol versions spoken; version 1 is always implied
+    pub versions: Vec<ProtocolVersion>,
+    /// Whether read-only commands may be issued without a user
+    pub anonymous: bool,
+    /// Limits the server enforces
+    pub limits: Limits,
+    /// Capability tokens not interpreted by this crate
+    pub extensions: Vec<String>,
+}
+
+/// Limits a server advertises so clients can stay within them
+///
+/// Sent as `MAXLINE=`, `MAXSUBSCRIPTIONS=` and `MAXBATCH=`
+/// tokens. None means the server did not say.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Longest line accepted, in bytes, line ending excluded
+    pub max_line_length: Option<u32>,
+    /// Most subscriptions one user may have
+    pub max_subscriptions: Option<u32>,
+    /// Most items accepted by one batch command
+    pub max_batch_size: Option<u32>,
+}
+
+impl Limits {
+    /// The capability token for each limit, with the limit
+    pub(crate) const TOKENS: [&'static str; 3] = ["MAXLINE=", "MAXSUBSCRIPTIONS=", "MAXBATCH="];
+
+    /// Whether no limit is advertised
+    pub fn is_empty(&self) -> bool {
+        *self == Limits::default()
+    }
+
+    /// The limit sent with a token, such as `MAXLINE=`
+    pub(crate) fn get_mut(&mut self, token: &str) -> Option<&mut Option<u32>> {
+        match token {
+            "MAXLINE=" => Some(&mut self.max_line_length),
+            "MAXSUBSCRIPTIONS=" => Some(&mut self.max_subscriptions),
+            "MAXBATCH=" => Some(&mut self.max_batch_size),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Limits {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let limits = [
+            self.max_line_length,
+            self.max_subscriptions,
+            self.max_batch_size,
+        ];
+        let mut separator = "";
+
+        for (token, limit) in Limits::TOKENS.iter().zip(&limits) {
+            if let Some(limit) = limit {
+                write!(f, "{}{}{}", separator, token, limit)?;
+                separator = " ";
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Capabilities {
+    /// The capabilities of this crate: every known version
+    pub fn new() -> Capabilities {
+        Capabilities {
+            versions: ProtocolVersion::ALL.to_vec(),
+            anonymous: false,
+            limits: Limits::default(),
+            extensions: Vec::new(),
+        }
+    }
+
+    /// Whether nothing is advertised
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+            && !self.anonymous
+            && self.limits.is_empty()
+            && self.extensions.is_empty()
+    }
+
+    /// Longest line the server accepts, in bytes
+    pub fn max_line_length(&self) -> Option<usize> {
+        self.limits.max_line_length.map(widen)
+    }
+
+    /// Most subscriptions the server allows one user
+    pub fn max_subscriptions(&self) -> Option<usize> {
+        self.limits.max_subscriptions.map(widen)
+    }
+
+    /// Most items the server accepts in one batch command
+    pub fn max_batch_size(&self) -> Option<usize> {
+        self.limits.max_batch_size.map(widen)
+    }
+
+    /// Whether a protocol version is supported
+    pub fn supports(&self, version: ProtocolVersion) -> bool {
+        version == ProtocolVersion::V1 || self.versions.contains(&version)
+    }
+
+    /// The highest version supported by both sides
+    pub fn negotiate(&self, other: &Capabilities) -> ProtocolVersion {
+        self.versions
+            .iter()
+            .copied()
+            .filter(|version| other.supports(*version))
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+/// Limits above the address space are no limit at all
+fn widen(limit: u32) -> usize {
+    usize::try_from(limit).unwrap_or(usize::MAX)
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut separator = "";
+
+        if !self.versions.is_empty() {
+            let versions: Vec<String> = self.versions.iter().map(|v| v.to_string()).collect();
+
+            write!(f, "VERSIONS={}", versions.join(","))?;
+            separator = " ";
+        }
+
+        if self.anonymous {
+            write!(f, "{}ANONYMOUS", separator)?;
+            separator = " ";
+        }
+
+        if !self.limits.is_empty() {
+            write!(f, "{}{}", separator, self.limits)?;
+            separator = " ";
+        }
+
+        for extension in &self.extensions {
+            write!(f, "{}{}", separator, extension)?;
+            separator = " ";
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use thiserror::Error;
+
+/// A URL the server notifies when new entries arrive for the
+/// user who registered it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Webhook {
+    pub id: i64,
+    pub url: String,
+}
+
+/// Reasons a webhook callback URL is rejected
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum WebhookUrlError {
+    #[error("scheme must be http or https")]
+    UnsupportedScheme,
+    #[error("missing host")]
+    MissingHost,
+    #[error("invalid port")]
+    InvalidPort,
+}
+
+/// Check that a callback URL is an absolute http or https URL
+/// with a host
+///
+/// Servers are still responsible for deciding which hosts they
+/// are willing to call.
+pub fn validate_webhook_url(url: &str) -> Result<(), WebhookUrlError> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .ok_or(WebhookUrlError::UnsupportedScheme)?;
+
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return Err(WebhookUrlError::UnsupportedScheme);
+    }
+
+    let authority = rest.split(&['/', '?', '#'][..]).next().unwrap_or_default();
+    let host_and_port = match authority.rsplit_once('@') {
+        Some((_, host_and_port)) => host_and_port,
+        None => authority,
+    };
+
+    // Bracketed IPv6 literals contain colons of their own
+    let (host, port) = match host_and_port.strip_prefix('[') {
+        Some(literal) => match literal.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, port)) => match port.strip_prefix(':') {
+                Some(port) => (host, Some(port)),
+                None => return Err(WebhookUrlError::InvalidPort),
+            },
+            None => return Err(WebhookUrlError::MissingHost),
+        },
+        None => match host_and_port.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_and_port, None),
+        },
+    };
+
+    if host.is_empty() {
+        return Err(WebhookUrlError::MissingHost);
+    }
+
+    match port {
+        Some(port) if port.parse::<u16>().is_err() => Err(WebhookUrlError::InvalidPort),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,241 @@
+//! Logging of raw protocol lines
+//!
+//! Interop problems between implementations are easiest to
+//! see in the exact lines exchanged. Clients and servers hand
+//! every line they send or receive to a WireLog, which passes
+//! it through its redactors and on to a sink with its
+//! direction, time and connection. Logging can be switched on
+//! and off while connections are open.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{ArgumentDescriptor, Command, Response, REDACTED};
+
+/// Which way a line travelled, from the logging side's view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Direction::Sent => write!(f, "sent"),
+            Direction::Received => write!(f, "received"),
+        }
+    }
+}
+
+/// One logged line
+///
+/// Displayed as `<seconds>.<milliseconds> <connection>
+/// <direction> <line>`, with the time since the Unix epoch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WireRecord<'a> {
+    pub connection: u64,
+    pub direction: Direction,
+    pub at: SystemTime,
+    /// The line without its line ending, after redaction, with
+    /// invalid UTF-8 replaced
+    pub line: Cow<'a, str>,
+}
+
+impl<'a> fmt::Display for WireRecord<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let since_epoch = self.at.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+        write!(
+            f,
+            "{}.{:03} {} {} {}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_millis(),
+            self.connection,
+            self.direction,
+            self.line
+        )
+    }
+}
+
+/// Where logged lines go
+pub trait WireSink: Send + Sync {
+    fn record(&self, record: &WireRecord);
+}
+
+impl<F> WireSink for F
+where
+    F: Fn(&WireRecord) + Send + Sync,
+{
+    fn record(&self, record: &WireRecord) {
+        self(record)
+    }
+}
+
+/// Rewrites a line before it is logged, or returns None to
+/// leave it unchanged
+pub type Redactor = Box<dyn Fn(Direction, &str) -> Option<String> + Send + Sync>;
+
+/// Hide arguments the descriptors mark as secret
+///
+/// Commands and responses with a secret argument, such as the
+/// credentials of AUTH or the token of a ResumeToken response,
+/// are logged as their verb or code followed by `[redacted]`.
+/// Other lines are left unchanged.
+///
+/// Lines are recognized by their first word alone, whichever
+/// way they travelled, so a line that fails to parse is still
+/// redacted rather than logged with its secret.
+pub fn redact_secrets(_direction: Direction, line: &str) -> Option<String> {
+    let message_start = match line.strip_prefix('@') {
+        Some(_) => line.find(' ').map_or(line.len(), |index| index + 1),
+        None => 0,
+    };
+    let (request_id, message) = line.split_at(message_start);
+    let word = message.split_whitespace().next()?;
+
+    let has_secret =
+        |arguments: &[ArgumentDescriptor]| arguments.iter().any(|argument| argument.secret);
+    let secret = match word.parse::<u8>() {
+        Ok(code) => Response::descriptor_for(code)
+            .is_some_and(|descriptor| has_secret(descriptor.arguments)),
+        Err(_) => Command::descriptor_for(&word.to_ascii_uppercase())
+            .is_some_and(|descriptor| has_secret(descriptor.arguments)),
+    };
+
+    if !secret {
+        return None;
+    }
+
+    Some(format!("{}{} {}", request_id, word, REDACTED))
+}
+
+/// Logs lines to a sink while enabled
+///
+/// Shared between connections behind an Arc; every method
+/// takes `&self`.
+pub struct WireLog {
+    sink: Box<dyn WireSink>,
+    redactors: Vec<Redactor>,
+    enabled: AtomicBool,
+    next_connection: AtomicU64,
+}
+
+impl fmt::Debug for WireLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("WireLog")
+            .field("redactors", &self.redactors.len())
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
+
+impl WireLog {
+    /// A log redacting secrets, enabled from the start
+    pub fn new<S: WireSink + 'static>(sink: S) -> WireLog {
+        WireLog {
+            sink: Box::new(sink),
+            redactors: vec![Box::new(redact_secrets)],
+            enabled: AtomicBool::new(true),
+            next_connection: AtomicU64::new(0),
+        }
+    }
+
+    /// Pass lines through `redactor` after the redactors
+    /// already added
+    pub fn with_redactor<F>(mut self, redactor: F) -> WireLog
+    where
+        F: Fn(Direction, &str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.redactors.push(Box::new(redactor));
+        self
+    }
+
+    /// Log lines without redacting anything
+    pub fn without_redactors(mut self) -> WireLog {
+        self.redactors.clear();
+        self
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// An id for a new connection, distinct from the others
+    /// handed out by this log
+    pub fn connection(&self) -> u64 {
+        self.next_connection.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Log a line written to a connection
+    pub fn sent(&self, connection: u64, line: &[u8]) {
+        self.log(connection, Direction::Sent, line);
+    }
+
+    /// Log a line read from a connection
+    pub fn received(&self, connection: u64, line: &[u8]) {
+        self.log(connection, Direction::Received, line);
+    }
+
+    fn log(&self, connection: u64, direction: Direction, line: &[u8]) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        let mut line = String::from_utf8_lossy(line);
+
+        for redactor in &self.redactors {
+            if let Some(redacted) = redactor(direction, &line) {
+                line = Cow::Owned(redacted);
+            }
+        }
+
+        self.sink.record(&WireRecord {
+            connection,
+            direction,
+            at: SystemTime::now(),
+            line,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_credentials() {
+        assert_eq!(
+            redact_secrets(Direction::Received, "AUTH PASSWORD bob hunter2").as_deref(),
+            Some("AUTH [redacted]")
+        );
+    }
+
+    #[test]
+    fn redacts_malformed_auth() {
+        assert_eq!(
+            redact_secrets(Direction::Received, "@7 AUTH PASSWORD bob hunter2 extra").as_deref(),
+            Some("@7 AUTH [redacted]")
+        );
+    }
+
+    #[test]
+    fn redacts_resume_token_response() {
+        assert_eq!(
+            redact_secrets(Direction::Sent, "84 0123456789abcdef 1700000000").as_deref(),
+            Some("84 [redacted]")
+        );
+    }
+
+    #[test]
+    fn leaves_other_lines() {
+        assert_eq!(redact_secrets(Direction::Received, "LISTUNREAD"), None);
+        assert_eq!(redact_secrets(Direction::Sent, "10 bob"), None);
+    }
+}